@@ -0,0 +1,8 @@
+#!/bin/sh
+# Compiles every shader next to its source as <name>.spv (needs glslc from the Vulkan SDK)
+set -e
+cd "$(dirname "$0")"
+for shader in *.vert *.frag *.geom *.tesc *.tese *.comp; do
+    [ -f "$shader" ] || continue
+    glslc "$shader" -o "$shader.spv"
+done
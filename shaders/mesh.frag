@@ -0,0 +1,10 @@
+#version 450
+
+layout(location = 0) in vec3 frag_color;
+
+layout(location = 0) out vec4 out_color;
+
+void main() {
+    // Linear color, the SRGB swapchain does the gamma encode
+    out_color = vec4(frag_color, 1.0);
+}
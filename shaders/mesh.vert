@@ -0,0 +1,21 @@
+#version 450
+
+layout(location = 0) in vec3 in_position;
+layout(location = 1) in vec3 in_color;
+
+// Per instance, a mat4 takes locations 2..5
+layout(location = 2) in mat4 in_model;
+
+// Written by the Camera each frame, proj already maps to Vulkan clip space
+layout(set = 0, binding = 0) uniform UniformBufferObject {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+} ubo;
+
+layout(location = 0) out vec3 frag_color;
+
+void main() {
+    gl_Position = ubo.proj * ubo.view * ubo.model * in_model * vec4(in_position, 1.0);
+    frag_color = in_color;
+}
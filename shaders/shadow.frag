@@ -0,0 +1,4 @@
+#version 450
+
+// Depth-only pass, the depth is written by the fixed function stages
+void main() {}
@@ -0,0 +1,14 @@
+#version 450
+
+layout(location = 0) in vec3 in_position;
+
+// Per instance, a mat4 takes locations 2..5
+layout(location = 2) in mat4 in_model;
+
+layout(push_constant) uniform Light {
+    mat4 view_projection;
+} light;
+
+void main() {
+    gl_Position = light.view_projection * in_model * vec4(in_position, 1.0);
+}
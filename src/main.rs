@@ -2,14 +2,21 @@
 
 mod vulkan;
 
+use std::time::{Duration, Instant};
+
 use winit::application::ApplicationHandler;
 use winit::event::WindowEvent;
 use winit::event_loop::{ActiveEventLoop, ControlFlow, EventLoop};
 use winit::window::{self, Window, WindowId};
 
+// Shows the FPS from Context::frame_stats in the window title
+const SHOW_FPS_IN_TITLE: bool = false;
+const TITLE_UPDATE_INTERVAL: Duration = Duration::from_millis(500);
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let event_loop = EventLoop::new()?;
-    event_loop.set_control_flow(ControlFlow::Poll);
+    // Continuous rendering is driven by request_redraw, so the loop can sleep between events
+    event_loop.set_control_flow(ControlFlow::Wait);
 
     let mut app = App::new();
     event_loop.run_app(&mut app)?;
@@ -20,6 +27,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 struct App {
     context: Option<vulkan::Context>,
     window: Option<Window>,
+    // Last title update, set_title every frame is needlessly expensive on some platforms
+    title_updated: Option<Instant>,
 }
 
 impl App {
@@ -27,39 +36,26 @@ impl App {
         Self {
             context: None,
             window: None,
+            title_updated: None,
         }
     }
 }
 
 impl ApplicationHandler for App {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
-        match (&self.window, &self.context) {
-            (None, None) => {
-                let window = event_loop
+        if self.window.is_none() {
+            // A context without its window has a dangling surface, drop it and start over
+            self.context = None;
+            self.window = Some(
+                event_loop
                     .create_window(winit::window::WindowAttributes::default())
-                    .expect("Failed to create window");
-
-                let context = vulkan::Context::new(&window);
-
-                self.window = Some(window);
-                self.context = Some(context);
-            }
-            (None, Some(_)) => {
-                panic!(
-                    "Vulkan context without window you should probably create both (treat it as both are None)"
-                );
-                self.window = Some(
-                    event_loop
-                        .create_window(winit::window::WindowAttributes::default())
-                        .expect("Failed to create window"),
-                )
-            }
-            (Some(window), None) => {
-                self.context = Some(vulkan::Context::new(window));
-            }
+                    .expect("Failed to create window"),
+            );
+        }
 
-            (Some(_), Some(_)) => (),
-        };
+        if let (Some(window), None) = (&self.window, &self.context) {
+            self.context = Some(vulkan::Context::new(window));
+        }
 
         println!("Resumed - Success");
     }
@@ -67,7 +63,7 @@ impl ApplicationHandler for App {
     fn window_event(
         &mut self,
         event_loop: &ActiveEventLoop,
-        _window_id: WindowId,
+        window_id: WindowId,
         event: WindowEvent,
     ) {
         match event {
@@ -78,18 +74,60 @@ impl ApplicationHandler for App {
 
             WindowEvent::RedrawRequested => {
                 // println!("Redraw - requirested");
-                if let Some(window) = &self.window {
-                    window.request_redraw();
+                if let (Some(context), Some(window)) = (&mut self.context, &self.window) {
+                    context
+                        .poll_shader_reload()
+                        .expect("Failed to reload shaders");
+                    context.draw_frame(window_id).expect("Failed to draw frame");
+                    if SHOW_FPS_IN_TITLE
+                        && self
+                            .title_updated
+                            .is_none_or(|updated| updated.elapsed() >= TITLE_UPDATE_INTERVAL)
+                        && let Some(stats) = context.frame_stats(window_id)
+                    {
+                        window.set_title(&format!(
+                            "{:.0} FPS ({:.2} ms, {:.2}..{:.2})",
+                            stats.fps, stats.avg_ms, stats.min_ms, stats.max_ms
+                        ));
+                        self.title_updated = Some(Instant::now());
+                    }
+                    // Stop requesting frames while minimized, the next resize wakes us up
+                    if !context.is_paused(window_id) {
+                        window.request_redraw();
+                    }
                 }
             }
-            WindowEvent::Resized(_new_size) => {
+            WindowEvent::Resized(new_size) => {
+                // Swapchain is recreated lazily at the next draw
                 if let (Some(context), Some(window)) = (&mut self.context, &self.window) {
+                    context.resized(window_id, new_size);
+                    window.request_redraw();
+                }
+            }
+            WindowEvent::Destroyed => {
+                println!("Window - destroyed");
+                // GPU resources must go before the surface's window does
+                if let Some(context) = &mut self.context {
                     context
-                        .recreate_swapchain(window)
-                        .expect("Failed to crecreate swapchain")
+                        .remove_window(window_id)
+                        .expect("Failed to remove window");
+                    if context.window_count() == 0 {
+                        self.context = None;
+                    }
                 }
             }
             _ => {}
         }
     }
+
+    fn exiting(&mut self, _event_loop: &ActiveEventLoop) {
+        // Waits for the device to go idle, then destroys everything before the window
+        // it was created from
+        if let Some(context) = self.context.take() {
+            context.destroy();
+        }
+        self.window = None;
+
+        println!("Exiting - Success");
+    }
 }
@@ -1,308 +1,4317 @@
 use ash::{ext, khr, vk};
 use raw_window_handle::{HasDisplayHandle, HasWindowHandle};
-use std::ffi::{CStr, c_char};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::{CStr, CString, c_char};
+use std::mem::ManuallyDrop;
+use std::rc::Rc;
+use winit::window::WindowId;
 
+mod allocator;
+mod animation;
+mod buffer;
+mod camera;
+mod command;
+mod compute;
+mod dds;
+mod debug;
+mod depth;
+mod descriptor;
+mod device_info;
+mod frame_stats;
+mod gbuffer;
+mod gltf;
+mod image;
+mod image_fill;
+mod indirect;
+mod json;
+mod mesh;
+mod obj;
+mod owned;
+mod ownership;
+mod pipeline;
+mod query;
+mod readback;
+mod reflect;
+mod render_pass;
+mod render_scale;
+mod resource_state;
+mod sampler;
+mod scene;
+mod shader_watcher;
+mod shading_rate;
+mod shadow;
+mod staging;
+mod sync;
+mod texture;
+mod uniform;
+
+pub use allocator::{Allocation, AllocationKind, Allocator, AllocatorStats};
+pub use animation::InstanceAnimation;
+pub use buffer::{Buffer, IndexBuffer, VertexBuffer};
+pub use camera::{
+    Camera, UniformBufferObject, clip_correction, look_at, mat4_mul, orthographic, perspective,
+    viewport,
+};
+pub use command::{CommandPool, submit_once, submit_once_on};
+pub use compute::{AsyncCompute, ComputePipeline};
+pub use debug::DebugLabels;
+pub use depth::{
+    DepthBuffer, DepthStencilLayouts, aspect_flags_for_format, has_stencil_component,
+    transition_depth_stencil,
+};
+pub use descriptor::{
+    BindlessTextures, DescriptorSetLayoutBuilder, DescriptorWriter, PushDescriptorSet,
+    TransientDescriptorPool,
+};
+pub use device_info::DeviceInfo;
+pub use frame_stats::{FrameStats, FrameTimer};
+pub use gbuffer::GBuffer;
+pub use gltf::{GltfDocument, GltfImage, GltfMaterial, GltfMesh, GltfModel, GltfPrimitive};
+pub use image::{Image, layout_access_stage, texel_size, transition_layout};
+pub use image_fill::ImageFill;
+pub use indirect::{DrawIndirectCount, IndirectBuffer, cmd_draw_indexed_indirect};
+pub use mesh::{InstanceData, Mesh, Vertex, cmd_draw_indexed};
+pub use obj::parse_obj;
+pub use owned::{
+    Owned, OwnedBuffer, OwnedDescriptorPool, OwnedDescriptorSetLayout, OwnedFence, OwnedImage,
+    OwnedImageView, OwnedPipeline, OwnedPipelineLayout, OwnedSampler, OwnedSemaphore,
+    OwnedShaderModule,
+};
+pub use ownership::OwnershipTransfer;
+pub use pipeline::{
+    GraphicsPipeline, GraphicsPipelineBuilder, VertexInputBuilder, create_shader_module,
+    read_spirv, stencil_test_state, stencil_write_state,
+};
+pub use query::{OcclusionQueries, PipelineStatistics, StatisticsQueries};
+pub use readback::{ReadbackBuffer, aspect_texel_size};
+pub use reflect::{ReflectedBinding, ReflectedLayout, reflect_descriptors};
+pub use render_pass::{Framebuffers, RenderPass};
+pub use render_scale::ScaledTarget;
+pub use resource_state::{ImageUsage, ResourceState};
+pub use sampler::{Sampler, SamplerBuilder};
+pub use scene::{Scene, StencilMask};
+pub use shader_watcher::ShaderWatcher;
+pub use shading_rate::{FragmentShadingRate, KEEP_PIPELINE_RATE, supported_fragment_size};
+pub use shadow::ShadowMap;
+pub use staging::StagingRing;
+pub use sync::{FencePool, FenceStatus, FrameSync};
+pub use texture::{Cubemap, Texture, TextureArray};
+pub use uniform::{UniformBuffers, UniformLayout};
+
+const STAGING_SIZE_PER_FRAME: vk::DeviceSize = 4 * 1024 * 1024;
+// Per pool of Frame::allocate_transient, more pools are added when a frame needs more
+const TRANSIENT_SETS_PER_POOL: u32 = 256;
+const TRANSIENT_POOL_SIZES: &[(vk::DescriptorType, u32)] = &[
+    (vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 256),
+    (vk::DescriptorType::UNIFORM_BUFFER, 256),
+    (vk::DescriptorType::STORAGE_BUFFER, 64),
+    (vk::DescriptorType::SAMPLED_IMAGE, 64),
+    (vk::DescriptorType::STORAGE_IMAGE, 64),
+    (vk::DescriptorType::SAMPLER, 64),
+];
+const MAIN_PASS_LABEL_COLOR: [f32; 4] = [0.2, 0.4, 1.0, 1.0];
+
+// Version the renderer is written against
+const REQUESTED_API_VERSION: u32 = vk::API_VERSION_1_3;
+// Run on older loaders with the highest version they support instead of failing, never
+// below MIN_API_VERSION (vkGetPhysicalDeviceFeatures2 needs 1.1)
+const ALLOW_API_VERSION_DOWNGRADE: bool = false;
+const MIN_API_VERSION: u32 = vk::API_VERSION_1_1;
+// Prints every physical device with its capabilities at startup, for bug reports
+const LOG_DEVICE_LIST: bool = false;
+
+// Defaults of InstanceConfig
 const APP_NAME: &CStr = c"VULKAN-SANDBOX";
 const ENGINE_NAME: &CStr = c"NO ENGINE";
-const INSTANCE_LAYERS: &[*const c_char] = &[
-    c"VK_LAYER_KHRONOS_validation".as_ptr() as *const c_char,
-    // c"VK_LAYER_LUNARG_monitor".as_ptr() as *const c_char,
-    // c"VK_LAYER_LUNARG_api_dump".as_ptr() as *const c_char,
-];
+// Only enabled with InstanceConfig::validation
+const INSTANCE_LAYERS: &[*const c_char] =
+    &[c"VK_LAYER_KHRONOS_validation".as_ptr() as *const c_char];
+// Vulkan SDK debugging layers, enabled with InstanceConfig::api_dump / monitor
+const API_DUMP_LAYER: &CStr = c"VK_LAYER_LUNARG_api_dump";
+const MONITOR_LAYER: &CStr = c"VK_LAYER_LUNARG_monitor";
 const INSTANCE_EXTENSIONS: &[*const c_char] = &[];
+// Enabled only when the loader or a layer provides them
+const OPTIONAL_INSTANCE_EXTENSIONS: &[&CStr] = &[
+    ext::debug_utils::NAME, // For debug labels in RenderDoc/NSight captures
+    khr::get_surface_capabilities2::NAME, // Required by VK_EXT_full_screen_exclusive
+];
 const DEVICE_EXTENSIONS: &[*const c_char] = &[
     khr::swapchain::NAME.as_ptr() as *const c_char, // For swapchain support
 ];
+// Enabled only when the physical device supports them
+const OPTIONAL_DEVICE_EXTENSIONS: &[&CStr] = &[
+    ext::memory_budget::NAME,            // For live heap budget/usage
+    khr::draw_indirect_count::NAME,      // For GPU written draw counts
+    ext::full_screen_exclusive::NAME,    // For exclusive fullscreen (Windows only)
+    khr::push_descriptor::NAME,          // For per-draw descriptors without allocating sets
+    khr::present_id::NAME,               // Required by VK_KHR_present_wait
+    khr::present_wait::NAME,             // For waiting until a frame is on screen
+    khr::maintenance1::NAME,             // Core since 1.1, for negative viewport heights
+    ext::descriptor_indexing::NAME,      // Core since 1.2, for bindless textures on 1.1
+    khr::image_format_list::NAME,        // Required by VK_KHR_swapchain_mutable_format
+    khr::swapchain_mutable_format::NAME, // For UNORM views of sRGB swapchain images
+    khr::fragment_shading_rate::NAME,    // For coarse shading of less important draws
+];
+
+// One memory heap in Context::memory_stats, sizes in bytes
+#[derive(Debug, Clone, Copy)]
+pub struct HeapStat {
+    pub heap_index: u32,
+    // DEVICE_LOCAL for VRAM
+    pub flags: vk::MemoryHeapFlags,
+    pub total: vk::DeviceSize,
+    // Whole process usage and what it can allocate without hurting performance, both
+    // from VK_EXT_memory_budget. None (unknown) without the extension.
+    pub used: Option<vk::DeviceSize>,
+    pub budget: Option<vk::DeviceSize>,
+    // Blocks the allocator holds on this heap, and how much of them is handed out
+    pub allocator_reserved: vk::DeviceSize,
+    pub allocator_used: vk::DeviceSize,
+}
 
 pub struct Context {
-    instance: Instance,
-    surface: Surface,
-    device: Device,
-    swapchain: Swapchain,
+    // Torn down in declaration order by `destroy`, dependents before what they depend on.
+    // The ManuallyDrop fields are only ever dropped there.
+    windows: HashMap<WindowId, WindowContext>,
+    debug_labels: DebugLabels,
+    fragment_shading_rate: FragmentShadingRate,
+    // None when the shaders haven't been compiled, frames are only cleared then
+    scene: Option<Scene>,
+    // Created by the first compute_fill_image
+    image_fill: Option<ImageFill>,
+    // Watches the scene's SPIR-V files, see poll_shader_reload
+    shader_watcher: ShaderWatcher,
+    // Scene pipelines are built against it, every window allocates its own sets from it
+    uniform_layout: ManuallyDrop<UniformLayout>,
+    // Shared by every window, their swapchains must all use its color format
+    render_pass: ManuallyDrop<RenderPass>,
+    device: ManuallyDrop<Device>,
+    instance: ManuallyDrop<Instance>,
+    destroyed: bool,
+
+    // Used for every swapchain (re)creation
+    swapchain_config: SwapchainConfig,
+    clear_color: [f32; 4],
+    clear_depth: f32,
+    // Aspect follows whichever window is being drawn
+    camera: Camera,
+    // Number of frames every window's FrameStats cover
+    frame_stats_window: usize,
+    // Fraction of the window size the scene is rendered at, see set_render_scale
+    render_scale: f32,
+    // Of the main pass' color attachment, see set_color_load_op
+    color_load_op: vk::AttachmentLoadOp,
+}
+
+impl Drop for Context {
+    fn drop(&mut self) {
+        self.teardown();
+    }
+}
+
+// Configuration of everything Context::new sets up, see Context::builder. Each option
+// can also be changed later through the matching Context setter, except the instance,
+// device, depth buffer and sample count.
+pub struct ContextBuilder {
+    instance: Option<Instance>,
+    instance_config: InstanceConfig,
+    device_config: DeviceConfig,
+    swapchain_config: SwapchainConfig,
+    depth_buffer: bool,
+    samples: vk::SampleCountFlags,
+    clear_color: [f32; 4],
+    clear_depth: f32,
+    render_scale: f32,
+    frame_stats_window: usize,
+    check_compute_readback: bool,
+}
+
+impl ContextBuilder {
+    // Already created instance, e.g. with a custom loader (see Instance::with_loader_path).
+    // The instance config is ignored then.
+    pub fn instance(mut self, instance: Instance) -> Self {
+        self.instance = Some(instance);
+        self
+    }
+
+    // Replaces InstanceConfig::from_env, e.g. to set the app name
+    pub fn instance_config(mut self, config: InstanceConfig) -> Self {
+        self.instance_config = config;
+        self
+    }
+
+    // Overrides InstanceConfig::from_env
+    pub fn validation(mut self, enabled: bool) -> Self {
+        self.instance_config = self.instance_config.validation(enabled);
+        self
+    }
+
+    pub fn device_config(mut self, config: DeviceConfig) -> Self {
+        self.device_config = config;
+        self
+    }
+
+    // Ignored when device_config names a device that exists
+    pub fn gpu_preference(mut self, preference: GpuPreference) -> Self {
+        self.device_config.gpu_preference = preference;
+        self
+    }
+
+    // Replaces the present mode and frames in flight set so far
+    pub fn swapchain_config(mut self, config: SwapchainConfig) -> Self {
+        self.swapchain_config = config;
+        self
+    }
+
+    pub fn present_mode(mut self, present_mode: PresentModePreference) -> Self {
+        self.swapchain_config.present_mode = present_mode;
+        self
+    }
+
+    pub fn frames_in_flight(mut self, frames: usize) -> Self {
+        self.swapchain_config.frames_in_flight = frames;
+        self
+    }
+
+    // Without it the main pass has no depth attachment, scene pipelines still build but
+    // draw without depth testing
+    pub fn depth_buffer(mut self, enabled: bool) -> Self {
+        self.depth_buffer = enabled;
+        self
+    }
+
+    // Stencil component in the depth buffer (DeviceConfig::depth_stencil), the scene then
+    // draws its stencil mask (see StencilMask)
+    pub fn depth_stencil(mut self, enabled: bool) -> Self {
+        self.device_config.depth_stencil = enabled;
+        self
+    }
+
+    // MSAA for the main pass, resolved into the swapchain image at the end of it. Lowered
+    // to the highest count framebufferColorSampleCounts and framebufferDepthSampleCounts
+    // both support. Above TYPE_1 it needs the depth buffer and Vulkan 1.2, and rules out
+    // a render scale other than 1.0 and the LOAD color load op. Scene pipelines follow,
+    // pipelines of your own for the main pass need Context::samples.
+    pub fn samples(mut self, samples: vk::SampleCountFlags) -> Self {
+        self.samples = samples;
+        self
+    }
+
+    pub fn clear_color(mut self, color: [f32; 4]) -> Self {
+        self.clear_color = color;
+        self
+    }
+
+    pub fn clear_depth(mut self, depth: f32) -> Self {
+        self.clear_depth = depth;
+        self
+    }
+
+    // Validated by build, see Context::set_render_scale
+    pub fn render_scale(mut self, scale: f32) -> Self {
+        self.render_scale = scale;
+        self
+    }
+
+    pub fn frame_stats_window(mut self, frames: usize) -> Self {
+        self.frame_stats_window = frames;
+        self
+    }
+
+    // Runs Context::check_compute_readback at startup and prints the outcome, a quick
+    // check of the compute and readback paths on a new driver. Off by default.
+    pub fn check_compute_readback(mut self, enabled: bool) -> Self {
+        self.check_compute_readback = enabled;
+        self
+    }
+
+    // Creates the instance (unless one was given), device and everything `window` needs
+    pub fn build(
+        self,
+        window: &winit::window::Window,
+    ) -> Result<Context, Box<dyn std::error::Error>> {
+        let instance = match self.instance {
+            Some(instance) => instance,
+            None => Instance::with_config(Some(window), &self.instance_config)?,
+        };
+        let mut swapchain_config = self.swapchain_config;
+        // Saves recreating the swapchain in set_render_scale
+        if self.render_scale != 1.0 {
+            swapchain_config.image_usage |= vk::ImageUsageFlags::TRANSFER_DST;
+        }
+
+        let surface = Surface::new(&instance, window)?;
+        if LOG_DEVICE_LIST {
+            match instance.enumerate_devices_info(&surface) {
+                Ok(devices) => device_info::print_device_table(&devices),
+                Err(err) => println!("Warning: Failed to enumerate devices ({err})"),
+            }
+        }
+        let device = Device::with_config(&instance, &surface, &self.device_config)?;
+        let size = window.inner_size();
+        let format = Swapchain::query_format(&device, &surface, &swapchain_config)?;
+        let swapchain = Swapchain::new(
+            &instance,
+            &device,
+            &surface,
+            size,
+            &swapchain_config,
+            format,
+            None,
+        )?;
+        let depth_format = self.depth_buffer.then(|| device.preferred_depth_format());
+        let samples = clamp_sample_count(&device, self.samples);
+        let render_pass = main_render_pass(
+            &device,
+            swapchain.format.format,
+            depth_format,
+            samples,
+            vk::AttachmentLoadOp::CLEAR,
+        )?;
+        let uniform_layout = UniformLayout::new(&device)?;
+        let window_context = WindowContext::new(
+            &device,
+            &render_pass,
+            &uniform_layout,
+            surface,
+            swapchain,
+            size,
+            swapchain_config.frames_in_flight,
+        )?;
+        let scene = match Scene::new(&device, &render_pass, &uniform_layout) {
+            Ok(scene) => Some(scene),
+            Err(err) => {
+                println!("Warning: Scene disabled ({err}), run shaders/compile.sh");
+                None
+            }
+        };
+        let debug_labels = DebugLabels::new(&instance, &device);
+        let fragment_shading_rate = FragmentShadingRate::new(&instance, &device);
+        let shader_watcher = ShaderWatcher::new(Scene::shader_paths());
+
+        let mut context = Context {
+            windows: HashMap::from([(window.id(), window_context)]),
+            debug_labels,
+            fragment_shading_rate,
+            scene,
+            image_fill: None,
+            shader_watcher,
+            uniform_layout: ManuallyDrop::new(uniform_layout),
+            render_pass: ManuallyDrop::new(render_pass),
+            device: ManuallyDrop::new(device),
+            instance: ManuallyDrop::new(instance),
+            destroyed: false,
+
+            swapchain_config,
+            clear_color: self.clear_color,
+            clear_depth: self.clear_depth,
+            camera: Camera::default(),
+            frame_stats_window: frame_stats::DEFAULT_FRAME_STATS_WINDOW,
+            render_scale: 1.0,
+            color_load_op: vk::AttachmentLoadOp::CLEAR,
+        };
+        context.set_frame_stats_window(self.frame_stats_window);
+        if self.check_compute_readback {
+            match context.check_compute_readback() {
+                Ok(true) => println!("Compute readback check passed"),
+                Ok(false) => println!("Warning: Compute readback check read wrong values"),
+                Err(err) => println!("Warning: Compute readback check failed ({err})"),
+            }
+        }
+        // The swapchain already has TRANSFER_DST usage, nothing gets recreated
+        if self.render_scale != 1.0 {
+            context.set_render_scale(self.render_scale)?;
+        }
+
+        Ok(context)
+    }
 }
 
 impl Context {
+    // Instance and device are created once here and shared by windows added later
     pub fn new(window: &winit::window::Window) -> Self {
-        let instance = Instance::new(window).expect("Instance Error");
-        let surface = Surface::new(&instance, window).expect("Surface Error");
-        let device = Device::new(&instance, &surface).expect("Device Error");
-        let swapchain =
-            Swapchain::new(&instance, &device, &surface, window, None).expect("Swapchain Error");
+        let instance = Instance::new(Some(window)).expect("Instance Error");
+        Self::with_instance(window, instance)
+    }
 
-        Self {
+    // For an instance with a custom loader, see Instance::with_loader_path
+    pub fn with_instance(window: &winit::window::Window, instance: Instance) -> Self {
+        Self::with_config(
+            window,
             instance,
-            surface,
-            device,
-            swapchain,
+            &DeviceConfig::default(),
+            SwapchainConfig::default(),
+        )
+    }
+
+    // Prefers a specific GPU (see DeviceConfig) and creates the first swapchain with
+    // `swapchain_config`. Its color_space_mode is fixed from here on.
+    pub fn with_config(
+        window: &winit::window::Window,
+        instance: Instance,
+        device_config: &DeviceConfig,
+        swapchain_config: SwapchainConfig,
+    ) -> Self {
+        Self::builder()
+            .instance(instance)
+            .device_config(device_config.clone())
+            .swapchain_config(swapchain_config)
+            .build(window)
+            .expect("Context Error")
+    }
+
+    // Defaults match Context::new, see ContextBuilder
+    pub fn builder() -> ContextBuilder {
+        ContextBuilder {
+            instance: None,
+            instance_config: InstanceConfig::from_env(),
+            device_config: DeviceConfig::default(),
+            swapchain_config: SwapchainConfig::default(),
+            depth_buffer: true,
+            samples: vk::SampleCountFlags::TYPE_1,
+            clear_color: [0.0, 0.0, 0.0, 1.0],
+            clear_depth: 1.0,
+            render_scale: 1.0,
+            frame_stats_window: frame_stats::DEFAULT_FRAME_STATS_WINDOW,
+            check_compute_readback: false,
         }
     }
 
-    pub fn recreate_swapchain(
+    // Blocks until every queue of the device is idle, nothing recorded so far is in use
+    // by the GPU afterwards
+    pub fn wait_idle(&self) -> Result<(), Box<dyn std::error::Error>> {
+        unsafe { self.device.device.device_wait_idle()? };
+        Ok(())
+    }
+
+    // Explicit teardown, dropping the context does the same
+    pub fn destroy(mut self) {
+        self.teardown();
+    }
+
+    // Idempotent, runs from both destroy and Drop
+    fn teardown(&mut self) {
+        if self.destroyed {
+            return;
+        }
+        self.destroyed = true;
+
+        // Submitted frames still reference the command buffers, framebuffers and pipelines,
+        // and queued presents wait on render_finished semaphores of their window. Destroying
+        // any of it before the GPU is done is the "object in use" validation error.
+        if let Err(err) = self.wait_idle() {
+            println!("Warning: Wait idle failed during teardown ({err})");
+        }
+
+        // Per window: sync -> uniforms -> compute -> staging -> command pool -> framebuffers
+        // -> depth -> swapchain -> surface, the swapchain has to go before the surface it was
+        // created for
+        self.windows.clear();
+        // Pipelines
+        self.scene = None;
+        self.image_fill = None;
+
+        unsafe {
+            ManuallyDrop::drop(&mut self.uniform_layout);
+            ManuallyDrop::drop(&mut self.render_pass);
+            ManuallyDrop::drop(&mut self.device);
+            ManuallyDrop::drop(&mut self.instance);
+        }
+    }
+
+    // Creates a surface and swapchain for another window on the existing device
+    pub fn add_window(
         &mut self,
         window: &winit::window::Window,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        let new_swapchain = Swapchain::new(
+        if self.windows.contains_key(&window.id()) {
+            return Err(format!("Window {:?} already has a context", window.id()).into());
+        }
+
+        let surface = Surface::new(&self.instance, window)?;
+
+        // The queues were picked for the first surface and can't change anymore
+        let present_supported = unsafe {
+            surface.loader.get_physical_device_surface_support(
+                self.device.physical_device,
+                self.device.present_queue_family_idx,
+                surface.surface,
+            )?
+        };
+        if !present_supported {
+            return Err("Present queue family can't present to the new window's surface".into());
+        }
+
+        let size = window.inner_size();
+        // The render pass is shared, so any supported format matching it will do even if
+        // the surface would prefer another one
+        let Some(format) =
+            Swapchain::find_format(&self.device, &surface, self.render_pass.color_format)?
+        else {
+            return Err(format!(
+                "Window surface doesn't support the shared render pass format {:?}",
+                self.render_pass.color_format
+            )
+            .into());
+        };
+        let swapchain = Swapchain::new(
             &self.instance,
             &self.device,
-            &self.surface,
-            window,
-            Some(self.swapchain.swapchain),
-        )
-        .expect("Swapchain Recreation Error");
+            &surface,
+            size,
+            &self.swapchain_config,
+            format,
+            None,
+        )?;
 
-        self.swapchain = new_swapchain;
+        let mut window_context = WindowContext::new(
+            &self.device,
+            &self.render_pass,
+            &self.uniform_layout,
+            surface,
+            swapchain,
+            size,
+            self.swapchain_config.frames_in_flight,
+        )?;
+        window_context
+            .frame_timer
+            .set_window(self.frame_stats_window);
+        window_context.render_scale = self.render_scale;
+        // Same restriction as set_color_load_op, the main pass already loads
+        if self.color_load_op == vk::AttachmentLoadOp::LOAD
+            && window_context.active_ownership_transfer().is_some()
+        {
+            return Err(
+                "Preserving swapchain contents doesn't work with exclusive present ownership"
+                    .into(),
+            );
+        }
+        window_context.set_color_load_op(self.color_load_op);
+        if let Err(err) = window_context.update_scaled_target(&self.device, &self.render_pass) {
+            println!(
+                "Warning: Render scale {} not applied ({err})",
+                self.render_scale
+            );
+        }
+        self.windows.insert(window.id(), window_context);
 
         Ok(())
     }
-}
 
-pub struct Instance {
-    pub entry: ash::Entry,
-    pub instance: ash::Instance,
-}
+    // Must be called before the window itself is destroyed, its surface references it
+    pub fn remove_window(&mut self, window_id: WindowId) -> Result<(), Box<dyn std::error::Error>> {
+        if self.windows.contains_key(&window_id) {
+            // Its frames may still be in flight
+            self.wait_idle()?;
+            self.windows.remove(&window_id);
+        }
+        Ok(())
+    }
 
-impl Drop for Instance {
-    fn drop(&mut self) {
-        unsafe {
-            self.instance.destroy_instance(None);
+    pub fn window_count(&self) -> usize {
+        self.windows.len()
+    }
+
+    // Only records the new size, recreation is deferred to draw_frame so a burst of
+    // resize events results in a single swapchain recreation
+    pub fn resized(&mut self, window_id: WindowId, new_size: winit::dpi::PhysicalSize<u32>) {
+        if let Some(window) = self.windows.get_mut(&window_id) {
+            window.resized(new_size);
         }
     }
-}
 
-impl Instance {
-    pub fn new(window: &winit::window::Window) -> Result<Self, Box<dyn std::error::Error>> {
-        let entry = unsafe { ash::Entry::load()? };
+    // Unknown windows count as paused, nothing is drawn for them
+    pub fn is_paused(&self, window_id: WindowId) -> bool {
+        self.windows
+            .get(&window_id)
+            .is_none_or(|window| window.paused)
+    }
 
-        // Create extensions vector
-        let mut extension_names: Vec<*const i8> = Vec::from(INSTANCE_EXTENSIONS);
+    // Takes effect with the next swapchain recreation, which is requested for every window.
+    // The color space mode is kept, the shared render pass is built for its format.
+    pub fn set_swapchain_config(&mut self, mut config: SwapchainConfig) {
+        if config.color_space_mode != self.swapchain_config.color_space_mode {
+            println!(
+                "Warning: color_space_mode can only be set at context creation, keeping {:?}",
+                self.swapchain_config.color_space_mode
+            );
+            config.color_space_mode = self.swapchain_config.color_space_mode;
+        }
+        self.swapchain_config = config;
+        for window in self.windows.values_mut() {
+            window.framebuffer_resized = true;
+        }
+    }
 
-        use raw_window_handle::HasDisplayHandle;
-        extension_names.append(
-            &mut ash_window::enumerate_required_extensions(window.display_handle()?.as_raw())?
-                .to_vec(),
-        );
+    pub fn swapchain_config(&self) -> &SwapchainConfig {
+        &self.swapchain_config
+    }
 
-        // Add platform-specific surface extensions
-        #[cfg(target_os = "windows")]
-        extension_names.push(khr::win32_surface::NAME.as_ptr());
-        #[cfg(target_os = "linux")]
-        extension_names.push(khr::wayland_surface::NAME.as_ptr());
-        #[cfg(target_os = "linux")]
-        extension_names.push(khr::xlib_surface::NAME.as_ptr());
-        #[cfg(target_os = "macos")]
-        extension_names.push(ext::metal_surface::NAME.as_ptr());
-        #[cfg(target_os = "macos")]
-        extension_names.push(khr::portability_enumeration::NAME.as_ptr());
+    // Applied to every window right away, unlike set_swapchain_config nothing is recreated
+    pub fn set_acquire_timeout(&mut self, timeout: std::time::Duration) {
+        self.swapchain_config.acquire_timeout = timeout;
+        for window in self.windows.values_mut() {
+            window.swapchain.acquire_timeout = timeout_nanos(timeout);
+        }
+    }
 
-        // Verify layers are available
-        let available_layers = unsafe { entry.enumerate_instance_layer_properties()? };
-        for &layer_ptr in INSTANCE_LAYERS {
-            let layer_name = unsafe { CStr::from_ptr(layer_ptr) };
-            let found = available_layers.iter().any(|prop| {
-                let prop_name = unsafe { CStr::from_ptr(prop.layer_name.as_ptr()) };
-                prop_name == layer_name
-            });
-            if !found {
-                panic!("Warning: Layer {layer_name:?} not available");
+    // Renders the scene at `scale` times the window size into an offscreen image, which
+    // is blitted to the swapchain image with linear filtering (e.g. 0.75 for speed, up
+    // to MAX_RENDER_SCALE for supersampling). Draw callbacks get the scaled extent. The
+    // swapchains are recreated with TRANSFER_DST usage the first time, that fails if the
+    // surface can't provide it. 1.0 renders to the swapchain directly again.
+    pub fn set_render_scale(&mut self, scale: f32) -> Result<(), Box<dyn std::error::Error>> {
+        if !(scale > 0.0 && scale <= render_scale::MAX_RENDER_SCALE) {
+            return Err(format!(
+                "Render scale {scale} outside of (0, {}]",
+                render_scale::MAX_RENDER_SCALE
+            )
+            .into());
+        }
+        // The scaled target's pass is single sampled, scene pipelines wouldn't fit it
+        if scale != 1.0 && self.render_pass.samples != vk::SampleCountFlags::TYPE_1 {
+            return Err("A render scale other than 1.0 doesn't work with MSAA".into());
+        }
+
+        // In flight frames may still use the current targets
+        self.wait_idle()?;
+
+        let transfer_dst = vk::ImageUsageFlags::TRANSFER_DST;
+        if scale != 1.0 && !self.swapchain_config.image_usage.contains(transfer_dst) {
+            self.swapchain_config.image_usage |= transfer_dst;
+            let window_ids: Vec<_> = self.windows.keys().copied().collect();
+            for window_id in window_ids {
+                // Paused windows pick it up when they come back
+                if let Some(window) = self.windows.get_mut(&window_id) {
+                    window.framebuffer_resized = true;
+                }
+                self.recreate_swapchain(window_id)?;
             }
         }
 
-        let app_info = vk::ApplicationInfo::default()
-            .application_name(APP_NAME)
-            .engine_name(ENGINE_NAME)
-            .api_version(vk::API_VERSION_1_3);
+        self.render_scale = scale;
+        for window in self.windows.values_mut() {
+            window.render_scale = scale;
+            if !window.paused {
+                window.update_scaled_target(&self.device, &self.render_pass)?;
+            }
+        }
+        Ok(())
+    }
 
-        let mut create_flags = vk::InstanceCreateFlags::default();
-        #[cfg(target_os = "macos")]
+    pub fn render_scale(&self) -> f32 {
+        self.render_scale
+    }
+
+    // LOAD keeps what the swapchain image held instead of clearing it, for accumulation
+    // or redrawing only what changed. That's the image's own last frame, with N images it
+    // is N frames old, so incremental rendering has to track damage per image
+    // (Frame::image_index). Images without a defined previous content (new swapchains,
+    // the first frame after switching) are still cleared to the clear color. Depth is
+    // always cleared. Has no effect while a render scale other than 1.0 is active, the
+    // blit overwrites the whole image, and can't be combined with
+    // SwapchainConfig::exclusive_present_ownership, the images aren't transferred back, or
+    // with MSAA (ContextBuilder::samples), the resolve overwrites the whole image too.
+    pub fn set_color_load_op(
+        &mut self,
+        load_op: vk::AttachmentLoadOp,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if load_op != vk::AttachmentLoadOp::CLEAR && load_op != vk::AttachmentLoadOp::LOAD {
+            return Err(
+                format!("Main pass color load op {load_op:?}, expected CLEAR or LOAD").into(),
+            );
+        }
+        if load_op == vk::AttachmentLoadOp::LOAD
+            && self
+                .windows
+                .values()
+                .any(|window| window.active_ownership_transfer().is_some())
         {
-            create_flags |= vk::InstanceCreateFlags::ENUMERATE_PORTABILITY_KHR;
+            return Err(
+                "Preserving swapchain contents doesn't work with exclusive present ownership"
+                    .into(),
+            );
+        }
+        if load_op == vk::AttachmentLoadOp::LOAD
+            && self.render_pass.samples != vk::SampleCountFlags::TYPE_1
+        {
+            return Err("Preserving swapchain contents doesn't work with MSAA".into());
+        }
+        if load_op == self.color_load_op {
+            return Ok(());
         }
 
-        let instance_create_info = vk::InstanceCreateInfo::default()
-            .application_info(&app_info)
-            .enabled_layer_names(INSTANCE_LAYERS)
-            .enabled_extension_names(&extension_names)
-            .flags(create_flags);
+        // Only the load op and initial layout change, the pass stays compatible with the
+        // framebuffers and pipelines built for the old one
+        let render_pass = main_render_pass(
+            &self.device,
+            self.render_pass.color_format,
+            self.render_pass.depth_format,
+            self.render_pass.samples,
+            load_op,
+        )?;
+        // In flight frames may still use the old pass
+        self.wait_idle()?;
+        *self.render_pass = render_pass;
+        self.color_load_op = load_op;
+        for window in self.windows.values_mut() {
+            window.set_color_load_op(load_op);
+        }
+        Ok(())
+    }
 
-        let instance = unsafe { entry.create_instance(&instance_create_info, None)? };
+    pub fn color_load_op(&self) -> vk::AttachmentLoadOp {
+        self.color_load_op
+    }
 
-        Ok(Self { entry, instance })
+    pub fn set_clear_color(&mut self, r: f32, g: f32, b: f32, a: f32) {
+        self.clear_color = [r, g, b, a];
     }
-}
 
-pub struct Surface {
-    surface: vk::SurfaceKHR,
-    loader: khr::surface::Instance,
+    pub fn clear_color(&self) -> [f32; 4] {
+        self.clear_color
+    }
 
-    window_handle: raw_window_handle::RawWindowHandle,
-    display_handle: raw_window_handle::RawDisplayHandle,
-}
+    pub fn set_clear_depth(&mut self, depth: f32) {
+        self.clear_depth = depth;
+    }
 
-impl Drop for Surface {
-    fn drop(&mut self) {
-        unsafe {
-            self.loader.destroy_surface(self.surface, None);
+    pub fn clear_depth(&self) -> f32 {
+        self.clear_depth
+    }
+
+    pub fn allocator(&self) -> &Rc<RefCell<Allocator>> {
+        self.device.allocator()
+    }
+
+    // Per heap sizes and usage for a debug overlay. The allocator only knows its own
+    // blocks, SHADER_DEVICE_ADDRESS buffers (dedicated memory, see Buffer::new) only show
+    // up in the VK_EXT_memory_budget figures, which are None without that extension.
+    pub fn memory_stats(&self) -> Vec<HeapStat> {
+        let memory_properties = &self.device.memory_properties;
+        let budget = self.device.memory_budget();
+        let allocator_usage = self.device.allocator().borrow().heap_usage();
+
+        memory_properties.memory_heaps[..memory_properties.memory_heap_count as usize]
+            .iter()
+            .enumerate()
+            .map(|(idx, heap)| {
+                let (allocator_reserved, allocator_used) =
+                    allocator_usage.get(idx).copied().unwrap_or_default();
+                let live = budget
+                    .as_ref()
+                    .and_then(|budget| budget.iter().find(|&&(heap, ..)| heap as usize == idx));
+                HeapStat {
+                    heap_index: idx as u32,
+                    flags: heap.flags,
+                    total: heap.size,
+                    used: live.map(|&(_, _, usage)| usage),
+                    budget: live.map(|&(_, budget, _)| budget),
+                    allocator_reserved,
+                    allocator_used,
+                }
+            })
+            .collect()
+    }
+
+    // Staging space for uploads recorded into the window's current frame command buffer
+    pub fn staging(&mut self, window_id: WindowId) -> Option<&mut StagingRing> {
+        self.windows
+            .get_mut(&window_id)
+            .map(|window| &mut window.staging)
+    }
+
+    // Debug label wrappers for command buffers recorded outside of the main pass
+    pub fn debug_labels(&self) -> &DebugLabels {
+        &self.debug_labels
+    }
+
+    // Per-draw shading rates for pipelines built with
+    // GraphicsPipelineBuilder::fragment_shading_rate, no-ops without device support
+    pub fn fragment_shading_rate(&self) -> &FragmentShadingRate {
+        &self.fragment_shading_rate
+    }
+
+    // CPU frame times of the window, None until it drew two frames
+    pub fn frame_stats(&self, window_id: WindowId) -> Option<FrameStats> {
+        self.windows
+            .get(&window_id)
+            .and_then(|window| window.frame_timer.stats())
+    }
+
+    // Applies to existing and future windows
+    pub fn set_frame_stats_window(&mut self, frames: usize) {
+        self.frame_stats_window = frames.max(1);
+        for window in self.windows.values_mut() {
+            window.frame_timer.set_window(self.frame_stats_window);
         }
     }
-}
 
-impl Surface {
-    fn new(
-        instance: &Instance,
-        window: &winit::window::Window,
-    ) -> Result<Self, Box<dyn std::error::Error>> {
-        let window_handle = window.window_handle()?.as_raw();
-        let display_handle = window.display_handle()?.as_raw();
+    pub fn camera(&self) -> &Camera {
+        &self.camera
+    }
 
-        let loader = khr::surface::Instance::new(&instance.entry, &instance.instance);
-        let surface = unsafe {
-            ash_window::create_surface(
-                &instance.entry,
-                &instance.instance,
-                display_handle,
-                window_handle,
-                None,
-            )
-        }?;
+    // Aspect is overwritten from the swapchain extent every frame
+    pub fn camera_mut(&mut self) -> &mut Camera {
+        &mut self.camera
+    }
+
+    // Human readable report of the instance, device and every window's swapchain, meant
+    // to be attached to bug reports
+    pub fn dump_diagnostics(&self) -> String {
+        let instance = &*self.instance;
+        let device = &*self.device;
+        let props = &device.properties;
+        let device_name = unsafe { CStr::from_ptr(props.device_name.as_ptr()) };
+        let device_extensions: Vec<_> = DEVICE_EXTENSIONS
+            .iter()
+            .map(|&name| unsafe { CStr::from_ptr(name) })
+            .chain(device.enabled_optional_extensions.iter().copied())
+            .map(CStr::to_string_lossy)
+            .collect();
+
+        let features = &device.enabled_features;
+        let enabled_features: Vec<_> = [
+            (
+                "multiDrawIndirect",
+                features.multi_draw_indirect == vk::TRUE,
+            ),
+            (
+                "pipelineStatisticsQuery",
+                features.pipeline_statistics_query == vk::TRUE,
+            ),
+            (
+                "occlusionQueryPrecise",
+                features.occlusion_query_precise == vk::TRUE,
+            ),
+            ("geometryShader", features.geometry_shader == vk::TRUE),
+            (
+                "tessellationShader",
+                features.tessellation_shader == vk::TRUE,
+            ),
+            ("samplerAnisotropy", features.sampler_anisotropy == vk::TRUE),
+            (
+                "sampleRateShading",
+                features.sample_rate_shading == vk::TRUE,
+            ),
+            (
+                "shaderStorageImageWriteWithoutFormat",
+                features.shader_storage_image_write_without_format == vk::TRUE,
+            ),
+            ("descriptorIndexing", device.descriptor_indexing),
+            ("bufferDeviceAddress", device.buffer_device_address),
+            ("presentWait", device.present_wait),
+            (
+                "separateDepthStencilLayouts",
+                device.separate_depth_stencil_layouts,
+            ),
+            ("negativeViewportHeight", device.flip_viewport_y),
+            ("pipelineFragmentShadingRate", device.fragment_shading_rate),
+        ]
+        .into_iter()
+        .filter_map(|(name, enabled)| enabled.then_some(name))
+        .collect();
+
+        let list = |items: &[String]| {
+            if items.is_empty() {
+                "none".to_string()
+            } else {
+                items.join(", ")
+            }
+        };
+        let to_strings = |items: &[std::borrow::Cow<'_, str>]| {
+            items
+                .iter()
+                .map(|item| item.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let mut lines = vec![
+            format!(
+                "Instance: Vulkan {}, validation {}",
+                format_api_version(instance.api_version),
+                if instance.validation { "on" } else { "off" }
+            ),
+            format!("  Layers: {}", list(&instance.enabled_layers)),
+            format!("  Extensions: {}", list(&instance.enabled_extensions)),
+            format!(
+                "Device: {} ({:?}), Vulkan {}, driver {}, vendor {:#06x}, device {:#06x}",
+                device_name.to_string_lossy(),
+                props.device_type,
+                format_api_version(device.api_version),
+                device_info::driver_version_string(props.vendor_id, props.driver_version),
+                props.vendor_id,
+                props.device_id
+            ),
+            format!("  Extensions: {}", list(&to_strings(&device_extensions))),
+            format!(
+                "  Features: {}",
+                list(
+                    &enabled_features
+                        .iter()
+                        .map(|name| name.to_string())
+                        .collect::<Vec<_>>()
+                )
+            ),
+            format!(
+                "  Queue families: graphics {}, present {}, compute {}",
+                device.graphics_queue_family_idx,
+                device.present_queue_family_idx,
+                device.compute_queue_family_idx
+            ),
+            format!("  Depth format: {:?}", device.preferred_depth_format()),
+        ];
+        for (window_id, window) in &self.windows {
+            let swapchain = &window.swapchain;
+            lines.push(format!(
+                "Window {window_id:?}: {}x{}, {:?} {:?}, {:?}, {} images, {} frames in flight, {:?} sharing{}",
+                swapchain.extent.width,
+                swapchain.extent.height,
+                swapchain.format.format,
+                swapchain.format.color_space,
+                swapchain.present_mode,
+                swapchain.image_count,
+                window.frames_in_flight,
+                swapchain.sharing_mode,
+                if window.paused { ", paused" } else { "" }
+            ));
+        }
+        lines.join("\n")
+    }
+
+    // Raw handles for interop with other Vulkan code (e.g. an ImGui renderer). They stay
+    // owned by the context, never destroy them and don't use them after `destroy`.
+    pub fn raw_instance(&self) -> &ash::Instance {
+        &self.instance.instance
+    }
+
+    pub fn raw_device(&self) -> &ash::Device {
+        &self.device.device
+    }
+
+    pub fn raw_physical_device(&self) -> vk::PhysicalDevice {
+        self.device.physical_device
+    }
+
+    // Submissions from outside must not race the context's own, they share the queue
+    pub fn graphics_queue(&self) -> vk::Queue {
+        self.device.graphics_queue
+    }
+
+    pub fn graphics_queue_family_index(&self) -> u32 {
+        self.device.graphics_queue_family_idx
+    }
+
+    // Format of the shared main render pass, changes when the surface's does
+    pub fn color_format(&self) -> vk::Format {
+        self.render_pass.color_format
+    }
+
+    // Of the main pass, see ContextBuilder::samples
+    pub fn samples(&self) -> vk::SampleCountFlags {
+        self.render_pass.samples
+    }
+
+    // Same for every window, they share one render pass
+    pub fn swapchain_format(&self, window_id: WindowId) -> Option<vk::Format> {
+        self.windows
+            .get(&window_id)
+            .map(|window| window.swapchain.format.format)
+    }
+
+    // True with ColorSpaceMode::ManualGamma, the last pass has to gamma encode itself
+    pub fn needs_manual_gamma(&self, window_id: WindowId) -> Option<bool> {
+        self.windows
+            .get(&window_id)
+            .map(|window| window.swapchain.needs_manual_gamma())
+    }
+
+    pub fn swapchain_extent(&self, window_id: WindowId) -> Option<vk::Extent2D> {
+        self.windows
+            .get(&window_id)
+            .map(|window| window.swapchain.extent)
+    }
+
+    // Replaced on every swapchain recreation, don't hold on to them across resizes
+    pub fn image_views(&self, window_id: WindowId) -> Option<&[vk::ImageView]> {
+        self.windows
+            .get(&window_id)
+            .map(|window| window.swapchain.image_views.as_slice())
+    }
+
+    // UNORM aliases of image_views for UI that outputs sRGB encoded colors, see
+    // SwapchainConfig::unorm_views. Render passes using them need the format from
+    // srgb_to_unorm(swapchain_format). Empty when the views couldn't be created.
+    pub fn unorm_image_views(&self, window_id: WindowId) -> Option<&[vk::ImageView]> {
+        self.windows
+            .get(&window_id)
+            .map(|window| window.swapchain.unorm_image_views.as_slice())
+    }
+
+    // Call once per frame, rebuilds the scene pipeline when its SPIR-V files changed. A
+    // pipeline that fails to build is logged and the old one kept.
+    pub fn poll_shader_reload(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.shader_watcher.poll() {
+            return Ok(());
+        }
+
+        match &mut self.scene {
+            Some(scene) => {
+                let pipelines =
+                    Scene::create_pipeline(&self.device, &self.render_pass, &self.uniform_layout)
+                        .and_then(|pipeline| {
+                            let stencil_mask = Scene::create_stencil_mask(
+                                &self.device,
+                                &self.render_pass,
+                                &self.uniform_layout,
+                            )?;
+                            Ok((pipeline, stencil_mask))
+                        });
+                match pipelines {
+                    Ok((pipeline, stencil_mask)) => {
+                        // The old pipelines may still be bound in in-flight frames
+                        unsafe { self.device.device.device_wait_idle()? };
+                        scene.pipeline = pipeline;
+                        scene.stencil_mask = stencil_mask;
+                        println!("Reloaded scene shaders");
+                    }
+                    Err(err) => {
+                        println!("Warning: Shader reload failed ({err}), keeping the old pipeline");
+                    }
+                }
+            }
+            // Shaders weren't compiled at startup, maybe they are now
+            None => match Scene::new(&self.device, &self.render_pass, &self.uniform_layout) {
+                Ok(scene) => {
+                    self.scene = Some(scene);
+                    println!("Scene enabled after shader change");
+                }
+                Err(err) => println!("Warning: Scene still disabled ({err})"),
+            },
+        }
+
+        Ok(())
+    }
+
+    pub fn draw_frame(&mut self, window_id: WindowId) -> Result<(), Box<dyn std::error::Error>> {
+        self.draw_frame_with(window_id, |_| {})
+    }
+
+    // Like draw_frame, `overlay` records extra draws (UI, debug overlays) into the main
+    // pass after the scene, see RenderContext. Not called for frames that are skipped.
+    pub fn draw_frame_with(
+        &mut self,
+        window_id: WindowId,
+        overlay: impl FnOnce(&RenderContext),
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        // Nothing to draw otherwise, an out of date swapchain was already recreated
+        if let Ok(frame) = self.begin_frame(window_id)? {
+            overlay(&frame.render_context());
+            frame.end()?;
+        }
+        Ok(())
+    }
+
+    // Starts a frame the caller records into: waits for the frame's fence, acquires an
+    // image and returns with the command buffer inside the main render pass, the scene
+    // already drawn. Finish it with Frame::end. The inner Err is OutOfDate when there's
+    // no image to draw into, the swapchain was recreated (or the window is minimized),
+    // and NotReady when no image was available within SwapchainConfig::acquire_timeout.
+    // Either way just try again next frame.
+    pub fn begin_frame(
+        &mut self,
+        window_id: WindowId,
+    ) -> Result<Result<Frame<'_>, SwapchainStatus>, Box<dyn std::error::Error>> {
+        // One per attachment in attachment order (color, then depth/stencil), Vulkan ignores
+        // values for attachments that don't exist or aren't cleared
+        let clear_values = [
+            vk::ClearValue {
+                color: vk::ClearColorValue {
+                    float32: self.clear_color,
+                },
+            },
+            vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: self.clear_depth,
+                    stencil: 0,
+                },
+            },
+        ];
+
+        let window = self
+            .windows
+            .get(&window_id)
+            .ok_or_else(|| format!("No context for window {window_id:?}"))?;
+        // No swapchain since recreate_surface (or its recreation failed), or a settled
+        // resize nothing was presented for yet. Acquiring would use a null or stale handle.
+        if window.swapchain.is_released()
+            || (window.framebuffer_resized
+                && window.resize_settled(self.swapchain_config.resize_debounce))
+        {
+            self.recreate_swapchain(window_id)?;
+        }
+        let window = self
+            .windows
+            .get_mut(&window_id)
+            .ok_or_else(|| format!("No context for window {window_id:?}"))?;
+
+        if window.paused {
+            window.frame_timer.pause();
+            return Ok(Err(SwapchainStatus::OutOfDate));
+        }
+        window.frame_timer.tick();
+
+        let current_frame = window.current_frame;
+        let in_flight = window.sync.in_flight[current_frame];
+        let image_available = window.sync.image_available[current_frame];
+        let command_buffer = window.command_pool.buffers[current_frame];
+
+        // Wait until the GPU is done with this frame's resources
+        self.device
+            .wait_for_fences(&[in_flight], true, std::time::Duration::MAX)?;
+        // Transfers recorded the last time this frame was current are done
+        window.staging.reset(current_frame);
+        // And so are the draws using its transient descriptor sets
+        window.transient_descriptors.begin_frame(current_frame)?;
+
+        // The frame's uniform buffer is no longer read either
+        self.camera
+            .set_aspect_from_extent(window.swapchain.rotated_extent());
+        let ubo = self.camera.uniform(
+            camera::IDENTITY,
+            camera::clip_correction(
+                window.swapchain.pre_transform_matrix(),
+                self.device.flip_viewport_y,
+            ),
+        );
+        window.uniforms.update(current_frame, &ubo)?;
+
+        // Suboptimal images are still rendered and presented, present reports it again
+        let image_index = match window.swapchain.acquire_next_image(image_available)? {
+            (image_index, SwapchainStatus::Optimal | SwapchainStatus::Suboptimal) => image_index,
+            (_, SwapchainStatus::OutOfDate) => {
+                self.recreate_swapchain(window_id)?;
+                return Ok(Err(SwapchainStatus::OutOfDate));
+            }
+            // The fence wasn't reset, next time this frame waits on it again right away
+            (_, SwapchainStatus::NotReady) => {
+                println!("Warning: No swapchain image within the acquire timeout, skipping frame");
+                return Ok(Err(SwapchainStatus::NotReady));
+            }
+        };
+
+        // Compute runs ahead on its own queue, the graphics submission in end_frame waits
+        // for it. Only once an image was acquired, an early return would leave the
+        // semaphore signaled with nobody waiting on it.
+        if let Some(animation) = &window.instance_animation {
+            let compute_command_buffer = animation.record(&window.async_compute, current_frame)?;
+            let finished = window.async_compute.finished[current_frame];
+            window.submit_compute(
+                &self.device,
+                compute_command_buffer,
+                finished,
+                vk::PipelineStageFlags::VERTEX_INPUT,
+            )?;
+        }
+
+        begin_command_buffer(
+            &self.device.device,
+            &self.render_pass,
+            self.scene.as_ref(),
+            &self.debug_labels,
+            window,
+            image_index,
+            &clear_values,
+        )?;
+
+        let extent = window.render_extent();
+        Ok(Ok(Frame {
+            image_index,
+            frame_index: current_frame,
+            command_buffer,
+            extent,
+            window_id,
+            context: self,
+            ended: false,
+        }))
+    }
+
+    // Second half of begin_frame: ends the pass, submits and presents
+    fn end_frame(
+        &mut self,
+        window_id: WindowId,
+        image_index: u32,
+    ) -> Result<SwapchainStatus, Box<dyn std::error::Error>> {
+        let window = self
+            .windows
+            .get_mut(&window_id)
+            .ok_or_else(|| format!("No context for window {window_id:?}"))?;
+
+        let current_frame = window.current_frame;
+        let in_flight = window.sync.in_flight[current_frame];
+        let image_available = window.sync.image_available[current_frame];
+        let command_buffer = window.command_pool.buffers[current_frame];
+        // Per image, see FrameSync
+        let render_finished = window.sync.render_finished[image_index as usize];
+
+        end_command_buffer(&self.device.device, &self.debug_labels, window, image_index)?;
+
+        let (compute_semaphores, compute_stages): (Vec<_>, Vec<_>) =
+            window.compute_waits.drain(..).unzip();
+        let wait_semaphores: Vec<_> = std::iter::once(image_available)
+            .chain(compute_semaphores)
+            .collect();
+        let wait_stages: Vec<_> = std::iter::once(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .chain(compute_stages)
+            .collect();
+        let command_buffers = [command_buffer];
+        let signal_semaphores = [render_finished];
+
+        let submit_info = vk::SubmitInfo::default()
+            .wait_semaphores(&wait_semaphores)
+            .wait_dst_stage_mask(&wait_stages)
+            .command_buffers(&command_buffers)
+            .signal_semaphores(&signal_semaphores);
+
+        let ownership_transfer = window.active_ownership_transfer();
+
+        // With an ownership transfer the acquire submission signals the fence instead, it
+        // finishes last and its command buffer is reused with the frame too
+        let graphics_fence = if ownership_transfer.is_some() {
+            vk::Fence::null()
+        } else {
+            in_flight
+        };
+
+        // Reset right before the submission signaling it, after an error anywhere earlier
+        // in the frame the fence stays signaled and the next wait on it can't hang
+        if graphics_fence != vk::Fence::null() {
+            self.device.reset_fences(&[graphics_fence])?;
+        }
+        unsafe {
+            self.device.device.queue_submit(
+                self.device.graphics_queue,
+                &[submit_info],
+                graphics_fence,
+            )?
+        };
+
+        let present_wait_semaphore = match ownership_transfer {
+            Some(transfer) => {
+                let acquire_command_buffer = transfer
+                    .record_acquire(current_frame, window.swapchain.images[image_index as usize])?;
+                let acquired = transfer.acquired[image_index as usize];
+
+                let acquire_command_buffers = [acquire_command_buffer];
+                let acquire_wait_stages = [vk::PipelineStageFlags::ALL_COMMANDS];
+                let acquire_signal_semaphores = [acquired];
+                let acquire_submit_info = vk::SubmitInfo::default()
+                    .wait_semaphores(&signal_semaphores)
+                    .wait_dst_stage_mask(&acquire_wait_stages)
+                    .command_buffers(&acquire_command_buffers)
+                    .signal_semaphores(&acquire_signal_semaphores);
+
+                self.device.reset_fences(&[in_flight])?;
+                unsafe {
+                    self.device.device.queue_submit(
+                        self.device.present_queue,
+                        &[acquire_submit_info],
+                        in_flight,
+                    )?
+                };
+
+                acquired
+            }
+            None => render_finished,
+        };
+
+        window.present_id += 1;
+        window.frame_present_ids[current_frame] = window.present_id;
+        let status = window.swapchain.present(
+            self.device.present_queue,
+            image_index,
+            present_wait_semaphore,
+            window.present_id,
+        )?;
+        // While the window is being resized (which usually also reports Suboptimal) the
+        // recreation waits until the size settled, see SwapchainConfig::resize_debounce. An
+        // out of date swapchain can't be presented to anymore, that one goes right away.
+        let resize_settled = window.resize_settled(self.swapchain_config.resize_debounce);
+        let needs_recreation = status == SwapchainStatus::OutOfDate
+            || ((status == SwapchainStatus::Suboptimal || window.framebuffer_resized)
+                && resize_settled);
+
+        window.current_frame = (current_frame + 1) % window.frames_in_flight;
+
+        if needs_recreation {
+            self.recreate_swapchain(window_id)?;
+        }
+
+        Ok(status)
+    }
+
+    // Format and present mode are chosen again from the surface's current support, a
+    // format change rebuilds the render pass and scene pipeline (see next_swapchain_format).
+    // Without a usable format the window stays paused, the others keep drawing.
+    pub fn recreate_swapchain(
+        &mut self,
+        window_id: WindowId,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let format = self.next_swapchain_format(window_id)?;
+        let window = self
+            .windows
+            .get_mut(&window_id)
+            .ok_or_else(|| format!("No context for window {window_id:?}"))?;
+        let Some(format) = format else {
+            window.paused = true;
+            return Ok(());
+        };
+        window.recreate_swapchain(
+            &self.instance,
+            &self.device,
+            &self.render_pass,
+            &self.uniform_layout,
+            &self.swapchain_config,
+            format,
+        )
+    }
+
+    // Surface format for the window's next swapchain. A surface can prefer another format
+    // after a monitor change or an HDR toggle. The render pass and the scene pipeline bake
+    // in the old one, so while the window is the only one both are rebuilt for it.
+    // Otherwise the other windows' framebuffers are built for the shared pass, its format
+    // is kept if the surface still lists it and None returned if not. Renderers created for
+    // RenderContext::render_pass have to follow themselves, see Context::color_format.
+    fn next_swapchain_format(
+        &mut self,
+        window_id: WindowId,
+    ) -> Result<Option<vk::SurfaceFormatKHR>, Box<dyn std::error::Error>> {
+        let window = self
+            .windows
+            .get(&window_id)
+            .ok_or_else(|| format!("No context for window {window_id:?}"))?;
+        // Minimized windows query again once they get a real size, recreate_swapchain
+        // only pauses them
+        if window.size.width == 0 || window.size.height == 0 {
+            return Ok(Some(window.swapchain.format));
+        }
+
+        let format =
+            Swapchain::query_format(&self.device, &window.surface, &self.swapchain_config)?;
+        let old_format = self.render_pass.color_format;
+        if format.format == old_format {
+            return Ok(Some(format));
+        }
+        if self.windows.len() > 1 {
+            let shared = Swapchain::find_format(&self.device, &window.surface, old_format)?;
+            if shared.is_none() {
+                println!(
+                    "Warning: Surface of window {window_id:?} no longer supports {old_format:?}, the format of the render pass shared with the other windows"
+                );
+            }
+            return Ok(shared);
+        }
+        println!(
+            "Swapchain format changed from {old_format:?} to {:?}, rebuilding the render pass and pipelines",
+            format.format
+        );
+
+        // In flight frames may still use the old pass and pipeline
+        self.wait_idle()?;
+        let render_pass = main_render_pass(
+            &self.device,
+            format.format,
+            self.render_pass.depth_format,
+            self.render_pass.samples,
+            self.color_load_op,
+        )?;
+        if let Some(scene) = &mut self.scene {
+            scene.pipeline =
+                Scene::create_pipeline(&self.device, &render_pass, &self.uniform_layout)?;
+            scene.stencil_mask =
+                Scene::create_stencil_mask(&self.device, &render_pass, &self.uniform_layout)?;
+        }
+        // Built for the old format, update_scaled_target and
+        // recreate_size_dependent_resources create them again
+        for window in self.windows.values_mut() {
+            window.scaled_target = None;
+            window.msaa_color = None;
+        }
+        *self.render_pass = render_pass;
+
+        Ok(Some(format))
+    }
+
+    // For a window whose native handle changed (reparenting, display hotplug): builds a
+    // new surface and swapchain for it. Fails if the device can't present to the new
+    // surface, the window stays paused then.
+    pub fn recreate_surface(
+        &mut self,
+        window: &winit::window::Window,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let window_context = self
+            .windows
+            .get_mut(&window.id())
+            .ok_or_else(|| format!("No context for window {:?}", window.id()))?;
+        window_context.recreate_surface(&self.instance, &self.device, window)?;
+        // The new surface may well support other formats
+        self.recreate_swapchain(window.id())
+    }
+
+    // Fills `image` with `color` from a compute shader and leaves it in `final_layout`,
+    // blocking until done (see ImageFill::fill for the requirements). Read it back with
+    // Image::read_pixels to check the result.
+    pub fn compute_fill_image(
+        &mut self,
+        image: &Image,
+        color: [f32; 4],
+        final_layout: vk::ImageLayout,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let image_fill = match &mut self.image_fill {
+            Some(image_fill) => image_fill,
+            slot @ None => slot.insert(ImageFill::new(&self.device)?),
+        };
+        image_fill.fill(&self.device, image, color, final_layout)
+    }
+
+    // One-shot render into a new sampleable texture, see Texture::render. With `depth`
+    // the pass gets a transient depth buffer in Device::preferred_depth_format, cleared
+    // like the main pass'.
+    pub fn render_to_texture(
+        &self,
+        extent: vk::Extent2D,
+        format: vk::Format,
+        depth: bool,
+        record: impl FnOnce(&RenderContext),
+    ) -> Result<Texture, Box<dyn std::error::Error>> {
+        let depth_format = depth.then(|| self.device.preferred_depth_format());
+        Texture::render(
+            &self.device,
+            extent,
+            format,
+            depth_format,
+            self.clear_depth,
+            record,
+        )
+    }
+
+    // Fills an RGBA8 image with a known color from a compute shader and compares what a
+    // ReadbackBuffer copies back, Ok(false) on a mismatch. Blocks until done, see
+    // ContextBuilder::check_compute_readback.
+    pub fn check_compute_readback(&mut self) -> Result<bool, Box<dyn std::error::Error>> {
+        // Exact k/255 values, UNORM stores of anything in between may round either way
+        const COLOR: [f32; 4] = [0.0, 1.0, 51.0 / 255.0, 1.0];
+        // An odd width pads the rows on most devices
+        let extent = vk::Extent3D {
+            width: 13,
+            height: 7,
+            depth: 1,
+        };
+        let format = vk::Format::R8G8B8A8_UNORM;
+
+        let create_info = vk::ImageCreateInfo::default()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .extent(extent)
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::TRANSFER_SRC)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED);
+        let image = Image::new(
+            &self.device,
+            &create_info,
+            vk::ImageViewType::TYPE_2D,
+            vk::ImageAspectFlags::COLOR,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+        self.compute_fill_image(&image, COLOR, vk::ImageLayout::TRANSFER_SRC_OPTIMAL)?;
+
+        let mut readback =
+            ReadbackBuffer::new(&self.device, format, vk::ImageAspectFlags::COLOR, extent)?;
+        let mut copied = Ok(());
+        submit_once(&self.device, |cmd| {
+            copied = readback.copy_from_image(
+                &self.device.device,
+                cmd,
+                image.image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                extent,
+            );
+        })?;
+        copied?;
+
+        let expected = COLOR.map(|channel| (channel * 255.0).round() as u8);
+        let pixels = readback.read_packed();
+        Ok(pixels.len() == (extent.width * extent.height * 4) as usize
+            && pixels.chunks_exact(4).all(|texel| texel == expected))
+    }
+
+    // Escape hatch for commands the crate doesn't wrap (experimental extensions, custom
+    // barriers): `record` fills a fresh primary command buffer for queue 0 of
+    // `queue_family` (one of the Device's graphics/present/compute families), which is
+    // submitted right away. Blocks until the GPU finished it, so it serializes with the
+    // caller, keep it out of the per frame path. Frames in flight keep running meanwhile.
+    pub fn record_custom<F: FnOnce(&ash::Device, vk::CommandBuffer)>(
+        &self,
+        queue_family: u32,
+        record: F,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let queue = self.device.get_queue(queue_family, 0)?;
+        submit_once_on(&self.device, queue_family, queue, |command_buffer| {
+            record(&self.device.device, command_buffer)
+        })
+    }
+
+    // Submits `command_buffer` (recorded from the window's AsyncCompute pool) to the
+    // compute queue. The window's next graphics submission waits on `signal_semaphore` at
+    // `wait_stage`, so the compute results are ready by then. Resources shared between the
+    // queues need AsyncCompute's release/acquire barriers with a dedicated compute family.
+    pub fn submit_compute(
+        &mut self,
+        window_id: WindowId,
+        command_buffer: vk::CommandBuffer,
+        signal_semaphore: vk::Semaphore,
+        wait_stage: vk::PipelineStageFlags,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let window = self
+            .windows
+            .get_mut(&window_id)
+            .ok_or_else(|| format!("No context for window {window_id:?}"))?;
+        window.submit_compute(&self.device, command_buffer, signal_semaphore, wait_stage)
+    }
+
+    // Id of the window's last queued present (0 before the first), see wait_for_present
+    pub fn last_present_id(&self, window_id: WindowId) -> Option<u64> {
+        self.windows.get(&window_id).map(|window| window.present_id)
+    }
+
+    // Blocks until the window's present `present_id` is on screen. Waiting for the id of
+    // the frame before the last one ahead of begin_frame paces rendering to the display
+    // refresh, with less latency than relying on MAILBOX alone.
+    // Without VK_KHR_present_wait, or for presents of an older swapchain, this falls back
+    // to the fence of the frame in flight that queued it: rendering has finished, the
+    // image may not be visible yet. Ids older than the frames in flight return right away.
+    // Present wait is mostly found on Linux (Mesa, NVIDIA) and Windows drivers, and even
+    // there depends on the window system, MoltenVK and most Android drivers lack it.
+    pub fn wait_for_present(
+        &self,
+        window_id: WindowId,
+        present_id: u64,
+    ) -> Result<SwapchainStatus, Box<dyn std::error::Error>> {
+        let window = self
+            .windows
+            .get(&window_id)
+            .ok_or_else(|| format!("No context for window {window_id:?}"))?;
+        if present_id > window.present_id {
+            return Err(format!(
+                "Present {present_id} hasn't been queued yet (last is {})",
+                window.present_id
+            )
+            .into());
+        }
+
+        if window.swapchain.supports_present_wait() && present_id >= window.first_present_id {
+            return window.swapchain.wait_for_present(present_id);
+        }
+
+        if let Some(frame) = window
+            .frame_present_ids
+            .iter()
+            .position(|&id| id == present_id)
+        {
+            self.device.wait_for_fences(
+                &[window.sync.in_flight[frame]],
+                true,
+                std::time::Duration::MAX,
+            )?;
+        }
+        Ok(SwapchainStatus::Optimal)
+    }
+
+    // How many times the window's swapchain was recreated, for profiling resizes
+    pub fn swapchain_recreations(&self, window_id: WindowId) -> Option<u32> {
+        self.windows
+            .get(&window_id)
+            .map(|window| window.swapchain_recreations)
+    }
+
+    // Correction for the window's surface transform (see Swapchain::pre_transform_matrix)
+    // and a flipped viewport (see camera::clip_correction), to pre-multiply into a
+    // camera::perspective projection. Fetch it every frame since it changes with device
+    // rotation.
+    pub fn pre_transform_matrix(&self, window_id: WindowId) -> Option<[[f32; 4]; 4]> {
+        self.windows.get(&window_id).map(|window| {
+            camera::clip_correction(
+                window.swapchain.pre_transform_matrix(),
+                self.device.flip_viewport_y,
+            )
+        })
+    }
+
+    // Also re-acquired after every swapchain recreation until released. Returns false
+    // when VK_EXT_full_screen_exclusive isn't available.
+    pub fn acquire_full_screen_exclusive(
+        &mut self,
+        window_id: WindowId,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let window = self
+            .windows
+            .get_mut(&window_id)
+            .ok_or_else(|| format!("No context for window {window_id:?}"))?;
+        let acquired = window.swapchain.acquire_full_screen_exclusive()?;
+        window.full_screen_exclusive = acquired;
+        Ok(acquired)
+    }
+
+    pub fn release_full_screen_exclusive(
+        &mut self,
+        window_id: WindowId,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let window = self
+            .windows
+            .get_mut(&window_id)
+            .ok_or_else(|| format!("No context for window {window_id:?}"))?;
+        window.full_screen_exclusive = false;
+        window.swapchain.release_full_screen_exclusive()
+    }
+}
+
+// Everything tied to one window's surface, the device and render pass are shared
+pub struct WindowContext {
+    // Fields are dropped in declaration order, dependents before what they depend on
+    pub sync: FrameSync,
+    pub uniforms: UniformBuffers,
+    // None when shaders/animate.comp isn't compiled
+    pub instance_animation: Option<InstanceAnimation>,
+    pub async_compute: AsyncCompute,
+    // Some when graphics and present use different queue families
+    pub ownership_transfer: Option<OwnershipTransfer>,
+    pub staging: StagingRing,
+    // Sets handed out by Frame::allocate_transient, reset when the frame comes around again
+    pub transient_descriptors: TransientDescriptorPool,
+    pub command_pool: CommandPool,
+    // Some while rendering at a render scale other than 1.0, see Context::set_render_scale
+    pub scaled_target: Option<ScaledTarget>,
+    pub framebuffers: Framebuffers,
+    // Some when the main pass is multisampled, resolved into the swapchain image
+    pub msaa_color: Option<Image>,
+    // None when the render pass has no depth attachment, see ContextBuilder::depth_buffer.
+    // Multisampled like msaa_color.
+    pub depth_buffer: Option<DepthBuffer>,
+    pub swapchain: Swapchain,
+    pub surface: Surface,
+
+    pub current_frame: usize,
+    // Last known window size, used for the extent when the surface leaves it to us
+    pub size: winit::dpi::PhysicalSize<u32>,
+    // Set on window resize, the swapchain is recreated at the end of a frame once the size
+    // settled
+    pub framebuffer_resized: bool,
+    // Last change of `size`, None once a swapchain was created for it
+    pub last_resize: Option<std::time::Instant>,
+    // Window is minimized (0x0) or has no swapchain (see Swapchain::is_released), nothing
+    // can be presented
+    pub paused: bool,
+    // Exclusive fullscreen was requested and is re-acquired for new swapchains
+    pub full_screen_exclusive: bool,
+    pub frame_timer: FrameTimer,
+    // Swapchains created after the first one, resize bursts should only add one
+    pub swapchain_recreations: u32,
+    // Compute submissions the next graphics submission waits on, see submit_compute
+    pub compute_waits: Vec<(vk::Semaphore, vk::PipelineStageFlags)>,
+    // Id of the last queued present (0 before the first), see Context::wait_for_present.
+    // Keeps counting across swapchains, `first_present_id` is the current one's first.
+    pub present_id: u64,
+    pub first_present_id: u64,
+    // Present id queued by each frame in flight
+    pub frame_present_ids: Vec<u64>,
+    // SwapchainConfig::frames_in_flight clamped to the swapchain's image count, every per
+    // frame resource exists this many times. Follows the image count on recreation.
+    pub frames_in_flight: usize,
+    // Requested render scale, scaled_target is None when it couldn't be honored
+    pub render_scale: f32,
+    // Context::color_load_op, with LOAD fresh images get cleared in the pass
+    pub color_load_op: vk::AttachmentLoadOp,
+}
+
+impl WindowContext {
+    fn new(
+        device: &Device,
+        render_pass: &RenderPass,
+        uniform_layout: &UniformLayout,
+        surface: Surface,
+        swapchain: Swapchain,
+        size: winit::dpi::PhysicalSize<u32>,
+        requested_frames_in_flight: usize,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let msaa_color = (render_pass.samples != vk::SampleCountFlags::TYPE_1)
+            .then(|| msaa_color_target(device, render_pass, swapchain.extent))
+            .transpose()?;
+        let depth_buffer = render_pass
+            .depth_format
+            .map(|format| main_depth_buffer(device, render_pass, swapchain.extent, format))
+            .transpose()?;
+        let framebuffers = main_framebuffers(
+            device,
+            render_pass,
+            &swapchain,
+            msaa_color.as_ref(),
+            depth_buffer.as_ref(),
+        )?;
+        let image_count = swapchain.images.len();
+        let frames_in_flight = clamp_frames_in_flight(requested_frames_in_flight, image_count);
+        let FrameResources {
+            sync,
+            uniforms,
+            instance_animation,
+            async_compute,
+            ownership_transfer,
+            staging,
+            transient_descriptors,
+            command_pool,
+        } = FrameResources::new(device, uniform_layout, frames_in_flight, image_count)?;
+
+        Ok(Self {
+            sync,
+            uniforms,
+            instance_animation,
+            async_compute,
+            ownership_transfer,
+            staging,
+            transient_descriptors,
+            command_pool,
+            scaled_target: None,
+            framebuffers,
+            msaa_color,
+            depth_buffer,
+            swapchain,
+            surface,
+
+            current_frame: 0,
+            size,
+            framebuffer_resized: false,
+            last_resize: None,
+            paused: size.width == 0 || size.height == 0,
+            full_screen_exclusive: false,
+            frame_timer: FrameTimer::new(frame_stats::DEFAULT_FRAME_STATS_WINDOW),
+            swapchain_recreations: 0,
+            compute_waits: Vec::new(),
+            present_id: 0,
+            first_present_id: 1,
+            frame_present_ids: vec![0; frames_in_flight],
+            frames_in_flight,
+            render_scale: 1.0,
+            color_load_op: vk::AttachmentLoadOp::CLEAR,
+        })
+    }
+
+    // With LOAD every image is treated as new until it was rendered to once, its current
+    // contents (or layout) may not be defined
+    fn set_color_load_op(&mut self, load_op: vk::AttachmentLoadOp) {
+        self.color_load_op = load_op;
+        if load_op == vk::AttachmentLoadOp::LOAD {
+            self.swapchain.uninitialized_images.fill(true);
+        }
+    }
+
+    fn submit_compute(
+        &mut self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        signal_semaphore: vk::Semaphore,
+        wait_stage: vk::PipelineStageFlags,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let command_buffers = [command_buffer];
+        let signal_semaphores = [signal_semaphore];
+        let submit_info = vk::SubmitInfo::default()
+            .command_buffers(&command_buffers)
+            .signal_semaphores(&signal_semaphores);
+
+        // No fence, the frame's in_flight fence covers it through the graphics submission
+        unsafe {
+            device
+                .device
+                .queue_submit(device.compute_queue, &[submit_info], vk::Fence::null())?
+        };
+        self.compute_waits.push((signal_semaphore, wait_stage));
+
+        Ok(())
+    }
+
+    // Only used while the swapchain is EXCLUSIVE, otherwise sharing is CONCURRENT
+    fn active_ownership_transfer(&self) -> Option<&OwnershipTransfer> {
+        self.ownership_transfer
+            .as_ref()
+            .filter(|_| self.swapchain.sharing_mode == vk::SharingMode::EXCLUSIVE)
+    }
+
+    // Extent the scene is rendered at, the swapchain's unless a scaled target is active
+    pub fn render_extent(&self) -> vk::Extent2D {
+        self.scaled_target
+            .as_ref()
+            .map_or(self.swapchain.extent, |target| target.extent)
+    }
+
+    // Creates, resizes or drops the scaled target to match render_scale and the swapchain
+    // extent. The device must be idle.
+    fn update_scaled_target(
+        &mut self,
+        device: &Device,
+        render_pass: &RenderPass,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if self.render_scale == 1.0 {
+            self.scaled_target = None;
+            return Ok(());
+        }
+        if !self
+            .swapchain
+            .image_usage
+            .contains(vk::ImageUsageFlags::TRANSFER_DST)
+        {
+            self.scaled_target = None;
+            return Err("Swapchain images lack TRANSFER_DST usage, can't blit to them".into());
+        }
+
+        let extent = render_scale::scaled_extent(self.swapchain.extent, self.render_scale);
+        if self
+            .scaled_target
+            .as_ref()
+            .is_none_or(|target| target.extent != extent)
+        {
+            // Old target first, both at once may not fit
+            self.scaled_target = None;
+            self.scaled_target = Some(ScaledTarget::new(device, render_pass, extent)?);
+        }
+        Ok(())
+    }
+
+    // Events that end up at the swapchain's current size don't request a recreation,
+    // out of date swapchains are still caught by acquire/present
+    fn resized(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
+        let was_paused = self.paused;
+        if new_size != self.size {
+            self.last_resize = Some(std::time::Instant::now());
+        }
+        self.size = new_size;
+        // Without a swapchain there's nothing to present to until begin_frame recreated it
+        self.paused = new_size.width == 0 || new_size.height == 0 || self.swapchain.is_released();
+        let extent = self.swapchain.extent;
+        self.framebuffer_resized |=
+            was_paused || new_size.width != extent.width || new_size.height != extent.height;
+    }
+
+    // No size change within the last `debounce`
+    fn resize_settled(&self, debounce: std::time::Duration) -> bool {
+        self.last_resize
+            .is_none_or(|last_resize| last_resize.elapsed() >= debounce)
+    }
+
+    // Replaces the surface, the swapchain has to be recreated afterwards
+    fn recreate_surface(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        window: &winit::window::Window,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        // Nothing in flight may still use the swapchain images
+        unsafe { device.device.device_wait_idle()? };
+        self.swapchain.release();
+        // Until a swapchain exists again nothing must be drawn
+        self.paused = true;
+        self.surface.recreate(instance, window)?;
+
+        // The queues were picked for the old surface and can't change anymore
+        let present_supported = unsafe {
+            self.surface.loader.get_physical_device_surface_support(
+                device.physical_device,
+                device.present_queue_family_idx,
+                self.surface.surface,
+            )?
+        };
+        if !present_supported {
+            return Err("Present queue family can't present to the recreated surface".into());
+        }
+
+        // Stays paused until recreate_swapchain built a swapchain for the new surface
+        self.size = window.inner_size();
+        self.framebuffer_resized = true;
+        Ok(())
+    }
+
+    fn recreate_swapchain(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        render_pass: &RenderPass,
+        uniform_layout: &UniformLayout,
+        config: &SwapchainConfig,
+        format: vk::SurfaceFormatKHR,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if self.size.width == 0 || self.size.height == 0 {
+            // Minimized, recreate once the window gets a real size again
+            self.paused = true;
+            return Ok(());
+        }
+
+        // Checked before creating the new swapchain, creating it retires the old one
+        if format.format != render_pass.color_format {
+            return Err(format!(
+                "Swapchain format {:?} doesn't match the render pass ({:?})",
+                format.format, render_pass.color_format
+            )
+            .into());
+        }
+
+        // Old framebuffers and image views may still be in use by in-flight frames
+        unsafe { device.device.device_wait_idle()? };
+
+        let new_swapchain = Swapchain::new(
+            instance,
+            device,
+            &self.surface,
+            self.size,
+            config,
+            format,
+            Some(self.swapchain.swapchain),
+        )?;
+        if new_swapchain.present_mode != self.swapchain.present_mode {
+            println!(
+                "Present mode changed from {:?} to {:?}",
+                self.swapchain.present_mode, new_swapchain.present_mode
+            );
+        }
+        // The images are new, so their views (owned by the swapchain) always are too. The
+        // old swapchain is kept alive until the framebuffers referencing its views are gone.
+        let old_swapchain = std::mem::replace(&mut self.swapchain, new_swapchain);
+        self.set_color_load_op(self.color_load_op);
+        self.first_present_id = self.present_id + 1;
+        self.recreate_size_dependent_resources(device, render_pass, self.swapchain.extent)?;
+        drop(old_swapchain);
+
+        // The image count can change with the new swapchain, and with it the number of
+        // frames in flight. Every per frame resource is rebuilt then, the device is idle.
+        let image_count = self.swapchain.images.len();
+        let frames_in_flight = clamp_frames_in_flight(config.frames_in_flight, image_count);
+        if frames_in_flight != self.frames_in_flight {
+            let FrameResources {
+                sync,
+                uniforms,
+                instance_animation,
+                async_compute,
+                ownership_transfer,
+                staging,
+                transient_descriptors,
+                command_pool,
+            } = FrameResources::new(device, uniform_layout, frames_in_flight, image_count)?;
+            self.sync = sync;
+            self.uniforms = uniforms;
+            self.instance_animation = instance_animation;
+            self.async_compute = async_compute;
+            self.ownership_transfer = ownership_transfer;
+            self.staging = staging;
+            self.transient_descriptors = transient_descriptors;
+            self.command_pool = command_pool;
+            self.frames_in_flight = frames_in_flight;
+            self.frame_present_ids = vec![0; frames_in_flight];
+            self.current_frame = 0;
+            // Nothing recorded for the old frames will be submitted anymore
+            self.compute_waits.clear();
+        } else {
+            self.sync.set_image_count(image_count)?;
+            if let Some(transfer) = &mut self.ownership_transfer {
+                transfer.set_image_count(image_count)?;
+            }
+        }
+
+        self.swapchain_recreations += 1;
+        self.framebuffer_resized = false;
+        self.last_resize = None;
+        self.paused = false;
+
+        // Lost mode (e.g. alt-tab) can fail to come back right away, stays requested so the
+        // next recreation tries again
+        if self.full_screen_exclusive
+            && let Err(err) = self.swapchain.acquire_full_screen_exclusive()
+        {
+            println!("Warning: Failed to re-acquire exclusive fullscreen ({err})");
+        }
+
+        Ok(())
+    }
+
+    // Every attachment sized to the swapchain extent, plus the framebuffers using them. New
+    // render targets go here so swapchain recreation picks them up. The device must be
+    // idle, and `extent` must match the current swapchain. Attachments already at `extent`
+    // (out of date swapchains, config changes) are kept, the framebuffers never are since
+    // they reference the swapchain's image views.
+    fn recreate_size_dependent_resources(
+        &mut self,
+        device: &Device,
+        render_pass: &RenderPass,
+        extent: vk::Extent2D,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        // Keeps drawing at full resolution instead of failing the recreation
+        if let Err(err) = self.update_scaled_target(device, render_pass) {
+            println!(
+                "Warning: Render scale {} not applied ({err})",
+                self.render_scale
+            );
+        }
+
+        let has_extent = |image: &Image| {
+            image.extent.width == extent.width && image.extent.height == extent.height
+        };
+        let multisampled = render_pass.samples != vk::SampleCountFlags::TYPE_1;
+        // None after the surface format changed, see Context::next_swapchain_format
+        let new_msaa_color = match &self.msaa_color {
+            Some(color) if has_extent(color) => None,
+            _ if multisampled => Some(msaa_color_target(device, render_pass, extent)?),
+            _ => None,
+        };
+        let new_depth_buffer = match &self.depth_buffer {
+            Some(depth_buffer) if !has_extent(&depth_buffer.image) => Some(main_depth_buffer(
+                device,
+                render_pass,
+                extent,
+                depth_buffer.format(),
+            )?),
+            _ => None,
+        };
+        let new_framebuffers = main_framebuffers(
+            device,
+            render_pass,
+            &self.swapchain,
+            new_msaa_color.as_ref().or(self.msaa_color.as_ref()),
+            new_depth_buffer.as_ref().or(self.depth_buffer.as_ref()),
+        )?;
+
+        // Old framebuffers go first, they reference the old attachments' image views
+        self.framebuffers = new_framebuffers;
+        if new_msaa_color.is_some() {
+            self.msaa_color = new_msaa_color;
+        }
+        if new_depth_buffer.is_some() {
+            self.depth_buffer = new_depth_buffer;
+        }
+
+        Ok(())
+    }
+}
+
+// Everything a window has once per frame in flight
+struct FrameResources {
+    sync: FrameSync,
+    uniforms: UniformBuffers,
+    instance_animation: Option<InstanceAnimation>,
+    async_compute: AsyncCompute,
+    ownership_transfer: Option<OwnershipTransfer>,
+    staging: StagingRing,
+    transient_descriptors: TransientDescriptorPool,
+    command_pool: CommandPool,
+}
+
+impl FrameResources {
+    fn new(
+        device: &Device,
+        uniform_layout: &UniformLayout,
+        frames_in_flight: usize,
+        image_count: usize,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let command_pool = CommandPool::new(
+            device,
+            device.graphics_queue_family_idx,
+            frames_in_flight as u32,
+        )?;
+        let staging = StagingRing::new(device, STAGING_SIZE_PER_FRAME, frames_in_flight)?;
+        let pool_sizes: Vec<_> = TRANSIENT_POOL_SIZES
+            .iter()
+            .map(|&(ty, count)| {
+                vk::DescriptorPoolSize::default()
+                    .ty(ty)
+                    .descriptor_count(count)
+            })
+            .collect();
+        let transient_descriptors = TransientDescriptorPool::new(
+            device,
+            frames_in_flight,
+            &pool_sizes,
+            TRANSIENT_SETS_PER_POOL,
+        )?;
+        let sync = FrameSync::new(device, frames_in_flight, image_count)?;
+        let ownership_transfer = OwnershipTransfer::new(device, frames_in_flight, image_count)?;
+        let uniforms = UniformBuffers::new(
+            device,
+            uniform_layout,
+            std::mem::size_of::<UniformBufferObject>() as vk::DeviceSize,
+            frames_in_flight,
+        )?;
+        let async_compute = AsyncCompute::new(device, frames_in_flight)?;
+        let instance_animation = match InstanceAnimation::new(device, frames_in_flight) {
+            Ok(animation) => Some(animation),
+            Err(err) => {
+                println!("Warning: Instance animation disabled ({err})");
+                None
+            }
+        };
+
+        Ok(Self {
+            sync,
+            uniforms,
+            instance_animation,
+            async_compute,
+            ownership_transfer,
+            staging,
+            transient_descriptors,
+            command_pool,
+        })
+    }
+}
+
+// Highest supported count not above `requested`, for color and depth attachments alike
+fn clamp_sample_count(device: &Device, requested: vk::SampleCountFlags) -> vk::SampleCountFlags {
+    let limits = &device.properties.limits;
+    let supported = limits.framebuffer_color_sample_counts & limits.framebuffer_depth_sample_counts;
+    let samples = [
+        vk::SampleCountFlags::TYPE_64,
+        vk::SampleCountFlags::TYPE_32,
+        vk::SampleCountFlags::TYPE_16,
+        vk::SampleCountFlags::TYPE_8,
+        vk::SampleCountFlags::TYPE_4,
+        vk::SampleCountFlags::TYPE_2,
+    ]
+    .into_iter()
+    .find(|&samples| samples.as_raw() <= requested.as_raw() && supported.contains(samples))
+    .unwrap_or(vk::SampleCountFlags::TYPE_1);
+    if samples != requested {
+        println!("Warning: {requested:?} MSAA not supported, using {samples:?}");
+    }
+    samples
+}
+
+// Single sampled passes keep `color_load_op`, multisampled ones always clear (see
+// Context::set_color_load_op)
+fn main_render_pass(
+    device: &Device,
+    color_format: vk::Format,
+    depth_format: Option<vk::Format>,
+    samples: vk::SampleCountFlags,
+    color_load_op: vk::AttachmentLoadOp,
+) -> Result<RenderPass, Box<dyn std::error::Error>> {
+    if samples == vk::SampleCountFlags::TYPE_1 {
+        return RenderPass::with_color_format(device, color_format, depth_format, color_load_op);
+    }
+    let depth_format = depth_format.ok_or("MSAA needs the depth buffer")?;
+    RenderPass::multisampled(
+        device,
+        color_format,
+        depth_format,
+        samples,
+        None,
+        vk::ImageLayout::PRESENT_SRC_KHR,
+    )
+}
+
+// Multisampled color attachment of the main pass, only lives during the pass
+fn msaa_color_target(
+    device: &Device,
+    render_pass: &RenderPass,
+    extent: vk::Extent2D,
+) -> Result<Image, Box<dyn std::error::Error>> {
+    let create_info = vk::ImageCreateInfo::default()
+        .image_type(vk::ImageType::TYPE_2D)
+        .format(render_pass.color_format)
+        .extent(vk::Extent3D {
+            width: extent.width,
+            height: extent.height,
+            depth: 1,
+        })
+        .mip_levels(1)
+        .array_layers(1)
+        .samples(render_pass.samples)
+        .tiling(vk::ImageTiling::OPTIMAL)
+        .usage(vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .initial_layout(vk::ImageLayout::UNDEFINED);
+
+    Image::new(
+        device,
+        &create_info,
+        vk::ImageViewType::TYPE_2D,
+        vk::ImageAspectFlags::COLOR,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )
+}
+
+fn main_depth_buffer(
+    device: &Device,
+    render_pass: &RenderPass,
+    extent: vk::Extent2D,
+    format: vk::Format,
+) -> Result<DepthBuffer, Box<dyn std::error::Error>> {
+    if render_pass.samples == vk::SampleCountFlags::TYPE_1 {
+        DepthBuffer::with_format(device, extent, format)
+    } else {
+        DepthBuffer::multisampled(device, extent, format, render_pass.samples)
+    }
+}
+
+// Swapchain framebuffers of the main pass, resolving from `msaa_color` when it's Some
+fn main_framebuffers(
+    device: &Device,
+    render_pass: &RenderPass,
+    swapchain: &Swapchain,
+    msaa_color: Option<&Image>,
+    depth_buffer: Option<&DepthBuffer>,
+) -> Result<Framebuffers, Box<dyn std::error::Error>> {
+    let depth_view = depth_buffer.map(|depth| depth.image.view);
+    match msaa_color {
+        Some(color) => Framebuffers::multisampled_swapchain(
+            device,
+            render_pass,
+            swapchain,
+            color.view,
+            depth_view.ok_or("MSAA needs the depth buffer")?,
+        ),
+        None => Framebuffers::new(device, render_pass, swapchain, depth_view),
+    }
+}
+
+// More frames in flight than images would have a frame wait on an image another frame
+// in flight still holds
+fn clamp_frames_in_flight(requested: usize, image_count: usize) -> usize {
+    let frames_in_flight = requested.clamp(1, image_count.max(1));
+    if frames_in_flight != requested {
+        println!(
+            "Frames in flight adjusted from {requested} to {frames_in_flight} for {image_count} swapchain images"
+        );
+    }
+    frames_in_flight
+}
+
+// Handed to the Context::draw_frame_with callback (or taken from Frame::render_context)
+// while the main render pass is active. UI renderers (egui_ash_renderer,
+// imgui-rs-vulkan-renderer) have to be created for `render_pass`, or a compatible one:
+// same attachment formats and sample counts (Context::samples), with a depth attachment
+// in the depth buffer's format. A surface format change rebuilds the pass, recreate them
+// when Context::color_format changed. Set viewport and scissor before drawing,
+// the scene's aren't guaranteed to be there. Only record into `command_buffer`, it is
+// submitted on the graphics queue, don't begin or end passes or submit from the callback,
+// and create the renderer with the graphics queue (Context::graphics_queue). Resources
+// the draws use must stay alive until the frame's fence signals, `frame_index`
+// identifies the frame in flight for per-frame buffers.
+pub struct RenderContext<'a> {
+    pub device: &'a ash::Device,
+    pub command_buffer: vk::CommandBuffer,
+    pub render_pass: vk::RenderPass,
+    // 0..WindowContext::frames_in_flight
+    pub frame_index: usize,
+    // Swapchain image being rendered to
+    pub image_index: u32,
+    pub extent: vk::Extent2D,
+}
+
+// A frame between Context::begin_frame and Frame::end. The command buffer is recording
+// inside the main render pass, the same rules as for RenderContext apply. The context
+// stays borrowed until the frame ended, dropping the frame ends it too.
+pub struct Frame<'a> {
+    pub image_index: u32,
+    // 0..WindowContext::frames_in_flight
+    pub frame_index: usize,
+    pub command_buffer: vk::CommandBuffer,
+    pub extent: vk::Extent2D,
+    window_id: WindowId,
+    context: &'a mut Context,
+    ended: bool,
+}
+
+impl Drop for Frame<'_> {
+    fn drop(&mut self) {
+        // The image was acquired and the fence reset, not submitting would deadlock the
+        // next wait on this frame
+        if !self.ended {
+            println!("Warning: Frame dropped without Frame::end, ending it");
+            if let Err(err) = self.context.end_frame(self.window_id, self.image_index) {
+                println!("Warning: Failed to end frame ({err})");
+            }
+        }
+    }
+}
+
+impl Frame<'_> {
+    pub fn device(&self) -> &ash::Device {
+        &self.context.device.device
+    }
+
+    // Descriptor set for this frame only, freed when the same frame index begins again.
+    // Write it with update_descriptor_sets before binding it, never free it.
+    pub fn allocate_transient(
+        &mut self,
+        layout: vk::DescriptorSetLayout,
+    ) -> Result<vk::DescriptorSet, Box<dyn std::error::Error>> {
+        let window = self
+            .context
+            .windows
+            .get_mut(&self.window_id)
+            .ok_or_else(|| format!("No context for window {:?}", self.window_id))?;
+        window.transient_descriptors.allocate_transient(layout)
+    }
+
+    pub fn render_context(&self) -> RenderContext<'_> {
+        RenderContext {
+            device: self.device(),
+            command_buffer: self.command_buffer,
+            render_pass: self.context.render_pass.render_pass,
+            frame_index: self.frame_index,
+            image_index: self.image_index,
+            extent: self.extent,
+        }
+    }
+
+    // Ends the render pass, submits and presents. Anything but Optimal means the swapchain
+    // has been recreated already.
+    pub fn end(mut self) -> Result<SwapchainStatus, Box<dyn std::error::Error>> {
+        self.ended = true;
+        self.context.end_frame(self.window_id, self.image_index)
+    }
+}
+
+// UNDEFINED -> PRESENT_SRC_KHR for a freshly acquired image, see
+// SwapchainConfig::initialize_image_layouts
+fn cmd_initialize_swapchain_image(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+) {
+    // The submission waits for image_available at COLOR_ATTACHMENT_OUTPUT, the transition
+    // has to come after that wait and before the main pass writes the image
+    let barrier = vk::ImageMemoryBarrier::default()
+        .old_layout(vk::ImageLayout::UNDEFINED)
+        .new_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+        .src_access_mask(vk::AccessFlags::empty())
+        .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image)
+        .subresource_range(vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        });
+    unsafe {
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[barrier],
+        );
+    }
+}
+
+// Resets and begins the frame's command buffer and records up to the scene in the main pass,
+// which is left open for Frame users
+fn begin_command_buffer(
+    device: &ash::Device,
+    render_pass: &RenderPass,
+    scene: Option<&Scene>,
+    debug_labels: &DebugLabels,
+    window: &mut WindowContext,
+    image_index: u32,
+    clear_values: &[vk::ClearValue],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let command_buffer = window.command_pool.buffers[window.current_frame];
+    let extent = window.render_extent();
+
+    unsafe {
+        device.reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())?;
+        device.begin_command_buffer(command_buffer, &vk::CommandBufferBeginInfo::default())?;
+    }
+
+    let fresh_image =
+        std::mem::take(&mut window.swapchain.uninitialized_images[image_index as usize]);
+    if fresh_image {
+        cmd_initialize_swapchain_image(
+            device,
+            command_buffer,
+            window.swapchain.images[image_index as usize],
+        );
+    }
+
+    // The scaled pass is compatible with the main one, the scene's pipelines work in both
+    let (render_pass, framebuffer) = match &window.scaled_target {
+        Some(target) => (
+            target.render_pass.render_pass,
+            target.framebuffers.framebuffers[0],
+        ),
+        None => (
+            render_pass.render_pass,
+            window.framebuffers.framebuffers[image_index as usize],
+        ),
+    };
+    let render_pass_begin = vk::RenderPassBeginInfo::default()
+        .render_pass(render_pass)
+        .framebuffer(framebuffer)
+        .render_area(vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent,
+        })
+        .clear_values(clear_values);
+
+    // Written by compute this frame, take it over from the compute family first
+    let animated_instances = window.instance_animation.as_ref().map(|animation| {
+        let instances = &animation.instances[window.current_frame];
+        window.async_compute.cmd_acquire_buffer(
+            command_buffer,
+            instances.buffer.buffer,
+            vk::PipelineStageFlags::VERTEX_INPUT,
+            vk::AccessFlags::VERTEX_ATTRIBUTE_READ,
+        );
+        instances
+    });
+
+    if let Some(scene) = scene {
+        scene.record_shadow(device, command_buffer, animated_instances, debug_labels);
+    }
+
+    debug_labels.cmd_begin_label(command_buffer, "Main Pass", MAIN_PASS_LABEL_COLOR);
+    unsafe {
+        device.cmd_begin_render_pass(
+            command_buffer,
+            &render_pass_begin,
+            vk::SubpassContents::INLINE,
+        );
+    }
+
+    // A loading pass has nothing to load from a fresh image, it starts out cleared like
+    // with CLEAR
+    if fresh_image
+        && window.color_load_op == vk::AttachmentLoadOp::LOAD
+        && window.scaled_target.is_none()
+    {
+        let clear = vk::ClearAttachment {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            color_attachment: 0,
+            clear_value: clear_values[0],
+        };
+        let rect = vk::ClearRect {
+            rect: vk::Rect2D {
+                offset: vk::Offset2D::default(),
+                extent,
+            },
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        unsafe { device.cmd_clear_attachments(command_buffer, &[clear], &[rect]) };
+    }
+
+    if let Some(scene) = scene {
+        scene.record(
+            device,
+            command_buffer,
+            extent,
+            window.uniforms.sets[window.current_frame],
+            animated_instances,
+            debug_labels,
+        );
+    }
+
+    Ok(())
+}
+
+fn end_command_buffer(
+    device: &ash::Device,
+    debug_labels: &DebugLabels,
+    window: &WindowContext,
+    image_index: u32,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let command_buffer = window.command_pool.buffers[window.current_frame];
+
+    unsafe {
+        device.cmd_end_render_pass(command_buffer);
+    }
+    debug_labels.cmd_end_label(command_buffer);
+
+    if let Some(target) = &window.scaled_target {
+        target.cmd_blit(
+            device,
+            command_buffer,
+            window.swapchain.images[image_index as usize],
+            window.swapchain.extent,
+        );
+    }
+
+    if let Some(transfer) = window.active_ownership_transfer() {
+        transfer.cmd_release(
+            command_buffer,
+            window.swapchain.images[image_index as usize],
+        );
+    }
+
+    unsafe {
+        device.end_command_buffer(command_buffer)?;
+    }
+
+    Ok(())
+}
+
+pub struct Instance {
+    pub entry: ash::Entry,
+    pub instance: ash::Instance,
+    // Negotiated in Instance::new, gate features on this rather than REQUESTED_API_VERSION
+    pub api_version: u32,
+
+    // Subset of OPTIONAL_INSTANCE_EXTENSIONS that was available and enabled
+    pub enabled_optional_extensions: Vec<&'static CStr>,
+    // Every extension the instance was created with, required ones included
+    pub enabled_extensions: Vec<String>,
+    // Surface extensions are enabled, false for instances created without a window
+    pub surface_support: bool,
+    // Validation layers are enabled, see InstanceConfig
+    pub validation: bool,
+    // Every layer the instance was created with
+    pub enabled_layers: Vec<String>,
+    // Prints validation messages, only with validation and VK_EXT_debug_utils
+    debug_messenger: Option<(ext::debug_utils::Instance, vk::DebugUtilsMessengerEXT)>,
+}
+
+impl Drop for Instance {
+    fn drop(&mut self) {
+        unsafe {
+            if let Some((loader, messenger)) = &self.debug_messenger {
+                loader.destroy_debug_utils_messenger(*messenger, None);
+            }
+            self.instance.destroy_instance(None);
+        }
+    }
+}
+
+// Validation layers and the messenger printing their output. Without validation neither
+// is looked up or created, there is no overhead.
+#[derive(Debug, Clone)]
+pub struct InstanceConfig {
+    pub validation: bool,
+    // Reported in VkApplicationInfo. Drivers match these against known apps and engines
+    // for per-application workarounds and optimizations, so ship your own.
+    pub app_name: CString,
+    pub app_version: u32,
+    pub engine_name: CString,
+    pub engine_version: u32,
+    // VK_LAYER_LUNARG_api_dump, prints every Vulkan call with its parameters to stdout.
+    // Very verbose, meant for short runs.
+    pub api_dump: bool,
+    // VK_LAYER_LUNARG_monitor, shows the frame rate in the window title
+    pub monitor: bool,
+}
+
+impl Default for InstanceConfig {
+    // Validation on in debug builds only
+    fn default() -> Self {
+        Self {
+            validation: cfg!(debug_assertions),
+            app_name: APP_NAME.into(),
+            app_version: 0,
+            engine_name: ENGINE_NAME.into(),
+            engine_version: 0,
+            api_dump: false,
+            monitor: false,
+        }
+    }
+}
+
+impl InstanceConfig {
+    // Environment variable overriding the build's default, "1" enables, "0" disables
+    pub const VALIDATION_ENV: &str = "VK_REF_VALIDATION";
+
+    // The default, overridden by VK_REF_VALIDATION when set. Lets validation be turned on
+    // for a release build in the field.
+    pub fn from_env() -> Self {
+        let config = Self::default();
+        match std::env::var(Self::VALIDATION_ENV).as_deref() {
+            Ok("1") => config.validation(true),
+            Ok("0") => config.validation(false),
+            Ok(value) => {
+                println!(
+                    "Warning: Ignoring {}={value:?}, expected 1 or 0",
+                    Self::VALIDATION_ENV
+                );
+                config
+            }
+            Err(_) => config,
+        }
+    }
+
+    pub fn validation(mut self, enabled: bool) -> Self {
+        self.validation = enabled;
+        self
+    }
+
+    // Interior NULs are dropped, the name can't contain them
+    pub fn app_name(mut self, name: &str) -> Self {
+        self.app_name = c_string_lossy(name);
+        self
+    }
+
+    pub fn app_version(mut self, major: u32, minor: u32, patch: u32) -> Self {
+        self.app_version = vk::make_api_version(0, major, minor, patch);
+        self
+    }
+
+    pub fn engine_name(mut self, name: &str) -> Self {
+        self.engine_name = c_string_lossy(name);
+        self
+    }
+
+    pub fn engine_version(mut self, major: u32, minor: u32, patch: u32) -> Self {
+        self.engine_version = vk::make_api_version(0, major, minor, patch);
+        self
+    }
+
+    // Skipped with a warning when VK_LAYER_LUNARG_api_dump isn't installed
+    pub fn api_dump(mut self, enabled: bool) -> Self {
+        self.api_dump = enabled;
+        self
+    }
+
+    // Skipped with a warning when VK_LAYER_LUNARG_monitor isn't installed
+    pub fn monitor(mut self, enabled: bool) -> Self {
+        self.monitor = enabled;
+        self
+    }
+}
+
+fn c_string_lossy(value: &str) -> CString {
+    CString::new(value.replace('\0', "")).unwrap_or_default()
+}
+
+impl Instance {
+    // Validation follows InstanceConfig::from_env (VK_REF_VALIDATION). Without a window no
+    // surface extensions are enabled and no display connection is needed, for compute-only
+    // tools on headless machines. Such an instance can't create a Surface.
+    pub fn new(window: Option<&winit::window::Window>) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::with_config(window, &InstanceConfig::from_env())
+    }
+
+    // Uses the system Vulkan loader, loaded at runtime. With the "linked" feature the loader
+    // is linked at build time instead: libvulkan (vulkan-1.lib on Windows) has to be
+    // found by the linker, on macOS link MoltenVK or the Vulkan SDK loader as a framework
+    // or dylib. Apps shipping MoltenVK without a loader can point the linker at
+    // libMoltenVK directly.
+    pub fn with_config(
+        window: Option<&winit::window::Window>,
+        config: &InstanceConfig,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::with_entry_and_config(window, load_entry()?, config)
+    }
+
+    // Layers the system loader can enable (e.g. "VK_LAYER_KHRONOS_validation"), without
+    // creating an instance. The loader is loaded only for the query.
+    pub fn available_layers() -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let entry = load_entry()?;
+        let layers = unsafe { entry.enumerate_instance_layer_properties()? };
+        Ok(layers
+            .iter()
+            .map(|props| unsafe { CStr::from_ptr(props.layer_name.as_ptr()) })
+            .map(|name| name.to_string_lossy().into_owned())
+            .collect())
+    }
+
+    // Instance extensions of the system loader and its implicit layers, without creating
+    // an instance. Extensions of explicit layers aren't included.
+    pub fn available_instance_extensions() -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let entry = load_entry()?;
+        let extensions = unsafe { entry.enumerate_instance_extension_properties(None)? };
+        Ok(extensions
+            .iter()
+            .map(|props| unsafe { CStr::from_ptr(props.extension_name.as_ptr()) })
+            .map(|name| name.to_string_lossy().into_owned())
+            .collect())
+    }
+
+    // Loads the Vulkan loader from `path`, for apps shipping their own. On macOS that's
+    // usually MoltenVK bundled in the app (e.g. Contents/Frameworks/libMoltenVK.dylib),
+    // there is no system loader to find. Falls back to the system loader if the path
+    // can't be loaded.
+    pub fn with_loader_path(
+        window: Option<&winit::window::Window>,
+        path: impl AsRef<std::path::Path>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = path.as_ref();
+        let entry = match unsafe { ash::Entry::load_from(path) } {
+            Ok(entry) => entry,
+            Err(err) => {
+                println!(
+                    "Warning: Failed to load Vulkan loader from {} ({err}), using the system loader",
+                    path.display()
+                );
+                unsafe { ash::Entry::load()? }
+            }
+        };
+        Self::with_entry(window, entry)
+    }
+
+    // For an entry created elsewhere, e.g. ash::Entry::linked() with ash's "linked"
+    // feature when the loader is statically linked
+    pub fn with_entry(
+        window: Option<&winit::window::Window>,
+        entry: ash::Entry,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::with_entry_and_config(window, entry, &InstanceConfig::from_env())
+    }
+
+    pub fn with_entry_and_config(
+        window: Option<&winit::window::Window>,
+        entry: ash::Entry,
+        config: &InstanceConfig,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        // None means a 1.0 loader, vkEnumerateInstanceVersion was added in 1.1
+        let available_version =
+            unsafe { entry.try_enumerate_instance_version()? }.unwrap_or(vk::API_VERSION_1_0);
+        let api_version = negotiate_api_version(
+            available_version,
+            REQUESTED_API_VERSION,
+            MIN_API_VERSION,
+            ALLOW_API_VERSION_DOWNGRADE,
+        )?;
+        println!(
+            "Vulkan instance version {} (loader supports {})",
+            format_api_version(api_version),
+            format_api_version(available_version)
+        );
+
+        // Create extensions vector
+        let mut extension_names: Vec<*const i8> = Vec::from(INSTANCE_EXTENSIONS);
+        if let Some(window) = window {
+            extension_names.extend(surface_extensions(window)?);
+        }
+        #[cfg(target_os = "macos")]
+        extension_names.push(khr::portability_enumeration::NAME.as_ptr());
+
+        // Enable optional extensions that are available
+        let available_extensions = unsafe { entry.enumerate_instance_extension_properties(None)? };
+        let enabled_optional_extensions: Vec<&'static CStr> = OPTIONAL_INSTANCE_EXTENSIONS
+            .iter()
+            .copied()
+            .filter(|&name| {
+                // Extends VK_KHR_surface, which isn't enabled without a window
+                if window.is_none() && name == khr::get_surface_capabilities2::NAME {
+                    return false;
+                }
+                let found = available_extensions.iter().any(|props| {
+                    let prop_name = unsafe { CStr::from_ptr(props.extension_name.as_ptr()) };
+                    prop_name == name
+                });
+                if !found {
+                    println!("Optional instance extension {name:?} not available");
+                }
+                found
+            })
+            .collect();
+        extension_names.extend(enabled_optional_extensions.iter().map(|name| name.as_ptr()));
+
+        // Verify layers are available, a missing one (e.g. no Vulkan SDK installed) turns
+        // validation off instead of failing instance creation
+        let mut validation = config.validation;
+        let available_layers = if validation || config.api_dump || config.monitor {
+            unsafe { entry.enumerate_instance_layer_properties()? }
+        } else {
+            Vec::new()
+        };
+        let layer_available = |layer_name: &CStr| {
+            available_layers.iter().any(|prop| {
+                let prop_name = unsafe { CStr::from_ptr(prop.layer_name.as_ptr()) };
+                prop_name == layer_name
+            })
+        };
+        if validation {
+            for &layer_ptr in INSTANCE_LAYERS {
+                let layer_name = unsafe { CStr::from_ptr(layer_ptr) };
+                if !layer_available(layer_name) {
+                    println!("Warning: Layer {layer_name:?} not available, validation disabled");
+                    validation = false;
+                }
+            }
+        }
+        println!(
+            "Validation {}",
+            if validation { "enabled" } else { "disabled" }
+        );
+        let mut layers: Vec<*const c_char> = if validation {
+            INSTANCE_LAYERS.to_vec()
+        } else {
+            Vec::new()
+        };
+        for (enabled, layer_name) in [
+            (config.api_dump, API_DUMP_LAYER),
+            (config.monitor, MONITOR_LAYER),
+        ] {
+            if !enabled {
+                continue;
+            }
+            if layer_available(layer_name) {
+                println!("Layer {layer_name:?} enabled");
+                layers.push(layer_name.as_ptr());
+            } else {
+                println!("Warning: Layer {layer_name:?} not available, skipped");
+            }
+        }
+
+        let app_info = vk::ApplicationInfo::default()
+            .application_name(&config.app_name)
+            .application_version(config.app_version)
+            .engine_name(&config.engine_name)
+            .engine_version(config.engine_version)
+            .api_version(api_version);
+
+        let mut create_flags = vk::InstanceCreateFlags::default();
+        #[cfg(target_os = "macos")]
+        {
+            create_flags |= vk::InstanceCreateFlags::ENUMERATE_PORTABILITY_KHR;
+        }
+
+        let instance_create_info = vk::InstanceCreateInfo::default()
+            .application_info(&app_info)
+            .enabled_layer_names(&layers)
+            .enabled_extension_names(&extension_names)
+            .flags(create_flags);
+
+        let instance = unsafe { entry.create_instance(&instance_create_info, None)? };
+        let enabled_extensions = extension_names
+            .iter()
+            .map(|&name| {
+                unsafe { CStr::from_ptr(name) }
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect();
+        let enabled_layers = layers
+            .iter()
+            .map(|&name| {
+                unsafe { CStr::from_ptr(name) }
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect();
+
+        // Dropping on error destroys the instance
+        let mut instance = Self {
+            entry,
+            instance,
+            api_version,
+            enabled_optional_extensions,
+            enabled_extensions,
+            surface_support: window.is_some(),
+            validation,
+            enabled_layers,
+            debug_messenger: None,
+        };
+
+        if validation && instance.is_extension_enabled(ext::debug_utils::NAME) {
+            let loader = ext::debug_utils::Instance::new(&instance.entry, &instance.instance);
+            let messenger_create_info = vk::DebugUtilsMessengerCreateInfoEXT::default()
+                .message_severity(
+                    vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
+                        | vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
+                )
+                .message_type(
+                    vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
+                        | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
+                        | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
+                )
+                .pfn_user_callback(Some(debug::validation_callback));
+            let messenger =
+                unsafe { loader.create_debug_utils_messenger(&messenger_create_info, None)? };
+            instance.debug_messenger = Some((loader, messenger));
+        }
+
+        Ok(instance)
+    }
+
+    pub fn is_extension_enabled(&self, name: &CStr) -> bool {
+        self.enabled_optional_extensions.contains(&name)
+    }
+}
+
+// Everything ash_window needs for `window`'s display plus the platform surface extensions
+fn surface_extensions(
+    window: &winit::window::Window,
+) -> Result<Vec<*const c_char>, Box<dyn std::error::Error>> {
+    use raw_window_handle::HasDisplayHandle;
+    let mut extension_names =
+        ash_window::enumerate_required_extensions(window.display_handle()?.as_raw())?.to_vec();
+
+    #[cfg(target_os = "windows")]
+    extension_names.push(khr::win32_surface::NAME.as_ptr());
+    #[cfg(target_os = "linux")]
+    extension_names.push(khr::wayland_surface::NAME.as_ptr());
+    #[cfg(target_os = "linux")]
+    extension_names.push(khr::xlib_surface::NAME.as_ptr());
+    #[cfg(target_os = "macos")]
+    extension_names.push(ext::metal_surface::NAME.as_ptr());
+
+    Ok(extension_names)
+}
+
+// System loader, see Instance::with_config
+fn load_entry() -> Result<ash::Entry, Box<dyn std::error::Error>> {
+    #[cfg(feature = "linked")]
+    let entry = ash::Entry::linked();
+    #[cfg(not(feature = "linked"))]
+    let entry = unsafe { ash::Entry::load()? };
+    Ok(entry)
+}
+
+// ash has no wrapper for vkGetPhysicalDeviceFragmentShadingRatesKHR, the usual two call
+// enumeration through the raw function pointer. Sizes usable with single sampled
+// attachments only, in the driver's order (largest first).
+fn query_fragment_shading_rates(
+    instance: &Instance,
+    physical_device: vk::PhysicalDevice,
+) -> Result<Vec<vk::Extent2D>, Box<dyn std::error::Error>> {
+    let loader = khr::fragment_shading_rate::Instance::new(&instance.entry, &instance.instance);
+    let get_rates = loader.fp().get_physical_device_fragment_shading_rates_khr;
+
+    let mut count = 0;
+    unsafe { get_rates(physical_device, &mut count, std::ptr::null_mut()) }.result()?;
+    let mut rates = vec![vk::PhysicalDeviceFragmentShadingRateKHR::default(); count as usize];
+    unsafe { get_rates(physical_device, &mut count, rates.as_mut_ptr()) }.result()?;
+    rates.truncate(count as usize);
+
+    Ok(rates
+        .iter()
+        .filter(|rate| rate.sample_counts.contains(vk::SampleCountFlags::TYPE_1))
+        .map(|rate| rate.fragment_size)
+        .collect())
+}
+
+// Saturates, Duration::MAX waits forever like u64::MAX
+fn timeout_nanos(timeout: std::time::Duration) -> u64 {
+    u64::try_from(timeout.as_nanos()).unwrap_or(u64::MAX)
+}
+
+// Patch versions are ignored, only major.minor decide which features exist
+fn negotiate_api_version(
+    available: u32,
+    requested: u32,
+    min: u32,
+    allow_downgrade: bool,
+) -> Result<u32, String> {
+    let major_minor = |version: u32| {
+        (
+            vk::api_version_major(version),
+            vk::api_version_minor(version),
+        )
+    };
+    let available_mm = major_minor(available);
+
+    if available_mm >= major_minor(requested) {
+        return Ok(requested);
+    }
+
+    if !allow_downgrade {
+        return Err(format!(
+            "Vulkan {} required, but the loader only supports {}",
+            format_api_version(requested),
+            format_api_version(available)
+        ));
+    }
+    if available_mm < major_minor(min) {
+        return Err(format!(
+            "Vulkan {} is the minimum supported, but the loader only supports {}",
+            format_api_version(min),
+            format_api_version(available)
+        ));
+    }
+
+    println!(
+        "Warning: Vulkan {} not supported, falling back to {}",
+        format_api_version(requested),
+        format_api_version(available)
+    );
+    Ok(vk::make_api_version(0, available_mm.0, available_mm.1, 0))
+}
+
+fn format_api_version(version: u32) -> String {
+    format!(
+        "{}.{}.{}",
+        vk::api_version_major(version),
+        vk::api_version_minor(version),
+        vk::api_version_patch(version)
+    )
+}
+
+pub struct Surface {
+    surface: vk::SurfaceKHR,
+    loader: khr::surface::Instance,
+
+    window_handle: raw_window_handle::RawWindowHandle,
+    display_handle: raw_window_handle::RawDisplayHandle,
+
+    // Monitor the window was on at creation, exclusive fullscreen on Win32 needs it. None
+    // when winit couldn't tell, swapchains are then created without exclusive mode.
+    #[cfg(target_os = "windows")]
+    hmonitor: Option<vk::HMONITOR>,
+}
+
+impl Drop for Surface {
+    fn drop(&mut self) {
+        unsafe {
+            self.loader.destroy_surface(self.surface, None);
+        }
+    }
+}
+
+impl Surface {
+    fn new(
+        instance: &Instance,
+        window: &winit::window::Window,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        if !instance.surface_support {
+            return Err("Instance was created without a window, surfaces aren't supported".into());
+        }
+        let window_handle = window.window_handle()?.as_raw();
+        let display_handle = window.display_handle()?.as_raw();
+
+        let loader = khr::surface::Instance::new(&instance.entry, &instance.instance);
+        let surface = unsafe {
+            ash_window::create_surface(
+                &instance.entry,
+                &instance.instance,
+                display_handle,
+                window_handle,
+                None,
+            )
+        }?;
+
+        #[cfg(target_os = "windows")]
+        let hmonitor = {
+            use winit::platform::windows::MonitorHandleExtWindows;
+            let hmonitor = window.current_monitor().map(|monitor| monitor.hmonitor());
+            if hmonitor.is_none() {
+                println!("Warning: Window has no current monitor, exclusive fullscreen disabled");
+            }
+            hmonitor
+        };
+
+        Ok(Self {
+            surface,
+            loader,
+            window_handle,
+            display_handle,
+            #[cfg(target_os = "windows")]
+            hmonitor,
+        })
+    }
+
+    // Replaces the surface with a new one for the window's current handles, after the
+    // window was reparented or moved to another display. Every swapchain created for the
+    // old surface must be destroyed before (see Swapchain::release). Present support of
+    // the device's queues has to be checked again by the caller.
+    pub fn recreate(
+        &mut self,
+        instance: &Instance,
+        window: &winit::window::Window,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        // Some platforms (e.g. Android) allow only one surface per window, the old one has
+        // to go first. Dropping the null handle below is a no-op.
+        unsafe { self.loader.destroy_surface(self.surface, None) };
+        self.surface = vk::SurfaceKHR::null();
+        *self = Self::new(instance, window)?;
+        Ok(())
+    }
+}
+
+// Prefers one family with both graphics support and present support for `surface`, so
+// the swapchain images can stay EXCLUSIVE. Falls back to the first graphics family and
+// the first one that can present.
+fn find_queue_families(
+    queue_family_properties: &[vk::QueueFamilyProperties],
+    surface: &Surface,
+    physical_device: vk::PhysicalDevice,
+) -> (Option<u32>, Option<u32>) {
+    let supports_present = |idx: u32| unsafe {
+        surface
+            .loader
+            .get_physical_device_surface_support(physical_device, idx, surface.surface)
+            .unwrap_or(false)
+    };
+    let graphics_families = queue_family_properties
+        .iter()
+        .enumerate()
+        .filter(|(_, props)| props.queue_flags.contains(vk::QueueFlags::GRAPHICS))
+        .map(|(idx, _)| idx as u32);
+
+    let mut graphics = None;
+    for idx in graphics_families {
+        if supports_present(idx) {
+            return (Some(idx), Some(idx));
+        }
+        graphics.get_or_insert(idx);
+    }
+
+    let present = (0..queue_family_properties.len() as u32).find(|&idx| supports_present(idx));
+
+    (graphics, present)
+}
+
+// Whether every extension in DEVICE_EXTENSIONS is available on `physical_device`
+fn supports_required_extensions(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+) -> bool {
+    let Ok(available) =
+        (unsafe { instance.enumerate_device_extension_properties(physical_device) })
+    else {
+        return false;
+    };
+    DEVICE_EXTENSIONS.iter().all(|&ptr| {
+        let required = unsafe { CStr::from_ptr(ptr) };
+        available.iter().any(|props| {
+            let name = unsafe { CStr::from_ptr(props.extension_name.as_ptr()) };
+            name == required
+        })
+    })
+}
+
+// Physical device preference for multi-GPU systems, the index is checked before the name.
+// Indices follow Instance::enumerate_devices_info.
+#[derive(Debug, Clone)]
+pub struct DeviceConfig {
+    pub preferred_index: Option<usize>,
+    // Case insensitive substring of the device name, e.g. "NVIDIA"
+    pub preferred_name: Option<String>,
+    // Queues created in every used family (graphics, present, compute), clamped to the
+    // family's queueCount. Queue 0 is what the renderer uses, the others are free for
+    // e.g. parallel uploads, see Device::get_queue.
+    pub queues_per_family: u32,
+    pub queue_priorities: QueuePriorities,
+    // Kind of GPU picked when there is no preferred index/name (or it didn't match)
+    pub gpu_preference: GpuPreference,
+    // Flip Y with a negative viewport height when maintenance1 is available, instead of
+    // in the projection (see camera.rs). Unsupported devices keep the projection flip.
+    pub flip_viewport_y: bool,
+    // Adds a stencil component to Device::preferred_depth_format (main depth buffer,
+    // DepthBuffer::new), off to keep depth-only setups lean
+    pub depth_stencil: bool,
+}
+
+impl Default for DeviceConfig {
+    fn default() -> Self {
+        Self {
+            preferred_index: None,
+            preferred_name: None,
+            queues_per_family: 1,
+            queue_priorities: QueuePriorities::default(),
+            gpu_preference: GpuPreference::Auto,
+            flip_viewport_y: true,
+            depth_stencil: false,
+        }
+    }
+}
+
+// Which suitable physical device wins, every candidate still needs graphics, present and
+// swapchain support. Ties go to the first one enumerated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GpuPreference {
+    // First suitable device in enumeration order
+    Auto,
+    // Discrete, then integrated, virtual and CPU implementations
+    HighPerformance,
+    // Integrated first (laptops, saves battery), then the HighPerformance order
+    LowPower,
+}
+
+impl GpuPreference {
+    // Lower is better, every type ranks the same with Auto
+    fn rank(self, device_type: vk::PhysicalDeviceType) -> usize {
+        let order: &[vk::PhysicalDeviceType] = match self {
+            Self::Auto => &[],
+            Self::HighPerformance => &[
+                vk::PhysicalDeviceType::DISCRETE_GPU,
+                vk::PhysicalDeviceType::INTEGRATED_GPU,
+                vk::PhysicalDeviceType::VIRTUAL_GPU,
+                vk::PhysicalDeviceType::CPU,
+            ],
+            Self::LowPower => &[
+                vk::PhysicalDeviceType::INTEGRATED_GPU,
+                vk::PhysicalDeviceType::DISCRETE_GPU,
+                vk::PhysicalDeviceType::VIRTUAL_GPU,
+                vk::PhysicalDeviceType::CPU,
+            ],
+        };
+        order
+            .iter()
+            .position(|&ty| ty == device_type)
+            .unwrap_or(order.len())
+    }
+}
+
+// Relative scheduling priority of the queues, in [0.0, 1.0]. Only a hint, hardware
+// without priority support ignores it. A family used by several roles gets the highest of
+// their priorities for its queue 0.
+#[derive(Debug, Clone)]
+pub struct QueuePriorities {
+    pub graphics: f32,
+    pub present: f32,
+    // Async compute, see Device::compute_queue
+    pub compute: f32,
+    // Queues past index 0 of every family, see DeviceConfig::queues_per_family
+    pub extra: f32,
+}
+
+impl Default for QueuePriorities {
+    // The renderer's queues first, the extra ones below them
+    fn default() -> Self {
+        Self {
+            graphics: 1.0,
+            present: 1.0,
+            compute: 1.0,
+            extra: 0.5,
+        }
+    }
+}
+
+impl QueuePriorities {
+    fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let priorities = [
+            ("graphics", self.graphics),
+            ("present", self.present),
+            ("compute", self.compute),
+            ("extra", self.extra),
+        ];
+        for (name, priority) in priorities {
+            if !(0.0..=1.0).contains(&priority) {
+                return Err(format!("Queue priority {name} = {priority} not in [0.0, 1.0]").into());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl DeviceConfig {
+    fn has_preference(&self) -> bool {
+        self.preferred_index.is_some() || self.preferred_name.is_some()
+    }
+
+    fn find_preferred(
+        &self,
+        physical_devices: &[vk::PhysicalDevice],
+        device_name: impl Fn(vk::PhysicalDevice) -> String,
+    ) -> Option<vk::PhysicalDevice> {
+        if let Some(&pdevice) = self
+            .preferred_index
+            .and_then(|idx| physical_devices.get(idx))
+        {
+            return Some(pdevice);
+        }
+
+        let pattern = self.preferred_name.as_ref()?.to_lowercase();
+        physical_devices
+            .iter()
+            .copied()
+            .find(|&pdevice| device_name(pdevice).to_lowercase().contains(&pattern))
+    }
+}
+
+pub struct Device {
+    pub physical_device: vk::PhysicalDevice,
+    pub device: ash::Device,
+    pub properties: vk::PhysicalDeviceProperties,
+    pub memory_properties: vk::PhysicalDeviceMemoryProperties,
+    // Lower of the instance's negotiated version and what the physical device supports
+    pub api_version: u32,
+
+    // Optional extensions that were found and enabled
+    pub enabled_optional_extensions: Vec<&'static CStr>,
+
+    // Core features the device was created with
+    pub enabled_features: vk::PhysicalDeviceFeatures,
+    // Set when all descriptor indexing features needed for bindless textures are enabled
+    pub descriptor_indexing: bool,
+    // bufferDeviceAddress is enabled, see Buffer::device_address
+    pub buffer_device_address: bool,
+    // presentId and presentWait are enabled, see Context::wait_for_present
+    pub present_wait: bool,
+    // Depth and stencil aspects can be in different layouts, see DepthStencilLayouts
+    pub separate_depth_stencil_layouts: bool,
+    // Main pass viewports have a negative height, the projection doesn't flip Y then.
+    // See DeviceConfig::flip_viewport_y and camera::clip_correction.
+    pub flip_viewport_y: bool,
+    // pipelineFragmentShadingRate is enabled, see FragmentShadingRate
+    pub fragment_shading_rate: bool,
+    // Fragment sizes supported without multisampling, largest first. Only 1x1 without
+    // fragment_shading_rate.
+    pub fragment_shading_rates: Vec<vk::Extent2D>,
+
+    pub graphics_queue_family_idx: u32,
+    pub graphics_queue: vk::Queue,
+
+    pub present_queue_family_idx: u32,
+    pub present_queue: vk::Queue,
+
+    // Same as the graphics family (and queue) when there is no compute-only family
+    pub compute_queue_family_idx: u32,
+    pub compute_queue: vk::Queue,
+
+    // (family, queues created) for every family in use, see get_queue
+    pub queue_counts: Vec<(u32, u32)>,
+
+    // Chosen once, see preferred_depth_format
+    depth_format: vk::Format,
+    // Only dropped in Drop, see fence_pool
+    fence_pool: ManuallyDrop<RefCell<FencePool>>,
+    // Only dropped in Drop, see allocator
+    allocator: ManuallyDrop<Rc<RefCell<Allocator>>>,
+
+    instance: ash::Instance, // Needed for physical device queries, same cheap clone as ash::Device
+}
+
+impl Drop for Device {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = self.device.device_wait_idle();
+            // Its fences have to go before the device
+            ManuallyDrop::drop(&mut self.fence_pool);
+            ManuallyDrop::drop(&mut self.allocator);
+            self.device.destroy_device(None);
+        }
+    }
+}
+
+impl Device {
+    // Picks the first suitable physical device
+    pub fn new(instance: &Instance, surface: &Surface) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::with_config(instance, surface, &DeviceConfig::default())
+    }
+
+    pub fn with_config(
+        instance: &Instance,
+        surface: &Surface,
+        config: &DeviceConfig,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        config.queue_priorities.validate()?;
+        let physical_devices = unsafe { instance.instance.enumerate_physical_devices()? };
+        if physical_devices.is_empty() {
+            return Err("No Vulkan physical devices found".into());
+        }
+
+        let device_name = |pdevice| {
+            let props = unsafe { instance.instance.get_physical_device_properties(pdevice) };
+            let name = unsafe { CStr::from_ptr(props.device_name.as_ptr()) };
+            name.to_string_lossy().into_owned()
+        };
+
+        // The preference has to pass the same checks, otherwise selection falls back to
+        // the first suitable device
+        let mut selected_device = None;
+        if config.has_preference() {
+            match config.find_preferred(&physical_devices, device_name) {
+                Some(pdevice) => match Self::suitable_queue_families(instance, surface, pdevice) {
+                    Some(families) => {
+                        println!("Device preference honored");
+                        selected_device = Some((pdevice, families));
+                    }
+                    None => println!(
+                        "Warning: Preferred device {:?} lacks graphics, present or swapchain support, falling back",
+                        device_name(pdevice)
+                    ),
+                },
+                None => println!("Warning: No device matches {config:?}, falling back"),
+            }
+        }
+        if selected_device.is_none() {
+            let preference = config.gpu_preference;
+            println!("GPU preference {preference:?}");
+            // min_by_key keeps the first of equally ranked devices
+            selected_device = physical_devices
+                .iter()
+                .filter_map(|&pdevice| {
+                    Self::suitable_queue_families(instance, surface, pdevice)
+                        .map(|families| (pdevice, families))
+                })
+                .min_by_key(|&(pdevice, _)| {
+                    let props =
+                        unsafe { instance.instance.get_physical_device_properties(pdevice) };
+                    preference.rank(props.device_type)
+                });
+        }
+
+        let (
+            physical_device,
+            (graphics_queue_family_idx, present_queue_family_idx, compute_queue_family_idx),
+        ) = selected_device.ok_or("No suitable physical device found")?;
+        let device_type = unsafe {
+            instance
+                .instance
+                .get_physical_device_properties(physical_device)
+        }
+        .device_type;
+        println!(
+            "Selected device: {:?} ({device_type:?})",
+            device_name(physical_device)
+        );
+        if present_queue_family_idx == graphics_queue_family_idx {
+            println!("Graphics and present share queue family {graphics_queue_family_idx}");
+        } else {
+            println!(
+                "No queue family supports both graphics and present, using {graphics_queue_family_idx} and {present_queue_family_idx}"
+            );
+        }
+
+        // Create unique queue families
+        let mut unique_queue_families = vec![graphics_queue_family_idx];
+        if present_queue_family_idx != graphics_queue_family_idx {
+            unique_queue_families.push(present_queue_family_idx);
+        }
+        if !unique_queue_families.contains(&compute_queue_family_idx) {
+            unique_queue_families.push(compute_queue_family_idx);
+        }
+
+        let queue_family_properties = unsafe {
+            instance
+                .instance
+                .get_physical_device_queue_family_properties(physical_device)
+        };
+        let requested_queues = config.queues_per_family.max(1);
+        let queue_counts: Vec<(u32, u32)> = unique_queue_families
+            .iter()
+            .map(|&family_idx| {
+                let available = queue_family_properties[family_idx as usize].queue_count;
+                if requested_queues > available {
+                    println!(
+                        "Warning: Queue family {family_idx} only has {available} queues, {requested_queues} requested"
+                    );
+                }
+                (family_idx, requested_queues.min(available))
+            })
+            .collect();
+
+        let priorities = &config.queue_priorities;
+        let queue_priorities: Vec<Vec<f32>> = queue_counts
+            .iter()
+            .map(|&(family_idx, count)| {
+                let first = [
+                    (graphics_queue_family_idx, priorities.graphics),
+                    (present_queue_family_idx, priorities.present),
+                    (compute_queue_family_idx, priorities.compute),
+                ]
+                .into_iter()
+                .filter(|&(idx, _)| idx == family_idx)
+                .map(|(_, priority)| priority)
+                .fold(0.0, f32::max);
+                std::iter::once(first)
+                    .chain(std::iter::repeat_n(priorities.extra, count as usize - 1))
+                    .collect()
+            })
+            .collect();
+
+        // Create logical device
+        let queue_create_infos: Vec<_> = queue_counts
+            .iter()
+            .zip(&queue_priorities)
+            .map(|(&(family_idx, _), priorities)| {
+                vk::DeviceQueueCreateInfo::default()
+                    .queue_family_index(family_idx)
+                    .queue_priorities(priorities)
+            })
+            .collect();
+
+        let properties = unsafe {
+            instance
+                .instance
+                .get_physical_device_properties(physical_device)
+        };
+        let memory_properties = unsafe {
+            instance
+                .instance
+                .get_physical_device_memory_properties(physical_device)
+        };
+
+        let api_version = instance.api_version.min(properties.api_version);
+
+        // Query descriptor indexing (core in 1.2) for bindless textures, buffer device
+        // addresses (core in 1.2) for pointers in shaders and separate depth/stencil
+        // layouts (core in 1.2)
+        let mut supported_indexing = vk::PhysicalDeviceDescriptorIndexingFeatures::default();
+        let mut supported_address = vk::PhysicalDeviceBufferDeviceAddressFeatures::default();
+        let mut supported_separate_layouts =
+            vk::PhysicalDeviceSeparateDepthStencilLayoutsFeatures::default();
+        {
+            let mut features2 = vk::PhysicalDeviceFeatures2::default()
+                .push_next(&mut supported_indexing)
+                .push_next(&mut supported_address)
+                .push_next(&mut supported_separate_layouts);
+            unsafe {
+                instance
+                    .instance
+                    .get_physical_device_features2(physical_device, &mut features2)
+            };
+        }
+
+        let buffer_device_address = api_version >= vk::API_VERSION_1_2
+            && supported_address.buffer_device_address == vk::TRUE;
+        if !buffer_device_address {
+            println!("Buffer device addresses not supported");
+        }
+        let mut enabled_address =
+            vk::PhysicalDeviceBufferDeviceAddressFeatures::default().buffer_device_address(true);
+
+        let separate_depth_stencil_layouts = api_version >= vk::API_VERSION_1_2
+            && supported_separate_layouts.separate_depth_stencil_layouts == vk::TRUE;
+        if !separate_depth_stencil_layouts {
+            println!("Separate depth/stencil layouts not supported, using combined layouts");
+        }
+        let mut enabled_separate_layouts =
+            vk::PhysicalDeviceSeparateDepthStencilLayoutsFeatures::default()
+                .separate_depth_stencil_layouts(true);
+
+        let mut enabled_indexing = vk::PhysicalDeviceDescriptorIndexingFeatures::default()
+            .runtime_descriptor_array(true)
+            .descriptor_binding_partially_bound(true)
+            .descriptor_binding_variable_descriptor_count(true)
+            .descriptor_binding_sampled_image_update_after_bind(true)
+            .shader_sampled_image_array_non_uniform_indexing(true);
+
+        // Enable optional extensions that are available
+        let available_extensions = unsafe {
+            instance
+                .instance
+                .enumerate_device_extension_properties(physical_device)?
+        };
+        let enabled_optional_extensions: Vec<&'static CStr> = OPTIONAL_DEVICE_EXTENSIONS
+            .iter()
+            .copied()
+            .filter(|&name| {
+                let found = available_extensions.iter().any(|props| {
+                    let prop_name = unsafe { CStr::from_ptr(props.extension_name.as_ptr()) };
+                    prop_name == name
+                });
+                if !found {
+                    println!("Optional device extension {name:?} not available");
+                }
+                found
+            })
+            // Unusable without its instance level dependency
+            .filter(|&name| {
+                name != ext::full_screen_exclusive::NAME
+                    || instance.is_extension_enabled(khr::get_surface_capabilities2::NAME)
+            })
+            .collect::<Vec<_>>();
+        // Same for the mutable format swapchain, its dependency is a device extension
+        let has_format_list = enabled_optional_extensions.contains(&khr::image_format_list::NAME);
+        let enabled_optional_extensions: Vec<&'static CStr> = enabled_optional_extensions
+            .into_iter()
+            .filter(|&name| name != khr::swapchain_mutable_format::NAME || has_format_list)
+            // Its VK_KHR_create_renderpass2 dependency is only used as core
+            .filter(|&name| {
+                name != khr::fragment_shading_rate::NAME || api_version >= vk::API_VERSION_1_2
+            })
+            .collect();
+
+        // The features struct is only valid to enable on 1.2 or with the extension
+        let descriptor_indexing = (api_version >= vk::API_VERSION_1_2
+            || enabled_optional_extensions.contains(&ext::descriptor_indexing::NAME))
+            && supported_indexing.runtime_descriptor_array == vk::TRUE
+            && supported_indexing.descriptor_binding_partially_bound == vk::TRUE
+            && supported_indexing.descriptor_binding_variable_descriptor_count == vk::TRUE
+            && supported_indexing.descriptor_binding_sampled_image_update_after_bind == vk::TRUE
+            && supported_indexing.shader_sampled_image_array_non_uniform_indexing == vk::TRUE;
+        if !descriptor_indexing {
+            println!("Warning: Descriptor indexing not supported, bindless textures disabled");
+        }
+
+        // Both extensions only advertise support, the features still have to be there.
+        // The query is only valid once the extensions are known to exist.
+        let mut enabled_present_id =
+            vk::PhysicalDevicePresentIdFeaturesKHR::default().present_id(true);
+        let mut enabled_present_wait =
+            vk::PhysicalDevicePresentWaitFeaturesKHR::default().present_wait(true);
+        let present_wait = enabled_optional_extensions.contains(&khr::present_id::NAME)
+            && enabled_optional_extensions.contains(&khr::present_wait::NAME)
+            && {
+                let mut supported_present_id = vk::PhysicalDevicePresentIdFeaturesKHR::default();
+                let mut supported_present_wait =
+                    vk::PhysicalDevicePresentWaitFeaturesKHR::default();
+                let mut features2 = vk::PhysicalDeviceFeatures2::default()
+                    .push_next(&mut supported_present_id)
+                    .push_next(&mut supported_present_wait);
+                unsafe {
+                    instance
+                        .instance
+                        .get_physical_device_features2(physical_device, &mut features2)
+                };
+                supported_present_id.present_id == vk::TRUE
+                    && supported_present_wait.present_wait == vk::TRUE
+            };
+        if !present_wait {
+            println!("Present wait not supported, frames are paced by fences only");
+        }
+
+        // Same for per-draw shading rates
+        let mut enabled_shading_rate = vk::PhysicalDeviceFragmentShadingRateFeaturesKHR::default()
+            .pipeline_fragment_shading_rate(true);
+        let fragment_shading_rate =
+            enabled_optional_extensions.contains(&khr::fragment_shading_rate::NAME) && {
+                let mut supported_shading_rate =
+                    vk::PhysicalDeviceFragmentShadingRateFeaturesKHR::default();
+                let mut features2 =
+                    vk::PhysicalDeviceFeatures2::default().push_next(&mut supported_shading_rate);
+                unsafe {
+                    instance
+                        .instance
+                        .get_physical_device_features2(physical_device, &mut features2)
+                };
+                supported_shading_rate.pipeline_fragment_shading_rate == vk::TRUE
+            };
+        let fragment_shading_rates = if fragment_shading_rate {
+            query_fragment_shading_rates(instance, physical_device)?
+        } else {
+            println!("Fragment shading rates not supported, every draw shades at full rate");
+            vec![vk::Extent2D {
+                width: 1,
+                height: 1,
+            }]
+        };
+
+        // Negative viewport heights are core since 1.1, before that they need maintenance1
+        let flip_viewport_y = config.flip_viewport_y
+            && (api_version >= vk::API_VERSION_1_1
+                || enabled_optional_extensions.contains(&khr::maintenance1::NAME));
+        if config.flip_viewport_y && !flip_viewport_y {
+            println!("Negative viewport heights not supported, flipping Y in the projection");
+        }
+
+        let mut extension_names: Vec<*const c_char> = Vec::from(DEVICE_EXTENSIONS);
+        extension_names.extend(enabled_optional_extensions.iter().map(|name| name.as_ptr()));
+
+        // Optional core features, each enabled only when supported
+        let supported_features = unsafe {
+            instance
+                .instance
+                .get_physical_device_features(physical_device)
+        };
+        let device_features = vk::PhysicalDeviceFeatures::default()
+            .multi_draw_indirect(supported_features.multi_draw_indirect == vk::TRUE)
+            .pipeline_statistics_query(supported_features.pipeline_statistics_query == vk::TRUE)
+            .occlusion_query_precise(supported_features.occlusion_query_precise == vk::TRUE)
+            .geometry_shader(supported_features.geometry_shader == vk::TRUE)
+            .tessellation_shader(supported_features.tessellation_shader == vk::TRUE)
+            .sampler_anisotropy(supported_features.sampler_anisotropy == vk::TRUE)
+            .sample_rate_shading(supported_features.sample_rate_shading == vk::TRUE)
+            .shader_storage_image_write_without_format(
+                supported_features.shader_storage_image_write_without_format == vk::TRUE,
+            );
+
+        let mut device_create_info = vk::DeviceCreateInfo::default()
+            .queue_create_infos(&queue_create_infos)
+            .enabled_extension_names(&extension_names)
+            .enabled_features(&device_features);
+
+        if descriptor_indexing {
+            device_create_info = device_create_info.push_next(&mut enabled_indexing);
+        }
+        if buffer_device_address {
+            device_create_info = device_create_info.push_next(&mut enabled_address);
+        }
+        if separate_depth_stencil_layouts {
+            device_create_info = device_create_info.push_next(&mut enabled_separate_layouts);
+        }
+        if present_wait {
+            device_create_info = device_create_info
+                .push_next(&mut enabled_present_id)
+                .push_next(&mut enabled_present_wait);
+        }
+        if fragment_shading_rate {
+            device_create_info = device_create_info.push_next(&mut enabled_shading_rate);
+        }
+
+        let device = unsafe {
+            instance
+                .instance
+                .create_device(physical_device, &device_create_info, None)?
+        };
+
+        let graphics_queue = unsafe { device.get_device_queue(graphics_queue_family_idx, 0) };
+        let present_queue = unsafe { device.get_device_queue(present_queue_family_idx, 0) };
+        let compute_queue = unsafe { device.get_device_queue(compute_queue_family_idx, 0) };
+        if compute_queue_family_idx != graphics_queue_family_idx {
+            println!("Async compute on queue family {compute_queue_family_idx}");
+        }
+
+        let fence_pool = ManuallyDrop::new(RefCell::new(FencePool::new(&device)));
+        let allocator = ManuallyDrop::new(Rc::new(RefCell::new(Allocator::new(
+            &device,
+            &properties.limits,
+            memory_properties,
+            allocator::DEFAULT_BLOCK_SIZE,
+        ))));
+        let mut device = Self {
+            physical_device,
+            device,
+            properties,
+            memory_properties,
+            api_version,
+
+            enabled_optional_extensions,
+
+            enabled_features: device_features,
+            descriptor_indexing,
+            buffer_device_address,
+            present_wait,
+            separate_depth_stencil_layouts,
+            flip_viewport_y,
+            fragment_shading_rate,
+            fragment_shading_rates,
+
+            graphics_queue_family_idx,
+            graphics_queue,
+
+            present_queue_family_idx,
+            present_queue,
+
+            compute_queue_family_idx,
+            compute_queue,
+
+            queue_counts,
+
+            // Picked right below, the query needs the device
+            depth_format: vk::Format::UNDEFINED,
+            fence_pool,
+            allocator,
+
+            instance: instance.instance.clone(),
+        };
+        device.depth_format = DepthBuffer::find_format(&device, config.depth_stencil)?;
+        println!("Depth format {:?}", device.depth_format);
+
+        if let Some(subgroup) = device.subgroup_properties() {
+            println!(
+                "Subgroup size {}, operations {:?} in stages {:?}",
+                subgroup.subgroup_size, subgroup.supported_operations, subgroup.supported_stages
+            );
+        }
+
+        if let Some(budget) = device.memory_budget() {
+            for (heap_index, heap_budget, heap_usage) in budget {
+                println!(
+                    "Memory heap {heap_index}: {} MiB used of {} MiB budget",
+                    heap_usage / (1024 * 1024),
+                    heap_budget / (1024 * 1024)
+                );
+            }
+        }
+
+        Ok(device)
+    }
+
+    // Graphics, present and compute family when `pdevice` has graphics and present queues
+    // and the required extensions, None otherwise
+    fn suitable_queue_families(
+        instance: &Instance,
+        surface: &Surface,
+        pdevice: vk::PhysicalDevice,
+    ) -> Option<(u32, u32, u32)> {
+        let queue_family_properties = unsafe {
+            instance
+                .instance
+                .get_physical_device_queue_family_properties(pdevice)
+        };
+        let (graphics, present) = find_queue_families(&queue_family_properties, surface, pdevice);
+        let (graphics, present) = graphics.zip(present)?;
+        if !supports_required_extensions(&instance.instance, pdevice) {
+            return None;
+        }
+
+        // A compute-only family runs asynchronously to graphics, otherwise compute
+        // shares the graphics family (which always supports compute)
+        let compute = queue_family_properties
+            .iter()
+            .position(|props| {
+                props.queue_flags.contains(vk::QueueFlags::COMPUTE)
+                    && !props.queue_flags.contains(vk::QueueFlags::GRAPHICS)
+            })
+            .map_or(graphics, |idx| idx as u32);
+
+        Some((graphics, present, compute))
+    }
+
+    // Queue `index` of `family`, only families in use have queues, up to
+    // DeviceConfig::queues_per_family each. Queues need external synchronization, submit to
+    // each from one thread at a time. Index 0 is shared with the renderer.
+    pub fn get_queue(
+        &self,
+        family: u32,
+        index: u32,
+    ) -> Result<vk::Queue, Box<dyn std::error::Error>> {
+        let &(_, count) = self
+            .queue_counts
+            .iter()
+            .find(|&&(family_idx, _)| family_idx == family)
+            .ok_or_else(|| format!("No queues created in queue family {family}"))?;
+        if index >= count {
+            return Err(
+                format!("Queue {index} out of range, family {family} has {count} queues").into(),
+            );
+        }
+
+        Ok(unsafe { self.device.get_device_queue(family, index) })
+    }
+
+    pub fn is_extension_enabled(&self, name: &CStr) -> bool {
+        self.enabled_optional_extensions.contains(&name)
+            || DEVICE_EXTENSIONS.iter().any(|&ptr| {
+                let required_name = unsafe { CStr::from_ptr(ptr) };
+                required_name == name
+            })
+    }
+
+    // Subgroup (wave) size and the operations (ballot, shuffle, arithmetic, ...) shaders can
+    // use per stage. None below Vulkan 1.1, where the query doesn't exist.
+    pub fn subgroup_properties(&self) -> Option<vk::PhysicalDeviceSubgroupProperties<'static>> {
+        if self.api_version < vk::API_VERSION_1_1 {
+            return None;
+        }
+
+        let mut subgroup_properties = vk::PhysicalDeviceSubgroupProperties::default();
+        {
+            let mut properties2 =
+                vk::PhysicalDeviceProperties2::default().push_next(&mut subgroup_properties);
+            unsafe {
+                self.instance
+                    .get_physical_device_properties2(self.physical_device, &mut properties2)
+            };
+        }
+
+        // Drop the p_next pointer, it pointed into the chain above
+        Some(vk::PhysicalDeviceSubgroupProperties {
+            p_next: std::ptr::null_mut(),
+            ..subgroup_properties
+        })
+    }
+
+    // Mode for resolving multisampled depth (see RenderPass::multisampled): MIN when
+    // supported, it keeps the nearest sample for depth reads like SSAO, otherwise
+    // SAMPLE_ZERO. None below Vulkan 1.2, where depth/stencil resolve isn't core.
+    pub fn depth_resolve_mode(&self) -> Option<vk::ResolveModeFlags> {
+        if self.api_version < vk::API_VERSION_1_2 {
+            return None;
+        }
+
+        let mut resolve_properties = vk::PhysicalDeviceDepthStencilResolveProperties::default();
+        {
+            let mut properties2 =
+                vk::PhysicalDeviceProperties2::default().push_next(&mut resolve_properties);
+            unsafe {
+                self.instance
+                    .get_physical_device_properties2(self.physical_device, &mut properties2)
+            };
+        }
+
+        let supported = resolve_properties.supported_depth_resolve_modes;
+        [vk::ResolveModeFlags::MIN, vk::ResolveModeFlags::SAMPLE_ZERO]
+            .into_iter()
+            .find(|&mode| supported.contains(mode))
+    }
+
+    // Live (heap_index, budget, usage) per memory heap, None without VK_EXT_memory_budget.
+    // Budget is how much the process can allocate on the heap before things degrade
+    // (other processes count against it too), usage is what this process currently has.
+    pub fn memory_budget(&self) -> Option<Vec<(u32, vk::DeviceSize, vk::DeviceSize)>> {
+        if !self.is_extension_enabled(ext::memory_budget::NAME) {
+            return None;
+        }
+
+        let mut budget_properties = vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+        let heap_count = {
+            let mut memory_properties2 =
+                vk::PhysicalDeviceMemoryProperties2::default().push_next(&mut budget_properties);
+            unsafe {
+                self.instance.get_physical_device_memory_properties2(
+                    self.physical_device,
+                    &mut memory_properties2,
+                )
+            };
+            memory_properties2.memory_properties.memory_heap_count
+        };
+
+        Some(
+            (0..heap_count as usize)
+                .map(|idx| {
+                    (
+                        idx as u32,
+                        budget_properties.heap_budget[idx],
+                        budget_properties.heap_usage[idx],
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    pub fn find_memory_type(
+        &self,
+        type_bits: u32,
+        properties: vk::MemoryPropertyFlags,
+    ) -> Result<u32, Box<dyn std::error::Error>> {
+        find_memory_type(&self.memory_properties, type_bits, properties)
+    }
+
+    pub fn format_supports(
+        &self,
+        format: vk::Format,
+        tiling: vk::ImageTiling,
+        features: vk::FormatFeatureFlags,
+    ) -> bool {
+        let properties = unsafe {
+            self.instance
+                .get_physical_device_format_properties(self.physical_device, format)
+        };
+        tiling_supports(&properties, tiling, features)
+    }
+
+    // Fences for one-off submissions, return them with FencePool::release once waited on.
+    // Don't hold the borrow across calls that submit (submit_once borrows it too).
+    pub fn fence_pool(&self) -> &RefCell<FencePool> {
+        &self.fence_pool
+    }
+
+    // Memory blocks Buffer::new and Image::new suballocate from. Resources hold a clone,
+    // the last one alive frees the blocks, so anything still alive when the device is
+    // dropped outlives it.
+    pub fn allocator(&self) -> &Rc<RefCell<Allocator>> {
+        &self.allocator
+    }
+
+    // Blocks until all (`wait_all`) or any of `fences` are signaled, at most `timeout`
+    // (Duration::MAX waits forever). An empty slice is signaled right away. Timing out is
+    // a status, actual errors like a lost device are passed on.
+    pub fn wait_for_fences(
+        &self,
+        fences: &[vk::Fence],
+        wait_all: bool,
+        timeout: std::time::Duration,
+    ) -> Result<FenceStatus, Box<dyn std::error::Error>> {
+        if fences.is_empty() {
+            return Ok(FenceStatus::Signaled);
+        }
+        match unsafe {
+            self.device
+                .wait_for_fences(fences, wait_all, timeout_nanos(timeout))
+        } {
+            Ok(()) => Ok(FenceStatus::Signaled),
+            // Success code, but ash reports it as Err
+            Err(vk::Result::TIMEOUT) => Ok(FenceStatus::TimedOut),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    // The fences must not be used by a pending submission anymore
+    pub fn reset_fences(&self, fences: &[vk::Fence]) -> Result<(), Box<dyn std::error::Error>> {
+        if !fences.is_empty() {
+            unsafe { self.device.reset_fences(fences)? };
+        }
+        Ok(())
+    }
+
+    // Depth attachment format used by the main render pass and every DepthBuffer::new, so
+    // passes sharing depth buffers stay compatible. Shadow maps pick their own (sampled,
+    // never with stencil).
+    pub fn preferred_depth_format(&self) -> vk::Format {
+        self.depth_format
+    }
+
+    pub fn depth_format_has_stencil(&self) -> bool {
+        has_stencil_component(self.depth_format)
+    }
+
+    // First of `candidates` (in preference order) with all `features` for `tiling`
+    pub fn find_supported_format(
+        &self,
+        candidates: &[vk::Format],
+        tiling: vk::ImageTiling,
+        features: vk::FormatFeatureFlags,
+    ) -> Result<vk::Format, Box<dyn std::error::Error>> {
+        find_supported_format(candidates, tiling, features, |format| unsafe {
+            self.instance
+                .get_physical_device_format_properties(self.physical_device, format)
+        })
+    }
+}
+
+// Feature set matching `tiling` contains all of `features`
+fn tiling_supports(
+    properties: &vk::FormatProperties,
+    tiling: vk::ImageTiling,
+    features: vk::FormatFeatureFlags,
+) -> bool {
+    match tiling {
+        vk::ImageTiling::LINEAR => properties.linear_tiling_features.contains(features),
+        vk::ImageTiling::OPTIMAL => properties.optimal_tiling_features.contains(features),
+        // DRM format modifier tiling reports its features per modifier
+        _ => false,
+    }
+}
+
+// Properties come from `format_properties` so the selection doesn't depend on a live device
+fn find_supported_format(
+    candidates: &[vk::Format],
+    tiling: vk::ImageTiling,
+    features: vk::FormatFeatureFlags,
+    format_properties: impl Fn(vk::Format) -> vk::FormatProperties,
+) -> Result<vk::Format, Box<dyn std::error::Error>> {
+    candidates
+        .iter()
+        .copied()
+        .find(|&format| tiling_supports(&format_properties(format), tiling, features))
+        .ok_or_else(|| {
+            format!("None of {candidates:?} supports {features:?} with {tiling:?} tiling").into()
+        })
+}
 
-        Ok(Self {
-            surface,
-            loader,
-            window_handle,
-            display_handle,
+// Index of the first memory type allowed by `type_bits` that has all requested properties
+fn find_memory_type(
+    memory_properties: &vk::PhysicalDeviceMemoryProperties,
+    type_bits: u32,
+    properties: vk::MemoryPropertyFlags,
+) -> Result<u32, Box<dyn std::error::Error>> {
+    (0..memory_properties.memory_type_count)
+        .find(|&idx| {
+            type_bits & (1 << idx) != 0
+                && memory_properties.memory_types[idx as usize]
+                    .property_flags
+                    .contains(properties)
         })
-    }
+        .ok_or_else(|| format!("No memory type with {properties:?} found").into())
 }
 
-pub struct Device {
-    pub physical_device: vk::PhysicalDevice,
-    pub device: ash::Device,
+pub const DEFAULT_FRAMES_IN_FLIGHT: usize = 2;
 
-    pub graphics_queue_family_idx: u32,
-    pub graphics_queue: vk::Queue,
+pub const DEFAULT_RESIZE_DEBOUNCE: std::time::Duration = std::time::Duration::from_millis(50);
 
-    pub present_queue_family_idx: u32,
-    pub present_queue: vk::Queue,
+// Far longer than any frame takes, only reached when presentation is stuck
+pub const DEFAULT_ACQUIRE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+
+// Requests applied at swapchain creation, each clamped to what the surface supports
+#[derive(Debug, Clone, Copy)]
+pub struct SwapchainConfig {
+    // None keeps the driver minimum + 1. 3 gives triple buffering, which only helps when
+    // frames_in_flight lets the CPU run far enough ahead to fill the extra image.
+    pub desired_image_count: Option<u32>,
+    // Frames the CPU may record ahead of the GPU, clamped to 1..=image count per swapchain
+    // (see WindowContext::frames_in_flight)
+    pub frames_in_flight: usize,
+    // Picks a PRE_MULTIPLIED/POST_MULTIPLIED composite alpha when supported so the window
+    // can be see-through, the window itself must be created transparent too
+    pub prefer_transparent: bool,
+    // Extra uses of the swapchain images, e.g. TRANSFER_SRC for screenshots. COLOR_ATTACHMENT
+    // is always included, bits the surface doesn't support are dropped with a warning.
+    pub image_usage: vk::ImageUsageFlags,
+    // With separate graphics and present queue families, keep the images EXCLUSIVE and
+    // transfer ownership every frame instead of using CONCURRENT sharing. Usually faster,
+    // costs an extra submission on the present queue per frame.
+    pub exclusive_present_ownership: bool,
+    pub present_mode: PresentModePreference,
+    pub color_space_mode: ColorSpaceMode,
+    // Longest acquire_next_image blocks before reporting NotReady, so a hung compositor
+    // doesn't freeze the app. Applied without recreating the swapchain.
+    pub acquire_timeout: std::time::Duration,
+    // New swapchain images are UNDEFINED, the main pass handles that but commands touching
+    // an image before it (e.g. cmd_clear_color_image) make the validation layers complain.
+    // With this each image is moved to PRESENT_SRC_KHR at the start of the first frame it's
+    // acquired for. Images can't be used before they are acquired, so this can't happen
+    // right at creation.
+    pub initialize_image_layouts: bool,
+    // How long the window size has to stay the same before a resize recreates the
+    // swapchain, so dragging the window border doesn't recreate it every frame. Frames in
+    // between are stretched by the presentation engine. Zero recreates right away.
+    pub resize_debounce: std::time::Duration,
+    // With an sRGB format, also create a UNORM view of every image
+    // (Swapchain::unorm_image_views) aliasing the same memory. UI libraries usually output
+    // colors that are already sRGB encoded, rendering them through the sRGB view encodes
+    // them a second time. Needs VK_KHR_swapchain_mutable_format, skipped with a warning
+    // when it isn't available.
+    pub unorm_views: bool,
 }
 
-impl Drop for Device {
-    fn drop(&mut self) {
-        unsafe {
-            let _ = self.device.device_wait_idle();
-            self.device.destroy_device(None);
+impl Default for SwapchainConfig {
+    fn default() -> Self {
+        Self {
+            desired_image_count: None,
+            frames_in_flight: DEFAULT_FRAMES_IN_FLIGHT,
+            prefer_transparent: false,
+            image_usage: vk::ImageUsageFlags::COLOR_ATTACHMENT,
+            exclusive_present_ownership: false,
+            present_mode: PresentModePreference::Auto,
+            color_space_mode: ColorSpaceMode::HardwareSrgb,
+            acquire_timeout: DEFAULT_ACQUIRE_TIMEOUT,
+            initialize_image_layouts: false,
+            resize_debounce: DEFAULT_RESIZE_DEBOUNCE,
+            unorm_views: false,
         }
     }
 }
 
-impl Device {
-    pub fn new(instance: &Instance, surface: &Surface) -> Result<Self, Box<dyn std::error::Error>> {
-        let physical_devices = unsafe { instance.instance.enumerate_physical_devices()? };
-        if physical_devices.is_empty() {
-            return Err("No Vulkan physical devices found".into());
-        }
-
-        // Find a suitable device with graphics and present queues
-        let mut selected_device = None;
+// Who gamma encodes the final image. The display expects sRGB encoded values while
+// lighting math happens in linear space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSpaceMode {
+    // *_SRGB swapchain format, the hardware encodes on write. Shaders output linear color,
+    // encoding it again in the shader gives washed-out colors.
+    HardwareSrgb,
+    // *_UNORM swapchain format, written values reach the display as is. The last pass must
+    // apply the gamma curve (pow(c, 1.0 / 2.2) or the exact sRGB function), otherwise
+    // everything looks too dark. See Swapchain::needs_manual_gamma.
+    ManualGamma,
+}
 
-        for &pdevice in &physical_devices {
-            let queue_familie_properties = unsafe {
-                instance
-                    .instance
-                    .get_physical_device_queue_family_properties(pdevice)
-            };
+impl ColorSpaceMode {
+    fn formats(self) -> &'static [vk::Format] {
+        match self {
+            Self::HardwareSrgb => &[vk::Format::B8G8R8A8_SRGB, vk::Format::R8G8B8A8_SRGB],
+            Self::ManualGamma => &[vk::Format::B8G8R8A8_UNORM, vk::Format::R8G8B8A8_UNORM],
+        }
+    }
+}
 
-            let graphics_queue =
-                queue_familie_properties
-                    .iter()
-                    .enumerate()
-                    .find_map(|(idx, props)| {
-                        if props.queue_flags.contains(vk::QueueFlags::GRAPHICS) {
-                            Some(idx as u32)
-                        } else {
-                            None
-                        }
-                    });
-
-            let present_queue =
-                queue_familie_properties
-                    .iter()
-                    .enumerate()
-                    .find_map(|(idx, _props)| {
-                        let supports_present = unsafe {
-                            surface
-                                .loader
-                                .get_physical_device_surface_support(
-                                    pdevice,
-                                    idx as u32,
-                                    surface.surface,
-                                )
-                                .unwrap_or(false)
-                        };
-                        if supports_present {
-                            Some(idx as u32)
-                        } else {
-                            None
-                        }
-                    });
-
-            if let (Some(graphics), Some(present)) = (graphics_queue, present_queue) {
-                let props = unsafe { instance.instance.get_physical_device_properties(pdevice) };
-                let name = unsafe { CStr::from_ptr(props.device_name.as_ptr()) };
-                println!("Selected device: {:?}", name);
-
-                selected_device = Some((pdevice, graphics, present));
-                break;
-            }
-        }
-
-        let (physical_device, graphics_queue_family_idx, present_queue_family_idx) =
-            selected_device.ok_or("No suitable physical device found")?;
+// UNORM format with the same layout as an sRGB swapchain format, None for anything else
+pub fn srgb_to_unorm(format: vk::Format) -> Option<vk::Format> {
+    match format {
+        vk::Format::B8G8R8A8_SRGB => Some(vk::Format::B8G8R8A8_UNORM),
+        vk::Format::R8G8B8A8_SRGB => Some(vk::Format::R8G8B8A8_UNORM),
+        _ => None,
+    }
+}
 
-        // Create logical device
-        let queue_priorities = [1.0f32];
+// Present modes are tried in order, FIFO (always supported) is the final fallback.
+//   MAILBOX:      no tearing, the newest frame replaces a queued one. Lowest latency
+//                 without tearing, but renders frames that are never shown.
+//   FIFO_RELAXED: vsync while keeping up, a late frame is presented right away instead
+//                 of waiting a whole vblank, so misses tear briefly rather than stutter.
+//   FIFO:         strict vsync, a missed vblank repeats the previous frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PresentModePreference {
+    // VSync on integrated GPUs and CPU implementations (saves battery, MAILBOX keeps
+    // rendering frames nobody sees), LowLatency on everything else
+    Auto,
+    // MAILBOX, then FIFO_RELAXED
+    LowLatency,
+    // FIFO_RELAXED
+    AdaptiveVSync,
+    // FIFO only
+    VSync,
+}
 
-        // Create unique queue families
-        let mut unique_queue_families = vec![graphics_queue_family_idx];
-        if present_queue_family_idx != graphics_queue_family_idx {
-            unique_queue_families.push(present_queue_family_idx);
+impl PresentModePreference {
+    // Auto picked for `device_type`, every other preference is kept
+    pub fn resolve(self, device_type: vk::PhysicalDeviceType) -> Self {
+        if self != Self::Auto {
+            return self;
         }
+        match device_type {
+            vk::PhysicalDeviceType::INTEGRATED_GPU | vk::PhysicalDeviceType::CPU => Self::VSync,
+            _ => Self::LowLatency,
+        }
+    }
 
-        let queue_create_infos: Vec<_> = unique_queue_families
-            .iter()
-            .map(|&family_idx| {
-                vk::DeviceQueueCreateInfo::default()
-                    .queue_family_index(family_idx)
-                    .queue_priorities(&queue_priorities)
-            })
-            .collect();
-
-        let device_features = vk::PhysicalDeviceFeatures::default();
-
-        let device_create_info = vk::DeviceCreateInfo::default()
-            .queue_create_infos(&queue_create_infos)
-            .enabled_extension_names(DEVICE_EXTENSIONS)
-            .enabled_features(&device_features);
+    fn search_order(self) -> &'static [vk::PresentModeKHR] {
+        match self {
+            // Resolved before searching, see resolve
+            Self::Auto => &[],
+            Self::LowLatency => &[
+                vk::PresentModeKHR::MAILBOX,
+                vk::PresentModeKHR::FIFO_RELAXED,
+            ],
+            Self::AdaptiveVSync => &[vk::PresentModeKHR::FIFO_RELAXED],
+            Self::VSync => &[],
+        }
+    }
+}
 
-        let device = unsafe {
-            instance
-                .instance
-                .create_device(physical_device, &device_create_info, None)?
-        };
+// currentExtent when the windowing system defines it, otherwise `size` clamped to what the
+// surface supports
+fn choose_extent(
+    surface_capabilities: &vk::SurfaceCapabilitiesKHR,
+    size: winit::dpi::PhysicalSize<u32>,
+) -> vk::Extent2D {
+    if surface_capabilities.current_extent.width != u32::MAX {
+        return surface_capabilities.current_extent;
+    }
+    let extent = vk::Extent2D {
+        width: clamp_extent_axis(
+            "width",
+            size.width,
+            surface_capabilities.min_image_extent.width,
+            surface_capabilities.max_image_extent.width,
+        ),
+        height: clamp_extent_axis(
+            "height",
+            size.height,
+            surface_capabilities.min_image_extent.height,
+            surface_capabilities.max_image_extent.height,
+        ),
+    };
+    if extent.width != size.width || extent.height != size.height {
+        println!(
+            "Warning: Window size {}x{} clamped to supported swapchain extent {}x{}",
+            size.width, size.height, extent.width, extent.height
+        );
+    }
+    extent
+}
 
-        let graphics_queue = unsafe { device.get_device_queue(graphics_queue_family_idx, 0) };
-        let present_queue = unsafe { device.get_device_queue(present_queue_family_idx, 0) };
+// Drivers that misreport min > max get max alone, u32::clamp would panic on them
+fn clamp_extent_axis(axis: &str, requested: u32, min: u32, max: u32) -> u32 {
+    if min > max {
+        println!("Warning: Surface reports min image {axis} {min} above max {max}, ignoring min");
+        return requested.min(max);
+    }
+    requested.clamp(min, max)
+}
 
-        Ok(Self {
-            physical_device,
-            device,
+// The driver may create more images than requested, min_image_count is only a lower bound
+// and max_image_count 0 means there is no upper one
+fn clamp_image_count(desired: u32, min_image_count: u32, max_image_count: u32) -> u32 {
+    let max_image_count = if max_image_count > 0 {
+        max_image_count
+    } else {
+        u32::MAX
+    };
+    desired.clamp(min_image_count, max_image_count)
+}
 
-            graphics_queue_family_idx,
-            graphics_queue,
+// OPAQUE unless transparency is preferred, otherwise whatever the surface offers
+fn choose_composite_alpha(
+    supported: vk::CompositeAlphaFlagsKHR,
+    prefer_transparent: bool,
+) -> Result<vk::CompositeAlphaFlagsKHR, Box<dyn std::error::Error>> {
+    let transparent = [
+        vk::CompositeAlphaFlagsKHR::PRE_MULTIPLIED,
+        vk::CompositeAlphaFlagsKHR::POST_MULTIPLIED,
+    ];
+    let others = [
+        vk::CompositeAlphaFlagsKHR::OPAQUE,
+        vk::CompositeAlphaFlagsKHR::PRE_MULTIPLIED,
+        vk::CompositeAlphaFlagsKHR::POST_MULTIPLIED,
+        vk::CompositeAlphaFlagsKHR::INHERIT,
+    ];
 
-            present_queue_family_idx,
-            present_queue,
-        })
-    }
+    let preferred: &[_] = if prefer_transparent {
+        &transparent
+    } else {
+        &[]
+    };
+    preferred
+        .iter()
+        .chain(&others)
+        .copied()
+        .find(|&mode| supported.contains(mode))
+        .ok_or_else(|| format!("Surface supports no composite alpha mode ({supported:?})").into())
 }
 
 pub struct Swapchain {
@@ -310,15 +4319,43 @@ pub struct Swapchain {
     pub swapchain: vk::SwapchainKHR,
     pub images: Vec<vk::Image>,
     pub image_views: Vec<vk::ImageView>,
+    // Per image UNORM views, see SwapchainConfig::unorm_views. Empty without that option,
+    // a non-sRGB format or VK_KHR_swapchain_mutable_format.
+    pub unorm_image_views: Vec<vk::ImageView>,
     pub format: vk::SurfaceFormatKHR,
+    pub present_mode: vk::PresentModeKHR,
     pub extent: vk::Extent2D,
+    // Window size the swapchain was created for, `extent` differs when the surface fixes
+    // its own extent or the size had to be clamped to the supported range
+    pub requested_extent: vk::Extent2D,
+    // Actual number of images, may differ from SwapchainConfig::desired_image_count
+    pub image_count: u32,
+    pub composite_alpha: vk::CompositeAlphaFlagsKHR,
+    // Requested usage minus what the surface doesn't support
+    pub image_usage: vk::ImageUsageFlags,
+    // EXCLUSIVE with separate graphics/present families means ownership transfers
+    pub sharing_mode: vk::SharingMode,
+    // Rotation the presentation engine applies, rendering has to counter it with
+    // pre_transform_matrix. Always IDENTITY on desktop.
+    pub pre_transform: vk::SurfaceTransformFlagsKHR,
+    // Some when VK_EXT_full_screen_exclusive is enabled, the swapchain is then created
+    // application controlled and only goes exclusive between acquire/release calls
+    full_screen_exclusive: Option<ext::full_screen_exclusive::Device>,
+    // Some when Device::present_wait, presents then carry an id to wait on
+    present_wait: Option<khr::present_wait::Device>,
+    // In nanoseconds, see SwapchainConfig::acquire_timeout
+    pub acquire_timeout: u64,
+    // Per image, still waiting for its transition to PRESENT_SRC_KHR at the first acquire
+    // (see SwapchainConfig::initialize_image_layouts). All false without that option,
+    // unless Context::color_load_op is LOAD.
+    pub uninitialized_images: Vec<bool>,
     device: ash::Device, // Device is only 48 bytes wrapper (safe to clone if cleanup done correctly)
 }
 
 impl Drop for Swapchain {
     fn drop(&mut self) {
         unsafe {
-            for &view in &self.image_views {
+            for &view in self.image_views.iter().chain(&self.unorm_image_views) {
                 self.device.destroy_image_view(view, None);
             }
             self.loader.destroy_swapchain(self.swapchain, None);
@@ -327,25 +4364,74 @@ impl Drop for Swapchain {
 }
 
 impl Swapchain {
-    pub fn new(
-        instance: &Instance,
+    // Format a swapchain on `surface` gets right now: the color space mode's preferred
+    // formats first, then the other mode's (see needs_manual_gamma), then anything in
+    // SRGB_NONLINEAR. Each (re)creation queries it again, it can change with the monitor.
+    pub fn query_format(
         device: &Device,
         surface: &Surface,
-        window: &winit::window::Window,
-        old_swapchain: Option<vk::SwapchainKHR>,
-    ) -> Result<Self, Box<dyn std::error::Error>> {
-        let surface_capabilities = unsafe {
+        config: &SwapchainConfig,
+    ) -> Result<vk::SurfaceFormatKHR, Box<dyn std::error::Error>> {
+        let surface_formats = unsafe {
             surface
                 .loader
-                .get_physical_device_surface_capabilities(device.physical_device, surface.surface)?
+                .get_physical_device_surface_formats(device.physical_device, surface.surface)?
+        };
+        let srgb_nonlinear = |format: vk::Format| {
+            surface_formats
+                .iter()
+                .copied()
+                .find(|f| f.format == format && f.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR)
         };
 
-        // Query color formats supported by surface
+        let fallback_formats = [ColorSpaceMode::HardwareSrgb, ColorSpaceMode::ManualGamma]
+            .into_iter()
+            .flat_map(ColorSpaceMode::formats);
+        config
+            .color_space_mode
+            .formats()
+            .iter()
+            .chain(fallback_formats)
+            .find_map(|&format| srgb_nonlinear(format))
+            .or_else(|| {
+                surface_formats
+                    .iter()
+                    .copied()
+                    .find(|f| f.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR)
+            })
+            .ok_or_else(|| "Surface has no SRGB_NONLINEAR format".into())
+    }
+
+    // `format` in SRGB_NONLINEAR if `surface` supports it
+    pub fn find_format(
+        device: &Device,
+        surface: &Surface,
+        format: vk::Format,
+    ) -> Result<Option<vk::SurfaceFormatKHR>, Box<dyn std::error::Error>> {
         let surface_formats = unsafe {
             surface
                 .loader
                 .get_physical_device_surface_formats(device.physical_device, surface.surface)?
         };
+        Ok(surface_formats
+            .into_iter()
+            .find(|f| f.format == format && f.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR))
+    }
+
+    pub fn new(
+        instance: &Instance,
+        device: &Device,
+        surface: &Surface,
+        size: winit::dpi::PhysicalSize<u32>,
+        config: &SwapchainConfig,
+        format: vk::SurfaceFormatKHR,
+        old_swapchain: Option<vk::SwapchainKHR>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let surface_capabilities = unsafe {
+            surface
+                .loader
+                .get_physical_device_surface_capabilities(device.physical_device, surface.surface)?
+        };
 
         // Query supported presentation modes
         let present_modes = unsafe {
@@ -355,50 +4441,42 @@ impl Swapchain {
             )?
         };
 
-        // Choose surface format
-        let format = surface_formats
-            .iter()
-            .find(|&f| {
-                f.format == vk::Format::B8G8R8A8_SRGB
-                    && f.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
-            })
-            .copied()
-            .unwrap_or_else(|| panic!("Not supported format found"));
+        if !config.color_space_mode.formats().contains(&format.format) {
+            println!(
+                "Warning: No {:?} surface format available, falling back to {:?}",
+                config.color_space_mode, format.format
+            );
+        }
+
+        let mut preference = config.present_mode;
+        if preference == PresentModePreference::Auto {
+            let device_type = unsafe {
+                device
+                    .instance
+                    .get_physical_device_properties(device.physical_device)
+            }
+            .device_type;
+            preference = preference.resolve(device_type);
+            println!("Present mode preference Auto: {preference:?} for a {device_type:?} device");
+        }
 
-        // Choose present mode (prefer mailbox for lower latency)
-        let present_mode = present_modes
+        // First supported mode of the preference's search order
+        let present_mode = preference
+            .search_order()
             .iter()
-            .cloned()
-            .find(|&mode| mode == vk::PresentModeKHR::MAILBOX)
+            .copied()
+            .find(|mode| present_modes.contains(mode))
             // FIFO is guaranteed on all GPUs
             .unwrap_or(vk::PresentModeKHR::FIFO);
 
-        // Choose extent
-        let extent = if surface_capabilities.current_extent.width != u32::MAX {
-            // the extent is defined by windowing system
-            surface_capabilities.current_extent
-        } else {
-            // Get size from the winit
-            let size = window.inner_size();
-            // Create extent clamped to surface capabilities
-            vk::Extent2D {
-                width: size.width.clamp(
-                    surface_capabilities.min_image_extent.width,
-                    surface_capabilities.max_image_extent.width,
-                ),
-                height: size.height.clamp(
-                    surface_capabilities.min_image_extent.height,
-                    surface_capabilities.max_image_extent.height,
-                ),
-            }
-        };
+        let extent = choose_extent(&surface_capabilities, size);
 
-        let image_count = (surface_capabilities.min_image_count + 1).min(
-            if surface_capabilities.max_image_count > 0 {
-                surface_capabilities.max_image_count
-            } else {
-                u32::MAX
-            },
+        let min_image_count = clamp_image_count(
+            config
+                .desired_image_count
+                .unwrap_or(surface_capabilities.min_image_count + 1),
+            surface_capabilities.min_image_count,
+            surface_capabilities.max_image_count,
         );
 
         // let queue_family_indices = &[
@@ -406,30 +4484,57 @@ impl Swapchain {
         //     device.present_queue_family_idx,
         // ];
 
-        let (image_sharing_mode, queue_family_indices) =
-            if device.graphics_queue_family_idx == device.present_queue_family_idx {
-                (vk::SharingMode::EXCLUSIVE, vec![])
-            } else {
-                (
-                    vk::SharingMode::CONCURRENT,
-                    vec![
-                        device.graphics_queue_family_idx,
-                        device.present_queue_family_idx,
-                    ],
-                )
-            };
+        let (image_sharing_mode, queue_family_indices) = if device.graphics_queue_family_idx
+            == device.present_queue_family_idx
+            || config.exclusive_present_ownership
+        {
+            (vk::SharingMode::EXCLUSIVE, vec![])
+        } else {
+            (
+                vk::SharingMode::CONCURRENT,
+                vec![
+                    device.graphics_queue_family_idx,
+                    device.present_queue_family_idx,
+                ],
+            )
+        };
+
+        // Rendering into the images needs COLOR_ATTACHMENT, so that one has to be there
+        let requested_usage = config.image_usage | vk::ImageUsageFlags::COLOR_ATTACHMENT;
+        let supported_usage = surface_capabilities.supported_usage_flags;
+        if !supported_usage.contains(vk::ImageUsageFlags::COLOR_ATTACHMENT) {
+            return Err("Surface images can't be used as color attachments".into());
+        }
+        let image_usage = requested_usage & supported_usage;
+        if image_usage != requested_usage {
+            println!(
+                "Warning: Surface doesn't support swapchain usage {:?}, dropped",
+                requested_usage & !supported_usage
+            );
+        }
+
+        let composite_alpha = choose_composite_alpha(
+            surface_capabilities.supported_composite_alpha,
+            config.prefer_transparent,
+        )?;
+        if config.prefer_transparent && composite_alpha == vk::CompositeAlphaFlagsKHR::OPAQUE {
+            println!("Warning: Surface has no transparent composite alpha, using OPAQUE");
+        }
+
+        // Matching the current transform avoids a compositor rotation pass, we pre-rotate instead
+        let pre_transform = surface_capabilities.current_transform;
 
         let mut swapchain_create_info = vk::SwapchainCreateInfoKHR::default()
             .surface(surface.surface)
-            .min_image_count(image_count)
+            .min_image_count(min_image_count)
             .image_format(format.format)
             .image_color_space(format.color_space)
             .image_extent(extent)
             .image_array_layers(1)
-            .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
+            .image_usage(image_usage)
             .image_sharing_mode(image_sharing_mode)
-            .pre_transform(surface_capabilities.current_transform)
-            .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
+            .pre_transform(pre_transform)
+            .composite_alpha(composite_alpha)
             .present_mode(present_mode)
             .clipped(true);
 
@@ -442,41 +4547,100 @@ impl Swapchain {
             swapchain_create_info = swapchain_create_info.old_swapchain(old_swapchain);
         }
 
+        // Views of the other format are only allowed when it's listed at creation
+        let unorm_format = srgb_to_unorm(format.format).filter(|_| config.unorm_views);
+        if config.unorm_views && unorm_format.is_none() {
+            println!(
+                "Warning: {:?} is not an sRGB format, no UNORM views",
+                format.format
+            );
+        }
+        let unorm_format = unorm_format.filter(|_| {
+            let supported = device.is_extension_enabled(khr::swapchain_mutable_format::NAME);
+            if !supported {
+                println!("Warning: VK_KHR_swapchain_mutable_format not available, no UNORM views");
+            }
+            supported
+        });
+        let view_formats = [format.format, unorm_format.unwrap_or(format.format)];
+        let mut format_list = vk::ImageFormatListCreateInfo::default().view_formats(&view_formats);
+        if unorm_format.is_some() {
+            swapchain_create_info = swapchain_create_info
+                .flags(vk::SwapchainCreateFlagsKHR::MUTABLE_FORMAT)
+                .push_next(&mut format_list);
+        }
+
+        // Application controlled mode on a Win32 surface requires the monitor
+        #[cfg(target_os = "windows")]
+        let has_monitor = surface.hmonitor.is_some();
+        #[cfg(not(target_os = "windows"))]
+        let has_monitor = true;
+        let full_screen_exclusive = (device.is_extension_enabled(ext::full_screen_exclusive::NAME)
+            && has_monitor)
+            .then(|| ext::full_screen_exclusive::Device::new(&instance.instance, &device.device));
+
+        let mut full_screen_exclusive_info = vk::SurfaceFullScreenExclusiveInfoEXT::default()
+            .full_screen_exclusive(vk::FullScreenExclusiveEXT::APPLICATION_CONTROLLED);
+        #[cfg(target_os = "windows")]
+        let mut full_screen_exclusive_win32_info =
+            vk::SurfaceFullScreenExclusiveWin32InfoEXT::default()
+                .hmonitor(surface.hmonitor.unwrap_or_default());
+        if full_screen_exclusive.is_some() {
+            swapchain_create_info =
+                swapchain_create_info.push_next(&mut full_screen_exclusive_info);
+            #[cfg(target_os = "windows")]
+            {
+                swapchain_create_info =
+                    swapchain_create_info.push_next(&mut full_screen_exclusive_win32_info);
+            }
+        }
+
         let loader = khr::swapchain::Device::new(&instance.instance, &device.device);
         let swapchain = unsafe { loader.create_swapchain(&swapchain_create_info, None)? };
 
         let images = unsafe { loader.get_swapchain_images(swapchain)? };
 
-        let image_views: Vec<_> = images
-            .iter()
-            .map(|&image| {
-                let create_info = vk::ImageViewCreateInfo::default()
-                    .image(image)
-                    .view_type(vk::ImageViewType::TYPE_2D)
-                    .format(format.format)
-                    .components(vk::ComponentMapping {
-                        r: vk::ComponentSwizzle::IDENTITY,
-                        g: vk::ComponentSwizzle::IDENTITY,
-                        b: vk::ComponentSwizzle::IDENTITY,
-                        a: vk::ComponentSwizzle::IDENTITY,
-                    })
-                    .subresource_range(vk::ImageSubresourceRange {
-                        aspect_mask: vk::ImageAspectFlags::COLOR,
-                        base_mip_level: 0,
-                        level_count: 1,
-                        base_array_layer: 0,
-                        layer_count: 1,
-                    });
-
-                unsafe { device.device.create_image_view(&create_info, None) }
-            })
-            .collect::<Result<Vec<_>, _>>()?;
+        let create_views = |view_format: vk::Format| {
+            images
+                .iter()
+                .map(|&image| {
+                    let create_info = vk::ImageViewCreateInfo::default()
+                        .image(image)
+                        .view_type(vk::ImageViewType::TYPE_2D)
+                        .format(view_format)
+                        .components(vk::ComponentMapping {
+                            r: vk::ComponentSwizzle::IDENTITY,
+                            g: vk::ComponentSwizzle::IDENTITY,
+                            b: vk::ComponentSwizzle::IDENTITY,
+                            a: vk::ComponentSwizzle::IDENTITY,
+                        })
+                        .subresource_range(vk::ImageSubresourceRange {
+                            aspect_mask: aspect_flags_for_format(format.format),
+                            base_mip_level: 0,
+                            level_count: 1,
+                            base_array_layer: 0,
+                            layer_count: 1,
+                        });
+
+                    unsafe { device.device.create_image_view(&create_info, None) }
+                })
+                .collect::<Result<Vec<_>, _>>()
+        };
+        let image_views = create_views(format.format)?;
+        let unorm_image_views = match unorm_format.map(create_views).transpose() {
+            Ok(views) => views.unwrap_or_default(),
+            Err(err) => {
+                for &view in &image_views {
+                    unsafe { device.device.destroy_image_view(view, None) };
+                }
+                return Err(err.into());
+            }
+        };
 
+        let image_count = images.len() as u32;
         println!(
-            "Created swapchain: {}x{}, {} images",
-            extent.width,
-            extent.height,
-            images.len()
+            "Created swapchain: {}x{}, {image_count} images (requested {min_image_count})",
+            extent.width, extent.height
         );
 
         Ok(Self {
@@ -484,16 +4648,401 @@ impl Swapchain {
             swapchain,
             images,
             image_views,
+            unorm_image_views,
             format,
+            present_mode,
             extent,
+            requested_extent: vk::Extent2D {
+                width: size.width,
+                height: size.height,
+            },
+            image_count,
+            composite_alpha,
+            image_usage,
+            sharing_mode: image_sharing_mode,
+            pre_transform,
+            full_screen_exclusive,
+            present_wait: device
+                .present_wait
+                .then(|| khr::present_wait::Device::new(&instance.instance, &device.device)),
+            acquire_timeout: timeout_nanos(config.acquire_timeout),
+            uninitialized_images: vec![config.initialize_image_layouts; image_count as usize],
             device: device.device.clone(),
         })
     }
 
-    fn recreate(self, window: &winit::window::Window) -> Result<(), Box<dyn std::error::Error>> {
-        // Wait for all GPU operations to complete before destroying resources
-        unsafe { self.device.device_wait_idle() };
+    // Destroys the swapchain and its image views right away, for when its surface has to
+    // go (see Surface::recreate). The GPU must be done with them, framebuffers still
+    // referencing the views can only be destroyed afterwards. Recreate the swapchain before
+    // using it again, dropping it is a no-op.
+    pub fn release(&mut self) {
+        unsafe {
+            for view in self
+                .image_views
+                .drain(..)
+                .chain(self.unorm_image_views.drain(..))
+            {
+                self.device.destroy_image_view(view, None);
+            }
+            self.loader.destroy_swapchain(self.swapchain, None);
+        }
+        self.swapchain = vk::SwapchainKHR::null();
+        self.images.clear();
+        self.uninitialized_images.clear();
+    }
+
+    // After release, until a new swapchain replaces this one
+    pub fn is_released(&self) -> bool {
+        self.swapchain == vk::SwapchainKHR::null()
+    }
+
+    // The swapchain format doesn't gamma encode, the shaders have to
+    pub fn needs_manual_gamma(&self) -> bool {
+        !matches!(
+            self.format.format,
+            vk::Format::B8G8R8A8_SRGB | vk::Format::R8G8B8A8_SRGB
+        )
+    }
+
+    // Column major rotation around Z to pre-multiply into the projection (clip = M * P * V),
+    // so the image appears upright after the presentation engine rotates it. Identity for
+    // IDENTITY and any transform other than the plain rotations.
+    pub fn pre_transform_matrix(&self) -> [[f32; 4]; 4] {
+        // (cos, sin) of the rotation, exact to keep axis aligned geometry crisp
+        let (cos, sin) = match self.pre_transform {
+            vk::SurfaceTransformFlagsKHR::ROTATE_90 => (0.0, 1.0),
+            vk::SurfaceTransformFlagsKHR::ROTATE_180 => (-1.0, 0.0),
+            vk::SurfaceTransformFlagsKHR::ROTATE_270 => (0.0, -1.0),
+            _ => (1.0, 0.0),
+        };
+
+        [
+            [cos, sin, 0.0, 0.0],
+            [-sin, cos, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ]
+    }
+
+    pub fn is_rotated_90(&self) -> bool {
+        matches!(
+            self.pre_transform,
+            vk::SurfaceTransformFlagsKHR::ROTATE_90 | vk::SurfaceTransformFlagsKHR::ROTATE_270
+        )
+    }
+
+    // Extent as the user sees it, width and height swapped for 90/270 degree transforms.
+    // Use it for the projection's aspect ratio and for viewports laid out in the rotated
+    // orientation, the framebuffer itself keeps `extent`. Same as `extent` for IDENTITY.
+    pub fn rotated_extent(&self) -> vk::Extent2D {
+        if self.is_rotated_90() {
+            vk::Extent2D {
+                width: self.extent.height,
+                height: self.extent.width,
+            }
+        } else {
+            self.extent
+        }
+    }
+
+    // Returns false when VK_EXT_full_screen_exclusive isn't available, presentation then
+    // just continues normally
+    pub fn acquire_full_screen_exclusive(&self) -> Result<bool, Box<dyn std::error::Error>> {
+        let Some(loader) = &self.full_screen_exclusive else {
+            return Ok(false);
+        };
+        unsafe { loader.acquire_full_screen_exclusive_mode(self.swapchain)? };
+        Ok(true)
+    }
+
+    pub fn release_full_screen_exclusive(&self) -> Result<bool, Box<dyn std::error::Error>> {
+        let Some(loader) = &self.full_screen_exclusive else {
+            return Ok(false);
+        };
+        unsafe { loader.release_full_screen_exclusive_mode(self.swapchain)? };
+        Ok(true)
+    }
+
+    // Signals `semaphore` once the image is ready to be rendered to. With OutOfDate or
+    // NotReady no image was acquired, the index is meaningless and the semaphore stays
+    // unsignaled.
+    pub fn acquire_next_image(
+        &self,
+        semaphore: vk::Semaphore,
+    ) -> Result<(u32, SwapchainStatus), Box<dyn std::error::Error>> {
+        self.acquire_next_image_with_fence(semaphore, vk::Fence::null())
+    }
+
+    // Like acquire_next_image, `fence` (unsignaled) also signals once the presentation
+    // engine is done with the image. Waiting on it tells the CPU when the image actually
+    // became available, the time from acquire to signal is the acquisition latency, e.g.
+    // for frame pacing. Either handle may be null, not both. Results map the same way,
+    // without an acquired image the fence stays unsignaled too.
+    pub fn acquire_next_image_with_fence(
+        &self,
+        semaphore: vk::Semaphore,
+        fence: vk::Fence,
+    ) -> Result<(u32, SwapchainStatus), Box<dyn std::error::Error>> {
+        if semaphore == vk::Semaphore::null() && fence == vk::Fence::null() {
+            return Err("Acquiring an image needs a semaphore, a fence or both".into());
+        }
+
+        let result = unsafe {
+            self.loader
+                .acquire_next_image(self.swapchain, self.acquire_timeout, semaphore, fence)
+        };
+        match result {
+            Ok((image_index, suboptimal)) => Ok((image_index, SwapchainStatus::from(suboptimal))),
+            Err(err) => SwapchainStatus::from_error(err).map(|status| (0, status)),
+        }
+    }
+
+    // Queues `image_index` for presentation once `wait_semaphore` is signaled.
+    // `present_id` has to increase with every present, it's ignored without present wait.
+    pub fn present(
+        &self,
+        queue: vk::Queue,
+        image_index: u32,
+        wait_semaphore: vk::Semaphore,
+        present_id: u64,
+    ) -> Result<SwapchainStatus, Box<dyn std::error::Error>> {
+        let wait_semaphores = [wait_semaphore];
+        let swapchains = [self.swapchain];
+        let image_indices = [image_index];
+        let present_ids = [present_id];
+        let mut present_id_info = vk::PresentIdKHR::default().present_ids(&present_ids);
+        let mut present_info = vk::PresentInfoKHR::default()
+            .wait_semaphores(&wait_semaphores)
+            .swapchains(&swapchains)
+            .image_indices(&image_indices);
+        if self.present_wait.is_some() {
+            present_info = present_info.push_next(&mut present_id_info);
+        }
+
+        match unsafe { self.loader.queue_present(queue, &present_info) } {
+            Ok(suboptimal) => Ok(SwapchainStatus::from(suboptimal)),
+            Err(err) => SwapchainStatus::from_error(err),
+        }
+    }
+
+    pub fn supports_present_wait(&self) -> bool {
+        self.present_wait.is_some()
+    }
+
+    // Blocks until the present with `present_id` (or a later one) is visible, errors
+    // without present wait
+    pub fn wait_for_present(
+        &self,
+        present_id: u64,
+    ) -> Result<SwapchainStatus, Box<dyn std::error::Error>> {
+        let loader = self
+            .present_wait
+            .as_ref()
+            .ok_or("VK_KHR_present_wait is not enabled")?;
+        match unsafe { loader.wait_for_present(self.swapchain, present_id, u64::MAX) } {
+            Ok(()) => Ok(SwapchainStatus::Optimal),
+            Err(vk::Result::SUBOPTIMAL_KHR) => Ok(SwapchainStatus::Suboptimal),
+            Err(err) => SwapchainStatus::from_error(err),
+        }
+    }
+}
+
+// What acquire/present report about the swapchain, Suboptimal and OutOfDate mean the
+// caller should recreate it. Only OutOfDate forces it, Suboptimal images can still be
+// presented.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwapchainStatus {
+    Optimal,
+    // Still presentable, but no longer matches the surface (e.g. after a resize)
+    Suboptimal,
+    // Unusable, also reported when exclusive fullscreen was lost
+    OutOfDate,
+    // Acquire timed out, the swapchain is fine. Retry or skip the frame.
+    NotReady,
+}
+
+impl SwapchainStatus {
+    // Actual errors (device lost, out of memory, ...) are passed on
+    fn from_error(err: vk::Result) -> Result<Self, Box<dyn std::error::Error>> {
+        match err {
+            vk::Result::ERROR_OUT_OF_DATE_KHR
+            | vk::Result::ERROR_FULL_SCREEN_EXCLUSIVE_MODE_LOST_EXT => Ok(Self::OutOfDate),
+            // Success codes, but ash reports everything besides SUBOPTIMAL_KHR as Err
+            vk::Result::TIMEOUT | vk::Result::NOT_READY => Ok(Self::NotReady),
+            err => Err(err.into()),
+        }
+    }
+}
+
+impl From<bool> for SwapchainStatus {
+    // The suboptimal flag of a successful acquire/present
+    fn from(suboptimal: bool) -> Self {
+        if suboptimal {
+            Self::Suboptimal
+        } else {
+            Self::Optimal
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiling_supports_uses_the_features_of_the_requested_tiling() {
+        let properties = vk::FormatProperties {
+            linear_tiling_features: vk::FormatFeatureFlags::SAMPLED_IMAGE,
+            optimal_tiling_features: vk::FormatFeatureFlags::SAMPLED_IMAGE
+                | vk::FormatFeatureFlags::COLOR_ATTACHMENT,
+            ..Default::default()
+        };
+        let color =
+            vk::FormatFeatureFlags::SAMPLED_IMAGE | vk::FormatFeatureFlags::COLOR_ATTACHMENT;
 
-        todo!()
+        assert!(tiling_supports(
+            &properties,
+            vk::ImageTiling::LINEAR,
+            vk::FormatFeatureFlags::SAMPLED_IMAGE
+        ));
+        assert!(!tiling_supports(
+            &properties,
+            vk::ImageTiling::LINEAR,
+            color
+        ));
+        assert!(tiling_supports(
+            &properties,
+            vk::ImageTiling::OPTIMAL,
+            color
+        ));
+        assert!(!tiling_supports(
+            &properties,
+            vk::ImageTiling::DRM_FORMAT_MODIFIER_EXT,
+            vk::FormatFeatureFlags::SAMPLED_IMAGE
+        ));
+    }
+
+    #[test]
+    fn tiling_supports_needs_every_feature() {
+        let properties = vk::FormatProperties {
+            optimal_tiling_features: vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT,
+            ..Default::default()
+        };
+
+        assert!(!tiling_supports(
+            &properties,
+            vk::ImageTiling::OPTIMAL,
+            vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT
+                | vk::FormatFeatureFlags::SAMPLED_IMAGE
+        ));
+        assert!(!tiling_supports(
+            &properties,
+            vk::ImageTiling::LINEAR,
+            vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT
+        ));
+        // Nothing requested is always supported
+        assert!(tiling_supports(
+            &vk::FormatProperties::default(),
+            vk::ImageTiling::LINEAR,
+            vk::FormatFeatureFlags::empty()
+        ));
+    }
+
+    #[test]
+    fn find_supported_format_picks_the_first_supported_candidate() {
+        let properties = |format| vk::FormatProperties {
+            optimal_tiling_features: if format == vk::Format::D32_SFLOAT {
+                vk::FormatFeatureFlags::empty()
+            } else {
+                vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT
+            },
+            ..Default::default()
+        };
+        let candidates = [
+            vk::Format::D32_SFLOAT,
+            vk::Format::D32_SFLOAT_S8_UINT,
+            vk::Format::D24_UNORM_S8_UINT,
+        ];
+
+        let format = find_supported_format(
+            &candidates,
+            vk::ImageTiling::OPTIMAL,
+            vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT,
+            properties,
+        );
+        assert_eq!(format.unwrap(), vk::Format::D32_SFLOAT_S8_UINT);
+        assert!(
+            find_supported_format(
+                &candidates,
+                vk::ImageTiling::LINEAR,
+                vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT,
+                properties,
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn clamp_image_count_stays_within_the_surface_limits() {
+        assert_eq!(clamp_image_count(3, 2, 8), 3);
+        // Below min_image_count
+        assert_eq!(clamp_image_count(1, 2, 8), 2);
+        // Above max_image_count
+        assert_eq!(clamp_image_count(10, 2, 8), 8);
+        assert_eq!(clamp_image_count(3, 3, 3), 3);
+    }
+
+    #[test]
+    fn clamp_image_count_without_max_is_unbounded() {
+        assert_eq!(clamp_image_count(16, 2, 0), 16);
+        assert_eq!(clamp_image_count(u32::MAX, 2, 0), u32::MAX);
+        assert_eq!(clamp_image_count(1, 2, 0), 2);
+    }
+
+    #[test]
+    fn clamp_extent_axis_clamps_to_the_surface_range() {
+        assert_eq!(clamp_extent_axis("width", 800, 1, 4096), 800);
+        assert_eq!(clamp_extent_axis("width", 0, 1, 4096), 1);
+        assert_eq!(clamp_extent_axis("width", 5000, 1, 4096), 4096);
+        // min above max is ignored instead of panicking
+        assert_eq!(clamp_extent_axis("height", 800, 1000, 600), 600);
+        assert_eq!(clamp_extent_axis("height", 400, 1000, 600), 400);
+    }
+
+    #[test]
+    fn choose_extent_follows_the_window_size_only_without_current_extent() {
+        let size = winit::dpi::PhysicalSize::new(1280, 720);
+        let mut surface_capabilities = vk::SurfaceCapabilitiesKHR {
+            current_extent: vk::Extent2D {
+                width: 800,
+                height: 600,
+            },
+            min_image_extent: vk::Extent2D {
+                width: 1,
+                height: 1,
+            },
+            max_image_extent: vk::Extent2D {
+                width: 1024,
+                height: 1024,
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            choose_extent(&surface_capabilities, size),
+            surface_capabilities.current_extent
+        );
+
+        // u32::MAX leaves the extent to the swapchain
+        surface_capabilities.current_extent = vk::Extent2D {
+            width: u32::MAX,
+            height: u32::MAX,
+        };
+        assert_eq!(
+            choose_extent(&surface_capabilities, size),
+            vk::Extent2D {
+                width: 1024,
+                height: 720,
+            }
+        );
     }
 }
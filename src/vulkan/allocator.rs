@@ -0,0 +1,384 @@
+use ash::vk;
+
+use super::find_memory_type;
+
+pub const DEFAULT_BLOCK_SIZE: vk::DeviceSize = 64 * 1024 * 1024;
+
+// Linear resources (buffers, LINEAR images) and optimal-tiling images that share a block
+// must be bufferImageGranularity apart, so every allocation remembers which one it is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllocationKind {
+    Linear,
+    Optimal,
+}
+
+#[derive(Debug)]
+pub struct Allocation {
+    pub memory: vk::DeviceMemory,
+    pub offset: vk::DeviceSize,
+    pub size: vk::DeviceSize,
+    // Set when the block is HOST_VISIBLE, blocks stay mapped for their whole life since
+    // the same vk::DeviceMemory can't be mapped twice
+    pub mapped: Option<*mut u8>,
+    block_index: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AllocatorStats {
+    pub block_count: usize,
+    pub allocation_count: usize,
+    pub reserved_bytes: vk::DeviceSize,
+    pub used_bytes: vk::DeviceSize,
+    pub free_bytes: vk::DeviceSize,
+    pub free_ranges: usize,
+    pub largest_free_range: vk::DeviceSize,
+    // 0.0 when all free space is contiguous, approaching 1.0 as it splinters
+    pub fragmentation: f32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Range {
+    offset: vk::DeviceSize,
+    size: vk::DeviceSize,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Used {
+    offset: vk::DeviceSize,
+    size: vk::DeviceSize,
+    kind: AllocationKind,
+}
+
+struct MemoryBlock {
+    memory: vk::DeviceMemory,
+    memory_type_index: u32,
+    size: vk::DeviceSize,
+    mapped: Option<*mut u8>,
+    // Both sorted by offset
+    free: Vec<Range>,
+    used: Vec<Used>,
+}
+
+// Requests big vk::DeviceMemory blocks per memory type and hands out pieces of them, keeping
+// the number of vkAllocateMemory calls far below maxMemoryAllocationCount (often ~4096)
+pub struct Allocator {
+    // Freed blocks leave a None so block indices held by live allocations stay valid
+    blocks: Vec<Option<MemoryBlock>>,
+    block_size: vk::DeviceSize,
+    granularity: vk::DeviceSize,
+    max_allocation_count: u32,
+    memory_properties: vk::PhysicalDeviceMemoryProperties,
+    device: ash::Device,
+}
+
+impl Drop for Allocator {
+    fn drop(&mut self) {
+        unsafe {
+            for block in self.blocks.iter().flatten() {
+                self.device.free_memory(block.memory, None);
+            }
+        }
+    }
+}
+
+impl Allocator {
+    // Takes the pieces of a Device rather than the Device itself, every Device creates one
+    // for its buffers and images (see Device::allocator)
+    pub fn new(
+        device: &ash::Device,
+        limits: &vk::PhysicalDeviceLimits,
+        memory_properties: vk::PhysicalDeviceMemoryProperties,
+        block_size: vk::DeviceSize,
+    ) -> Self {
+        Self {
+            blocks: Vec::new(),
+            block_size,
+            granularity: limits.buffer_image_granularity.max(1),
+            max_allocation_count: limits.max_memory_allocation_count,
+            memory_properties,
+            device: device.clone(),
+        }
+    }
+
+    pub fn alloc(
+        &mut self,
+        requirements: vk::MemoryRequirements,
+        memory_flags: vk::MemoryPropertyFlags,
+        kind: AllocationKind,
+    ) -> Result<Allocation, Box<dyn std::error::Error>> {
+        let memory_type_index = find_memory_type(
+            &self.memory_properties,
+            requirements.memory_type_bits,
+            memory_flags,
+        )?;
+        let alignment = requirements.alignment.max(1);
+
+        // Try existing blocks of the same memory type first
+        for (block_index, block) in self.blocks.iter_mut().enumerate() {
+            let Some(block) = block else { continue };
+            if block.memory_type_index != memory_type_index {
+                continue;
+            }
+            if let Some(offset) =
+                block.find_offset(requirements.size, alignment, kind, self.granularity)
+            {
+                block.take(offset, requirements.size, kind);
+                return Ok(Allocation {
+                    memory: block.memory,
+                    offset,
+                    size: requirements.size,
+                    mapped: block.mapped.map(|ptr| unsafe { ptr.add(offset as usize) }),
+                    block_index,
+                });
+            }
+        }
+
+        // Oversized requests get a block of their own
+        let block_size = self.block_size.max(requirements.size);
+        let block = self.allocate_block(memory_type_index, block_size)?;
+
+        let block_index = match self.blocks.iter().position(Option::is_none) {
+            Some(idx) => idx,
+            None => {
+                self.blocks.push(None);
+                self.blocks.len() - 1
+            }
+        };
+        let block = self.blocks[block_index].insert(block);
+
+        block.take(0, requirements.size, kind);
+
+        Ok(Allocation {
+            memory: block.memory,
+            offset: 0,
+            size: requirements.size,
+            mapped: block.mapped,
+            block_index,
+        })
+    }
+
+    pub fn free(&mut self, allocation: Allocation) {
+        let Some(block) = self
+            .blocks
+            .get_mut(allocation.block_index)
+            .and_then(Option::as_mut)
+        else {
+            println!("Warning: Freeing allocation of an unknown memory block");
+            return;
+        };
+
+        block.release(allocation.offset);
+
+        // Give empty blocks back to the driver
+        if block.used.is_empty() {
+            let block = self.blocks[allocation.block_index].take().unwrap();
+            unsafe { self.device.free_memory(block.memory, None) };
+        }
+    }
+
+    pub fn stats(&self) -> AllocatorStats {
+        let mut stats = AllocatorStats::default();
+
+        for block in self.blocks.iter().flatten() {
+            stats.block_count += 1;
+            stats.allocation_count += block.used.len();
+            stats.reserved_bytes += block.size;
+            stats.used_bytes += block
+                .used
+                .iter()
+                .map(|used| used.size)
+                .sum::<vk::DeviceSize>();
+            stats.free_ranges += block.free.len();
+            for range in &block.free {
+                stats.free_bytes += range.size;
+                stats.largest_free_range = stats.largest_free_range.max(range.size);
+            }
+        }
+
+        if stats.free_bytes > 0 {
+            stats.fragmentation = 1.0 - stats.largest_free_range as f32 / stats.free_bytes as f32;
+        }
+
+        stats
+    }
+
+    // (reserved, used) bytes of the allocator's blocks per memory heap, indexed like
+    // VkPhysicalDeviceMemoryProperties::memoryHeaps
+    pub fn heap_usage(&self) -> Vec<(vk::DeviceSize, vk::DeviceSize)> {
+        let mut usage = vec![(0, 0); self.memory_properties.memory_heap_count as usize];
+        for block in self.blocks.iter().flatten() {
+            let heap_index = self.memory_properties.memory_types[block.memory_type_index as usize]
+                .heap_index as usize;
+            let (reserved, used) = &mut usage[heap_index];
+            *reserved += block.size;
+            *used += block
+                .used
+                .iter()
+                .map(|used| used.size)
+                .sum::<vk::DeviceSize>();
+        }
+        usage
+    }
+
+    fn allocate_block(
+        &self,
+        memory_type_index: u32,
+        size: vk::DeviceSize,
+    ) -> Result<MemoryBlock, Box<dyn std::error::Error>> {
+        let block_count = self.blocks.iter().flatten().count() as u32;
+        if block_count >= self.max_allocation_count {
+            return Err(format!(
+                "Allocator reached maxMemoryAllocationCount ({})",
+                self.max_allocation_count
+            )
+            .into());
+        }
+
+        let allocate_info = vk::MemoryAllocateInfo::default()
+            .allocation_size(size)
+            .memory_type_index(memory_type_index);
+        let memory = unsafe { self.device.allocate_memory(&allocate_info, None)? };
+
+        let host_visible = self.memory_properties.memory_types[memory_type_index as usize]
+            .property_flags
+            .contains(vk::MemoryPropertyFlags::HOST_VISIBLE);
+        let mapped = if host_visible {
+            match unsafe {
+                self.device
+                    .map_memory(memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty())
+            } {
+                Ok(ptr) => Some(ptr as *mut u8),
+                Err(err) => {
+                    unsafe { self.device.free_memory(memory, None) };
+                    return Err(err.into());
+                }
+            }
+        } else {
+            None
+        };
+
+        println!(
+            "Allocated memory block: {} MiB, memory type {memory_type_index}",
+            size / (1024 * 1024)
+        );
+
+        Ok(MemoryBlock {
+            memory,
+            memory_type_index,
+            size,
+            mapped,
+            free: vec![Range { offset: 0, size }],
+            used: Vec::new(),
+        })
+    }
+}
+
+impl MemoryBlock {
+    // First fit, returns the offset an allocation would be placed at
+    fn find_offset(
+        &self,
+        size: vk::DeviceSize,
+        alignment: vk::DeviceSize,
+        kind: AllocationKind,
+        granularity: vk::DeviceSize,
+    ) -> Option<vk::DeviceSize> {
+        self.free.iter().find_map(|range| {
+            let mut offset = range.offset.next_multiple_of(alignment);
+
+            // Resource of the other kind right before, keep off its last page
+            let previous = self
+                .used
+                .iter()
+                .rev()
+                .find(|used| used.offset + used.size <= range.offset);
+            if let Some(previous) = previous
+                && previous.kind != kind
+                && same_page(previous.offset + previous.size - 1, offset, granularity)
+            {
+                offset = offset
+                    .next_multiple_of(granularity)
+                    .next_multiple_of(alignment);
+            }
+
+            let end = offset.checked_add(size)?;
+            if end > range.offset + range.size {
+                return None;
+            }
+
+            // Resource of the other kind right after, it must not share our last page
+            let range_end = range.offset + range.size;
+            let next = self.used.iter().find(|used| used.offset >= range_end);
+            if let Some(next) = next
+                && next.kind != kind
+                && same_page(end - 1, next.offset, granularity)
+            {
+                return None;
+            }
+
+            Some(offset)
+        })
+    }
+
+    fn take(&mut self, offset: vk::DeviceSize, size: vk::DeviceSize, kind: AllocationKind) {
+        let idx = self
+            .free
+            .iter()
+            .position(|range| range.offset <= offset && offset + size <= range.offset + range.size)
+            .expect("Allocation outside of free ranges");
+        let range = self.free.remove(idx);
+
+        // Split what is left on both sides back into the free list (alignment padding included)
+        let after = Range {
+            offset: offset + size,
+            size: range.offset + range.size - (offset + size),
+        };
+        if after.size > 0 {
+            self.free.insert(idx, after);
+        }
+        let before = Range {
+            offset: range.offset,
+            size: offset - range.offset,
+        };
+        if before.size > 0 {
+            self.free.insert(idx, before);
+        }
+
+        let used_idx = self.used.partition_point(|used| used.offset < offset);
+        self.used.insert(used_idx, Used { offset, size, kind });
+    }
+
+    fn release(&mut self, offset: vk::DeviceSize) {
+        let Some(used_idx) = self.used.iter().position(|used| used.offset == offset) else {
+            println!("Warning: Double free of allocation at offset {offset}");
+            return;
+        };
+        let used = self.used.remove(used_idx);
+
+        let idx = self
+            .free
+            .partition_point(|range| range.offset < used.offset);
+        self.free.insert(
+            idx,
+            Range {
+                offset: used.offset,
+                size: used.size,
+            },
+        );
+
+        // Merge with neighbours
+        if idx + 1 < self.free.len()
+            && self.free[idx].offset + self.free[idx].size == self.free[idx + 1].offset
+        {
+            self.free[idx].size += self.free[idx + 1].size;
+            self.free.remove(idx + 1);
+        }
+        if idx > 0 && self.free[idx - 1].offset + self.free[idx - 1].size == self.free[idx].offset {
+            self.free[idx - 1].size += self.free[idx].size;
+            self.free.remove(idx);
+        }
+    }
+}
+
+fn same_page(a: vk::DeviceSize, b: vk::DeviceSize, page_size: vk::DeviceSize) -> bool {
+    a / page_size == b / page_size
+}
@@ -0,0 +1,163 @@
+use ash::vk;
+use std::time::Instant;
+
+use super::scene::{GRID_SIZE, SHADER_DIR};
+use super::{
+    AsyncCompute, Buffer, ComputePipeline, DescriptorSetLayoutBuilder, DescriptorWriter, Device,
+    InstanceData, VertexBuffer, read_spirv,
+};
+
+const WORKGROUP_SIZE: u32 = 64;
+
+// Push constants of shaders/animate.comp
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct AnimateParams {
+    time: f32,
+    grid_size: u32,
+}
+
+// Async compute demo: shaders/animate.comp rewrites the scene's instance transforms every
+// frame on the compute queue, the graphics queue draws from the result in the same frame.
+// One instance buffer per frame in flight, compute never writes a buffer graphics may
+// still be reading.
+pub struct InstanceAnimation {
+    pub pipeline: ComputePipeline,
+    // Storage buffers for compute, instance vertex buffers for graphics
+    pub instances: Vec<VertexBuffer>,
+    pub set_layout: vk::DescriptorSetLayout,
+    pub pool: vk::DescriptorPool,
+    pub sets: Vec<vk::DescriptorSet>,
+    start: Instant,
+    device: ash::Device,
+}
+
+impl Drop for InstanceAnimation {
+    fn drop(&mut self) {
+        unsafe {
+            // Destroying the pool frees the sets allocated from it
+            self.device.destroy_descriptor_pool(self.pool, None);
+            self.device
+                .destroy_descriptor_set_layout(self.set_layout, None);
+        }
+    }
+}
+
+impl InstanceAnimation {
+    pub fn new(
+        device: &Device,
+        frames_in_flight: usize,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let spv = read_spirv(format!("{SHADER_DIR}/animate.comp.spv"))?;
+
+        let instance_count = GRID_SIZE * GRID_SIZE;
+        let size =
+            (instance_count as usize * std::mem::size_of::<InstanceData>()) as vk::DeviceSize;
+        let instances = (0..frames_in_flight)
+            .map(|_| {
+                let buffer = Buffer::new(
+                    device,
+                    size,
+                    vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::VERTEX_BUFFER,
+                    vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                )?;
+                Ok(VertexBuffer::from_buffer(buffer, instance_count))
+            })
+            .collect::<Result<Vec<_>, Box<dyn std::error::Error>>>()?;
+
+        // Released into the animation once everything else was created
+        let set_layout = DescriptorSetLayoutBuilder::new()
+            .storage_buffer(0, vk::ShaderStageFlags::COMPUTE)
+            .build(device)?;
+
+        let push_constant_ranges = [vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .offset(0)
+            .size(std::mem::size_of::<AnimateParams>() as u32)];
+        let pipeline =
+            ComputePipeline::new(device, &spv, &[set_layout.handle()], &push_constant_ranges)?;
+
+        let pool_sizes = [vk::DescriptorPoolSize::default()
+            .ty(vk::DescriptorType::STORAGE_BUFFER)
+            .descriptor_count(frames_in_flight as u32)];
+        let pool_create_info = vk::DescriptorPoolCreateInfo::default()
+            .pool_sizes(&pool_sizes)
+            .max_sets(frames_in_flight as u32);
+        let pool = unsafe {
+            device
+                .device
+                .create_descriptor_pool(&pool_create_info, None)?
+        };
+        let set_layout = set_layout.into_raw();
+
+        // Dropping on error destroys the pool and layout
+        let mut animation = Self {
+            pipeline,
+            instances,
+            set_layout,
+            pool,
+            sets: Vec::new(),
+            start: Instant::now(),
+            device: device.device.clone(),
+        };
+
+        let set_layouts = vec![set_layout; frames_in_flight];
+        let allocate_info = vk::DescriptorSetAllocateInfo::default()
+            .descriptor_pool(pool)
+            .set_layouts(&set_layouts);
+        animation.sets = unsafe { device.device.allocate_descriptor_sets(&allocate_info)? };
+
+        for (&set, instances) in animation.sets.iter().zip(&animation.instances) {
+            DescriptorWriter::new(set)
+                .storage_buffer(0, &instances.buffer, 0, vk::WHOLE_SIZE)
+                .update(device)?;
+        }
+
+        Ok(animation)
+    }
+
+    // Records the frame's compute work into `compute`'s command buffer, submit it with
+    // Context::submit_compute (or WindowContext::submit_compute) afterwards
+    pub fn record(
+        &self,
+        compute: &AsyncCompute,
+        frame: usize,
+    ) -> Result<vk::CommandBuffer, Box<dyn std::error::Error>> {
+        let command_buffer = compute.begin(frame)?;
+        let params = AnimateParams {
+            time: self.start.elapsed().as_secs_f32(),
+            grid_size: GRID_SIZE,
+        };
+        let group_count = self.instances[frame].count.div_ceil(WORKGROUP_SIZE);
+
+        unsafe {
+            self.device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline.pipeline,
+            );
+            self.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline.layout,
+                0,
+                &[self.sets[frame]],
+                &[],
+            );
+            self.device.cmd_push_constants(
+                command_buffer,
+                self.pipeline.layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                super::buffer::as_bytes(std::slice::from_ref(&params)),
+            );
+            self.device.cmd_dispatch(command_buffer, group_count, 1, 1);
+        }
+
+        compute.cmd_release_buffer(command_buffer, self.instances[frame].buffer.buffer);
+
+        unsafe { self.device.end_command_buffer(command_buffer)? };
+
+        Ok(command_buffer)
+    }
+}
@@ -0,0 +1,346 @@
+use ash::vk;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use super::{Allocation, AllocationKind, Allocator, Device, submit_once};
+
+pub struct Buffer {
+    pub buffer: vk::Buffer,
+    pub memory: vk::DeviceMemory,
+    // Offset into `memory`, 0 for dedicated (SHADER_DEVICE_ADDRESS) buffers
+    pub memory_offset: vk::DeviceSize,
+    pub size: vk::DeviceSize,
+    pub usage: vk::BufferUsageFlags,
+    // None for dedicated buffers, which free their memory themselves
+    allocation: Option<(Allocation, Rc<RefCell<Allocator>>)>,
+    device: ash::Device,
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_buffer(self.buffer, None);
+        }
+        match self.allocation.take() {
+            Some((allocation, allocator)) => allocator.borrow_mut().free(allocation),
+            // Freeing implicitly unmaps persistently mapped memory
+            None => unsafe { self.device.free_memory(self.memory, None) },
+        }
+    }
+}
+
+impl Buffer {
+    // Buffer placed inside one of the device allocator's memory blocks. Usage with
+    // SHADER_DEVICE_ADDRESS gets its own vk::DeviceMemory instead, allocated with the
+    // DEVICE_ADDRESS flag the blocks don't have, see `device_address`.
+    pub fn new(
+        device: &Device,
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+        memory_flags: vk::MemoryPropertyFlags,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        if usage.contains(vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS) {
+            return Self::new_dedicated(device, size, usage, memory_flags);
+        }
+
+        let buffer = Self::create_buffer(device, size, usage)?;
+
+        let requirements = unsafe { device.device.get_buffer_memory_requirements(buffer) };
+
+        let allocator = device.allocator();
+        let allocation =
+            match allocator
+                .borrow_mut()
+                .alloc(requirements, memory_flags, AllocationKind::Linear)
+            {
+                Ok(allocation) => allocation,
+                Err(err) => {
+                    unsafe { device.device.destroy_buffer(buffer, None) };
+                    return Err(err);
+                }
+            };
+
+        if let Err(err) = unsafe {
+            device
+                .device
+                .bind_buffer_memory(buffer, allocation.memory, allocation.offset)
+        } {
+            unsafe { device.device.destroy_buffer(buffer, None) };
+            allocator.borrow_mut().free(allocation);
+            return Err(err.into());
+        }
+
+        Ok(Self {
+            buffer,
+            memory: allocation.memory,
+            memory_offset: allocation.offset,
+            size,
+            usage,
+            allocation: Some((allocation, Rc::clone(allocator))),
+            device: device.device.clone(),
+        })
+    }
+
+    fn new_dedicated(
+        device: &Device,
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+        memory_flags: vk::MemoryPropertyFlags,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let buffer = Self::create_buffer(device, size, usage)?;
+
+        let requirements = unsafe { device.device.get_buffer_memory_requirements(buffer) };
+
+        let memory = device
+            .find_memory_type(requirements.memory_type_bits, memory_flags)
+            .and_then(|memory_type_index| {
+                let mut flags_info = vk::MemoryAllocateFlagsInfo::default()
+                    .flags(vk::MemoryAllocateFlags::DEVICE_ADDRESS);
+                let allocate_info = vk::MemoryAllocateInfo::default()
+                    .allocation_size(requirements.size)
+                    .memory_type_index(memory_type_index)
+                    .push_next(&mut flags_info);
+                Ok(unsafe { device.device.allocate_memory(&allocate_info, None)? })
+            });
+
+        let memory = match memory {
+            Ok(memory) => memory,
+            Err(err) => {
+                unsafe { device.device.destroy_buffer(buffer, None) };
+                return Err(err);
+            }
+        };
+
+        if let Err(err) = unsafe { device.device.bind_buffer_memory(buffer, memory, 0) } {
+            unsafe {
+                device.device.destroy_buffer(buffer, None);
+                device.device.free_memory(memory, None);
+            }
+            return Err(err.into());
+        }
+
+        Ok(Self {
+            buffer,
+            memory,
+            memory_offset: 0,
+            size,
+            usage,
+            allocation: None,
+            device: device.device.clone(),
+        })
+    }
+
+    fn create_buffer(
+        device: &Device,
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+    ) -> Result<vk::Buffer, Box<dyn std::error::Error>> {
+        if usage.contains(vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS)
+            && !device.buffer_device_address
+        {
+            return Err("SHADER_DEVICE_ADDRESS usage needs the bufferDeviceAddress feature".into());
+        }
+
+        let create_info = vk::BufferCreateInfo::default()
+            .size(size)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+
+        Ok(unsafe { device.device.create_buffer(&create_info, None)? })
+    }
+
+    // GPU virtual address for buffer references in shaders (GL_EXT_buffer_reference), also
+    // what ray tracing structures are built from. Only for buffers created with
+    // SHADER_DEVICE_ADDRESS usage.
+    pub fn device_address(&self) -> Result<vk::DeviceAddress, Box<dyn std::error::Error>> {
+        if !self
+            .usage
+            .contains(vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS)
+        {
+            return Err("Buffer wasn't created with SHADER_DEVICE_ADDRESS usage".into());
+        }
+
+        let info = vk::BufferDeviceAddressInfo::default().buffer(self.buffer);
+        Ok(unsafe { self.device.get_buffer_device_address(&info) })
+    }
+
+    // Maps the whole buffer, memory has to be HOST_VISIBLE.
+    // Suballocated buffers are already mapped by their block, that pointer is returned.
+    pub fn map(&self) -> Result<*mut u8, Box<dyn std::error::Error>> {
+        if let Some((allocation, _)) = &self.allocation {
+            return allocation
+                .mapped
+                .ok_or_else(|| "Suballocated buffer memory is not HOST_VISIBLE".into());
+        }
+
+        let ptr = unsafe {
+            self.device
+                .map_memory(self.memory, 0, self.size, vk::MemoryMapFlags::empty())?
+        };
+
+        Ok(ptr as *mut u8)
+    }
+
+    pub fn unmap(&self) {
+        // Memory blocks stay mapped until the allocator frees them
+        if self.allocation.is_none() {
+            unsafe { self.device.unmap_memory(self.memory) };
+        }
+    }
+
+    // One-shot map + copy + unmap, memory has to be HOST_VISIBLE | HOST_COHERENT
+    pub fn write<T: Copy>(&self, data: &[T]) -> Result<(), Box<dyn std::error::Error>> {
+        let bytes = std::mem::size_of_val(data) as vk::DeviceSize;
+        if bytes > self.size {
+            return Err(format!("Write of {bytes} bytes exceeds buffer size {}", self.size).into());
+        }
+
+        let ptr = self.map()?;
+        unsafe {
+            std::ptr::copy_nonoverlapping(data.as_ptr() as *const u8, ptr, bytes as usize);
+        }
+        self.unmap();
+
+        Ok(())
+    }
+}
+
+// Creates a DEVICE_LOCAL buffer and fills it through a temporary staging buffer
+pub(super) fn upload_device_local(
+    device: &Device,
+    bytes: &[u8],
+    usage: vk::BufferUsageFlags,
+) -> Result<Buffer, Box<dyn std::error::Error>> {
+    let size = bytes.len().max(1) as vk::DeviceSize;
+
+    let staging = Buffer::new(
+        device,
+        size,
+        vk::BufferUsageFlags::TRANSFER_SRC,
+        vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+    )?;
+    staging.write(bytes)?;
+
+    let buffer = Buffer::new(
+        device,
+        size,
+        usage | vk::BufferUsageFlags::TRANSFER_DST,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+
+    submit_once(device, |cmd| unsafe {
+        let region = vk::BufferCopy::default().size(size);
+        device
+            .device
+            .cmd_copy_buffer(cmd, staging.buffer, buffer.buffer, &[region]);
+    })?;
+
+    Ok(buffer)
+}
+
+pub(super) fn as_bytes<T: Copy>(data: &[T]) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(data.as_ptr() as *const u8, std::mem::size_of_val(data)) }
+}
+
+// Vertex data (per vertex or per instance). Static buffers live in device local memory,
+// dynamic ones (see `new_dynamic`) stay mapped in host visible memory.
+pub struct VertexBuffer {
+    pub buffer: Buffer,
+    pub count: u32,
+    // Some for dynamic buffers
+    mapped: Option<*mut u8>,
+}
+
+impl VertexBuffer {
+    pub fn new<T: Copy>(device: &Device, data: &[T]) -> Result<Self, Box<dyn std::error::Error>> {
+        let buffer =
+            upload_device_local(device, as_bytes(data), vk::BufferUsageFlags::VERTEX_BUFFER)?;
+
+        Ok(Self::from_buffer(buffer, data.len() as u32))
+    }
+
+    // Wraps a buffer filled some other way, e.g. by a compute shader
+    pub fn from_buffer(buffer: Buffer, count: u32) -> Self {
+        Self {
+            buffer,
+            count,
+            mapped: None,
+        }
+    }
+
+    // Room for `capacity` elements of T in HOST_VISIBLE | HOST_COHERENT memory, mapped for
+    // the buffer's whole life. For geometry rewritten every frame (UI, immediate mode),
+    // small enough that reading it over the bus beats a staging copy. Starts out empty.
+    //
+    // `update` writes while the GPU may still read the buffer from an earlier frame, so
+    // create one per frame in flight and only update the current frame's once its fence
+    // has signaled. Coherent memory needs no flush, the queue submit makes the writes
+    // visible.
+    pub fn new_dynamic<T: Copy>(
+        device: &Device,
+        capacity: u32,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let size = (capacity.max(1) as usize * std::mem::size_of::<T>()) as vk::DeviceSize;
+        let buffer = Buffer::new(
+            device,
+            size,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+        let mapped = buffer.map()?;
+
+        Ok(Self {
+            buffer,
+            count: 0,
+            mapped: Some(mapped),
+        })
+    }
+
+    // Replaces the contents of a dynamic buffer and sets `count`, draws use `count`
+    // elements afterwards. Data past the capacity is rejected rather than reallocating,
+    // a new buffer could still be referenced by recorded command buffers.
+    pub fn update<T: Copy>(&mut self, data: &[T]) -> Result<(), Box<dyn std::error::Error>> {
+        let ptr = self
+            .mapped
+            .ok_or("update needs a dynamic vertex buffer, see VertexBuffer::new_dynamic")?;
+
+        let bytes = std::mem::size_of_val(data) as vk::DeviceSize;
+        if bytes > self.buffer.size {
+            return Err(format!(
+                "Vertex update of {bytes} bytes exceeds capacity of {} bytes",
+                self.buffer.size
+            )
+            .into());
+        }
+
+        unsafe {
+            std::ptr::copy_nonoverlapping(data.as_ptr() as *const u8, ptr, bytes as usize);
+        }
+        self.count = data.len() as u32;
+
+        Ok(())
+    }
+}
+
+// u32 indices in device local memory
+pub struct IndexBuffer {
+    pub buffer: Buffer,
+    pub count: u32,
+}
+
+impl IndexBuffer {
+    pub const INDEX_TYPE: vk::IndexType = vk::IndexType::UINT32;
+
+    pub fn new(device: &Device, indices: &[u32]) -> Result<Self, Box<dyn std::error::Error>> {
+        let buffer = upload_device_local(
+            device,
+            as_bytes(indices),
+            vk::BufferUsageFlags::INDEX_BUFFER,
+        )?;
+
+        Ok(Self {
+            buffer,
+            count: indices.len() as u32,
+        })
+    }
+}
@@ -0,0 +1,218 @@
+use ash::vk;
+
+// Matrices are column major ([column][row]) like GLSL's mat4, so they upload as is.
+// World space is right handed with Y up, the projection maps it to Vulkan's clip space
+// (Y down, depth 0..1).
+//
+// Vulkan's Y points down in framebuffer space where OpenGL's points up, there are two
+// ways to get an upright image out of Y up math:
+//   - Negate Y in the projection (perspective/orthographic do). Works everywhere, but
+//     the matrix differs from what OpenGL style code expects.
+//   - Keep the projection and flip the viewport instead: y = height, height = -height.
+//     Needs Vulkan 1.1 or VK_KHR_maintenance1, see Device::flip_viewport_y.
+// Both give the same framebuffer contents, so the winding (and front_face) is the same.
+// The main pass uses the viewport flip when available, undoing the projection's flip
+// with clip_correction. Offscreen passes like the shadow map keep the projection flip.
+
+pub const IDENTITY: [[f32; 4]; 4] = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+// Layout matches the std140 block in mesh.vert, mat4 columns need no padding
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct UniformBufferObject {
+    pub model: [[f32; 4]; 4],
+    pub view: [[f32; 4]; 4],
+    pub proj: [[f32; 4]; 4],
+}
+
+impl Default for UniformBufferObject {
+    fn default() -> Self {
+        Self {
+            model: IDENTITY,
+            view: IDENTITY,
+            proj: IDENTITY,
+        }
+    }
+}
+
+pub struct Camera {
+    pub position: [f32; 3],
+    pub target: [f32; 3],
+    pub up: [f32; 3],
+    // Vertical field of view in radians
+    pub fov_y: f32,
+    pub aspect: f32,
+    pub near: f32,
+    pub far: f32,
+}
+
+impl Default for Camera {
+    // Looks down -Z at the origin, the demo grid (-1..1 on X and Y) fits the view
+    fn default() -> Self {
+        Self {
+            position: [0.0, 0.0, 2.5],
+            target: [0.0, 0.0, 0.0],
+            up: [0.0, 1.0, 0.0],
+            fov_y: 60f32.to_radians(),
+            aspect: 1.0,
+            near: 0.1,
+            far: 100.0,
+        }
+    }
+}
+
+impl Camera {
+    // Pass Swapchain::rotated_extent so rotated surfaces keep the aspect the user sees.
+    // A 0 sized extent (minimized) keeps the last aspect.
+    pub fn set_aspect_from_extent(&mut self, extent: vk::Extent2D) {
+        if extent.width > 0 && extent.height > 0 {
+            self.aspect = extent.width as f32 / extent.height as f32;
+        }
+    }
+
+    pub fn view(&self) -> [[f32; 4]; 4] {
+        look_at(self.position, self.target, self.up)
+    }
+
+    pub fn projection(&self) -> [[f32; 4]; 4] {
+        perspective(self.fov_y, self.aspect, self.near, self.far)
+    }
+
+    // `pre_transform` is Swapchain::pre_transform_matrix, IDENTITY when there is none
+    pub fn uniform(
+        &self,
+        model: [[f32; 4]; 4],
+        pre_transform: [[f32; 4]; 4],
+    ) -> UniformBufferObject {
+        UniformBufferObject {
+            model,
+            view: self.view(),
+            proj: mat4_mul(pre_transform, self.projection()),
+        }
+    }
+}
+
+// Right handed view matrix, the camera looks down its local -Z
+pub fn look_at(eye: [f32; 3], target: [f32; 3], up: [f32; 3]) -> [[f32; 4]; 4] {
+    let forward = normalize(sub(target, eye));
+    let side = normalize(cross(forward, up));
+    let up = cross(side, forward);
+
+    [
+        [side[0], up[0], -forward[0], 0.0],
+        [side[1], up[1], -forward[1], 0.0],
+        [side[2], up[2], -forward[2], 0.0],
+        [-dot(side, eye), -dot(up, eye), dot(forward, eye), 1.0],
+    ]
+}
+
+// Right handed perspective for Vulkan: Y is negated because clip space Y points down,
+// and depth maps near..far to 0..1 instead of OpenGL's -1..1. Flipping Y in the
+// projection mirrors the winding, counter-clockwise triangles in world space end up
+// clockwise in framebuffer space.
+pub fn perspective(fov_y: f32, aspect: f32, near: f32, far: f32) -> [[f32; 4]; 4] {
+    let focal = 1.0 / (fov_y / 2.0).tan();
+
+    [
+        [focal / aspect, 0.0, 0.0, 0.0],
+        [0.0, -focal, 0.0, 0.0],
+        [0.0, 0.0, far / (near - far), -1.0],
+        [0.0, 0.0, near * far / (near - far), 0.0],
+    ]
+}
+
+// Right handed orthographic projection for Vulkan, same Y flip and 0..1 depth as
+// `perspective`. Used for directional light shadow maps.
+pub fn orthographic(
+    left: f32,
+    right: f32,
+    bottom: f32,
+    top: f32,
+    near: f32,
+    far: f32,
+) -> [[f32; 4]; 4] {
+    [
+        [2.0 / (right - left), 0.0, 0.0, 0.0],
+        [0.0, -2.0 / (top - bottom), 0.0, 0.0],
+        [0.0, 0.0, 1.0 / (near - far), 0.0],
+        [
+            -(right + left) / (right - left),
+            (top + bottom) / (top - bottom),
+            near / (near - far),
+            1.0,
+        ],
+    ]
+}
+
+// Negates clip space Y
+pub const Y_FLIP: [[f32; 4]; 4] = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, -1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+// Pre-multiplied into a perspective/orthographic projection. With `flip_viewport_y` the
+// viewport flips Y again after everything in clip space, so the projection's flip is
+// cancelled last: the rotation happens as if the viewport weren't flipped.
+pub fn clip_correction(pre_transform: [[f32; 4]; 4], flip_viewport_y: bool) -> [[f32; 4]; 4] {
+    if flip_viewport_y {
+        mat4_mul(Y_FLIP, pre_transform)
+    } else {
+        pre_transform
+    }
+}
+
+// Covers `extent` with 0..1 depth, upside down (negative height) with `flip_y`
+pub fn viewport(extent: vk::Extent2D, flip_y: bool) -> vk::Viewport {
+    let (y, height) = if flip_y {
+        (extent.height as f32, -(extent.height as f32))
+    } else {
+        (0.0, extent.height as f32)
+    };
+    vk::Viewport {
+        x: 0.0,
+        y,
+        width: extent.width as f32,
+        height,
+        min_depth: 0.0,
+        max_depth: 1.0,
+    }
+}
+
+// a * b, so b is applied first
+pub fn mat4_mul(a: [[f32; 4]; 4], b: [[f32; 4]; 4]) -> [[f32; 4]; 4] {
+    let mut result = [[0.0; 4]; 4];
+    for (column, result_column) in result.iter_mut().enumerate() {
+        for (row, value) in result_column.iter_mut().enumerate() {
+            *value = (0..4).map(|k| a[k][row] * b[column][k]).sum();
+        }
+    }
+    result
+}
+
+fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn normalize(v: [f32; 3]) -> [f32; 3] {
+    let length = dot(v, v).sqrt();
+    [v[0] / length, v[1] / length, v[2] / length]
+}
@@ -0,0 +1,118 @@
+use ash::vk;
+
+use super::Device;
+
+pub struct CommandPool {
+    pub pool: vk::CommandPool,
+    pub buffers: Vec<vk::CommandBuffer>,
+    device: ash::Device,
+}
+
+impl Drop for CommandPool {
+    fn drop(&mut self) {
+        unsafe {
+            // Command buffers are freed together with the pool
+            self.device.destroy_command_pool(self.pool, None);
+        }
+    }
+}
+
+impl CommandPool {
+    // Creates a pool on the given queue family with `count` primary command buffers
+    // that can be reset individually (re-recorded every frame)
+    pub fn new(
+        device: &Device,
+        queue_family_idx: u32,
+        count: u32,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let create_info = vk::CommandPoolCreateInfo::default()
+            .queue_family_index(queue_family_idx)
+            .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER);
+
+        let pool = unsafe { device.device.create_command_pool(&create_info, None)? };
+
+        let allocate_info = vk::CommandBufferAllocateInfo::default()
+            .command_pool(pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(count);
+
+        let buffers = match unsafe { device.device.allocate_command_buffers(&allocate_info) } {
+            Ok(buffers) => buffers,
+            Err(err) => {
+                unsafe { device.device.destroy_command_pool(pool, None) };
+                return Err(err.into());
+            }
+        };
+
+        Ok(Self {
+            pool,
+            buffers,
+            device: device.device.clone(),
+        })
+    }
+}
+
+// Records and submits a one-off command buffer on the graphics queue and blocks until
+// it finished executing, meant for uploads and layout transitions at load time
+pub fn submit_once<F: FnOnce(vk::CommandBuffer)>(
+    device: &Device,
+    record: F,
+) -> Result<(), Box<dyn std::error::Error>> {
+    submit_once_on(
+        device,
+        device.graphics_queue_family_idx,
+        device.graphics_queue,
+        record,
+    )
+}
+
+// submit_once on any queue, `queue` has to belong to `queue_family_idx`
+pub fn submit_once_on<F: FnOnce(vk::CommandBuffer)>(
+    device: &Device,
+    queue_family_idx: u32,
+    queue: vk::Queue,
+    record: F,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let create_info = vk::CommandPoolCreateInfo::default()
+        .queue_family_index(queue_family_idx)
+        .flags(vk::CommandPoolCreateFlags::TRANSIENT);
+
+    let pool = unsafe { device.device.create_command_pool(&create_info, None)? };
+
+    let result = (|| -> Result<(), Box<dyn std::error::Error>> {
+        let allocate_info = vk::CommandBufferAllocateInfo::default()
+            .command_pool(pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(1);
+        let command_buffer = unsafe { device.device.allocate_command_buffers(&allocate_info)? }[0];
+
+        let begin_info = vk::CommandBufferBeginInfo::default()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        unsafe {
+            device
+                .device
+                .begin_command_buffer(command_buffer, &begin_info)?
+        };
+
+        record(command_buffer);
+
+        unsafe { device.device.end_command_buffer(command_buffer)? };
+
+        let command_buffers = [command_buffer];
+        let submit_info = vk::SubmitInfo::default().command_buffers(&command_buffers);
+
+        // Waits for this submission only, frames in flight on the same queue keep going
+        let fence = device.fence_pool().borrow_mut().acquire()?;
+        let submitted = unsafe { device.device.queue_submit(queue, &[submit_info], fence) }
+            .map_err(Into::into)
+            .and_then(|_| device.wait_for_fences(&[fence], true, std::time::Duration::MAX));
+        device.fence_pool().borrow_mut().release(fence)?;
+        submitted?;
+
+        Ok(())
+    })();
+
+    unsafe { device.device.destroy_command_pool(pool, None) };
+
+    result
+}
@@ -0,0 +1,199 @@
+use ash::vk;
+
+use super::{CommandPool, Device, OwnedPipelineLayout, OwnedShaderModule, create_shader_module};
+
+pub struct ComputePipeline {
+    pub pipeline: vk::Pipeline,
+    pub layout: vk::PipelineLayout,
+    device: ash::Device,
+}
+
+impl Drop for ComputePipeline {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_pipeline(self.pipeline, None);
+            self.device.destroy_pipeline_layout(self.layout, None);
+        }
+    }
+}
+
+impl ComputePipeline {
+    // Entry point is "main"
+    pub fn new(
+        device: &Device,
+        spv: &[u32],
+        set_layouts: &[vk::DescriptorSetLayout],
+        push_constant_ranges: &[vk::PushConstantRange],
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let layout_create_info = vk::PipelineLayoutCreateInfo::default()
+            .set_layouts(set_layouts)
+            .push_constant_ranges(push_constant_ranges);
+        // Destroyed on every error path, released into the pipeline on success
+        let layout = OwnedPipelineLayout::pipeline_layout(&device.device, unsafe {
+            device
+                .device
+                .create_pipeline_layout(&layout_create_info, None)?
+        });
+
+        // Modules are only needed during pipeline creation
+        let module =
+            OwnedShaderModule::shader_module(&device.device, create_shader_module(device, spv)?);
+
+        let stage = vk::PipelineShaderStageCreateInfo::default()
+            .stage(vk::ShaderStageFlags::COMPUTE)
+            .module(module.handle())
+            .name(c"main");
+        let create_info = vk::ComputePipelineCreateInfo::default()
+            .stage(stage)
+            .layout(layout.handle());
+
+        let pipelines = unsafe {
+            device
+                .device
+                .create_compute_pipelines(vk::PipelineCache::null(), &[create_info], None)
+        }
+        .map_err(|(_, err)| err)?;
+
+        Ok(Self {
+            pipeline: pipelines[0],
+            layout: layout.into_raw(),
+            device: device.device.clone(),
+        })
+    }
+}
+
+// Per frame in flight command buffers on the compute queue, plus the semaphores the
+// graphics submission waits on (see Context::submit_compute). With a dedicated compute
+// family, resources written here change queue family ownership: release them with
+// `cmd_release_buffer` at the end of the compute work, acquire them with
+// `cmd_acquire_buffer` in the graphics command buffer. Nothing is transferred back,
+// compute overwrites the contents anyway and may discard them.
+pub struct AsyncCompute {
+    pub command_pool: CommandPool,
+    // Signaled by the frame's compute submission
+    pub finished: Vec<vk::Semaphore>,
+    pub compute_queue_family_idx: u32,
+    pub graphics_queue_family_idx: u32,
+    device: ash::Device,
+}
+
+impl Drop for AsyncCompute {
+    fn drop(&mut self) {
+        unsafe {
+            for &semaphore in &self.finished {
+                self.device.destroy_semaphore(semaphore, None);
+            }
+        }
+    }
+}
+
+impl AsyncCompute {
+    pub fn new(
+        device: &Device,
+        frames_in_flight: usize,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let command_pool = CommandPool::new(
+            device,
+            device.compute_queue_family_idx,
+            frames_in_flight as u32,
+        )?;
+
+        // Dropping on error destroys whatever was already created
+        let mut compute = Self {
+            command_pool,
+            finished: Vec::with_capacity(frames_in_flight),
+            compute_queue_family_idx: device.compute_queue_family_idx,
+            graphics_queue_family_idx: device.graphics_queue_family_idx,
+            device: device.device.clone(),
+        };
+
+        let semaphore_info = vk::SemaphoreCreateInfo::default();
+        for _ in 0..frames_in_flight {
+            let semaphore = unsafe { device.device.create_semaphore(&semaphore_info, None)? };
+            compute.finished.push(semaphore);
+        }
+
+        Ok(compute)
+    }
+
+    pub fn needs_ownership_transfer(&self) -> bool {
+        self.compute_queue_family_idx != self.graphics_queue_family_idx
+    }
+
+    // Resets and begins the frame's command buffer, only call once the frame's fence
+    // has signaled
+    pub fn begin(&self, frame: usize) -> Result<vk::CommandBuffer, Box<dyn std::error::Error>> {
+        let command_buffer = self.command_pool.buffers[frame];
+        unsafe {
+            self.device
+                .reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())?;
+            self.device
+                .begin_command_buffer(command_buffer, &vk::CommandBufferBeginInfo::default())?;
+        }
+        Ok(command_buffer)
+    }
+
+    // Release half, after the compute shader wrote `buffer`. No-op without a dedicated
+    // compute family, the semaphore alone makes the writes visible then.
+    pub fn cmd_release_buffer(&self, command_buffer: vk::CommandBuffer, buffer: vk::Buffer) {
+        if !self.needs_ownership_transfer() {
+            return;
+        }
+
+        let barrier = self
+            .buffer_barrier(buffer)
+            .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .dst_access_mask(vk::AccessFlags::empty());
+        unsafe {
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[barrier],
+                &[],
+            );
+        }
+    }
+
+    // Acquire half, recorded in the graphics command buffer before `buffer` is read at
+    // `dst_stage` with `dst_access`
+    pub fn cmd_acquire_buffer(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        buffer: vk::Buffer,
+        dst_stage: vk::PipelineStageFlags,
+        dst_access: vk::AccessFlags,
+    ) {
+        if !self.needs_ownership_transfer() {
+            return;
+        }
+
+        let barrier = self
+            .buffer_barrier(buffer)
+            .src_access_mask(vk::AccessFlags::empty())
+            .dst_access_mask(dst_access);
+        unsafe {
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                dst_stage,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[barrier],
+                &[],
+            );
+        }
+    }
+
+    // Release and acquire must use identical families and range
+    fn buffer_barrier(&self, buffer: vk::Buffer) -> vk::BufferMemoryBarrier<'static> {
+        vk::BufferMemoryBarrier::default()
+            .src_queue_family_index(self.compute_queue_family_idx)
+            .dst_queue_family_index(self.graphics_queue_family_idx)
+            .buffer(buffer)
+            .offset(0)
+            .size(vk::WHOLE_SIZE)
+    }
+}
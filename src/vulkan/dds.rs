@@ -0,0 +1,175 @@
+use ash::vk;
+
+// Minimal DDS reader for block-compressed 2D textures (no cubemaps, arrays or volumes)
+//
+// Layout: "DDS " magic, 124 byte DDS_HEADER, optional 20 byte DDS_HEADER_DXT10 when the
+// pixel format fourCC is "DX10", then every mip level from largest to smallest.
+const MAGIC: &[u8; 4] = b"DDS ";
+const HEADER_SIZE: usize = 124;
+const DX10_HEADER_SIZE: usize = 20;
+
+const DDPF_FOURCC: u32 = 0x4;
+
+pub struct DdsImage<'a> {
+    pub format: vk::Format,
+    pub width: u32,
+    pub height: u32,
+    pub mip_levels: u32,
+    // Bytes per 4x4 block
+    pub block_bytes: u32,
+    // Every mip level back to back
+    pub data: &'a [u8],
+}
+
+impl<'a> DdsImage<'a> {
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        if bytes.len() < 4 + HEADER_SIZE || &bytes[0..4] != MAGIC {
+            return Err("Not a DDS file".into());
+        }
+
+        let header = &bytes[4..4 + HEADER_SIZE];
+        let read_u32 = |data: &[u8], offset: usize| {
+            u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+        };
+
+        if read_u32(header, 0) as usize != HEADER_SIZE {
+            return Err("Invalid DDS header size".into());
+        }
+
+        let height = read_u32(header, 8);
+        let width = read_u32(header, 12);
+        let depth = read_u32(header, 20);
+        // 0 means the file only has the base level
+        let mip_levels = read_u32(header, 24).max(1);
+
+        let pixel_format_flags = read_u32(header, 76);
+        let four_cc = &header[80..84];
+
+        if width == 0 || height == 0 {
+            return Err("DDS image has zero size".into());
+        }
+        // A full chain ends at 1x1, anything past that isn't a valid image either
+        let max_mip_levels = width.max(height).ilog2() + 1;
+        if mip_levels > max_mip_levels {
+            return Err(format!(
+                "DDS image has {mip_levels} mips, {width}x{height} allows at most {max_mip_levels}"
+            )
+            .into());
+        }
+        if depth > 1 {
+            return Err("Volume DDS textures are not supported".into());
+        }
+        if pixel_format_flags & DDPF_FOURCC == 0 {
+            return Err("Uncompressed DDS textures are not supported".into());
+        }
+
+        let mut data_offset = 4 + HEADER_SIZE;
+
+        let format = if four_cc == b"DX10" {
+            if bytes.len() < data_offset + DX10_HEADER_SIZE {
+                return Err("Truncated DDS DX10 header".into());
+            }
+            let dx10 = &bytes[data_offset..data_offset + DX10_HEADER_SIZE];
+            data_offset += DX10_HEADER_SIZE;
+
+            let dxgi_format = read_u32(dx10, 0);
+            let array_size = read_u32(dx10, 12);
+            if array_size > 1 {
+                return Err("DDS texture arrays are not supported".into());
+            }
+
+            dxgi_to_vk(dxgi_format)
+                .ok_or_else(|| format!("Unsupported DXGI format {dxgi_format} in DDS"))?
+        } else {
+            four_cc_to_vk(four_cc).ok_or_else(|| {
+                format!(
+                    "Unsupported DDS fourCC {:?}",
+                    String::from_utf8_lossy(four_cc)
+                )
+            })?
+        };
+
+        let block_bytes = block_bytes(format);
+
+        let expected_size: usize = (0..mip_levels)
+            .map(|level| mip_size(width, height, level, block_bytes) as usize)
+            .sum();
+        let data = &bytes[data_offset..];
+        if data.len() < expected_size {
+            return Err(format!(
+                "Truncated DDS data: {} bytes, {mip_levels} mips of {width}x{height} need {expected_size}",
+                data.len()
+            )
+            .into());
+        }
+
+        Ok(Self {
+            format,
+            width,
+            height,
+            mip_levels,
+            block_bytes,
+            data: &data[..expected_size],
+        })
+    }
+}
+
+// Size in bytes of a block-compressed mip level, partial blocks at the edges still
+// take a whole block: ceil(w / 4) * ceil(h / 4) * block_bytes
+pub fn mip_size(width: u32, height: u32, level: u32, block_bytes: u32) -> vk::DeviceSize {
+    let (width, height) = mip_extent(width, height, level);
+    width.div_ceil(4) as vk::DeviceSize
+        * height.div_ceil(4) as vk::DeviceSize
+        * block_bytes as vk::DeviceSize
+}
+
+pub fn mip_extent(width: u32, height: u32, level: u32) -> (u32, u32) {
+    let shift = |size: u32| size.checked_shr(level).unwrap_or(0).max(1);
+    (shift(width), shift(height))
+}
+
+// Bytes per 4x4 block of the BCn formats
+pub fn block_bytes(format: vk::Format) -> u32 {
+    match format {
+        vk::Format::BC1_RGB_UNORM_BLOCK
+        | vk::Format::BC1_RGB_SRGB_BLOCK
+        | vk::Format::BC1_RGBA_UNORM_BLOCK
+        | vk::Format::BC1_RGBA_SRGB_BLOCK
+        | vk::Format::BC4_UNORM_BLOCK
+        | vk::Format::BC4_SNORM_BLOCK => 8,
+        _ => 16,
+    }
+}
+
+fn four_cc_to_vk(four_cc: &[u8]) -> Option<vk::Format> {
+    Some(match four_cc {
+        b"DXT1" => vk::Format::BC1_RGBA_UNORM_BLOCK,
+        b"DXT2" | b"DXT3" => vk::Format::BC2_UNORM_BLOCK,
+        b"DXT4" | b"DXT5" => vk::Format::BC3_UNORM_BLOCK,
+        b"ATI1" | b"BC4U" => vk::Format::BC4_UNORM_BLOCK,
+        b"BC4S" => vk::Format::BC4_SNORM_BLOCK,
+        b"ATI2" | b"BC5U" => vk::Format::BC5_UNORM_BLOCK,
+        b"BC5S" => vk::Format::BC5_SNORM_BLOCK,
+        _ => return None,
+    })
+}
+
+fn dxgi_to_vk(dxgi_format: u32) -> Option<vk::Format> {
+    Some(match dxgi_format {
+        71 => vk::Format::BC1_RGBA_UNORM_BLOCK,
+        72 => vk::Format::BC1_RGBA_SRGB_BLOCK,
+        74 => vk::Format::BC2_UNORM_BLOCK,
+        75 => vk::Format::BC2_SRGB_BLOCK,
+        77 => vk::Format::BC3_UNORM_BLOCK,
+        78 => vk::Format::BC3_SRGB_BLOCK,
+        80 => vk::Format::BC4_UNORM_BLOCK,
+        81 => vk::Format::BC4_SNORM_BLOCK,
+        83 => vk::Format::BC5_UNORM_BLOCK,
+        84 => vk::Format::BC5_SNORM_BLOCK,
+        95 => vk::Format::BC6H_UFLOAT_BLOCK,
+        96 => vk::Format::BC6H_SFLOAT_BLOCK,
+        98 => vk::Format::BC7_UNORM_BLOCK,
+        99 => vk::Format::BC7_SRGB_BLOCK,
+        _ => return None,
+    })
+}
@@ -0,0 +1,85 @@
+use ash::{ext, vk};
+use std::ffi::{CString, c_void};
+
+use super::{Device, Instance};
+
+// Named command buffer regions shown by RenderDoc/NSight, every call is a no-op when
+// VK_EXT_debug_utils isn't enabled on the instance
+pub struct DebugLabels {
+    loader: Option<ext::debug_utils::Device>,
+}
+
+impl DebugLabels {
+    pub fn new(instance: &Instance, device: &Device) -> Self {
+        let loader = instance
+            .is_extension_enabled(ext::debug_utils::NAME)
+            .then(|| ext::debug_utils::Device::new(&instance.instance, &device.device));
+
+        Self { loader }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.loader.is_some()
+    }
+
+    // Opens a region closed by the matching `cmd_end_label`, regions may nest
+    pub fn cmd_begin_label(&self, command_buffer: vk::CommandBuffer, name: &str, color: [f32; 4]) {
+        let Some(loader) = &self.loader else {
+            return;
+        };
+
+        let name = label_name(name);
+        let label = vk::DebugUtilsLabelEXT::default()
+            .label_name(&name)
+            .color(color);
+        unsafe { loader.cmd_begin_debug_utils_label(command_buffer, &label) };
+    }
+
+    pub fn cmd_end_label(&self, command_buffer: vk::CommandBuffer) {
+        let Some(loader) = &self.loader else {
+            return;
+        };
+
+        unsafe { loader.cmd_end_debug_utils_label(command_buffer) };
+    }
+
+    // Single marker at the current point of the command buffer
+    pub fn cmd_insert_label(&self, command_buffer: vk::CommandBuffer, name: &str, color: [f32; 4]) {
+        let Some(loader) = &self.loader else {
+            return;
+        };
+
+        let name = label_name(name);
+        let label = vk::DebugUtilsLabelEXT::default()
+            .label_name(&name)
+            .color(color);
+        unsafe { loader.cmd_insert_debug_utils_label(command_buffer, &label) };
+    }
+}
+
+// Interior NULs would truncate the name anyway, drop them instead of failing
+fn label_name(name: &str) -> CString {
+    CString::new(name.replace('\0', "")).unwrap_or_default()
+}
+
+// Messenger callback of Instance, set up only with InstanceConfig::validation
+pub(super) unsafe extern "system" fn validation_callback(
+    severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    _types: vk::DebugUtilsMessageTypeFlagsEXT,
+    callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT<'_>,
+    _user_data: *mut c_void,
+) -> vk::Bool32 {
+    let message = unsafe {
+        callback_data
+            .as_ref()
+            .and_then(|data| data.message_as_c_str())
+    };
+    let message = message.map_or("(no message)".into(), |message| message.to_string_lossy());
+    if severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR) {
+        println!("Validation error: {message}");
+    } else {
+        println!("Validation warning: {message}");
+    }
+    // The call that triggered the message must not be aborted
+    vk::FALSE
+}
@@ -0,0 +1,285 @@
+use ash::vk;
+
+use super::{Device, Image, transition_layout};
+
+// Preferred first, the first one usable as a depth attachment is picked
+const DEPTH_FORMATS: &[vk::Format] = &[
+    vk::Format::D32_SFLOAT,
+    vk::Format::D32_SFLOAT_S8_UINT,
+    vk::Format::D24_UNORM_S8_UINT,
+];
+const DEPTH_STENCIL_FORMATS: &[vk::Format] = &[
+    vk::Format::D32_SFLOAT_S8_UINT,
+    vk::Format::D24_UNORM_S8_UINT,
+];
+
+// Depth attachment matching the swapchain extent
+pub struct DepthBuffer {
+    pub image: Image,
+    pub has_stencil: bool,
+}
+
+impl DepthBuffer {
+    // In Device::preferred_depth_format
+    pub fn new(device: &Device, extent: vk::Extent2D) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::with_format(device, extent, device.preferred_depth_format())
+    }
+
+    // For render passes created with another depth format
+    pub fn with_format(
+        device: &Device,
+        extent: vk::Extent2D,
+        format: vk::Format,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::create(
+            device,
+            extent,
+            format,
+            vk::SampleCountFlags::TYPE_1,
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+        )
+    }
+
+    // Depth attachment of RenderPass::multisampled, only lives during the pass
+    pub fn multisampled(
+        device: &Device,
+        extent: vk::Extent2D,
+        format: vk::Format,
+        samples: vk::SampleCountFlags,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::create(
+            device,
+            extent,
+            format,
+            samples,
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT
+                | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT,
+        )
+    }
+
+    // Single sample target multisampled depth is resolved into, sampled by later passes
+    pub fn resolve_target(
+        device: &Device,
+        extent: vk::Extent2D,
+        format: vk::Format,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::create(
+            device,
+            extent,
+            format,
+            vk::SampleCountFlags::TYPE_1,
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+        )
+    }
+
+    fn create(
+        device: &Device,
+        extent: vk::Extent2D,
+        format: vk::Format,
+        samples: vk::SampleCountFlags,
+        usage: vk::ImageUsageFlags,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let has_stencil = has_stencil_component(format);
+
+        let create_info = vk::ImageCreateInfo::default()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(samples)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED);
+
+        let image = Image::new(
+            device,
+            &create_info,
+            vk::ImageViewType::TYPE_2D,
+            aspect_flags_for_format(format),
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+
+        Ok(Self { image, has_stencil })
+    }
+
+    // Best depth attachment format, with a stencil component only when asked for so
+    // depth-only setups keep the smaller format. Device::preferred_depth_format caches it.
+    pub fn find_format(
+        device: &Device,
+        stencil: bool,
+    ) -> Result<vk::Format, Box<dyn std::error::Error>> {
+        let candidates = if stencil {
+            DEPTH_STENCIL_FORMATS
+        } else {
+            DEPTH_FORMATS
+        };
+
+        device.find_supported_format(
+            candidates,
+            vk::ImageTiling::OPTIMAL,
+            vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT,
+        )
+    }
+
+    pub fn format(&self) -> vk::Format {
+        self.image.format
+    }
+}
+
+pub fn has_stencil_component(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::D32_SFLOAT_S8_UINT
+            | vk::Format::D24_UNORM_S8_UINT
+            | vk::Format::D16_UNORM_S8_UINT
+    )
+}
+
+// Every aspect `format` has: what attachment views, barriers and clears of the whole
+// image need. Views that get sampled may only name one of DEPTH or STENCIL.
+pub fn aspect_flags_for_format(format: vk::Format) -> vk::ImageAspectFlags {
+    match format {
+        vk::Format::D16_UNORM | vk::Format::X8_D24_UNORM_PACK32 | vk::Format::D32_SFLOAT => {
+            vk::ImageAspectFlags::DEPTH
+        }
+        vk::Format::S8_UINT => vk::ImageAspectFlags::STENCIL,
+        format if has_stencil_component(format) => {
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        }
+        _ => vk::ImageAspectFlags::COLOR,
+    }
+}
+
+// Layouts of the depth and stencil aspects of a depth/stencil image. With
+// Device::separate_depth_stencil_layouts they are independent (e.g. stencil sampled while
+// depth is written, see RenderPass::depth_stencil_input), without it both aspects share
+// one combined DEPTH_STENCIL_* layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DepthStencilLayouts {
+    pub depth: vk::ImageLayout,
+    pub stencil: vk::ImageLayout,
+}
+
+impl DepthStencilLayouts {
+    // Fresh images, only valid as the old layouts of a transition
+    pub const UNDEFINED: Self = Self {
+        depth: vk::ImageLayout::UNDEFINED,
+        stencil: vk::ImageLayout::UNDEFINED,
+    };
+
+    // Attachment layout for aspects that are written, read-only for the others
+    pub fn new(device: &Device, depth_write: bool, stencil_write: bool) -> Self {
+        if device.separate_depth_stencil_layouts {
+            return Self {
+                depth: if depth_write {
+                    vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL
+                } else {
+                    vk::ImageLayout::DEPTH_READ_ONLY_OPTIMAL
+                },
+                stencil: if stencil_write {
+                    vk::ImageLayout::STENCIL_ATTACHMENT_OPTIMAL
+                } else {
+                    vk::ImageLayout::STENCIL_READ_ONLY_OPTIMAL
+                },
+            };
+        }
+
+        // The combined layouts cover every read/write mix (core since 1.1)
+        let layout = match (depth_write, stencil_write) {
+            (true, true) => vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            (true, false) => vk::ImageLayout::DEPTH_ATTACHMENT_STENCIL_READ_ONLY_OPTIMAL,
+            (false, true) => vk::ImageLayout::DEPTH_READ_ONLY_STENCIL_ATTACHMENT_OPTIMAL,
+            (false, false) => vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+        };
+        Self {
+            depth: layout,
+            stencil: layout,
+        }
+    }
+}
+
+// Moves every mip and layer of `image` from `old` to `new`. With separate layouts each
+// aspect of `format` gets its own barrier, otherwise one barrier covers both and only the
+// depth layouts are used (DepthStencilLayouts::new keeps them equal then).
+pub fn transition_depth_stencil(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+    format: vk::Format,
+    old: DepthStencilLayouts,
+    new: DepthStencilLayouts,
+) {
+    let aspects = aspect_flags_for_format(format);
+    let range = |aspect_mask| vk::ImageSubresourceRange {
+        aspect_mask,
+        base_mip_level: 0,
+        level_count: vk::REMAINING_MIP_LEVELS,
+        base_array_layer: 0,
+        layer_count: vk::REMAINING_ARRAY_LAYERS,
+    };
+
+    let transitions = if device.separate_depth_stencil_layouts {
+        vec![
+            (vk::ImageAspectFlags::DEPTH, old.depth, new.depth),
+            (vk::ImageAspectFlags::STENCIL, old.stencil, new.stencil),
+        ]
+    } else {
+        vec![(aspects, old.depth, new.depth)]
+    };
+    for (aspect, old_layout, new_layout) in transitions {
+        if aspects.contains(aspect) {
+            transition_layout(
+                &device.device,
+                command_buffer,
+                image,
+                range(aspect),
+                old_layout,
+                new_layout,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aspect_flags_for_depth_and_stencil_formats() {
+        let depth_stencil = vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL;
+        assert_eq!(
+            aspect_flags_for_format(vk::Format::D32_SFLOAT),
+            vk::ImageAspectFlags::DEPTH
+        );
+        assert_eq!(
+            aspect_flags_for_format(vk::Format::D24_UNORM_S8_UINT),
+            depth_stencil
+        );
+        assert_eq!(
+            aspect_flags_for_format(vk::Format::D32_SFLOAT_S8_UINT),
+            depth_stencil
+        );
+        assert_eq!(
+            aspect_flags_for_format(vk::Format::S8_UINT),
+            vk::ImageAspectFlags::STENCIL
+        );
+    }
+
+    #[test]
+    fn aspect_flags_for_color_formats() {
+        assert_eq!(
+            aspect_flags_for_format(vk::Format::B8G8R8A8_SRGB),
+            vk::ImageAspectFlags::COLOR
+        );
+        assert_eq!(
+            aspect_flags_for_format(vk::Format::R32_SFLOAT),
+            vk::ImageAspectFlags::COLOR
+        );
+    }
+}
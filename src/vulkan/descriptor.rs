@@ -0,0 +1,742 @@
+use ash::{khr, vk};
+
+use super::{Buffer, Device, OwnedDescriptorSetLayout};
+
+// Descriptor set layout from single bindings, for the common buffer descriptors:
+//
+//     DescriptorSetLayoutBuilder::new()
+//         .uniform_buffer(0, vk::ShaderStageFlags::VERTEX)
+//         .storage_buffer(1, vk::ShaderStageFlags::COMPUTE)
+//         .build(device)?
+#[derive(Clone, Default)]
+pub struct DescriptorSetLayoutBuilder {
+    bindings: Vec<vk::DescriptorSetLayoutBinding<'static>>,
+}
+
+impl DescriptorSetLayoutBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Any descriptor type, `count` > 1 for arrays of descriptors
+    pub fn binding(
+        mut self,
+        binding: u32,
+        descriptor_type: vk::DescriptorType,
+        count: u32,
+        stages: vk::ShaderStageFlags,
+    ) -> Self {
+        self.bindings.push(
+            vk::DescriptorSetLayoutBinding::default()
+                .binding(binding)
+                .descriptor_type(descriptor_type)
+                .descriptor_count(count)
+                .stage_flags(stages),
+        );
+        self
+    }
+
+    pub fn uniform_buffer(self, binding: u32, stages: vk::ShaderStageFlags) -> Self {
+        self.binding(binding, vk::DescriptorType::UNIFORM_BUFFER, 1, stages)
+    }
+
+    // `buffer` blocks in GLSL, readable and writable by the shader
+    pub fn storage_buffer(self, binding: u32, stages: vk::ShaderStageFlags) -> Self {
+        self.binding(binding, vk::DescriptorType::STORAGE_BUFFER, 1, stages)
+    }
+
+    // `subpassInput` of a later subpass (see RenderPass::with_input_attachments), only
+    // fragment shaders can read them
+    pub fn input_attachment(self, binding: u32) -> Self {
+        self.binding(
+            binding,
+            vk::DescriptorType::INPUT_ATTACHMENT,
+            1,
+            vk::ShaderStageFlags::FRAGMENT,
+        )
+    }
+
+    pub fn build(
+        &self,
+        device: &Device,
+    ) -> Result<OwnedDescriptorSetLayout, Box<dyn std::error::Error>> {
+        let input_attachments: u32 = self
+            .bindings
+            .iter()
+            .filter(|binding| binding.descriptor_type == vk::DescriptorType::INPUT_ATTACHMENT)
+            .map(|binding| binding.descriptor_count)
+            .sum();
+        let max_input_attachments = device
+            .properties
+            .limits
+            .max_per_stage_descriptor_input_attachments;
+        if input_attachments > max_input_attachments {
+            return Err(format!(
+                "{input_attachments} input attachments, the device supports {max_input_attachments} per stage"
+            )
+            .into());
+        }
+
+        let create_info = vk::DescriptorSetLayoutCreateInfo::default().bindings(&self.bindings);
+        Ok(OwnedDescriptorSetLayout::descriptor_set_layout(
+            &device.device,
+            unsafe {
+                device
+                    .device
+                    .create_descriptor_set_layout(&create_info, None)?
+            },
+        ))
+    }
+}
+
+// Buffer and input attachment descriptor writes into one set, buffers are checked
+// against their usage and the device's range and alignment limits before anything is
+// written
+pub struct DescriptorWriter {
+    set: vk::DescriptorSet,
+    buffers: Vec<BufferWrite>,
+    // (binding, info) of INPUT_ATTACHMENT descriptors
+    input_attachments: Vec<(u32, vk::DescriptorImageInfo)>,
+}
+
+struct BufferWrite {
+    binding: u32,
+    descriptor_type: vk::DescriptorType,
+    info: vk::DescriptorBufferInfo,
+    buffer_size: vk::DeviceSize,
+    buffer_usage: vk::BufferUsageFlags,
+}
+
+impl DescriptorWriter {
+    pub fn new(set: vk::DescriptorSet) -> Self {
+        Self {
+            set,
+            buffers: Vec::new(),
+            input_attachments: Vec::new(),
+        }
+    }
+
+    // View of the framebuffer attachment the input reads, the subpass has it in
+    // SHADER_READ_ONLY_OPTIMAL (see RenderPass::with_input_attachments)
+    pub fn input_attachment(mut self, binding: u32, view: vk::ImageView) -> Self {
+        self.input_attachments.push((
+            binding,
+            vk::DescriptorImageInfo::default()
+                .image_view(view)
+                .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+        ));
+        self
+    }
+
+    // `range` of WHOLE_SIZE covers the rest of the buffer after `offset`
+    pub fn uniform_buffer(
+        self,
+        binding: u32,
+        buffer: &Buffer,
+        offset: vk::DeviceSize,
+        range: vk::DeviceSize,
+    ) -> Self {
+        self.buffer(
+            binding,
+            vk::DescriptorType::UNIFORM_BUFFER,
+            buffer,
+            offset,
+            range,
+        )
+    }
+
+    pub fn storage_buffer(
+        self,
+        binding: u32,
+        buffer: &Buffer,
+        offset: vk::DeviceSize,
+        range: vk::DeviceSize,
+    ) -> Self {
+        self.buffer(
+            binding,
+            vk::DescriptorType::STORAGE_BUFFER,
+            buffer,
+            offset,
+            range,
+        )
+    }
+
+    fn buffer(
+        mut self,
+        binding: u32,
+        descriptor_type: vk::DescriptorType,
+        buffer: &Buffer,
+        offset: vk::DeviceSize,
+        range: vk::DeviceSize,
+    ) -> Self {
+        self.buffers.push(BufferWrite {
+            binding,
+            descriptor_type,
+            info: vk::DescriptorBufferInfo::default()
+                .buffer(buffer.buffer)
+                .offset(offset)
+                .range(range),
+            buffer_size: buffer.size,
+            buffer_usage: buffer.usage,
+        });
+        self
+    }
+
+    // The set must not be in use by the GPU
+    pub fn update(self, device: &Device) -> Result<(), Box<dyn std::error::Error>> {
+        let limits = &device.properties.limits;
+        for write in &self.buffers {
+            let BufferWrite {
+                binding,
+                descriptor_type,
+                info,
+                buffer_size,
+                buffer_usage,
+            } = *write;
+            let (usage, max_range, alignment) = match descriptor_type {
+                vk::DescriptorType::STORAGE_BUFFER => (
+                    vk::BufferUsageFlags::STORAGE_BUFFER,
+                    limits.max_storage_buffer_range,
+                    limits.min_storage_buffer_offset_alignment,
+                ),
+                _ => (
+                    vk::BufferUsageFlags::UNIFORM_BUFFER,
+                    limits.max_uniform_buffer_range,
+                    limits.min_uniform_buffer_offset_alignment,
+                ),
+            };
+            if !buffer_usage.contains(usage) {
+                return Err(format!(
+                    "Binding {binding}: {descriptor_type:?} needs a buffer with {usage:?} usage"
+                )
+                .into());
+            }
+            if info.offset % alignment.max(1) != 0 {
+                return Err(format!(
+                    "Binding {binding}: offset {} isn't a multiple of {alignment}",
+                    info.offset
+                )
+                .into());
+            }
+            let range = if info.range == vk::WHOLE_SIZE {
+                buffer_size.saturating_sub(info.offset)
+            } else {
+                info.range
+            };
+            let end = info.offset.checked_add(range);
+            if end.is_none_or(|end| end > buffer_size) {
+                return Err(format!(
+                    "Binding {binding}: {range} bytes at offset {} exceed the buffer size {buffer_size}",
+                    info.offset
+                )
+                .into());
+            }
+            if range > max_range as vk::DeviceSize {
+                return Err(format!(
+                    "Binding {binding}: {range} bytes exceed the {descriptor_type:?} range limit of {max_range}"
+                )
+                .into());
+            }
+        }
+
+        let writes: Vec<_> = self
+            .buffers
+            .iter()
+            .map(|write| {
+                vk::WriteDescriptorSet::default()
+                    .dst_set(self.set)
+                    .dst_binding(write.binding)
+                    .descriptor_type(write.descriptor_type)
+                    .buffer_info(std::slice::from_ref(&write.info))
+            })
+            .chain(self.input_attachments.iter().map(|(binding, info)| {
+                vk::WriteDescriptorSet::default()
+                    .dst_set(self.set)
+                    .dst_binding(*binding)
+                    .descriptor_type(vk::DescriptorType::INPUT_ATTACHMENT)
+                    .image_info(std::slice::from_ref(info))
+            }))
+            .collect();
+        unsafe { device.device.update_descriptor_sets(&writes, &[]) };
+        Ok(())
+    }
+}
+
+// Bindless texture array, shaders index it with the value returned from `register`:
+//
+//     #extension GL_EXT_nonuniform_qualifier : require
+//     layout(set = 0, binding = 0) uniform sampler2D textures[];
+//     ... texture(textures[nonuniformEXT(idx)], uv)
+pub struct BindlessTextures {
+    pub layout: vk::DescriptorSetLayout,
+    pub pool: vk::DescriptorPool,
+    pub set: vk::DescriptorSet,
+    capacity: u32,
+    count: u32,
+    device: ash::Device,
+}
+
+impl Drop for BindlessTextures {
+    fn drop(&mut self) {
+        unsafe {
+            // Destroying the pool frees the set allocated from it
+            self.device.destroy_descriptor_pool(self.pool, None);
+            self.device.destroy_descriptor_set_layout(self.layout, None);
+        }
+    }
+}
+
+impl BindlessTextures {
+    pub const BINDING: u32 = 0;
+
+    pub fn new(device: &Device, capacity: u32) -> Result<Self, Box<dyn std::error::Error>> {
+        if !device.descriptor_indexing {
+            return Err("Bindless textures require descriptor indexing support".into());
+        }
+
+        // The binding is UPDATE_AFTER_BIND, which has its own (often lower) limits
+        let max_sampled_images = max_update_after_bind_sampled_images(device);
+        if capacity == 0 || capacity > max_sampled_images {
+            return Err(format!(
+                "Bindless capacity {capacity} outside of supported range 1..={max_sampled_images}"
+            )
+            .into());
+        }
+
+        let bindings = [vk::DescriptorSetLayoutBinding::default()
+            .binding(Self::BINDING)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(capacity)
+            .stage_flags(vk::ShaderStageFlags::ALL)];
+
+        // VARIABLE_DESCRIPTOR_COUNT is only allowed on the last binding of the set
+        let binding_flags = [vk::DescriptorBindingFlags::UPDATE_AFTER_BIND
+            | vk::DescriptorBindingFlags::PARTIALLY_BOUND
+            | vk::DescriptorBindingFlags::VARIABLE_DESCRIPTOR_COUNT];
+        let mut binding_flags_info =
+            vk::DescriptorSetLayoutBindingFlagsCreateInfo::default().binding_flags(&binding_flags);
+
+        let layout_create_info = vk::DescriptorSetLayoutCreateInfo::default()
+            .bindings(&bindings)
+            .flags(vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL)
+            .push_next(&mut binding_flags_info);
+
+        let layout = unsafe {
+            device
+                .device
+                .create_descriptor_set_layout(&layout_create_info, None)?
+        };
+
+        let pool_sizes = [vk::DescriptorPoolSize::default()
+            .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(capacity)];
+
+        let pool_create_info = vk::DescriptorPoolCreateInfo::default()
+            .pool_sizes(&pool_sizes)
+            .max_sets(1)
+            .flags(vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND);
+
+        let pool = match unsafe {
+            device
+                .device
+                .create_descriptor_pool(&pool_create_info, None)
+        } {
+            Ok(pool) => pool,
+            Err(err) => {
+                unsafe { device.device.destroy_descriptor_set_layout(layout, None) };
+                return Err(err.into());
+            }
+        };
+
+        let descriptor_counts = [capacity];
+        let mut variable_count_info =
+            vk::DescriptorSetVariableDescriptorCountAllocateInfo::default()
+                .descriptor_counts(&descriptor_counts);
+
+        let set_layouts = [layout];
+        let allocate_info = vk::DescriptorSetAllocateInfo::default()
+            .descriptor_pool(pool)
+            .set_layouts(&set_layouts)
+            .push_next(&mut variable_count_info);
+
+        let set = match unsafe { device.device.allocate_descriptor_sets(&allocate_info) } {
+            Ok(sets) => sets[0],
+            Err(err) => {
+                unsafe {
+                    device.device.destroy_descriptor_pool(pool, None);
+                    device.device.destroy_descriptor_set_layout(layout, None);
+                }
+                return Err(err.into());
+            }
+        };
+
+        Ok(Self {
+            layout,
+            pool,
+            set,
+            capacity,
+            count: 0,
+            device: device.device.clone(),
+        })
+    }
+
+    // Writes the texture into the next free slot and returns its index for use in shaders.
+    // UPDATE_AFTER_BIND allows this while the set is bound in a pending command buffer.
+    pub fn register(
+        &mut self,
+        image_view: vk::ImageView,
+        sampler: vk::Sampler,
+        image_layout: vk::ImageLayout,
+    ) -> Result<u32, Box<dyn std::error::Error>> {
+        if self.count >= self.capacity {
+            return Err(format!("Bindless texture array full ({} slots)", self.capacity).into());
+        }
+
+        let index = self.count;
+
+        let image_info = [vk::DescriptorImageInfo::default()
+            .image_view(image_view)
+            .sampler(sampler)
+            .image_layout(image_layout)];
+
+        let write = vk::WriteDescriptorSet::default()
+            .dst_set(self.set)
+            .dst_binding(Self::BINDING)
+            .dst_array_element(index)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&image_info);
+
+        unsafe { self.device.update_descriptor_sets(&[write], &[]) };
+
+        self.count += 1;
+
+        Ok(index)
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+}
+
+// Small descriptor set that changes per draw (e.g. a single texture). With
+// VK_KHR_push_descriptor the writes are recorded straight into the command buffer and no
+// sets are allocated. Without it (or when the bindings exceed maxPushDescriptors) every
+// push allocates a regular set from the current frame's pool, call `begin_frame` once
+// the frame's fence has signaled to recycle them.
+pub struct PushDescriptorSet {
+    pub layout: vk::DescriptorSetLayout,
+    loader: Option<khr::push_descriptor::Device>,
+    // Fallback only, one pool per frame in flight
+    pools: Vec<vk::DescriptorPool>,
+    current_frame: usize,
+    device: ash::Device,
+}
+
+impl Drop for PushDescriptorSet {
+    fn drop(&mut self) {
+        unsafe {
+            for &pool in &self.pools {
+                self.device.destroy_descriptor_pool(pool, None);
+            }
+            self.device.destroy_descriptor_set_layout(self.layout, None);
+        }
+    }
+}
+
+impl PushDescriptorSet {
+    // `max_sets_per_frame` bounds the pushes per frame in the fallback path
+    pub fn new(
+        device: &Device,
+        bindings: &[vk::DescriptorSetLayoutBinding],
+        frames_in_flight: usize,
+        max_sets_per_frame: u32,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let descriptor_count: u32 = bindings.iter().map(|b| b.descriptor_count).sum();
+        let use_push = device.is_extension_enabled(khr::push_descriptor::NAME)
+            && {
+                let max_push_descriptors = max_push_descriptors(device);
+                if descriptor_count > max_push_descriptors {
+                    println!(
+                        "Warning: {descriptor_count} descriptors exceed maxPushDescriptors ({max_push_descriptors}), using regular descriptor sets"
+                    );
+                }
+                descriptor_count <= max_push_descriptors
+            };
+
+        let flags = if use_push {
+            vk::DescriptorSetLayoutCreateFlags::PUSH_DESCRIPTOR_KHR
+        } else {
+            vk::DescriptorSetLayoutCreateFlags::empty()
+        };
+        let layout_create_info = vk::DescriptorSetLayoutCreateInfo::default()
+            .bindings(bindings)
+            .flags(flags);
+        let layout = unsafe {
+            device
+                .device
+                .create_descriptor_set_layout(&layout_create_info, None)?
+        };
+
+        // Dropping on error destroys the layout and whatever pools were already created
+        let mut push_set = Self {
+            layout,
+            loader: None,
+            pools: Vec::new(),
+            current_frame: 0,
+            device: device.device.clone(),
+        };
+
+        if use_push {
+            push_set.loader = Some(khr::push_descriptor::Device::new(
+                &device.instance,
+                &device.device,
+            ));
+            return Ok(push_set);
+        }
+
+        let pool_sizes: Vec<vk::DescriptorPoolSize> = bindings
+            .iter()
+            .map(|binding| {
+                vk::DescriptorPoolSize::default()
+                    .ty(binding.descriptor_type)
+                    .descriptor_count(binding.descriptor_count * max_sets_per_frame)
+            })
+            .collect();
+        let pool_create_info = vk::DescriptorPoolCreateInfo::default()
+            .pool_sizes(&pool_sizes)
+            .max_sets(max_sets_per_frame);
+        for _ in 0..frames_in_flight {
+            let pool = unsafe {
+                device
+                    .device
+                    .create_descriptor_pool(&pool_create_info, None)?
+            };
+            push_set.pools.push(pool);
+        }
+
+        Ok(push_set)
+    }
+
+    pub fn is_push(&self) -> bool {
+        self.loader.is_some()
+    }
+
+    // Frees the sets the fallback allocated for `frame` last time around, no-op for push
+    // descriptors
+    pub fn begin_frame(&mut self, frame: usize) -> Result<(), Box<dyn std::error::Error>> {
+        if self.loader.is_some() {
+            return Ok(());
+        }
+        let Some(&pool) = self.pools.get(frame) else {
+            return Err(format!(
+                "Frame {frame} out of range, the push descriptor fallback has {} frames",
+                self.pools.len()
+            )
+            .into());
+        };
+        self.current_frame = frame;
+        unsafe {
+            self.device
+                .reset_descriptor_pool(pool, vk::DescriptorPoolResetFlags::empty())?
+        };
+        Ok(())
+    }
+
+    // Binds `writes` as set `set` of `pipeline_layout` for the following graphics draws.
+    // dst_set of the writes is ignored, `pipeline_layout` has to be created with `layout`
+    // at index `set`.
+    pub fn cmd_push_descriptor_set(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        pipeline_layout: vk::PipelineLayout,
+        set: u32,
+        writes: &[vk::WriteDescriptorSet],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(loader) = &self.loader {
+            unsafe {
+                loader.cmd_push_descriptor_set(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    pipeline_layout,
+                    set,
+                    writes,
+                );
+            }
+            return Ok(());
+        }
+
+        let Some(&pool) = self.pools.get(self.current_frame) else {
+            return Err("Push descriptor fallback has no pool for the current frame".into());
+        };
+        let set_layouts = [self.layout];
+        let allocate_info = vk::DescriptorSetAllocateInfo::default()
+            .descriptor_pool(pool)
+            .set_layouts(&set_layouts);
+        let descriptor_set = unsafe { self.device.allocate_descriptor_sets(&allocate_info)? }[0];
+
+        let writes: Vec<vk::WriteDescriptorSet> = writes
+            .iter()
+            .map(|&write| write.dst_set(descriptor_set))
+            .collect();
+        unsafe {
+            self.device.update_descriptor_sets(&writes, &[]);
+            self.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline_layout,
+                set,
+                &[descriptor_set],
+                &[],
+            );
+        }
+
+        Ok(())
+    }
+}
+
+// Sampled images an UPDATE_AFTER_BIND binding can hold, the lower of the per stage and
+// per set limits since the binding is visible to every stage
+fn max_update_after_bind_sampled_images(device: &Device) -> u32 {
+    let mut indexing_properties = vk::PhysicalDeviceDescriptorIndexingProperties::default();
+    let mut properties2 =
+        vk::PhysicalDeviceProperties2::default().push_next(&mut indexing_properties);
+    unsafe {
+        device
+            .instance
+            .get_physical_device_properties2(device.physical_device, &mut properties2)
+    };
+    indexing_properties
+        .max_per_stage_descriptor_update_after_bind_sampled_images
+        .min(indexing_properties.max_descriptor_set_update_after_bind_sampled_images)
+}
+
+fn max_push_descriptors(device: &Device) -> u32 {
+    let mut push_properties = vk::PhysicalDevicePushDescriptorPropertiesKHR::default();
+    let mut properties2 = vk::PhysicalDeviceProperties2::default().push_next(&mut push_properties);
+    unsafe {
+        device
+            .instance
+            .get_physical_device_properties2(device.physical_device, &mut properties2)
+    };
+    push_properties.max_push_descriptors
+}
+
+// Descriptor sets that live for a single frame, for contents that change every frame
+// (UI, immediate mode drawing). Every frame in flight has its own pools, all reset at once
+// by `begin_frame` after the frame's fence signaled. When a pool runs out another one
+// with the same sizes is added to the frame and kept for later frames.
+pub struct TransientDescriptorPool {
+    // Per frame in flight, the first one is always there
+    pools: Vec<Vec<vk::DescriptorPool>>,
+    // Pool of the current frame sets are allocated from, the ones before it are full
+    active: usize,
+    current_frame: usize,
+    pool_sizes: Vec<vk::DescriptorPoolSize>,
+    max_sets: u32,
+    device: ash::Device,
+}
+
+impl Drop for TransientDescriptorPool {
+    fn drop(&mut self) {
+        unsafe {
+            for &pool in self.pools.iter().flatten() {
+                self.device.destroy_descriptor_pool(pool, None);
+            }
+        }
+    }
+}
+
+impl TransientDescriptorPool {
+    // `pool_sizes` and `max_sets` apply to every single pool, not to the frame total
+    pub fn new(
+        device: &Device,
+        frames_in_flight: usize,
+        pool_sizes: &[vk::DescriptorPoolSize],
+        max_sets: u32,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        // Dropping on error destroys the pools created so far
+        let mut transient = Self {
+            pools: Vec::with_capacity(frames_in_flight),
+            active: 0,
+            current_frame: 0,
+            pool_sizes: pool_sizes.to_vec(),
+            max_sets,
+            device: device.device.clone(),
+        };
+        for _ in 0..frames_in_flight {
+            let pool = transient.create_pool()?;
+            transient.pools.push(vec![pool]);
+        }
+        Ok(transient)
+    }
+
+    // Frees every set allocated for `frame` last time around, the GPU must be done with it
+    pub fn begin_frame(&mut self, frame: usize) -> Result<(), Box<dyn std::error::Error>> {
+        if frame >= self.pools.len() {
+            return Err(format!(
+                "Frame {frame} out of range, the transient descriptor pools have {} frames",
+                self.pools.len()
+            )
+            .into());
+        }
+        self.current_frame = frame;
+        self.active = 0;
+        for &pool in &self.pools[frame] {
+            unsafe {
+                self.device
+                    .reset_descriptor_pool(pool, vk::DescriptorPoolResetFlags::empty())?
+            };
+        }
+        Ok(())
+    }
+
+    // Set valid until the same frame index begins again, never free it
+    pub fn allocate_transient(
+        &mut self,
+        layout: vk::DescriptorSetLayout,
+    ) -> Result<vk::DescriptorSet, Box<dyn std::error::Error>> {
+        let set_layouts = [layout];
+        loop {
+            let (pool, fresh) = match self.pools[self.current_frame].get(self.active) {
+                Some(&pool) => (pool, false),
+                None => {
+                    let pool = self.create_pool()?;
+                    let frame_pools = &mut self.pools[self.current_frame];
+                    frame_pools.push(pool);
+                    println!(
+                        "Transient descriptor pools of frame {} grown to {}",
+                        self.current_frame,
+                        frame_pools.len()
+                    );
+                    (pool, true)
+                }
+            };
+
+            let allocate_info = vk::DescriptorSetAllocateInfo::default()
+                .descriptor_pool(pool)
+                .set_layouts(&set_layouts);
+            match unsafe { self.device.allocate_descriptor_sets(&allocate_info) } {
+                Ok(sets) => return Ok(sets[0]),
+                // Full, move on to the next pool. A fresh pool failing too means the set
+                // doesn't fit into a single pool at all.
+                Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY | vk::Result::ERROR_FRAGMENTED_POOL)
+                    if !fresh =>
+                {
+                    self.active += 1;
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    fn create_pool(&self) -> Result<vk::DescriptorPool, Box<dyn std::error::Error>> {
+        let create_info = vk::DescriptorPoolCreateInfo::default()
+            .pool_sizes(&self.pool_sizes)
+            .max_sets(self.max_sets);
+        Ok(unsafe { self.device.create_descriptor_pool(&create_info, None)? })
+    }
+}
@@ -0,0 +1,136 @@
+use ash::vk;
+use std::ffi::CStr;
+
+use super::{
+    Instance, Surface, find_queue_families, format_api_version, supports_required_extensions,
+};
+
+const VENDOR_NVIDIA: u32 = 0x10DE;
+const VENDOR_INTEL: u32 = 0x8086;
+
+// Everything a bug report needs about one physical device
+#[derive(Debug, Clone)]
+pub struct DeviceInfo {
+    pub physical_device: vk::PhysicalDevice,
+    pub name: String,
+    pub device_type: vk::PhysicalDeviceType,
+    pub api_version: u32,
+    // Vendor specific encoding, see `driver_version_string`
+    pub driver_version: u32,
+    pub vendor_id: u32,
+    pub device_id: u32,
+    // Size and flags (DEVICE_LOCAL, ...) of every memory heap
+    pub memory_heaps: Vec<vk::MemoryHeap>,
+    // For the surface passed to Instance::enumerate_devices_info
+    pub graphics: bool,
+    pub present: bool,
+    pub swapchain: bool,
+}
+
+impl DeviceInfo {
+    // What Device::new requires
+    pub fn is_suitable(&self) -> bool {
+        self.graphics && self.present && self.swapchain
+    }
+
+    pub fn driver_version_string(&self) -> String {
+        driver_version_string(self.vendor_id, self.driver_version)
+    }
+
+    pub fn device_local_memory(&self) -> vk::DeviceSize {
+        self.memory_heaps
+            .iter()
+            .filter(|heap| heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL))
+            .map(|heap| heap.size)
+            .sum()
+    }
+}
+
+// Vendor specific encoding of VkPhysicalDeviceProperties::driverVersion
+pub fn driver_version_string(vendor_id: u32, version: u32) -> String {
+    match vendor_id {
+        VENDOR_NVIDIA => format!(
+            "{}.{}.{}.{}",
+            version >> 22,
+            (version >> 14) & 0xFF,
+            (version >> 6) & 0xFF,
+            version & 0x3F
+        ),
+        VENDOR_INTEL if cfg!(target_os = "windows") => {
+            format!("{}.{}", version >> 14, version & 0x3FFF)
+        }
+        _ => format_api_version(version),
+    }
+}
+
+impl Instance {
+    // Every physical device in enumeration order (the order Device::new tries them in)
+    pub fn enumerate_devices_info(
+        &self,
+        surface: &Surface,
+    ) -> Result<Vec<DeviceInfo>, Box<dyn std::error::Error>> {
+        let physical_devices = unsafe { self.instance.enumerate_physical_devices()? };
+
+        Ok(physical_devices
+            .into_iter()
+            .map(|physical_device| {
+                let props = unsafe {
+                    self.instance
+                        .get_physical_device_properties(physical_device)
+                };
+                let memory_properties = unsafe {
+                    self.instance
+                        .get_physical_device_memory_properties(physical_device)
+                };
+                let queue_family_properties = unsafe {
+                    self.instance
+                        .get_physical_device_queue_family_properties(physical_device)
+                };
+                let (graphics, present) =
+                    find_queue_families(&queue_family_properties, surface, physical_device);
+                let name = unsafe { CStr::from_ptr(props.device_name.as_ptr()) };
+
+                DeviceInfo {
+                    physical_device,
+                    name: name.to_string_lossy().into_owned(),
+                    device_type: props.device_type,
+                    api_version: props.api_version,
+                    driver_version: props.driver_version,
+                    vendor_id: props.vendor_id,
+                    device_id: props.device_id,
+                    memory_heaps: memory_properties.memory_heaps_as_slice().to_vec(),
+                    graphics: graphics.is_some(),
+                    present: present.is_some(),
+                    swapchain: supports_required_extensions(&self.instance, physical_device),
+                }
+            })
+            .collect())
+    }
+}
+
+pub(super) fn print_device_table(devices: &[DeviceInfo]) {
+    println!("Physical devices:");
+    for (idx, info) in devices.iter().enumerate() {
+        println!(
+            "  [{idx}] {} ({:?}), vendor {:#06x} device {:#06x}, Vulkan {}, driver {}, suitable: {}",
+            info.name,
+            info.device_type,
+            info.vendor_id,
+            info.device_id,
+            format_api_version(info.api_version),
+            info.driver_version_string(),
+            info.is_suitable()
+        );
+        println!(
+            "      graphics: {}, present: {}, swapchain: {}",
+            info.graphics, info.present, info.swapchain
+        );
+        for (heap_idx, heap) in info.memory_heaps.iter().enumerate() {
+            println!(
+                "      heap {heap_idx}: {} MiB {:?}",
+                heap.size / (1024 * 1024),
+                heap.flags
+            );
+        }
+    }
+}
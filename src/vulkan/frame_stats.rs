@@ -0,0 +1,79 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_FRAME_STATS_WINDOW: usize = 120;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FrameStats {
+    // From the average frame time, not the frames counted in the last second
+    pub fps: f32,
+    pub avg_ms: f32,
+    pub min_ms: f32,
+    pub max_ms: f32,
+}
+
+// CPU side time between consecutive frames over the last `window` frames
+pub struct FrameTimer {
+    deltas: VecDeque<Duration>,
+    window: usize,
+    last_frame: Option<Instant>,
+}
+
+impl FrameTimer {
+    pub fn new(window: usize) -> Self {
+        let window = window.max(1);
+        Self {
+            deltas: VecDeque::with_capacity(window),
+            window,
+            last_frame: None,
+        }
+    }
+
+    // Call once per frame, the first frame only sets the starting point
+    pub fn tick(&mut self) {
+        let now = Instant::now();
+        if let Some(last_frame) = self.last_frame.replace(now) {
+            if self.deltas.len() == self.window {
+                self.deltas.pop_front();
+            }
+            self.deltas.push_back(now - last_frame);
+        }
+    }
+
+    // Forget the last frame, so time spent not drawing (e.g. minimized) isn't counted
+    pub fn pause(&mut self) {
+        self.last_frame = None;
+    }
+
+    // Keeps the newest samples that still fit
+    pub fn set_window(&mut self, window: usize) {
+        self.window = window.max(1);
+        while self.deltas.len() > self.window {
+            self.deltas.pop_front();
+        }
+    }
+
+    pub fn window(&self) -> usize {
+        self.window
+    }
+
+    // None until two frames were drawn
+    pub fn stats(&self) -> Option<FrameStats> {
+        if self.deltas.is_empty() {
+            return None;
+        }
+
+        let to_ms = |delta: &Duration| delta.as_secs_f32() * 1000.0;
+        let total_ms: f32 = self.deltas.iter().map(to_ms).sum();
+        let avg_ms = total_ms / self.deltas.len() as f32;
+        let min_ms = self.deltas.iter().map(to_ms).fold(f32::MAX, f32::min);
+        let max_ms = self.deltas.iter().map(to_ms).fold(0.0, f32::max);
+
+        Some(FrameStats {
+            fps: if avg_ms > 0.0 { 1000.0 / avg_ms } else { 0.0 },
+            avg_ms,
+            min_ms,
+            max_ms,
+        })
+    }
+}
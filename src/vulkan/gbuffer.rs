@@ -0,0 +1,144 @@
+use ash::vk;
+
+use super::{
+    DepthBuffer, Device, Framebuffers, Image, RenderPass, aspect_flags_for_format,
+    has_stencil_component,
+};
+
+// Deferred shading targets at swapchain resolution. Create the render pass once with
+// `create_render_pass`, pipelines writing the G-buffer use `.color_attachments(3)` and a
+// fragment shader with these outputs:
+//
+//     layout(location = 0) out vec4 out_albedo;
+//     layout(location = 1) out vec4 out_normal;
+//     layout(location = 2) out vec4 out_position;
+//
+// After the pass the color targets are in SHADER_READ_ONLY_OPTIMAL for the lighting pass.
+// Tiled GPUs do better with RenderPass::with_input_attachments, which keeps the targets
+// on chip instead of storing and sampling them.
+pub struct GBuffer {
+    // Dropped first, it references every view below
+    pub framebuffer: Framebuffers,
+    pub albedo: Image,
+    // World space normal and position, 16 bit floats are plenty for scenes near the origin
+    pub normal: Image,
+    pub position: Image,
+    pub depth: DepthBuffer,
+    pub extent: vk::Extent2D,
+}
+
+impl GBuffer {
+    // Attachment order: albedo, normal, position
+    pub const FORMATS: [vk::Format; 3] = [
+        vk::Format::R8G8B8A8_UNORM,
+        vk::Format::R16G16B16A16_SFLOAT,
+        vk::Format::R16G16B16A16_SFLOAT,
+    ];
+
+    pub fn create_render_pass(
+        device: &Device,
+        depth_format: vk::Format,
+    ) -> Result<RenderPass, Box<dyn std::error::Error>> {
+        RenderPass::offscreen(device, &Self::FORMATS, Some(depth_format))
+    }
+
+    // Recreate with the new extent whenever the swapchain is recreated
+    pub fn new(
+        device: &Device,
+        render_pass: &RenderPass,
+        extent: vk::Extent2D,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        if render_pass.color_formats != Self::FORMATS {
+            return Err("Render pass wasn't created with GBuffer::create_render_pass".into());
+        }
+        let depth_format = render_pass
+            .depth_format
+            .ok_or("G-buffer render pass has no depth attachment")?;
+
+        let [albedo, normal, position] = Self::FORMATS;
+        let albedo = create_target(device, albedo, extent)?;
+        let normal = create_target(device, normal, extent)?;
+        let position = create_target(device, position, extent)?;
+        let depth = DepthBuffer::with_format(device, extent, depth_format)?;
+
+        let framebuffer = Framebuffers::offscreen(
+            device,
+            render_pass,
+            &[albedo.view, normal.view, position.view],
+            Some(depth.image.view),
+            extent,
+        )?;
+
+        Ok(Self {
+            framebuffer,
+            albedo,
+            normal,
+            position,
+            depth,
+            extent,
+        })
+    }
+
+    // Views in attachment order, for the lighting pass' descriptors
+    pub fn views(&self) -> [vk::ImageView; 3] {
+        [self.albedo.view, self.normal.view, self.position.view]
+    }
+
+    // One clear value per attachment, depth last
+    pub fn clear_values(&self, clear_depth: f32) -> [vk::ClearValue; 4] {
+        let color = vk::ClearValue {
+            color: vk::ClearColorValue {
+                float32: [0.0, 0.0, 0.0, 0.0],
+            },
+        };
+        [
+            color,
+            color,
+            color,
+            vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: clear_depth,
+                    stencil: 0,
+                },
+            },
+        ]
+    }
+}
+
+fn create_target(
+    device: &Device,
+    format: vk::Format,
+    extent: vk::Extent2D,
+) -> Result<Image, Box<dyn std::error::Error>> {
+    if !device.format_supports(
+        format,
+        vk::ImageTiling::OPTIMAL,
+        vk::FormatFeatureFlags::COLOR_ATTACHMENT | vk::FormatFeatureFlags::SAMPLED_IMAGE,
+    ) {
+        return Err(format!("{format:?} can't be used as a sampled color attachment").into());
+    }
+
+    let create_info = vk::ImageCreateInfo::default()
+        .image_type(vk::ImageType::TYPE_2D)
+        .format(format)
+        .extent(vk::Extent3D {
+            width: extent.width,
+            height: extent.height,
+            depth: 1,
+        })
+        .mip_levels(1)
+        .array_layers(1)
+        .samples(vk::SampleCountFlags::TYPE_1)
+        .tiling(vk::ImageTiling::OPTIMAL)
+        .usage(vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .initial_layout(vk::ImageLayout::UNDEFINED);
+
+    Image::new(
+        device,
+        &create_info,
+        vk::ImageViewType::TYPE_2D,
+        aspect_flags_for_format(format),
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )
+}
@@ -0,0 +1,926 @@
+use std::path::{Component, Path, PathBuf};
+
+use super::camera::IDENTITY;
+use super::json::Json;
+use super::{Device, Mesh, Texture, Vertex, mat4_mul};
+
+const GLB_MAGIC: u32 = 0x4654_6C67; // "glTF"
+const GLB_CHUNK_JSON: u32 = 0x4E4F_534A;
+const GLB_CHUNK_BIN: u32 = 0x004E_4942;
+
+const COMPONENT_BYTE: usize = 5120;
+const COMPONENT_UNSIGNED_BYTE: usize = 5121;
+const COMPONENT_SHORT: usize = 5122;
+const COMPONENT_UNSIGNED_SHORT: usize = 5123;
+const COMPONENT_UNSIGNED_INT: usize = 5125;
+const COMPONENT_FLOAT: usize = 5126;
+
+const MODE_TRIANGLES: usize = 4;
+// Accessors without a buffer view are all zeros, their count can't be checked against
+// any data. More elements than this are rejected instead of allocated.
+const MAX_ZERO_ELEMENTS: usize = 1 << 24;
+
+type Mat4 = [[f32; 4]; 4];
+// (mesh index, world transform)
+type MeshInstance = (usize, Mat4);
+
+// One triangle list with its vertices already in scene space (node transforms applied)
+pub struct GltfPrimitive {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+    // Index into GltfDocument::materials
+    pub material: Option<usize>,
+}
+
+pub struct GltfMaterial {
+    // Linear RGBA, multiplies the base color texture
+    pub base_color_factor: [f32; 4],
+    // Index into GltfDocument::images
+    pub base_color_image: Option<usize>,
+}
+
+// Encoded image file (PNG, JPEG, DDS, ...) embedded in the asset or read from its uri
+pub struct GltfImage {
+    pub data: Vec<u8>,
+    pub mime_type: Option<String>,
+}
+
+impl GltfImage {
+    fn is_dds(&self) -> bool {
+        self.mime_type.as_deref() == Some("image/vnd-ms.dds") || self.data.starts_with(b"DDS ")
+    }
+}
+
+// glTF 2.0 (.gltf with embedded or external buffers, or .glb) reduced to what the renderer
+// draws: triangle primitives with POSITION, NORMAL, TEXCOORD_0 and COLOR_0, indices, and
+// the base color of materials. The default scene's node transforms are baked into the
+// vertices. Skins, animations and morph targets are skipped with a warning, sparse
+// accessors, non-triangle modes and required extensions are errors.
+pub struct GltfDocument {
+    pub primitives: Vec<GltfPrimitive>,
+    pub materials: Vec<GltfMaterial>,
+    pub images: Vec<GltfImage>,
+}
+
+impl GltfDocument {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path)
+            .map_err(|err| format!("Failed to read {}: {err}", path.display()))?;
+        let base_dir = path.parent().unwrap_or(Path::new("."));
+        Self::parse(&bytes, base_dir).map_err(|err| format!("{}: {err}", path.display()).into())
+    }
+
+    // External buffers and images are resolved relative to `base_dir`
+    pub fn parse(bytes: &[u8], base_dir: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let Glb { json, bin } = if read_u32(bytes, 0) == Some(GLB_MAGIC) {
+            Glb::parse(bytes)?
+        } else {
+            Glb {
+                json: std::str::from_utf8(bytes)?,
+                bin: None,
+            }
+        };
+        let root = Json::parse(json)?;
+
+        let version = root
+            .get("asset")
+            .and_then(|asset| asset.get("version"))
+            .and_then(Json::as_str)
+            .unwrap_or_default();
+        if !version.starts_with("2.") {
+            return Err(format!("Unsupported glTF version {version:?}, only 2.x is").into());
+        }
+
+        let required: Vec<&str> = root
+            .get("extensionsRequired")
+            .map(Json::as_array)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(Json::as_str)
+            .collect();
+        if !required.is_empty() {
+            return Err(format!("Unsupported required extensions {required:?}").into());
+        }
+
+        for (key, feature) in [("skins", "Skins"), ("animations", "Animations")] {
+            if !array(&root, key).is_empty() {
+                println!("Warning: glTF {feature} aren't supported, ignoring them");
+            }
+        }
+
+        let buffers = array(&root, "buffers")
+            .iter()
+            .enumerate()
+            .map(|(idx, buffer)| load_buffer(buffer, idx, bin, base_dir))
+            .collect::<Result<Vec<_>, _>>()?;
+        let reader = AccessorReader {
+            root: &root,
+            buffers: &buffers,
+        };
+
+        let mut primitives = Vec::new();
+        for (mesh_idx, transform) in mesh_instances(&root)? {
+            let mesh = array(&root, "meshes")
+                .get(mesh_idx)
+                .ok_or_else(|| format!("Mesh {mesh_idx} doesn't exist"))?;
+            for (primitive_idx, primitive) in mesh
+                .get("primitives")
+                .map(Json::as_array)
+                .unwrap_or_default()
+                .iter()
+                .enumerate()
+            {
+                let primitive = read_primitive(&reader, primitive, transform)
+                    .map_err(|err| format!("Mesh {mesh_idx} primitive {primitive_idx}: {err}"))?;
+                primitives.push(primitive);
+            }
+        }
+
+        let materials = array(&root, "materials")
+            .iter()
+            .map(|material| read_material(&root, material))
+            .collect();
+
+        let images = array(&root, "images")
+            .iter()
+            .enumerate()
+            .map(|(idx, image)| {
+                load_image(&reader, image, base_dir).map_err(|err| format!("Image {idx}: {err}"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            primitives,
+            materials,
+            images,
+        })
+    }
+}
+
+pub struct GltfMesh {
+    pub mesh: Mesh,
+    // Index into GltfModel::materials
+    pub material: Option<usize>,
+}
+
+// GltfDocument uploaded to the GPU, one Mesh per primitive
+pub struct GltfModel {
+    pub meshes: Vec<GltfMesh>,
+    pub materials: Vec<GltfMaterial>,
+    // Same indices as GltfMaterial::base_color_image. Only DDS images are decoded here,
+    // the others are None: decode GltfDocument::images yourself (PNG, JPEG) and create
+    // them with Texture::from_rgba.
+    pub textures: Vec<Option<Texture>>,
+}
+
+impl GltfModel {
+    pub fn load(
+        device: &Device,
+        path: impl AsRef<Path>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::from_document(device, GltfDocument::from_file(path)?)
+    }
+
+    pub fn from_document(
+        device: &Device,
+        document: GltfDocument,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let meshes = document
+            .primitives
+            .iter()
+            .map(|primitive| {
+                Ok(GltfMesh {
+                    mesh: Mesh::new(device, &primitive.vertices, &primitive.indices)?,
+                    material: primitive.material,
+                })
+            })
+            .collect::<Result<Vec<_>, Box<dyn std::error::Error>>>()?;
+
+        let textures = document
+            .images
+            .iter()
+            .enumerate()
+            .map(|(idx, image)| {
+                if image.is_dds() {
+                    return Texture::from_dds(device, &image.data).map(Some);
+                }
+                println!(
+                    "Warning: glTF image {idx} ({}) needs a decoder, decode it and use Texture::from_rgba",
+                    image.mime_type.as_deref().unwrap_or("unknown type")
+                );
+                Ok(None)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            meshes,
+            materials: document.materials,
+            textures,
+        })
+    }
+}
+
+fn array<'a>(json: &'a Json, key: &str) -> &'a [Json] {
+    json.get(key).map(Json::as_array).unwrap_or_default()
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let word = bytes.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+}
+
+// JSON chunk and the optional BIN chunk of a .glb, a plain .gltf is only the JSON
+struct Glb<'a> {
+    json: &'a str,
+    bin: Option<&'a [u8]>,
+}
+
+impl<'a> Glb<'a> {
+    fn parse(bytes: &'a [u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        let version = read_u32(bytes, 4).ok_or("Truncated GLB header")?;
+        if version != 2 {
+            return Err(format!("Unsupported GLB version {version}").into());
+        }
+        let length = (read_u32(bytes, 8).ok_or("Truncated GLB header")? as usize).min(bytes.len());
+
+        let mut json = None;
+        let mut bin = None;
+        let mut offset = 12;
+        // `offset` stays at most `length`, only the chunk lengths come from the file
+        while offset + 8 <= length {
+            let chunk_length = read_u32(bytes, offset).unwrap_or_default() as usize;
+            let chunk_type = read_u32(bytes, offset + 4).unwrap_or_default();
+            let data = (offset + 8)
+                .checked_add(chunk_length)
+                .filter(|&end| end <= length)
+                .and_then(|end| bytes.get(offset + 8..end))
+                .ok_or("GLB chunk runs past the end of the file")?;
+            match chunk_type {
+                GLB_CHUNK_JSON if json.is_none() => json = Some(std::str::from_utf8(data)?),
+                GLB_CHUNK_BIN if bin.is_none() => bin = Some(data),
+                // Unknown chunks must be ignored
+                _ => {}
+            }
+            offset += 8 + data.len();
+        }
+
+        Ok(Self {
+            json: json.ok_or("GLB has no JSON chunk")?,
+            bin,
+        })
+    }
+}
+
+fn load_buffer(
+    buffer: &Json,
+    idx: usize,
+    bin: Option<&[u8]>,
+    base_dir: &Path,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let byte_length = buffer
+        .get("byteLength")
+        .and_then(Json::as_usize)
+        .unwrap_or(0);
+    let data = match buffer.get("uri").and_then(Json::as_str) {
+        Some(uri) => load_uri(uri, base_dir)?.0,
+        // Only the first buffer of a GLB may refer to the BIN chunk
+        None if idx == 0 => bin
+            .ok_or("Buffer 0 has no uri and there is no GLB BIN chunk")?
+            .to_vec(),
+        None => return Err(format!("Buffer {idx} has no uri").into()),
+    };
+    if data.len() < byte_length {
+        return Err(format!(
+            "Buffer {idx} has {} bytes, byteLength is {byte_length}",
+            data.len()
+        )
+        .into());
+    }
+    Ok(data)
+}
+
+// Contents and mime type (from data uris) of a data uri or a file next to the asset
+fn load_uri(
+    uri: &str,
+    base_dir: &Path,
+) -> Result<(Vec<u8>, Option<String>), Box<dyn std::error::Error>> {
+    if let Some(data_uri) = uri.strip_prefix("data:") {
+        let (header, payload) = data_uri.split_once(',').ok_or("Malformed data uri")?;
+        let mime_type = header.split(';').next().filter(|mime| !mime.is_empty());
+        if !header.ends_with(";base64") {
+            return Err("Only base64 data uris are supported".into());
+        }
+        return Ok((decode_base64(payload)?, mime_type.map(str::to_owned)));
+    }
+
+    // Files outside the asset's directory are off limits, the uri comes from the file
+    let relative = PathBuf::from(percent_decode(uri));
+    if relative
+        .components()
+        .any(|component| !matches!(component, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(format!("glTF uri {uri:?} points outside of the asset's directory").into());
+    }
+    let path = base_dir.join(relative);
+    let data =
+        std::fs::read(&path).map_err(|err| format!("Failed to read {}: {err}", path.display()))?;
+    Ok((data, None))
+}
+
+fn decode_base64(input: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut output = Vec::with_capacity(input.len() / 4 * 3);
+    let mut accumulator = 0u32;
+    let mut bits = 0;
+    for byte in input.bytes() {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            b'=' => break,
+            byte if byte.is_ascii_whitespace() => continue,
+            _ => return Err(format!("Invalid base64 character {:?}", byte as char).into()),
+        };
+        accumulator = (accumulator << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            output.push((accumulator >> bits) as u8);
+        }
+    }
+    Ok(output)
+}
+
+// uris are percent encoded, e.g. "my%20texture.png"
+fn percent_decode(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut idx = 0;
+    while idx < bytes.len() {
+        let escaped = (bytes[idx] == b'%')
+            .then(|| bytes.get(idx + 1..idx + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                idx += 3;
+            }
+            None => {
+                decoded.push(bytes[idx]);
+                idx += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+// (mesh, world transform) for every node with a mesh in the default scene. Without any
+// scene every mesh is used once, untransformed.
+fn mesh_instances(root: &Json) -> Result<Vec<MeshInstance>, Box<dyn std::error::Error>> {
+    let scenes = array(root, "scenes");
+    if scenes.is_empty() {
+        return Ok((0..array(root, "meshes").len())
+            .map(|idx| (idx, IDENTITY))
+            .collect());
+    }
+
+    let scene_idx = root.get("scene").and_then(Json::as_usize).unwrap_or(0);
+    let scene = scenes
+        .get(scene_idx)
+        .ok_or_else(|| format!("Scene {scene_idx} doesn't exist"))?;
+
+    let nodes = array(root, "nodes");
+    let mut instances = Vec::new();
+    // (node, parent transform)
+    let mut stack: Vec<(usize, Mat4)> = array(scene, "nodes")
+        .iter()
+        .filter_map(Json::as_usize)
+        .map(|node| (node, IDENTITY))
+        .collect();
+    // glTF nodes form trees, a node reached twice means a cycle or a shared child
+    let mut visited = vec![false; nodes.len()];
+    while let Some((node_idx, parent)) = stack.pop() {
+        let node = nodes
+            .get(node_idx)
+            .ok_or_else(|| format!("Node {node_idx} doesn't exist"))?;
+        if std::mem::replace(&mut visited[node_idx], true) {
+            return Err(format!("Node {node_idx} is reached more than once").into());
+        }
+        let transform = mat4_mul(parent, node_transform(node));
+
+        if let Some(mesh) = node.get("mesh").and_then(Json::as_usize) {
+            instances.push((mesh, transform));
+        }
+        for child in array(node, "children").iter().filter_map(Json::as_usize) {
+            stack.push((child, transform));
+        }
+    }
+
+    Ok(instances)
+}
+
+// `matrix` (column major like ours) or T * R * S
+fn node_transform(node: &Json) -> Mat4 {
+    let floats = |key: &str| -> Vec<f32> {
+        array(node, key)
+            .iter()
+            .filter_map(Json::as_f64)
+            .map(|value| value as f32)
+            .collect()
+    };
+
+    let matrix = floats("matrix");
+    if matrix.len() == 16 {
+        let mut result = [[0.0; 4]; 4];
+        for (idx, value) in matrix.into_iter().enumerate() {
+            result[idx / 4][idx % 4] = value;
+        }
+        return result;
+    }
+
+    let t = Some(floats("translation"))
+        .filter(|t| t.len() == 3)
+        .unwrap_or(vec![0.0; 3]);
+    let r = Some(floats("rotation"))
+        .filter(|r| r.len() == 4)
+        .unwrap_or(vec![0.0, 0.0, 0.0, 1.0]);
+    let s = Some(floats("scale"))
+        .filter(|s| s.len() == 3)
+        .unwrap_or(vec![1.0; 3]);
+
+    // Unit quaternion (x, y, z, w) to a rotation matrix, columns scaled by `s`
+    let (x, y, z, w) = (r[0], r[1], r[2], r[3]);
+    [
+        [
+            (1.0 - 2.0 * (y * y + z * z)) * s[0],
+            (2.0 * (x * y + z * w)) * s[0],
+            (2.0 * (x * z - y * w)) * s[0],
+            0.0,
+        ],
+        [
+            (2.0 * (x * y - z * w)) * s[1],
+            (1.0 - 2.0 * (x * x + z * z)) * s[1],
+            (2.0 * (y * z + x * w)) * s[1],
+            0.0,
+        ],
+        [
+            (2.0 * (x * z + y * w)) * s[2],
+            (2.0 * (y * z - x * w)) * s[2],
+            (1.0 - 2.0 * (x * x + y * y)) * s[2],
+            0.0,
+        ],
+        [t[0], t[1], t[2], 1.0],
+    ]
+}
+
+fn read_primitive(
+    reader: &AccessorReader,
+    primitive: &Json,
+    transform: Mat4,
+) -> Result<GltfPrimitive, Box<dyn std::error::Error>> {
+    let mode = primitive
+        .get("mode")
+        .and_then(Json::as_usize)
+        .unwrap_or(MODE_TRIANGLES);
+    if mode != MODE_TRIANGLES {
+        return Err(
+            format!("Primitive mode {mode} isn't supported, only triangles (4) are").into(),
+        );
+    }
+    if !array(primitive, "targets").is_empty() {
+        println!("Warning: glTF morph targets aren't supported, ignoring them");
+    }
+
+    let attributes = primitive
+        .get("attributes")
+        .ok_or("Primitive has no attributes")?;
+    let attribute = |name: &str| attributes.get(name).and_then(Json::as_usize);
+
+    let positions = reader.read(attribute("POSITION").ok_or("Primitive has no POSITION")?, 3)?;
+    let count = positions.len();
+    let normals = attribute("NORMAL")
+        .map(|idx| reader.read(idx, 3))
+        .transpose()?;
+    let uvs = attribute("TEXCOORD_0")
+        .map(|idx| reader.read(idx, 2))
+        .transpose()?;
+    let colors = attribute("COLOR_0")
+        .map(|idx| reader.read(idx, 3))
+        .transpose()?;
+    for (name, values) in [
+        ("NORMAL", &normals),
+        ("TEXCOORD_0", &uvs),
+        ("COLOR_0", &colors),
+    ] {
+        if values.as_ref().is_some_and(|values| values.len() != count) {
+            return Err(format!("{name} count doesn't match POSITION count {count}").into());
+        }
+    }
+
+    let normal_matrix = normal_matrix(transform);
+    let mut vertices: Vec<Vertex> = (0..count)
+        .map(|idx| {
+            let p = &positions[idx];
+            let position = transform_point(transform, [p[0], p[1], p[2]]);
+            let normal = normals.as_ref().map_or([0.0; 3], |normals| {
+                let n = &normals[idx];
+                normalize(transform_vector(normal_matrix, [n[0], n[1], n[2]]))
+            });
+            Vertex {
+                position,
+                color: colors.as_ref().map_or([1.0; 3], |colors| {
+                    [colors[idx][0], colors[idx][1], colors[idx][2]]
+                }),
+                normal,
+                // glTF already has v = 0 at the top like Vulkan
+                uv: uvs
+                    .as_ref()
+                    .map_or([0.0; 2], |uvs| [uvs[idx][0], uvs[idx][1]]),
+            }
+        })
+        .collect();
+
+    let mut indices = match primitive.get("indices").and_then(Json::as_usize) {
+        Some(idx) => reader.read_indices(idx)?,
+        None => (0..count as u32).collect(),
+    };
+    if indices.len() % 3 != 0 {
+        return Err(format!("{} indices don't form whole triangles", indices.len()).into());
+    }
+    if let Some(&index) = indices.iter().find(|&&index| index as usize >= count) {
+        return Err(format!("Index {index} out of range for {count} vertices").into());
+    }
+    // A mirroring transform flips the winding, flip it back
+    if determinant3(transform) < 0.0 {
+        for triangle in indices.chunks_exact_mut(3) {
+            triangle.swap(1, 2);
+        }
+    }
+
+    if normals.is_none() {
+        compute_smooth_normals(&mut vertices, &indices);
+    }
+
+    Ok(GltfPrimitive {
+        vertices,
+        indices,
+        material: primitive.get("material").and_then(Json::as_usize),
+    })
+}
+
+fn read_material(root: &Json, material: &Json) -> GltfMaterial {
+    let pbr = material.get("pbrMetallicRoughness");
+    let mut base_color_factor = [1.0; 4];
+    if let Some(factor) = pbr.and_then(|pbr| pbr.get("baseColorFactor")) {
+        for (target, value) in base_color_factor.iter_mut().zip(factor.as_array()) {
+            *target = value.as_f64().unwrap_or(1.0) as f32;
+        }
+    }
+
+    // MSFT_texture_dds points at a DDS version of the image, prefer it since that's what
+    // can be uploaded without a decoder
+    let base_color_image = pbr
+        .and_then(|pbr| pbr.get("baseColorTexture"))
+        .and_then(|texture| texture.get("index"))
+        .and_then(Json::as_usize)
+        .and_then(|idx| array(root, "textures").get(idx))
+        .and_then(|texture| {
+            texture
+                .get("extensions")
+                .and_then(|extensions| extensions.get("MSFT_texture_dds"))
+                .and_then(|dds| dds.get("source"))
+                .or(texture.get("source"))
+                .and_then(Json::as_usize)
+        });
+
+    GltfMaterial {
+        base_color_factor,
+        base_color_image,
+    }
+}
+
+fn load_image(
+    reader: &AccessorReader,
+    image: &Json,
+    base_dir: &Path,
+) -> Result<GltfImage, Box<dyn std::error::Error>> {
+    let mime_type = image
+        .get("mimeType")
+        .and_then(Json::as_str)
+        .map(str::to_owned);
+
+    if let Some(uri) = image.get("uri").and_then(Json::as_str) {
+        let (data, uri_mime_type) = load_uri(uri, base_dir)?;
+        return Ok(GltfImage {
+            data,
+            mime_type: mime_type.or(uri_mime_type),
+        });
+    }
+
+    let view = image
+        .get("bufferView")
+        .and_then(Json::as_usize)
+        .ok_or("Image has neither uri nor bufferView")?;
+    Ok(GltfImage {
+        data: reader.buffer_view(view)?.bytes.to_vec(),
+        mime_type,
+    })
+}
+
+struct BufferView<'a> {
+    bytes: &'a [u8],
+    stride: Option<usize>,
+}
+
+// Raw bytes of every element of an accessor, empty when it has no buffer view
+struct Elements<'a> {
+    data: Vec<&'a [u8]>,
+    component_type: usize,
+    components: usize,
+    normalized: bool,
+}
+
+struct AccessorReader<'a> {
+    root: &'a Json,
+    buffers: &'a [Vec<u8>],
+}
+
+impl AccessorReader<'_> {
+    fn buffer_view(&self, view_idx: usize) -> Result<BufferView<'_>, Box<dyn std::error::Error>> {
+        let view = array(self.root, "bufferViews")
+            .get(view_idx)
+            .ok_or_else(|| format!("Buffer view {view_idx} doesn't exist"))?;
+        let buffer_idx = view.get("buffer").and_then(Json::as_usize).unwrap_or(0);
+        let offset = view.get("byteOffset").and_then(Json::as_usize).unwrap_or(0);
+        let length = view.get("byteLength").and_then(Json::as_usize).unwrap_or(0);
+        let stride = view.get("byteStride").and_then(Json::as_usize);
+
+        let bytes = self
+            .buffers
+            .get(buffer_idx)
+            .zip(offset.checked_add(length))
+            .and_then(|(buffer, end)| buffer.get(offset..end))
+            .ok_or_else(|| format!("Buffer view {view_idx} is out of bounds"))?;
+        Ok(BufferView { bytes, stride })
+    }
+
+    // `components` lists the accepted component counts of the accessor type
+    fn elements(
+        &self,
+        accessor_idx: usize,
+        components: &[usize],
+    ) -> Result<Elements<'_>, Box<dyn std::error::Error>> {
+        let accessor = array(self.root, "accessors")
+            .get(accessor_idx)
+            .ok_or_else(|| format!("Accessor {accessor_idx} doesn't exist"))?;
+        if accessor.get("sparse").is_some() {
+            return Err(
+                format!("Accessor {accessor_idx}: sparse accessors aren't supported").into(),
+            );
+        }
+
+        let component_type = accessor
+            .get("componentType")
+            .and_then(Json::as_usize)
+            .unwrap_or(0);
+        let component_size = match component_type {
+            COMPONENT_BYTE | COMPONENT_UNSIGNED_BYTE => 1,
+            COMPONENT_SHORT | COMPONENT_UNSIGNED_SHORT => 2,
+            COMPONENT_UNSIGNED_INT | COMPONENT_FLOAT => 4,
+            other => {
+                return Err(format!(
+                    "Accessor {accessor_idx}: component type {other} isn't supported"
+                )
+                .into());
+            }
+        };
+        let type_components = match accessor
+            .get("type")
+            .and_then(Json::as_str)
+            .unwrap_or_default()
+        {
+            "SCALAR" => 1,
+            "VEC2" => 2,
+            "VEC3" => 3,
+            "VEC4" => 4,
+            other => {
+                return Err(format!(
+                    "Accessor {accessor_idx}: type {other:?} isn't supported here"
+                )
+                .into());
+            }
+        };
+        if !components.contains(&type_components) {
+            return Err(format!(
+                "Accessor {accessor_idx} has {type_components} components, expected {components:?}"
+            )
+            .into());
+        }
+
+        let count = accessor.get("count").and_then(Json::as_usize).unwrap_or(0);
+        let element_size = component_size * type_components;
+        let normalized = accessor
+            .get("normalized")
+            .and_then(Json::as_bool)
+            .unwrap_or(false);
+
+        // Without a buffer view every element is zero
+        let Some(view_idx) = accessor.get("bufferView").and_then(Json::as_usize) else {
+            if count > MAX_ZERO_ELEMENTS {
+                return Err(format!(
+                    "Accessor {accessor_idx} has {count} elements without a buffer view, at most {MAX_ZERO_ELEMENTS} are supported"
+                )
+                .into());
+            }
+            return Ok(Elements {
+                data: vec![&[][..]; count],
+                component_type,
+                components: type_components,
+                normalized,
+            });
+        };
+        let BufferView { bytes, stride } = self.buffer_view(view_idx)?;
+        let stride = stride.unwrap_or(element_size);
+        let offset = accessor
+            .get("byteOffset")
+            .and_then(Json::as_usize)
+            .unwrap_or(0);
+
+        if stride < element_size {
+            return Err(format!(
+                "Accessor {accessor_idx}: stride {stride} is smaller than its {element_size} byte elements"
+            )
+            .into());
+        }
+
+        // The end of the last element bounds every other one, so `count` can't exceed
+        // what the view holds
+        let end = match count.checked_sub(1) {
+            Some(last) => last
+                .checked_mul(stride)
+                .and_then(|last_start| last_start.checked_add(offset))
+                .and_then(|last_start| last_start.checked_add(element_size)),
+            None => Some(0),
+        };
+        if end.is_none_or(|end| end > bytes.len()) {
+            return Err(format!("Accessor {accessor_idx} runs past its buffer view").into());
+        }
+        let data = (0..count)
+            .map(|idx| {
+                let start = offset + idx * stride;
+                &bytes[start..start + element_size]
+            })
+            .collect();
+
+        Ok(Elements {
+            data,
+            component_type,
+            components: type_components,
+            normalized,
+        })
+    }
+
+    // Float data with at least `min_components` per element (extra ones are kept, missing
+    // ones of zero sized elements read as 0)
+    fn read(
+        &self,
+        accessor_idx: usize,
+        min_components: usize,
+    ) -> Result<Vec<Vec<f32>>, Box<dyn std::error::Error>> {
+        let components: Vec<usize> = (min_components..=4).collect();
+        let elements = self.elements(accessor_idx, &components)?;
+
+        Ok(elements
+            .data
+            .into_iter()
+            .map(|element| {
+                if element.is_empty() {
+                    return vec![0.0; elements.components];
+                }
+                element
+                    .chunks_exact(element.len() / elements.components)
+                    .map(|component| {
+                        component_to_f32(component, elements.component_type, elements.normalized)
+                    })
+                    .collect()
+            })
+            .collect())
+    }
+
+    fn read_indices(&self, accessor_idx: usize) -> Result<Vec<u32>, Box<dyn std::error::Error>> {
+        let elements = self.elements(accessor_idx, &[1])?;
+        elements
+            .data
+            .into_iter()
+            .map(|element| match (elements.component_type, element) {
+                (_, []) => Ok(0),
+                (COMPONENT_UNSIGNED_BYTE, [a]) => Ok(*a as u32),
+                (COMPONENT_UNSIGNED_SHORT, [a, b]) => Ok(u16::from_le_bytes([*a, *b]) as u32),
+                (COMPONENT_UNSIGNED_INT, [a, b, c, d]) => Ok(u32::from_le_bytes([*a, *b, *c, *d])),
+                _ => Err(
+                    format!("Accessor {accessor_idx}: indices have to be unsigned integers").into(),
+                ),
+            })
+            .collect()
+    }
+}
+
+fn component_to_f32(bytes: &[u8], component_type: usize, normalized: bool) -> f32 {
+    let (value, max) = match (component_type, bytes) {
+        (COMPONENT_FLOAT, [a, b, c, d]) => return f32::from_le_bytes([*a, *b, *c, *d]),
+        (COMPONENT_BYTE, [a]) => (*a as i8 as f32, i8::MAX as f32),
+        (COMPONENT_UNSIGNED_BYTE, [a]) => (*a as f32, u8::MAX as f32),
+        (COMPONENT_SHORT, [a, b]) => (i16::from_le_bytes([*a, *b]) as f32, i16::MAX as f32),
+        (COMPONENT_UNSIGNED_SHORT, [a, b]) => {
+            (u16::from_le_bytes([*a, *b]) as f32, u16::MAX as f32)
+        }
+        (COMPONENT_UNSIGNED_INT, [a, b, c, d]) => {
+            (u32::from_le_bytes([*a, *b, *c, *d]) as f32, u32::MAX as f32)
+        }
+        _ => return 0.0,
+    };
+    if normalized {
+        (value / max).max(-1.0)
+    } else {
+        value
+    }
+}
+
+fn transform_point(m: Mat4, p: [f32; 3]) -> [f32; 3] {
+    let mut result = [0.0; 3];
+    for (row, value) in result.iter_mut().enumerate() {
+        *value = m[0][row] * p[0] + m[1][row] * p[1] + m[2][row] * p[2] + m[3][row];
+    }
+    result
+}
+
+fn transform_vector(m: [[f32; 3]; 3], v: [f32; 3]) -> [f32; 3] {
+    let mut result = [0.0; 3];
+    for (row, value) in result.iter_mut().enumerate() {
+        *value = m[0][row] * v[0] + m[1][row] * v[1] + m[2][row] * v[2];
+    }
+    result
+}
+
+fn determinant3(m: Mat4) -> f32 {
+    m[0][0] * (m[1][1] * m[2][2] - m[2][1] * m[1][2])
+        - m[1][0] * (m[0][1] * m[2][2] - m[2][1] * m[0][2])
+        + m[2][0] * (m[0][1] * m[1][2] - m[1][1] * m[0][2])
+}
+
+// Cofactor matrix of the upper 3x3, the inverse transpose up to a scale, which is all a
+// direction that gets normalized needs. Keeps normals perpendicular under non-uniform scale.
+fn normal_matrix(m: Mat4) -> [[f32; 3]; 3] {
+    let column = |idx: usize| [m[idx][0], m[idx][1], m[idx][2]];
+    let (a, b, c) = (column(0), column(1), column(2));
+    // Columns of the cofactor matrix are the cross products of the other two columns
+    let sign = if determinant3(m) < 0.0 { -1.0 } else { 1.0 };
+    let scaled = |v: [f32; 3]| [v[0] * sign, v[1] * sign, v[2] * sign];
+    [
+        scaled(cross(b, c)),
+        scaled(cross(c, a)),
+        scaled(cross(a, b)),
+    ]
+}
+
+fn compute_smooth_normals(vertices: &mut [Vertex], indices: &[u32]) {
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|corner| vertices[triangle[corner] as usize].position);
+        // Area weighted, bigger faces count more
+        let normal = cross(sub(b, a), sub(c, a));
+        for &index in triangle {
+            let vertex_normal = &mut vertices[index as usize].normal;
+            for axis in 0..3 {
+                vertex_normal[axis] += normal[axis];
+            }
+        }
+    }
+    for vertex in vertices {
+        vertex.normal = normalize(vertex.normal);
+    }
+}
+
+fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+// Zero vectors (degenerate triangles) stay zero
+fn normalize(v: [f32; 3]) -> [f32; 3] {
+    let length = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+    if length > 0.0 {
+        [v[0] / length, v[1] / length, v[2] / length]
+    } else {
+        v
+    }
+}
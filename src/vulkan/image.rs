@@ -0,0 +1,535 @@
+use ash::vk;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use super::{Allocation, AllocationKind, Allocator, Buffer, Device, ReadbackBuffer, submit_once};
+
+// GPU image suballocated from the device allocator, with a view covering all of its mips
+// and layers
+pub struct Image {
+    pub image: vk::Image,
+    pub memory: vk::DeviceMemory,
+    // Offset into `memory`
+    pub memory_offset: vk::DeviceSize,
+    pub view: vk::ImageView,
+    pub format: vk::Format,
+    pub extent: vk::Extent3D,
+    pub mip_levels: u32,
+    pub array_layers: u32,
+    pub usage: vk::ImageUsageFlags,
+    pub aspect: vk::ImageAspectFlags,
+    // Taken in Drop to give the range back
+    allocation: Option<(Allocation, Rc<RefCell<Allocator>>)>,
+    device: ash::Device,
+}
+
+impl Drop for Image {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_image_view(self.view, None);
+            self.device.destroy_image(self.image, None);
+        }
+        if let Some((allocation, allocator)) = self.allocation.take() {
+            allocator.borrow_mut().free(allocation);
+        }
+    }
+}
+
+impl Image {
+    pub fn new(
+        device: &Device,
+        create_info: &vk::ImageCreateInfo,
+        view_type: vk::ImageViewType,
+        aspect: vk::ImageAspectFlags,
+        memory_flags: vk::MemoryPropertyFlags,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let image = unsafe { device.device.create_image(create_info, None)? };
+
+        let requirements = unsafe { device.device.get_image_memory_requirements(image) };
+
+        // Anything but OPTIMAL tiling is laid out like a buffer as far as
+        // bufferImageGranularity goes
+        let kind = if create_info.tiling == vk::ImageTiling::OPTIMAL {
+            AllocationKind::Optimal
+        } else {
+            AllocationKind::Linear
+        };
+        let allocator = device.allocator();
+        let allocation = match allocator
+            .borrow_mut()
+            .alloc(requirements, memory_flags, kind)
+        {
+            Ok(allocation) => allocation,
+            Err(err) => {
+                unsafe { device.device.destroy_image(image, None) };
+                return Err(err);
+            }
+        };
+
+        let view = unsafe {
+            device
+                .device
+                .bind_image_memory(image, allocation.memory, allocation.offset)
+        }
+        .and_then(|_| {
+            let view_create_info = vk::ImageViewCreateInfo::default()
+                .image(image)
+                .view_type(view_type)
+                .format(create_info.format)
+                .subresource_range(vk::ImageSubresourceRange {
+                    aspect_mask: aspect,
+                    base_mip_level: 0,
+                    level_count: create_info.mip_levels,
+                    base_array_layer: 0,
+                    layer_count: create_info.array_layers,
+                });
+            unsafe { device.device.create_image_view(&view_create_info, None) }
+        });
+        let view = match view {
+            Ok(view) => view,
+            Err(err) => {
+                unsafe { device.device.destroy_image(image, None) };
+                allocator.borrow_mut().free(allocation);
+                return Err(err.into());
+            }
+        };
+
+        Ok(Self {
+            image,
+            memory: allocation.memory,
+            memory_offset: allocation.offset,
+            view,
+            format: create_info.format,
+            extent: create_info.extent,
+            mip_levels: create_info.mip_levels,
+            array_layers: create_info.array_layers,
+            usage: create_info.usage,
+            aspect,
+            allocation: Some((allocation, Rc::clone(allocator))),
+            device: device.device.clone(),
+        })
+    }
+
+    // Uploads one tightly packed pixel buffer per array layer into mip 0 and leaves every
+    // layer in SHADER_READ_ONLY_OPTIMAL
+    pub fn upload_layers(
+        &self,
+        device: &Device,
+        layers: &[&[u8]],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if layers.len() as u32 != self.array_layers {
+            return Err(format!(
+                "Got {} layers of pixel data for an image with {} layers",
+                layers.len(),
+                self.array_layers
+            )
+            .into());
+        }
+
+        let layer_size = layers.first().map_or(0, |layer| layer.len());
+        if layers.iter().any(|layer| layer.len() != layer_size) {
+            return Err("All layers must have the same amount of pixel data".into());
+        }
+
+        // One region per layer, each reading its slice of the data
+        let regions: Vec<_> = (0..self.array_layers)
+            .map(|layer| {
+                vk::BufferImageCopy::default()
+                    .buffer_offset(layer as vk::DeviceSize * layer_size as vk::DeviceSize)
+                    .image_subresource(vk::ImageSubresourceLayers {
+                        aspect_mask: self.aspect,
+                        mip_level: 0,
+                        base_array_layer: layer,
+                        layer_count: 1,
+                    })
+                    .image_extent(self.extent)
+            })
+            .collect();
+
+        self.upload_regions(device, &layers.concat(), &regions)
+    }
+
+    // Stages `data` and copies it in with `regions` (offsets relative to `data`), the whole
+    // image goes UNDEFINED -> TRANSFER_DST -> SHADER_READ_ONLY_OPTIMAL
+    pub fn upload_regions(
+        &self,
+        device: &Device,
+        data: &[u8],
+        regions: &[vk::BufferImageCopy],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let staging = Buffer::new(
+            device,
+            data.len().max(1) as vk::DeviceSize,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+        staging.write(data)?;
+
+        let range = self.subresource_range();
+
+        submit_once(device, |cmd| unsafe {
+            transition_layout(
+                &device.device,
+                cmd,
+                self.image,
+                range,
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            );
+            device.device.cmd_copy_buffer_to_image(
+                cmd,
+                staging.buffer,
+                self.image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                regions,
+            );
+            transition_layout(
+                &device.device,
+                cmd,
+                self.image,
+                range,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            );
+        })
+    }
+
+    // Copies mip 0 of layer 0 back to the host, tightly packed rows. The image has to be in
+    // `layout` and is left in it, it needs TRANSFER_SRC usage and an uncompressed color format.
+    pub fn read_pixels(
+        &self,
+        device: &Device,
+        layout: vk::ImageLayout,
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let mut readback = ReadbackBuffer::new(device, self.format, self.aspect, self.extent)?;
+
+        let mut copied = Ok(());
+        submit_once(device, |cmd| {
+            copied = readback.copy_from_image(&device.device, cmd, self.image, layout, self.extent);
+        })?;
+        copied?;
+
+        // submit_once waited for the queue and the copy ended with a host barrier
+        Ok(readback.read_packed())
+    }
+
+    // Records a copy of mip 0 (every layer) into `dst`, for ping-ponging between offscreen
+    // targets. Both images need the same format, extent and layer count, TRANSFER_SRC and
+    // TRANSFER_DST usage respectively. They are passed in `src_layout`/`dst_layout` and left
+    // in them, `dst`'s previous contents are overwritten.
+    pub fn cmd_copy_to(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        dst: &Image,
+        src_layout: vk::ImageLayout,
+        dst_layout: vk::ImageLayout,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if self.format != dst.format {
+            return Err(format!(
+                "Can't copy {:?} into {:?}, use cmd_blit_to to convert",
+                self.format, dst.format
+            )
+            .into());
+        }
+        if self.extent != dst.extent || self.array_layers != dst.array_layers {
+            return Err(format!(
+                "Can't copy {:?} x{} into {:?} x{}, use cmd_blit_to to scale",
+                self.extent, self.array_layers, dst.extent, dst.array_layers
+            )
+            .into());
+        }
+
+        let region = vk::ImageCopy::default()
+            .src_subresource(self.mip0_layers())
+            .dst_subresource(dst.mip0_layers())
+            .extent(self.extent);
+        self.cmd_transfer_to(
+            device,
+            command_buffer,
+            dst,
+            src_layout,
+            dst_layout,
+            |src, dst| unsafe {
+                device.device.cmd_copy_image(
+                    command_buffer,
+                    src,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    dst,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &[region],
+                );
+            },
+        )
+    }
+
+    // Like cmd_copy_to, but the extents and formats may differ: mip 0 is scaled to cover all
+    // of `dst`'s mip 0 with `filter`. Only the layer count has to match. LINEAR needs
+    // SAMPLED_IMAGE_FILTER_LINEAR support of the source format.
+    pub fn cmd_blit_to(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        dst: &Image,
+        src_layout: vk::ImageLayout,
+        dst_layout: vk::ImageLayout,
+        filter: vk::Filter,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if self.array_layers != dst.array_layers {
+            return Err(format!(
+                "Can't blit {} layers into {}",
+                self.array_layers, dst.array_layers
+            )
+            .into());
+        }
+        let mut src_features = vk::FormatFeatureFlags::BLIT_SRC;
+        if filter == vk::Filter::LINEAR {
+            src_features |= vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR;
+        }
+        device.find_supported_format(&[self.format], vk::ImageTiling::OPTIMAL, src_features)?;
+        device.find_supported_format(
+            &[dst.format],
+            vk::ImageTiling::OPTIMAL,
+            vk::FormatFeatureFlags::BLIT_DST,
+        )?;
+
+        let corner = |extent: vk::Extent3D| vk::Offset3D {
+            x: extent.width as i32,
+            y: extent.height as i32,
+            z: extent.depth as i32,
+        };
+        let region = vk::ImageBlit::default()
+            .src_subresource(self.mip0_layers())
+            .src_offsets([vk::Offset3D::default(), corner(self.extent)])
+            .dst_subresource(dst.mip0_layers())
+            .dst_offsets([vk::Offset3D::default(), corner(dst.extent)]);
+        self.cmd_transfer_to(
+            device,
+            command_buffer,
+            dst,
+            src_layout,
+            dst_layout,
+            |src, dst| unsafe {
+                device.device.cmd_blit_image(
+                    command_buffer,
+                    src,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    dst,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &[region],
+                    filter,
+                );
+            },
+        )
+    }
+
+    // Moves both images into the transfer layouts around `record` and back afterwards
+    fn cmd_transfer_to(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        dst: &Image,
+        src_layout: vk::ImageLayout,
+        dst_layout: vk::ImageLayout,
+        record: impl FnOnce(vk::Image, vk::Image),
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if self.image == dst.image {
+            return Err("Source and destination are the same image".into());
+        }
+        // Both layouts are restored afterwards, which UNDEFINED can't be
+        if src_layout == vk::ImageLayout::UNDEFINED || dst_layout == vk::ImageLayout::UNDEFINED {
+            return Err("Image transfers need the current layout, not UNDEFINED".into());
+        }
+
+        let src_range = vk::ImageSubresourceRange {
+            level_count: 1,
+            ..self.subresource_range()
+        };
+        let dst_range = vk::ImageSubresourceRange {
+            level_count: 1,
+            ..dst.subresource_range()
+        };
+        let transitions = [
+            (
+                self.image,
+                src_range,
+                src_layout,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            ),
+            (
+                dst.image,
+                dst_range,
+                dst_layout,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            ),
+        ];
+
+        for (image, range, old_layout, new_layout) in transitions {
+            transition_layout(
+                &device.device,
+                command_buffer,
+                image,
+                range,
+                old_layout,
+                new_layout,
+            );
+        }
+        record(self.image, dst.image);
+        for (image, range, old_layout, new_layout) in transitions {
+            transition_layout(
+                &device.device,
+                command_buffer,
+                image,
+                range,
+                new_layout,
+                old_layout,
+            );
+        }
+
+        Ok(())
+    }
+
+    fn mip0_layers(&self) -> vk::ImageSubresourceLayers {
+        vk::ImageSubresourceLayers {
+            aspect_mask: self.aspect,
+            mip_level: 0,
+            base_array_layer: 0,
+            layer_count: self.array_layers,
+        }
+    }
+
+    // Every mip and layer
+    pub fn subresource_range(&self) -> vk::ImageSubresourceRange {
+        vk::ImageSubresourceRange {
+            aspect_mask: self.aspect,
+            base_mip_level: 0,
+            level_count: self.mip_levels,
+            base_array_layer: 0,
+            layer_count: self.array_layers,
+        }
+    }
+}
+
+// Bytes per texel of common uncompressed color formats, None for anything else
+pub fn texel_size(format: vk::Format) -> Option<u32> {
+    match format {
+        vk::Format::R8_UNORM | vk::Format::R8_SNORM | vk::Format::R8_UINT | vk::Format::R8_SRGB => {
+            Some(1)
+        }
+        vk::Format::R8G8_UNORM | vk::Format::R16_SFLOAT | vk::Format::R16_UINT => Some(2),
+        vk::Format::R8G8B8A8_UNORM
+        | vk::Format::R8G8B8A8_SNORM
+        | vk::Format::R8G8B8A8_UINT
+        | vk::Format::R8G8B8A8_SRGB
+        | vk::Format::B8G8R8A8_UNORM
+        | vk::Format::B8G8R8A8_SRGB
+        | vk::Format::A2B10G10R10_UNORM_PACK32
+        | vk::Format::B10G11R11_UFLOAT_PACK32
+        | vk::Format::R16G16_SFLOAT
+        | vk::Format::R32_SFLOAT
+        | vk::Format::R32_UINT => Some(4),
+        vk::Format::R16G16B16A16_SFLOAT
+        | vk::Format::R16G16B16A16_UNORM
+        | vk::Format::R32G32_SFLOAT => Some(8),
+        vk::Format::R32G32B32A32_SFLOAT | vk::Format::R32G32B32A32_UINT => Some(16),
+        _ => None,
+    }
+}
+
+// Access mask and pipeline stage that have to be synchronized with for an image in `layout`
+pub fn layout_access_stage(layout: vk::ImageLayout) -> (vk::AccessFlags, vk::PipelineStageFlags) {
+    match layout {
+        vk::ImageLayout::UNDEFINED | vk::ImageLayout::PREINITIALIZED => (
+            vk::AccessFlags::empty(),
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+        ),
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL => (
+            vk::AccessFlags::TRANSFER_WRITE,
+            vk::PipelineStageFlags::TRANSFER,
+        ),
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL => (
+            vk::AccessFlags::TRANSFER_READ,
+            vk::PipelineStageFlags::TRANSFER,
+        ),
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL => (
+            vk::AccessFlags::SHADER_READ,
+            vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER,
+        ),
+        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL => (
+            vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        ),
+        vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL => (
+            vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+        ),
+        // A write to either aspect is synchronized like a write to both
+        vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL
+        | vk::ImageLayout::STENCIL_ATTACHMENT_OPTIMAL
+        | vk::ImageLayout::DEPTH_ATTACHMENT_STENCIL_READ_ONLY_OPTIMAL
+        | vk::ImageLayout::DEPTH_READ_ONLY_STENCIL_ATTACHMENT_OPTIMAL => (
+            vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE
+                | vk::AccessFlags::SHADER_READ,
+            vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS
+                | vk::PipelineStageFlags::FRAGMENT_SHADER,
+        ),
+        vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL
+        | vk::ImageLayout::DEPTH_READ_ONLY_OPTIMAL
+        | vk::ImageLayout::STENCIL_READ_ONLY_OPTIMAL => (
+            vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags::SHADER_READ,
+            vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS | vk::PipelineStageFlags::FRAGMENT_SHADER,
+        ),
+        vk::ImageLayout::GENERAL => (
+            vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+            vk::PipelineStageFlags::COMPUTE_SHADER | vk::PipelineStageFlags::FRAGMENT_SHADER,
+        ),
+        vk::ImageLayout::PRESENT_SRC_KHR => (
+            vk::AccessFlags::empty(),
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+        ),
+        // Anything else is synchronized conservatively
+        _ => (
+            vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE,
+            vk::PipelineStageFlags::ALL_COMMANDS,
+        ),
+    }
+}
+
+// Records a pipeline barrier moving `range` of `image` from `old_layout` to `new_layout`
+pub fn transition_layout(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+    range: vk::ImageSubresourceRange,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+) {
+    let (src_access, src_stage) = layout_access_stage(old_layout);
+    let (dst_access, dst_stage) = layout_access_stage(new_layout);
+
+    let barrier = vk::ImageMemoryBarrier::default()
+        .old_layout(old_layout)
+        .new_layout(new_layout)
+        .src_access_mask(src_access)
+        .dst_access_mask(dst_access)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image)
+        .subresource_range(range);
+
+    unsafe {
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            src_stage,
+            dst_stage,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[barrier],
+        );
+    }
+}
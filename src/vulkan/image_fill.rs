@@ -0,0 +1,168 @@
+use ash::vk;
+
+use super::scene::SHADER_DIR;
+use super::{
+    ComputePipeline, Device, Image, read_spirv, reflect_descriptors, submit_once, transition_layout,
+};
+
+// Matches local_size_x/y of shaders/fill.comp
+const WORKGROUP_SIZE: u32 = 8;
+
+// Compute demo for storage images: shaders/fill.comp writes one color to every texel,
+// see Context::compute_fill_image. Fills are synchronous, so a single descriptor set is
+// reused for every image.
+pub struct ImageFill {
+    pub pipeline: ComputePipeline,
+    pub set_layout: vk::DescriptorSetLayout,
+    pub pool: vk::DescriptorPool,
+    pub set: vk::DescriptorSet,
+    device: ash::Device,
+}
+
+impl Drop for ImageFill {
+    fn drop(&mut self) {
+        unsafe {
+            // Destroying the pool frees the set allocated from it
+            self.device.destroy_descriptor_pool(self.pool, None);
+            self.device
+                .destroy_descriptor_set_layout(self.set_layout, None);
+        }
+    }
+}
+
+impl ImageFill {
+    pub fn new(device: &Device) -> Result<Self, Box<dyn std::error::Error>> {
+        if device
+            .enabled_features
+            .shader_storage_image_write_without_format
+            != vk::TRUE
+        {
+            return Err("shaderStorageImageWriteWithoutFormat is not supported".into());
+        }
+        let spv = read_spirv(format!("{SHADER_DIR}/fill.comp.spv"))?;
+
+        // Set 0 binding 0 storage image and the color push constant, as declared by the shader
+        let reflected = reflect_descriptors(&spv)?;
+        // Released into the fill once everything else was created
+        let set_layout = reflected
+            .create_set_layouts(device)?
+            .pop()
+            .ok_or("fill.comp declares no descriptor set")?;
+        let pipeline = ComputePipeline::new(
+            device,
+            &spv,
+            &[set_layout.handle()],
+            &reflected.push_constant_ranges(),
+        )?;
+
+        let pool_sizes = [vk::DescriptorPoolSize::default()
+            .ty(vk::DescriptorType::STORAGE_IMAGE)
+            .descriptor_count(1)];
+        let pool_create_info = vk::DescriptorPoolCreateInfo::default()
+            .pool_sizes(&pool_sizes)
+            .max_sets(1);
+        let pool = unsafe {
+            device
+                .device
+                .create_descriptor_pool(&pool_create_info, None)?
+        };
+        let set_layout = set_layout.into_raw();
+
+        // Dropping on error destroys the pool and layout
+        let mut fill = Self {
+            pipeline,
+            set_layout,
+            pool,
+            set: vk::DescriptorSet::null(),
+            device: device.device.clone(),
+        };
+
+        let set_layouts = [set_layout];
+        let allocate_info = vk::DescriptorSetAllocateInfo::default()
+            .descriptor_pool(pool)
+            .set_layouts(&set_layouts);
+        fill.set = unsafe { device.device.allocate_descriptor_sets(&allocate_info)? }[0];
+
+        Ok(fill)
+    }
+
+    // Fills mip 0 of a single layer 2D `image` with `color` and leaves it in `final_layout`,
+    // blocking until the GPU is done. The previous contents are discarded. The image needs
+    // STORAGE usage and a format with STORAGE_IMAGE support, `color` is converted to that
+    // format like any shader write.
+    pub fn fill(
+        &self,
+        device: &Device,
+        image: &Image,
+        color: [f32; 4],
+        final_layout: vk::ImageLayout,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if image.mip_levels != 1 || image.array_layers != 1 || image.extent.depth != 1 {
+            return Err("Only single mip, single layer 2D images can be filled".into());
+        }
+        device.find_supported_format(
+            &[image.format],
+            vk::ImageTiling::OPTIMAL,
+            vk::FormatFeatureFlags::STORAGE_IMAGE,
+        )?;
+
+        // GENERAL is the only layout storage writes are allowed in
+        let image_info = [vk::DescriptorImageInfo::default()
+            .image_view(image.view)
+            .image_layout(vk::ImageLayout::GENERAL)];
+        let write = vk::WriteDescriptorSet::default()
+            .dst_set(self.set)
+            .dst_binding(0)
+            .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+            .image_info(&image_info);
+        unsafe { device.device.update_descriptor_sets(&[write], &[]) };
+
+        let range = image.subresource_range();
+
+        submit_once(device, |cmd| unsafe {
+            transition_layout(
+                &device.device,
+                cmd,
+                image.image,
+                range,
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageLayout::GENERAL,
+            );
+            device.device.cmd_bind_pipeline(
+                cmd,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline.pipeline,
+            );
+            device.device.cmd_bind_descriptor_sets(
+                cmd,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline.layout,
+                0,
+                &[self.set],
+                &[],
+            );
+            device.device.cmd_push_constants(
+                cmd,
+                self.pipeline.layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                super::buffer::as_bytes(&color),
+            );
+            device.device.cmd_dispatch(
+                cmd,
+                image.extent.width.div_ceil(WORKGROUP_SIZE),
+                image.extent.height.div_ceil(WORKGROUP_SIZE),
+                1,
+            );
+            // Also with GENERAL as final layout, later reads still need the barrier
+            transition_layout(
+                &device.device,
+                cmd,
+                image.image,
+                range,
+                vk::ImageLayout::GENERAL,
+                final_layout,
+            );
+        })
+    }
+}
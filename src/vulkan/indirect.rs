@@ -0,0 +1,113 @@
+use ash::{khr, vk};
+
+use super::buffer::{as_bytes, upload_device_local};
+use super::{Buffer, Device};
+
+// Array of vk::DrawIndexedIndirectCommand read by the GPU at draw time. Also a STORAGE_BUFFER
+// so a compute pass can cull and rewrite the commands.
+pub struct IndirectBuffer {
+    pub buffer: Buffer,
+    pub draw_count: u32,
+    // Without multiDrawIndirect every command needs its own draw call
+    multi_draw: bool,
+}
+
+impl IndirectBuffer {
+    pub const STRIDE: u32 = std::mem::size_of::<vk::DrawIndexedIndirectCommand>() as u32;
+
+    pub fn new(
+        device: &Device,
+        commands: &[vk::DrawIndexedIndirectCommand],
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let buffer = upload_device_local(
+            device,
+            as_bytes(commands),
+            vk::BufferUsageFlags::INDIRECT_BUFFER | vk::BufferUsageFlags::STORAGE_BUFFER,
+        )?;
+
+        Ok(Self {
+            buffer,
+            draw_count: commands.len() as u32,
+            multi_draw: device.enabled_features.multi_draw_indirect == vk::TRUE,
+        })
+    }
+
+    // Issues every command in the buffer, vertex/index buffers must already be bound
+    pub fn draw(&self, device: &ash::Device, command_buffer: vk::CommandBuffer) {
+        if self.multi_draw {
+            cmd_draw_indexed_indirect(
+                device,
+                command_buffer,
+                self.buffer.buffer,
+                0,
+                self.draw_count,
+                Self::STRIDE,
+            );
+        } else {
+            for draw in 0..self.draw_count {
+                cmd_draw_indexed_indirect(
+                    device,
+                    command_buffer,
+                    self.buffer.buffer,
+                    draw as vk::DeviceSize * Self::STRIDE as vk::DeviceSize,
+                    1,
+                    Self::STRIDE,
+                );
+            }
+        }
+    }
+}
+
+// draw_count > 1 requires the multiDrawIndirect feature
+pub fn cmd_draw_indexed_indirect(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    buffer: vk::Buffer,
+    offset: vk::DeviceSize,
+    draw_count: u32,
+    stride: u32,
+) {
+    unsafe {
+        device.cmd_draw_indexed_indirect(command_buffer, buffer, offset, draw_count, stride);
+    }
+}
+
+// VK_KHR_draw_indirect_count, the number of draws is read from a GPU buffer as well so
+// culling in a compute shader can decide how many commands are valid
+pub struct DrawIndirectCount {
+    loader: khr::draw_indirect_count::Device,
+}
+
+impl DrawIndirectCount {
+    // None when the extension isn't enabled on the device
+    pub fn new(device: &Device) -> Option<Self> {
+        if !device.is_extension_enabled(khr::draw_indirect_count::NAME) {
+            return None;
+        }
+
+        Some(Self {
+            loader: khr::draw_indirect_count::Device::new(&device.instance, &device.device),
+        })
+    }
+
+    // Draws min(count in `count_buffer` at `count_offset`, max_draw_count) commands
+    pub fn draw(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        commands: &IndirectBuffer,
+        count_buffer: vk::Buffer,
+        count_offset: vk::DeviceSize,
+    ) {
+        unsafe {
+            self.loader.cmd_draw_indexed_indirect_count(
+                command_buffer,
+                commands.buffer.buffer,
+                0,
+                count_buffer,
+                count_offset,
+                commands.draw_count,
+                IndirectBuffer::STRIDE,
+            );
+        }
+    }
+}
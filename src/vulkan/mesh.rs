@@ -0,0 +1,177 @@
+use ash::vk;
+use std::path::Path;
+
+use super::obj::parse_obj;
+use super::{Device, IndexBuffer, VertexBuffer, VertexInputBuilder};
+
+pub const VERTEX_BINDING: u32 = 0;
+pub const INSTANCE_BINDING: u32 = 1;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Vertex {
+    pub position: [f32; 3],
+    pub color: [f32; 3],
+    pub normal: [f32; 3],
+    pub uv: [f32; 2],
+}
+
+impl Vertex {
+    // Locations 0 (position), 1 (color), 6 (normal) and 7 (uv) on the per-vertex binding,
+    // 2..=5 are taken by InstanceData
+    pub fn vertex_input(builder: VertexInputBuilder) -> VertexInputBuilder {
+        builder
+            .binding(
+                VERTEX_BINDING,
+                std::mem::size_of::<Self>() as u32,
+                vk::VertexInputRate::VERTEX,
+            )
+            .attribute(
+                VERTEX_BINDING,
+                0,
+                vk::Format::R32G32B32_SFLOAT,
+                std::mem::offset_of!(Self, position) as u32,
+            )
+            .attribute(
+                VERTEX_BINDING,
+                1,
+                vk::Format::R32G32B32_SFLOAT,
+                std::mem::offset_of!(Self, color) as u32,
+            )
+            .attribute(
+                VERTEX_BINDING,
+                6,
+                vk::Format::R32G32B32_SFLOAT,
+                std::mem::offset_of!(Self, normal) as u32,
+            )
+            .attribute(
+                VERTEX_BINDING,
+                7,
+                vk::Format::R32G32_SFLOAT,
+                std::mem::offset_of!(Self, uv) as u32,
+            )
+    }
+}
+
+// Per-instance data advanced once per instance instead of once per vertex
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct InstanceData {
+    // Column major, columns go to locations 2..=5
+    pub model: [[f32; 4]; 4],
+}
+
+impl InstanceData {
+    pub fn vertex_input(builder: VertexInputBuilder) -> VertexInputBuilder {
+        builder
+            .binding(
+                INSTANCE_BINDING,
+                std::mem::size_of::<Self>() as u32,
+                vk::VertexInputRate::INSTANCE,
+            )
+            .mat4_attribute(
+                INSTANCE_BINDING,
+                2,
+                std::mem::offset_of!(Self, model) as u32,
+            )
+    }
+
+    // Uniform scale followed by a translation
+    pub fn from_translation_scale(translation: [f32; 3], scale: f32) -> Self {
+        let [x, y, z] = translation;
+        Self {
+            model: [
+                [scale, 0.0, 0.0, 0.0],
+                [0.0, scale, 0.0, 0.0],
+                [0.0, 0.0, scale, 0.0],
+                [x, y, z, 1.0],
+            ],
+        }
+    }
+}
+
+pub struct Mesh {
+    pub vertices: VertexBuffer,
+    pub indices: IndexBuffer,
+}
+
+impl Mesh {
+    pub fn new(
+        device: &Device,
+        vertices: &[Vertex],
+        indices: &[u32],
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self {
+            vertices: VertexBuffer::new(device, vertices)?,
+            indices: IndexBuffer::new(device, indices)?,
+        })
+    }
+
+    // Loads a Wavefront OBJ, see obj::parse_obj for what is supported
+    pub fn from_obj(
+        device: &Device,
+        path: impl AsRef<Path>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read {}: {err}", path.display()))?;
+        let (vertices, indices) =
+            parse_obj(&source).map_err(|err| format!("{}: {err}", path.display()))?;
+
+        Self::new(device, &vertices, &indices)
+    }
+
+    // Binds the mesh and draws it once per entry of `instances`
+    pub fn draw_instanced(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        instances: &VertexBuffer,
+    ) {
+        unsafe {
+            device.cmd_bind_vertex_buffers(
+                command_buffer,
+                VERTEX_BINDING,
+                &[self.vertices.buffer.buffer, instances.buffer.buffer],
+                &[0, 0],
+            );
+            device.cmd_bind_index_buffer(
+                command_buffer,
+                self.indices.buffer.buffer,
+                0,
+                IndexBuffer::INDEX_TYPE,
+            );
+        }
+
+        cmd_draw_indexed(
+            device,
+            command_buffer,
+            self.indices.count,
+            instances.count,
+            0,
+            0,
+            0,
+        );
+    }
+}
+
+pub fn cmd_draw_indexed(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    index_count: u32,
+    instance_count: u32,
+    first_index: u32,
+    vertex_offset: i32,
+    first_instance: u32,
+) {
+    unsafe {
+        device.cmd_draw_indexed(
+            command_buffer,
+            index_count,
+            instance_count,
+            first_index,
+            vertex_offset,
+            first_instance,
+        );
+    }
+}
@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+
+use super::Vertex;
+
+// Position, texcoord and normal indices of a face corner, plus the face for flat normals
+type VertexKey = (usize, Option<usize>, Option<usize>, Option<usize>);
+
+// Wavefront OBJ geometry: v (with the optional "v x y z r g b" color extension), vt, vn and
+// f with any of the v, v/vt, v//vn and v/vt/vn forms, negative indices allowed. Polygons are
+// fan triangulated, faces without normals get a flat one. Everything else (o, g, s, usemtl,
+// mtllib, ...) is ignored.
+pub fn parse_obj(source: &str) -> Result<(Vec<Vertex>, Vec<u32>), Box<dyn std::error::Error>> {
+    let mut positions: Vec<[f32; 3]> = Vec::new();
+    let mut colors: Vec<[f32; 3]> = Vec::new();
+    let mut uvs: Vec<[f32; 2]> = Vec::new();
+    let mut normals: Vec<[f32; 3]> = Vec::new();
+
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    // Same corner of the same face data shares one vertex. Flat normals are per face, so
+    // those corners also key on the face.
+    let mut unique: HashMap<VertexKey, u32> = HashMap::new();
+
+    for (line_idx, line) in source.lines().enumerate() {
+        let line_number = line_idx + 1;
+        let line = line.split('#').next().unwrap_or_default();
+        let mut tokens = line.split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            continue;
+        };
+        let args: Vec<&str> = tokens.collect();
+        let error = |message: String| format!("OBJ line {line_number}: {message}");
+
+        match keyword {
+            "v" => {
+                let values = parse_floats(&args).map_err(error)?;
+                match values.len() {
+                    3 | 4 => colors.push([1.0, 1.0, 1.0]),
+                    6 => colors.push([values[3], values[4], values[5]]),
+                    count => {
+                        return Err(error(format!("v needs 3, 4 or 6 values, got {count}")).into());
+                    }
+                }
+                positions.push([values[0], values[1], values[2]]);
+            }
+            "vt" => {
+                let values = parse_floats(&args).map_err(error)?;
+                if values.is_empty() || values.len() > 3 {
+                    return Err(
+                        error(format!("vt needs 1 to 3 values, got {}", values.len())).into(),
+                    );
+                }
+                // OBJ has v = 0 at the bottom, Vulkan samples with v = 0 at the top
+                uvs.push([values[0], 1.0 - values.get(1).copied().unwrap_or(0.0)]);
+            }
+            "vn" => {
+                let values = parse_floats(&args).map_err(error)?;
+                if values.len() != 3 {
+                    return Err(error(format!("vn needs 3 values, got {}", values.len())).into());
+                }
+                normals.push([values[0], values[1], values[2]]);
+            }
+            "f" => {
+                if args.len() < 3 {
+                    return Err(
+                        error(format!("f needs at least 3 corners, got {}", args.len())).into(),
+                    );
+                }
+
+                let corners = args
+                    .iter()
+                    .map(|corner| parse_corner(corner, positions.len(), uvs.len(), normals.len()))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(error)?;
+
+                let flat_normal = corners.iter().any(|corner| corner.2.is_none()).then(|| {
+                    face_normal(
+                        positions[corners[0].0],
+                        positions[corners[1].0],
+                        positions[corners[2].0],
+                    )
+                });
+                let face = flat_normal.map(|_| line_idx);
+
+                let mut face_indices = Vec::with_capacity(corners.len());
+                for &(position, uv, normal) in &corners {
+                    let key = (position, uv, normal, face.filter(|_| normal.is_none()));
+                    let index = *unique.entry(key).or_insert_with(|| {
+                        vertices.push(Vertex {
+                            position: positions[position],
+                            color: colors[position],
+                            normal: normal
+                                .map_or(flat_normal.unwrap_or_default(), |idx| normals[idx]),
+                            uv: uv.map_or([0.0, 0.0], |idx| uvs[idx]),
+                        });
+                        vertices.len() as u32 - 1
+                    });
+                    face_indices.push(index);
+                }
+
+                // Fan around the first corner, exact for convex polygons
+                for idx in 1..face_indices.len() - 1 {
+                    indices.extend([face_indices[0], face_indices[idx], face_indices[idx + 1]]);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if indices.is_empty() {
+        return Err("OBJ contains no faces".into());
+    }
+
+    Ok((vertices, indices))
+}
+
+fn parse_floats(args: &[&str]) -> Result<Vec<f32>, String> {
+    args.iter()
+        .map(|arg| {
+            arg.parse::<f32>()
+                .map_err(|_| format!("invalid number {arg:?}"))
+        })
+        .collect()
+}
+
+// "v", "v/vt", "v//vn" or "v/vt/vn" into zero based (position, uv, normal) indices
+fn parse_corner(
+    corner: &str,
+    position_count: usize,
+    uv_count: usize,
+    normal_count: usize,
+) -> Result<(usize, Option<usize>, Option<usize>), String> {
+    let mut parts = corner.split('/');
+    let position = parts
+        .next()
+        .filter(|part| !part.is_empty())
+        .ok_or_else(|| format!("face corner {corner:?} has no position"))?;
+    let position = resolve_index(position, position_count, "position")?;
+
+    let uv = match parts.next() {
+        Some(part) if !part.is_empty() => Some(resolve_index(part, uv_count, "texcoord")?),
+        _ => None,
+    };
+    let normal = match parts.next() {
+        Some(part) if !part.is_empty() => Some(resolve_index(part, normal_count, "normal")?),
+        _ => None,
+    };
+
+    if parts.next().is_some() {
+        return Err(format!("face corner {corner:?} has too many parts"));
+    }
+
+    Ok((position, uv, normal))
+}
+
+// OBJ indices are one based, negative ones count back from the last element so far
+fn resolve_index(index: &str, count: usize, kind: &str) -> Result<usize, String> {
+    let value: i64 = index
+        .parse()
+        .map_err(|_| format!("invalid {kind} index {index:?}"))?;
+
+    let resolved = match value {
+        1.. => value - 1,
+        ..0 => count as i64 + value,
+        0 => return Err(format!("{kind} index 0, OBJ indices start at 1")),
+    };
+
+    if resolved < 0 || resolved >= count as i64 {
+        return Err(format!(
+            "{kind} index {value} out of range, {count} defined so far"
+        ));
+    }
+
+    Ok(resolved as usize)
+}
+
+// Counter-clockwise winding faces towards the viewer
+fn face_normal(a: [f32; 3], b: [f32; 3], c: [f32; 3]) -> [f32; 3] {
+    let u = [b[0] - a[0], b[1] - a[1], b[2] - a[2]];
+    let v = [c[0] - a[0], c[1] - a[1], c[2] - a[2]];
+    let normal = [
+        u[1] * v[2] - u[2] * v[1],
+        u[2] * v[0] - u[0] * v[2],
+        u[0] * v[1] - u[1] * v[0],
+    ];
+
+    let length = (normal[0] * normal[0] + normal[1] * normal[1] + normal[2] * normal[2]).sqrt();
+    if length > f32::EPSILON {
+        [normal[0] / length, normal[1] / length, normal[2] / length]
+    } else {
+        // Degenerate triangle
+        [0.0, 0.0, 1.0]
+    }
+}
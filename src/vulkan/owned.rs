@@ -0,0 +1,152 @@
+use ash::vk;
+
+// Raw Vulkan handle destroyed on drop, for objects that don't have a wrapper of their own
+// (ad-hoc objects in examples, or partially built ones on error paths). Like the other
+// wrappers it must be dropped before the device it was created on.
+pub struct Owned<T: Copy> {
+    handle: T,
+    // None once into_raw gave up ownership
+    destroy: Option<Box<dyn FnOnce(T)>>,
+}
+
+impl<T: Copy> Drop for Owned<T> {
+    fn drop(&mut self) {
+        if let Some(destroy) = self.destroy.take() {
+            destroy(self.handle);
+        }
+    }
+}
+
+impl<T: Copy> Owned<T> {
+    // `destroy` runs exactly once, when the guard is dropped
+    pub fn new(handle: T, destroy: impl FnOnce(T) + 'static) -> Self {
+        Self {
+            handle,
+            destroy: Some(Box::new(destroy)),
+        }
+    }
+
+    // Still owned by the guard, don't destroy it
+    pub fn handle(&self) -> T {
+        self.handle
+    }
+
+    // Releases the handle without destroying it, the caller is responsible for it again
+    pub fn into_raw(mut self) -> T {
+        self.destroy = None;
+        self.handle
+    }
+}
+
+pub type OwnedBuffer = Owned<vk::Buffer>;
+pub type OwnedImage = Owned<vk::Image>;
+pub type OwnedImageView = Owned<vk::ImageView>;
+pub type OwnedSampler = Owned<vk::Sampler>;
+pub type OwnedShaderModule = Owned<vk::ShaderModule>;
+pub type OwnedPipeline = Owned<vk::Pipeline>;
+pub type OwnedPipelineLayout = Owned<vk::PipelineLayout>;
+pub type OwnedDescriptorSetLayout = Owned<vk::DescriptorSetLayout>;
+pub type OwnedDescriptorPool = Owned<vk::DescriptorPool>;
+pub type OwnedSemaphore = Owned<vk::Semaphore>;
+pub type OwnedFence = Owned<vk::Fence>;
+
+impl OwnedBuffer {
+    pub fn buffer(device: &ash::Device, buffer: vk::Buffer) -> Self {
+        let device = device.clone();
+        Self::new(buffer, move |buffer| unsafe {
+            device.destroy_buffer(buffer, None)
+        })
+    }
+}
+
+impl OwnedImage {
+    // Only the image, memory bound to it is freed separately
+    pub fn image(device: &ash::Device, image: vk::Image) -> Self {
+        let device = device.clone();
+        Self::new(image, move |image| unsafe {
+            device.destroy_image(image, None)
+        })
+    }
+}
+
+impl OwnedImageView {
+    pub fn image_view(device: &ash::Device, view: vk::ImageView) -> Self {
+        let device = device.clone();
+        Self::new(view, move |view| unsafe {
+            device.destroy_image_view(view, None)
+        })
+    }
+}
+
+impl OwnedSampler {
+    pub fn sampler(device: &ash::Device, sampler: vk::Sampler) -> Self {
+        let device = device.clone();
+        Self::new(sampler, move |sampler| unsafe {
+            device.destroy_sampler(sampler, None)
+        })
+    }
+}
+
+impl OwnedShaderModule {
+    pub fn shader_module(device: &ash::Device, module: vk::ShaderModule) -> Self {
+        let device = device.clone();
+        Self::new(module, move |module| unsafe {
+            device.destroy_shader_module(module, None)
+        })
+    }
+}
+
+impl OwnedPipeline {
+    pub fn pipeline(device: &ash::Device, pipeline: vk::Pipeline) -> Self {
+        let device = device.clone();
+        Self::new(pipeline, move |pipeline| unsafe {
+            device.destroy_pipeline(pipeline, None)
+        })
+    }
+}
+
+impl OwnedPipelineLayout {
+    pub fn pipeline_layout(device: &ash::Device, layout: vk::PipelineLayout) -> Self {
+        let device = device.clone();
+        Self::new(layout, move |layout| unsafe {
+            device.destroy_pipeline_layout(layout, None)
+        })
+    }
+}
+
+impl OwnedDescriptorSetLayout {
+    pub fn descriptor_set_layout(device: &ash::Device, layout: vk::DescriptorSetLayout) -> Self {
+        let device = device.clone();
+        Self::new(layout, move |layout| unsafe {
+            device.destroy_descriptor_set_layout(layout, None)
+        })
+    }
+}
+
+impl OwnedDescriptorPool {
+    // Destroying the pool frees the sets allocated from it
+    pub fn descriptor_pool(device: &ash::Device, pool: vk::DescriptorPool) -> Self {
+        let device = device.clone();
+        Self::new(pool, move |pool| unsafe {
+            device.destroy_descriptor_pool(pool, None)
+        })
+    }
+}
+
+impl OwnedSemaphore {
+    pub fn semaphore(device: &ash::Device, semaphore: vk::Semaphore) -> Self {
+        let device = device.clone();
+        Self::new(semaphore, move |semaphore| unsafe {
+            device.destroy_semaphore(semaphore, None)
+        })
+    }
+}
+
+impl OwnedFence {
+    pub fn fence(device: &ash::Device, fence: vk::Fence) -> Self {
+        let device = device.clone();
+        Self::new(fence, move |fence| unsafe {
+            device.destroy_fence(fence, None)
+        })
+    }
+}
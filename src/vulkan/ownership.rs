@@ -0,0 +1,140 @@
+use ash::vk;
+
+use super::sync::resize_semaphores;
+use super::{CommandPool, Device};
+
+// Hands EXCLUSIVE swapchain images from the graphics to the present queue family. The
+// graphics command buffer releases the image, a small command buffer on the present queue
+// acquires it before presenting. No transfer back is needed, the render pass starts from
+// UNDEFINED and discards the old contents anyway.
+pub struct OwnershipTransfer {
+    // One buffer per frame in flight on the present queue family
+    pub command_pool: CommandPool,
+    // Signaled by the acquire submission, presentation waits on it. Per swapchain image
+    // like FrameSync::render_finished, for the same reason.
+    pub acquired: Vec<vk::Semaphore>,
+    pub graphics_queue_family_idx: u32,
+    pub present_queue_family_idx: u32,
+    device: ash::Device,
+}
+
+impl Drop for OwnershipTransfer {
+    fn drop(&mut self) {
+        unsafe {
+            for &semaphore in &self.acquired {
+                self.device.destroy_semaphore(semaphore, None);
+            }
+        }
+    }
+}
+
+impl OwnershipTransfer {
+    // None when graphics and present share a queue family, nothing to transfer then
+    pub fn new(
+        device: &Device,
+        frames_in_flight: usize,
+        image_count: usize,
+    ) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        if device.graphics_queue_family_idx == device.present_queue_family_idx {
+            return Ok(None);
+        }
+
+        let command_pool = CommandPool::new(
+            device,
+            device.present_queue_family_idx,
+            frames_in_flight as u32,
+        )?;
+
+        // Dropping on error destroys whatever was already created
+        let mut transfer = Self {
+            command_pool,
+            acquired: Vec::with_capacity(image_count),
+            graphics_queue_family_idx: device.graphics_queue_family_idx,
+            present_queue_family_idx: device.present_queue_family_idx,
+            device: device.device.clone(),
+        };
+
+        resize_semaphores(&transfer.device, &mut transfer.acquired, image_count)?;
+
+        Ok(Some(transfer))
+    }
+
+    // After swapchain recreation, while the device is idle
+    pub fn set_image_count(
+        &mut self,
+        image_count: usize,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        resize_semaphores(&self.device, &mut self.acquired, image_count)
+    }
+
+    // Release half, recorded in the graphics command buffer after the render pass left
+    // the image in PRESENT_SRC_KHR
+    pub fn cmd_release(&self, command_buffer: vk::CommandBuffer, image: vk::Image) {
+        let barrier = self
+            .barrier(image)
+            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .dst_access_mask(vk::AccessFlags::empty());
+
+        unsafe {
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[barrier],
+            );
+        }
+    }
+
+    // Records the acquire half into the frame's present queue command buffer. The
+    // submission has to wait on the graphics submission's semaphore.
+    pub fn record_acquire(
+        &self,
+        frame: usize,
+        image: vk::Image,
+    ) -> Result<vk::CommandBuffer, Box<dyn std::error::Error>> {
+        let command_buffer = self.command_pool.buffers[frame];
+
+        // Access masks are ignored for the acquire, visibility comes from the semaphore
+        let barrier = self.barrier(image);
+
+        unsafe {
+            self.device
+                .reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())?;
+            self.device
+                .begin_command_buffer(command_buffer, &vk::CommandBufferBeginInfo::default())?;
+            // The present family may not support graphics stages, stick to TOP/BOTTOM
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[barrier],
+            );
+            self.device.end_command_buffer(command_buffer)?;
+        }
+
+        Ok(command_buffer)
+    }
+
+    // Release and acquire must use identical layouts, families and range
+    fn barrier(&self, image: vk::Image) -> vk::ImageMemoryBarrier<'static> {
+        vk::ImageMemoryBarrier::default()
+            .old_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+            .new_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+            .src_queue_family_index(self.graphics_queue_family_idx)
+            .dst_queue_family_index(self.present_queue_family_idx)
+            .image(image)
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            })
+    }
+}
@@ -0,0 +1,545 @@
+use ash::vk;
+use std::path::Path;
+
+use super::Device;
+use super::shading_rate::{KEEP_PIPELINE_RATE, supported_fragment_size};
+
+pub(super) const SPIRV_MAGIC: u32 = 0x0723_0203;
+
+// Reads a compiled SPIR-V file (see shaders/compile.sh)
+pub fn read_spirv(path: impl AsRef<Path>) -> Result<Vec<u32>, Box<dyn std::error::Error>> {
+    let path = path.as_ref();
+    let mut file = std::fs::File::open(path)
+        .map_err(|err| format!("Failed to open shader {}: {err}", path.display()))?;
+    let code = ash::util::read_spv(&mut file)?;
+
+    // Catches truncated or half written files before they reach the driver
+    if code.first() != Some(&SPIRV_MAGIC) {
+        return Err(format!("{} is not a SPIR-V module", path.display()).into());
+    }
+
+    Ok(code)
+}
+
+pub fn create_shader_module(
+    device: &Device,
+    code: &[u32],
+) -> Result<vk::ShaderModule, Box<dyn std::error::Error>> {
+    let create_info = vk::ShaderModuleCreateInfo::default().code(code);
+    Ok(unsafe { device.device.create_shader_module(&create_info, None)? })
+}
+
+// Vertex buffer bindings and the attributes read from them, every binding has its own
+// stride and input rate (per vertex or per instance)
+#[derive(Clone, Default)]
+pub struct VertexInputBuilder {
+    pub bindings: Vec<vk::VertexInputBindingDescription>,
+    pub attributes: Vec<vk::VertexInputAttributeDescription>,
+}
+
+impl VertexInputBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn binding(mut self, binding: u32, stride: u32, input_rate: vk::VertexInputRate) -> Self {
+        self.bindings.push(vk::VertexInputBindingDescription {
+            binding,
+            stride,
+            input_rate,
+        });
+        self
+    }
+
+    pub fn attribute(
+        mut self,
+        binding: u32,
+        location: u32,
+        format: vk::Format,
+        offset: u32,
+    ) -> Self {
+        self.attributes.push(vk::VertexInputAttributeDescription {
+            location,
+            binding,
+            format,
+            offset,
+        });
+        self
+    }
+
+    // A mat4 input occupies 4 consecutive locations, one vec4 column each
+    pub fn mat4_attribute(mut self, binding: u32, location: u32, offset: u32) -> Self {
+        for column in 0..4 {
+            self = self.attribute(
+                binding,
+                location + column,
+                vk::Format::R32G32B32A32_SFLOAT,
+                offset + column * 16,
+            );
+        }
+        self
+    }
+}
+
+pub struct GraphicsPipeline {
+    pub pipeline: vk::Pipeline,
+    pub layout: vk::PipelineLayout,
+    device: ash::Device,
+}
+
+impl Drop for GraphicsPipeline {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_pipeline(self.pipeline, None);
+            self.device.destroy_pipeline_layout(self.layout, None);
+        }
+    }
+}
+
+impl GraphicsPipeline {
+    pub fn builder(vertex_spv: Vec<u32>, fragment_spv: Vec<u32>) -> GraphicsPipelineBuilder {
+        GraphicsPipelineBuilder {
+            stages: vec![
+                (vk::ShaderStageFlags::VERTEX, vertex_spv),
+                (vk::ShaderStageFlags::FRAGMENT, fragment_spv),
+            ],
+            patch_control_points: 0,
+            vertex_input: VertexInputBuilder::new(),
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            polygon_mode: vk::PolygonMode::FILL,
+            cull_mode: vk::CullModeFlags::BACK,
+            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+            depth_test: false,
+            depth_write: false,
+            depth_compare_op: vk::CompareOp::LESS,
+            stencil: None,
+            color_attachment_count: 1,
+            depth_bias: None,
+            samples: vk::SampleCountFlags::TYPE_1,
+            min_sample_shading: None,
+            fragment_shading_rate: None,
+            set_layouts: Vec::new(),
+            push_constant_ranges: Vec::new(),
+        }
+    }
+}
+
+// Viewport and scissor are dynamic state, so pipelines survive swapchain resizes
+pub struct GraphicsPipelineBuilder {
+    // SPIR-V per stage, every module's entry point is "main"
+    stages: Vec<(vk::ShaderStageFlags, Vec<u32>)>,
+    // Only used with tessellation stages
+    patch_control_points: u32,
+    vertex_input: VertexInputBuilder,
+    topology: vk::PrimitiveTopology,
+    polygon_mode: vk::PolygonMode,
+    cull_mode: vk::CullModeFlags,
+    front_face: vk::FrontFace,
+    depth_test: bool,
+    depth_write: bool,
+    depth_compare_op: vk::CompareOp,
+    // Front and back face state, None leaves the stencil test disabled
+    stencil: Option<(vk::StencilOpState, vk::StencilOpState)>,
+    // Has to match the subpass, one blend state is generated per attachment
+    color_attachment_count: u32,
+    // (constant factor, slope factor), None disables depth bias
+    depth_bias: Option<(f32, f32)>,
+    // Has to match the subpass' attachments, see RenderPass::multisampled
+    samples: vk::SampleCountFlags,
+    // minSampleShading, None shades once per pixel
+    min_sample_shading: Option<f32>,
+    // Pipeline fragment size, None without a shading rate (see fragment_shading_rate)
+    fragment_shading_rate: Option<vk::Extent2D>,
+    set_layouts: Vec<vk::DescriptorSetLayout>,
+    push_constant_ranges: Vec<vk::PushConstantRange>,
+}
+
+impl GraphicsPipelineBuilder {
+    // Adds a stage or replaces the SPIR-V of one already set
+    pub fn shader_stage(mut self, stage: vk::ShaderStageFlags, spv: Vec<u32>) -> Self {
+        match self
+            .stages
+            .iter_mut()
+            .find(|(existing, _)| *existing == stage)
+        {
+            Some((_, existing_spv)) => *existing_spv = spv,
+            None => self.stages.push((stage, spv)),
+        }
+        self
+    }
+
+    // Needs the geometryShader device feature
+    pub fn geometry_shader(self, spv: Vec<u32>) -> Self {
+        self.shader_stage(vk::ShaderStageFlags::GEOMETRY, spv)
+    }
+
+    // Needs the tessellationShader device feature and a PATCH_LIST topology, each patch
+    // has `patch_control_points` vertices
+    pub fn tessellation_shaders(
+        self,
+        control_spv: Vec<u32>,
+        evaluation_spv: Vec<u32>,
+        patch_control_points: u32,
+    ) -> Self {
+        let mut builder = self
+            .shader_stage(vk::ShaderStageFlags::TESSELLATION_CONTROL, control_spv)
+            .shader_stage(
+                vk::ShaderStageFlags::TESSELLATION_EVALUATION,
+                evaluation_spv,
+            );
+        builder.patch_control_points = patch_control_points;
+        builder
+    }
+
+    pub fn vertex_input(mut self, vertex_input: VertexInputBuilder) -> Self {
+        self.vertex_input = vertex_input;
+        self
+    }
+
+    pub fn topology(mut self, topology: vk::PrimitiveTopology) -> Self {
+        self.topology = topology;
+        self
+    }
+
+    pub fn polygon_mode(mut self, polygon_mode: vk::PolygonMode) -> Self {
+        self.polygon_mode = polygon_mode;
+        self
+    }
+
+    pub fn cull_mode(mut self, cull_mode: vk::CullModeFlags, front_face: vk::FrontFace) -> Self {
+        self.cull_mode = cull_mode;
+        self.front_face = front_face;
+        self
+    }
+
+    pub fn depth_test(mut self, test: bool, write: bool, compare_op: vk::CompareOp) -> Self {
+        self.depth_test = test;
+        self.depth_write = write;
+        self.depth_compare_op = compare_op;
+        self
+    }
+
+    // Compare op, fail/pass/depth-fail ops, masks and reference per face. Only has an
+    // effect when the render pass' depth attachment has a stencil component.
+    pub fn stencil_test(mut self, front: vk::StencilOpState, back: vk::StencilOpState) -> Self {
+        self.stencil = Some((front, back));
+        self
+    }
+
+    // Pushes rasterized depth away from the light in shadow map passes, so surfaces don't
+    // shadow themselves (shadow acne). Slope scaled bias grows with the surface angle.
+    pub fn depth_bias(mut self, constant_factor: f32, slope_factor: f32) -> Self {
+        self.depth_bias = Some((constant_factor, slope_factor));
+        self
+    }
+
+    pub fn samples(mut self, samples: vk::SampleCountFlags) -> Self {
+        self.samples = samples;
+        self
+    }
+
+    // Runs the fragment shader for at least `min_fraction` (0..=1) of the samples instead
+    // of once per pixel, which also antialiases what the shader computes (alpha tested
+    // foliage, specular highlights), not just the geometry edges. Costs up to `samples`
+    // times the fragment work, needs the sampleRateShading feature.
+    pub fn sample_shading(mut self, min_fraction: f32) -> Self {
+        self.min_sample_shading = Some(min_fraction);
+        self
+    }
+
+    // Shades `size` pixels (e.g. 2x2) per fragment shader invocation, rounded down to a
+    // size the device supports. The rate is dynamic state, so FragmentShadingRate::cmd_set
+    // has to be recorded before drawing with the pipeline, `size` is only its default.
+    // Ignored when the device has no fragment shading rates, can't be combined with
+    // sample_shading.
+    pub fn fragment_shading_rate(mut self, size: vk::Extent2D) -> Self {
+        self.fragment_shading_rate = Some(size);
+        self
+    }
+
+    // For multiple render targets, e.g. RenderPass::offscreen with GBuffer::FORMATS. 0 for
+    // depth-only passes.
+    pub fn color_attachments(mut self, count: u32) -> Self {
+        self.color_attachment_count = count;
+        self
+    }
+
+    pub fn descriptor_set_layouts(mut self, set_layouts: &[vk::DescriptorSetLayout]) -> Self {
+        self.set_layouts = set_layouts.to_vec();
+        self
+    }
+
+    pub fn push_constant_range(mut self, range: vk::PushConstantRange) -> Self {
+        self.push_constant_ranges.push(range);
+        self
+    }
+
+    pub fn build(
+        &self,
+        device: &Device,
+        render_pass: vk::RenderPass,
+        subpass: u32,
+    ) -> Result<GraphicsPipeline, Box<dyn std::error::Error>> {
+        self.validate_stages(device)?;
+
+        let layout_create_info = vk::PipelineLayoutCreateInfo::default()
+            .set_layouts(&self.set_layouts)
+            .push_constant_ranges(&self.push_constant_ranges);
+        let layout = unsafe {
+            device
+                .device
+                .create_pipeline_layout(&layout_create_info, None)?
+        };
+
+        let result = self.create_pipeline(device, layout, render_pass, subpass);
+
+        match result {
+            Ok(pipeline) => Ok(GraphicsPipeline {
+                pipeline,
+                layout,
+                device: device.device.clone(),
+            }),
+            Err(err) => {
+                unsafe { device.device.destroy_pipeline_layout(layout, None) };
+                Err(err)
+            }
+        }
+    }
+
+    // Catches what would otherwise be validation errors or a driver crash
+    fn validate_stages(&self, device: &Device) -> Result<(), Box<dyn std::error::Error>> {
+        let has_stage = |stage| self.stages.iter().any(|(existing, _)| *existing == stage);
+
+        if !has_stage(vk::ShaderStageFlags::VERTEX) {
+            return Err("Graphics pipeline needs a vertex shader".into());
+        }
+
+        let max_color_attachments = device.properties.limits.max_color_attachments;
+        if self.color_attachment_count > max_color_attachments {
+            return Err(format!(
+                "{} color attachments requested, the device supports {max_color_attachments}",
+                self.color_attachment_count
+            )
+            .into());
+        }
+
+        if let Some(min_fraction) = self.min_sample_shading {
+            if device.enabled_features.sample_rate_shading != vk::TRUE {
+                return Err("Sample shading requested, but sampleRateShading isn't enabled".into());
+            }
+            if !(0.0..=1.0).contains(&min_fraction) {
+                return Err(format!("Min sample shading {min_fraction} outside of [0, 1]").into());
+            }
+        }
+
+        if self.fragment_shading_rate.is_some() && self.min_sample_shading.is_some() {
+            return Err("Fragment shading rates can't be combined with sample shading".into());
+        }
+
+        if has_stage(vk::ShaderStageFlags::GEOMETRY)
+            && device.enabled_features.geometry_shader != vk::TRUE
+        {
+            return Err("Geometry shader requested, but geometryShader isn't enabled".into());
+        }
+
+        let has_control = has_stage(vk::ShaderStageFlags::TESSELLATION_CONTROL);
+        let has_evaluation = has_stage(vk::ShaderStageFlags::TESSELLATION_EVALUATION);
+        if has_control || has_evaluation {
+            if device.enabled_features.tessellation_shader != vk::TRUE {
+                return Err(
+                    "Tessellation shaders requested, but tessellationShader isn't enabled".into(),
+                );
+            }
+            if !(has_control && has_evaluation) {
+                return Err("Tessellation needs both control and evaluation shaders".into());
+            }
+            if self.topology != vk::PrimitiveTopology::PATCH_LIST {
+                return Err(format!(
+                    "Tessellation needs PATCH_LIST topology, got {:?}",
+                    self.topology
+                )
+                .into());
+            }
+            let max_patch_size = device.properties.limits.max_tessellation_patch_size;
+            if self.patch_control_points == 0 || self.patch_control_points > max_patch_size {
+                return Err(format!(
+                    "Patch control points {} outside of supported range 1..={max_patch_size}",
+                    self.patch_control_points
+                )
+                .into());
+            }
+        } else if self.topology == vk::PrimitiveTopology::PATCH_LIST {
+            return Err("PATCH_LIST topology needs tessellation shaders".into());
+        }
+
+        Ok(())
+    }
+
+    fn create_pipeline(
+        &self,
+        device: &Device,
+        layout: vk::PipelineLayout,
+        render_pass: vk::RenderPass,
+        subpass: u32,
+    ) -> Result<vk::Pipeline, Box<dyn std::error::Error>> {
+        let mut modules = Vec::with_capacity(self.stages.len());
+        for (_, spv) in &self.stages {
+            match create_shader_module(device, spv) {
+                Ok(module) => modules.push(module),
+                Err(err) => {
+                    for &module in &modules {
+                        unsafe { device.device.destroy_shader_module(module, None) };
+                    }
+                    return Err(err);
+                }
+            }
+        }
+
+        let stages: Vec<_> = self
+            .stages
+            .iter()
+            .zip(&modules)
+            .map(|(&(stage, _), &module)| {
+                vk::PipelineShaderStageCreateInfo::default()
+                    .stage(stage)
+                    .module(module)
+                    .name(c"main")
+            })
+            .collect();
+
+        let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::default()
+            .vertex_binding_descriptions(&self.vertex_input.bindings)
+            .vertex_attribute_descriptions(&self.vertex_input.attributes);
+
+        let input_assembly_state =
+            vk::PipelineInputAssemblyStateCreateInfo::default().topology(self.topology);
+
+        let tessellation_state = vk::PipelineTessellationStateCreateInfo::default()
+            .patch_control_points(self.patch_control_points);
+
+        // Actual viewport and scissor are set while recording
+        let viewport_state = vk::PipelineViewportStateCreateInfo::default()
+            .viewport_count(1)
+            .scissor_count(1);
+
+        let rasterization_state = vk::PipelineRasterizationStateCreateInfo::default()
+            .polygon_mode(self.polygon_mode)
+            .cull_mode(self.cull_mode)
+            .front_face(self.front_face)
+            .line_width(1.0);
+        let rasterization_state = match self.depth_bias {
+            Some((constant_factor, slope_factor)) => rasterization_state
+                .depth_bias_enable(true)
+                .depth_bias_constant_factor(constant_factor)
+                .depth_bias_slope_factor(slope_factor),
+            None => rasterization_state,
+        };
+
+        let multisample_state = vk::PipelineMultisampleStateCreateInfo::default()
+            .rasterization_samples(self.samples)
+            .sample_shading_enable(self.min_sample_shading.is_some())
+            .min_sample_shading(self.min_sample_shading.unwrap_or(0.0));
+
+        let (front, back) = self.stencil.unwrap_or_default();
+        let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::default()
+            .depth_test_enable(self.depth_test)
+            .depth_write_enable(self.depth_write)
+            .depth_compare_op(self.depth_compare_op)
+            .stencil_test_enable(self.stencil.is_some())
+            .front(front)
+            .back(back);
+
+        let color_blend_attachments = vec![
+            vk::PipelineColorBlendAttachmentState::default()
+                .color_write_mask(vk::ColorComponentFlags::RGBA)
+                .blend_enable(false);
+            self.color_attachment_count as usize
+        ];
+        let color_blend_state =
+            vk::PipelineColorBlendStateCreateInfo::default().attachments(&color_blend_attachments);
+
+        // Full rate shading when unsupported
+        let fragment_size = self.fragment_shading_rate.filter(|_| {
+            if !device.fragment_shading_rate {
+                println!("Warning: Fragment shading rates not supported, shading at full rate");
+            }
+            device.fragment_shading_rate
+        });
+        let mut shading_rate_state = vk::PipelineFragmentShadingRateStateCreateInfoKHR::default()
+            .combiner_ops(KEEP_PIPELINE_RATE);
+
+        let mut dynamic_states = vec![vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        if fragment_size.is_some() {
+            dynamic_states.push(vk::DynamicState::FRAGMENT_SHADING_RATE_KHR);
+        }
+        let dynamic_state =
+            vk::PipelineDynamicStateCreateInfo::default().dynamic_states(&dynamic_states);
+
+        let mut create_info = vk::GraphicsPipelineCreateInfo::default()
+            .stages(&stages)
+            .vertex_input_state(&vertex_input_state)
+            .input_assembly_state(&input_assembly_state)
+            .viewport_state(&viewport_state)
+            .rasterization_state(&rasterization_state)
+            .multisample_state(&multisample_state)
+            .depth_stencil_state(&depth_stencil_state)
+            .color_blend_state(&color_blend_state)
+            .dynamic_state(&dynamic_state)
+            .layout(layout)
+            .render_pass(render_pass)
+            .subpass(subpass);
+        if self.patch_control_points > 0 {
+            create_info = create_info.tessellation_state(&tessellation_state);
+        }
+        if let Some(size) = fragment_size {
+            shading_rate_state = shading_rate_state.fragment_size(supported_fragment_size(
+                &device.fragment_shading_rates,
+                size,
+            ));
+            create_info = create_info.push_next(&mut shading_rate_state);
+        }
+
+        let result = unsafe {
+            device
+                .device
+                .create_graphics_pipelines(vk::PipelineCache::null(), &[create_info], None)
+        };
+
+        // Modules are only needed during pipeline creation
+        for &module in &modules {
+            unsafe { device.device.destroy_shader_module(module, None) };
+        }
+
+        match result {
+            Ok(pipelines) => Ok(pipelines[0]),
+            Err((_, err)) => Err(err.into()),
+        }
+    }
+}
+
+// Stencil mask pass: always passes and writes `reference` wherever something is drawn
+pub fn stencil_write_state(reference: u32) -> vk::StencilOpState {
+    vk::StencilOpState {
+        fail_op: vk::StencilOp::KEEP,
+        pass_op: vk::StencilOp::REPLACE,
+        depth_fail_op: vk::StencilOp::KEEP,
+        compare_op: vk::CompareOp::ALWAYS,
+        compare_mask: 0xff,
+        write_mask: 0xff,
+        reference,
+    }
+}
+
+// Masked pass: only draws where the stencil value compares to `reference` with
+// `compare_op` (EQUAL to draw inside a mask, NOT_EQUAL for outlines), leaves it untouched
+pub fn stencil_test_state(reference: u32, compare_op: vk::CompareOp) -> vk::StencilOpState {
+    vk::StencilOpState {
+        fail_op: vk::StencilOp::KEEP,
+        pass_op: vk::StencilOp::KEEP,
+        depth_fail_op: vk::StencilOp::KEEP,
+        compare_op,
+        compare_mask: 0xff,
+        write_mask: 0x00,
+        reference,
+    }
+}
@@ -0,0 +1,246 @@
+use ash::vk;
+
+use super::Device;
+
+// Order matters, results are written in order of increasing flag bit
+const STATISTIC_FLAGS: vk::QueryPipelineStatisticFlags = vk::QueryPipelineStatisticFlags::from_raw(
+    vk::QueryPipelineStatisticFlags::INPUT_ASSEMBLY_VERTICES.as_raw()
+        | vk::QueryPipelineStatisticFlags::INPUT_ASSEMBLY_PRIMITIVES.as_raw()
+        | vk::QueryPipelineStatisticFlags::VERTEX_SHADER_INVOCATIONS.as_raw()
+        | vk::QueryPipelineStatisticFlags::CLIPPING_INVOCATIONS.as_raw()
+        | vk::QueryPipelineStatisticFlags::CLIPPING_PRIMITIVES.as_raw()
+        | vk::QueryPipelineStatisticFlags::FRAGMENT_SHADER_INVOCATIONS.as_raw()
+        | vk::QueryPipelineStatisticFlags::COMPUTE_SHADER_INVOCATIONS.as_raw(),
+);
+const STATISTIC_COUNT: usize = 7;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PipelineStatistics {
+    pub input_assembly_vertices: u64,
+    pub input_assembly_primitives: u64,
+    pub vertex_shader_invocations: u64,
+    pub clipping_invocations: u64,
+    pub clipping_primitives: u64,
+    pub fragment_shader_invocations: u64,
+    pub compute_shader_invocations: u64,
+}
+
+// One PIPELINE_STATISTICS query per frame in flight, requires pipelineStatisticsQuery
+pub struct StatisticsQueries {
+    pool: vk::QueryPool,
+    // Whether the frame's query was recorded since creation, reading one that never was
+    // would wait forever
+    written: Vec<bool>,
+    device: ash::Device,
+}
+
+impl Drop for StatisticsQueries {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_query_pool(self.pool, None);
+        }
+    }
+}
+
+impl StatisticsQueries {
+    pub fn new(
+        device: &Device,
+        frames_in_flight: usize,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        if device.enabled_features.pipeline_statistics_query != vk::TRUE {
+            return Err(
+                "Pipeline statistics queries require the pipelineStatisticsQuery feature".into(),
+            );
+        }
+
+        let create_info = vk::QueryPoolCreateInfo::default()
+            .query_type(vk::QueryType::PIPELINE_STATISTICS)
+            .query_count(frames_in_flight as u32)
+            .pipeline_statistics(STATISTIC_FLAGS);
+
+        let pool = unsafe { device.device.create_query_pool(&create_info, None)? };
+
+        Ok(Self {
+            pool,
+            written: vec![false; frames_in_flight],
+            device: device.device.clone(),
+        })
+    }
+
+    // Must be recorded outside of a render pass, before `begin`
+    pub fn reset(&self, command_buffer: vk::CommandBuffer, frame: usize) {
+        unsafe {
+            self.device
+                .cmd_reset_query_pool(command_buffer, self.pool, frame as u32, 1);
+        }
+    }
+
+    pub fn begin(&mut self, command_buffer: vk::CommandBuffer, frame: usize) {
+        unsafe {
+            self.device.cmd_begin_query(
+                command_buffer,
+                self.pool,
+                frame as u32,
+                vk::QueryControlFlags::empty(),
+            );
+        }
+        self.written[frame] = true;
+    }
+
+    pub fn end(&self, command_buffer: vk::CommandBuffer, frame: usize) {
+        unsafe {
+            self.device
+                .cmd_end_query(command_buffer, self.pool, frame as u32);
+        }
+    }
+
+    // Statistics of the last submission of `frame`, call once its fence has signaled.
+    // None when that frame was never recorded.
+    pub fn results(
+        &self,
+        frame: usize,
+    ) -> Result<Option<PipelineStatistics>, Box<dyn std::error::Error>> {
+        if !self.written[frame] {
+            return Ok(None);
+        }
+
+        let mut data = [[0u64; STATISTIC_COUNT]];
+        unsafe {
+            self.device.get_query_pool_results(
+                self.pool,
+                frame as u32,
+                &mut data,
+                vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WAIT,
+            )?
+        };
+
+        let [values] = data;
+        Ok(Some(PipelineStatistics {
+            input_assembly_vertices: values[0],
+            input_assembly_primitives: values[1],
+            vertex_shader_invocations: values[2],
+            clipping_invocations: values[3],
+            clipping_primitives: values[4],
+            fragment_shader_invocations: values[5],
+            compute_shader_invocations: values[6],
+        }))
+    }
+}
+
+// `capacity` OCCLUSION queries per frame in flight, each counting the samples that passed
+// the depth/stencil tests between its begin and end. Counts are exact only with the
+// occlusionQueryPrecise feature, otherwise any non-zero value just means "visible".
+pub struct OcclusionQueries {
+    pool: vk::QueryPool,
+    capacity: u32,
+    precise: bool,
+    // Queries begun per frame since its last reset
+    used: Vec<u32>,
+    device: ash::Device,
+}
+
+impl Drop for OcclusionQueries {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_query_pool(self.pool, None);
+        }
+    }
+}
+
+impl OcclusionQueries {
+    pub fn new(
+        device: &Device,
+        capacity: u32,
+        frames_in_flight: usize,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let create_info = vk::QueryPoolCreateInfo::default()
+            .query_type(vk::QueryType::OCCLUSION)
+            .query_count(capacity * frames_in_flight as u32);
+
+        let pool = unsafe { device.device.create_query_pool(&create_info, None)? };
+
+        Ok(Self {
+            pool,
+            capacity,
+            precise: device.enabled_features.occlusion_query_precise == vk::TRUE,
+            used: vec![0; frames_in_flight],
+            device: device.device.clone(),
+        })
+    }
+
+    // Must be recorded outside of a render pass before any query of the frame is begun
+    pub fn reset(&mut self, command_buffer: vk::CommandBuffer, frame: usize) {
+        unsafe {
+            self.device.cmd_reset_query_pool(
+                command_buffer,
+                self.pool,
+                frame as u32 * self.capacity,
+                self.capacity,
+            );
+        }
+        self.used[frame] = 0;
+    }
+
+    // Starts the next query of the frame and returns its index for `end`
+    pub fn begin(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        frame: usize,
+    ) -> Result<u32, Box<dyn std::error::Error>> {
+        let index = self.used[frame];
+        if index >= self.capacity {
+            return Err(format!(
+                "All {} occlusion queries of the frame in use",
+                self.capacity
+            )
+            .into());
+        }
+
+        let flags = if self.precise {
+            vk::QueryControlFlags::PRECISE
+        } else {
+            vk::QueryControlFlags::empty()
+        };
+
+        unsafe {
+            self.device.cmd_begin_query(
+                command_buffer,
+                self.pool,
+                frame as u32 * self.capacity + index,
+                flags,
+            );
+        }
+        self.used[frame] += 1;
+
+        Ok(index)
+    }
+
+    pub fn end(&self, command_buffer: vk::CommandBuffer, frame: usize, index: u32) {
+        unsafe {
+            self.device.cmd_end_query(
+                command_buffer,
+                self.pool,
+                frame as u32 * self.capacity + index,
+            );
+        }
+    }
+
+    // Sample counts of every query begun in `frame`, call once its fence has signaled
+    pub fn results(&self, frame: usize) -> Result<Vec<u64>, Box<dyn std::error::Error>> {
+        let mut data = vec![0u64; self.used[frame] as usize];
+        if data.is_empty() {
+            return Ok(data);
+        }
+
+        unsafe {
+            self.device.get_query_pool_results(
+                self.pool,
+                frame as u32 * self.capacity,
+                &mut data,
+                vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WAIT,
+            )?
+        };
+
+        Ok(data)
+    }
+}
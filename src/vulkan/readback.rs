@@ -0,0 +1,217 @@
+use ash::vk;
+
+use super::{Buffer, Device, aspect_flags_for_format, texel_size, transition_layout};
+
+// Host visible buffer sized for mip 0, layer 0 of one aspect of an image. Record
+// copy_from_image into a command buffer and read the result once its submission is done
+// (the fence signaled, or submit_once returned). Reusable for any number of copies, e.g.
+// one per screenshot.
+pub struct ReadbackBuffer {
+    pub buffer: Buffer,
+    pub format: vk::Format,
+    pub aspect: vk::ImageAspectFlags,
+    // Largest extent a copy may have
+    pub extent: vk::Extent3D,
+    // Bytes per texel of `aspect`
+    pub texel_size: u32,
+    // Bytes between the starts of two rows, padded to optimalBufferCopyRowPitchAlignment
+    pub row_pitch: vk::DeviceSize,
+    // Extent of the last copy_from_image, zero before the first
+    pub copied_extent: vk::Extent3D,
+    // Mapped for the buffer's whole lifetime
+    mapped: *const u8,
+}
+
+impl Drop for ReadbackBuffer {
+    fn drop(&mut self) {
+        self.buffer.unmap();
+    }
+}
+
+impl ReadbackBuffer {
+    // `aspect` is a single aspect, copies can't read depth and stencil at once
+    pub fn new(
+        device: &Device,
+        format: vk::Format,
+        aspect: vk::ImageAspectFlags,
+        extent: vk::Extent3D,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let texel_size = aspect_texel_size(format, aspect)
+            .ok_or_else(|| format!("Can't read back {aspect:?} of {format:?} images"))?;
+
+        // bufferRowLength is in texels, so the pitch also has to be a multiple of the texel
+        let alignment = device
+            .properties
+            .limits
+            .optimal_buffer_copy_row_pitch_alignment
+            .max(1);
+        let row_pitch = (extent.width as vk::DeviceSize * texel_size as vk::DeviceSize)
+            .next_multiple_of(alignment)
+            .next_multiple_of(texel_size as vk::DeviceSize);
+        let size = row_pitch * extent.height as vk::DeviceSize * extent.depth as vk::DeviceSize;
+
+        // Cached memory makes the host reads fast, not every device has it coherent
+        let usage = vk::BufferUsageFlags::TRANSFER_DST;
+        let coherent =
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT;
+        let buffer = Buffer::new(
+            device,
+            size.max(1),
+            usage,
+            coherent | vk::MemoryPropertyFlags::HOST_CACHED,
+        )
+        .or_else(|_| Buffer::new(device, size.max(1), usage, coherent))?;
+        let mapped = buffer.map()?;
+
+        Ok(Self {
+            buffer,
+            format,
+            aspect,
+            extent,
+            texel_size,
+            row_pitch,
+            copied_extent: vk::Extent3D::default(),
+            mapped,
+        })
+    }
+
+    // Records a copy of mip 0, layer 0 of `image` (in `layout`, left in it) into the
+    // buffer. The image needs TRANSFER_SRC usage and the buffer's format, `extent` at most
+    // the buffer's. Ends with a barrier making the copy visible to host reads.
+    pub fn copy_from_image(
+        &mut self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        image: vk::Image,
+        layout: vk::ImageLayout,
+        extent: vk::Extent3D,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        // The layout is restored afterwards, which UNDEFINED can't be
+        if layout == vk::ImageLayout::UNDEFINED {
+            return Err("Image readback needs the current layout, not UNDEFINED".into());
+        }
+        if extent.width > self.extent.width
+            || extent.height > self.extent.height
+            || extent.depth > self.extent.depth
+        {
+            return Err(format!(
+                "Copy of {extent:?} doesn't fit a readback buffer for {:?}",
+                self.extent
+            )
+            .into());
+        }
+
+        // Combined depth/stencil images transition both aspects together
+        let range = vk::ImageSubresourceRange {
+            aspect_mask: aspect_flags_for_format(self.format),
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        let region = vk::BufferImageCopy::default()
+            .buffer_row_length((self.row_pitch / self.texel_size as vk::DeviceSize) as u32)
+            .buffer_image_height(self.extent.height)
+            .image_subresource(vk::ImageSubresourceLayers {
+                aspect_mask: self.aspect,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            })
+            .image_extent(extent);
+        let to_host = vk::BufferMemoryBarrier::default()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::HOST_READ)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .buffer(self.buffer.buffer)
+            .offset(0)
+            .size(vk::WHOLE_SIZE);
+
+        unsafe {
+            transition_layout(
+                device,
+                command_buffer,
+                image,
+                range,
+                layout,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            );
+            device.cmd_copy_image_to_buffer(
+                command_buffer,
+                image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                self.buffer.buffer,
+                &[region],
+            );
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::HOST,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[to_host],
+                &[],
+            );
+            transition_layout(
+                device,
+                command_buffer,
+                image,
+                range,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                layout,
+            );
+        }
+
+        self.copied_extent = extent;
+        Ok(())
+    }
+
+    // Raw buffer contents, rows row_pitch bytes apart and slices extent.height rows apart.
+    // Only valid once the copy's submission completed.
+    pub fn read(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.mapped, self.buffer.size as usize) }
+    }
+
+    // The last copy with the row padding removed, tightly packed rows and slices
+    pub fn read_packed(&self) -> Vec<u8> {
+        let data = self.read();
+        let row_bytes = self.copied_extent.width as usize * self.texel_size as usize;
+        let slice_pitch = self.row_pitch as usize * self.extent.height as usize;
+
+        let mut pixels = Vec::with_capacity(
+            row_bytes * self.copied_extent.height as usize * self.copied_extent.depth as usize,
+        );
+        for slice in 0..self.copied_extent.depth as usize {
+            for row in 0..self.copied_extent.height as usize {
+                let start = slice * slice_pitch + row * self.row_pitch as usize;
+                pixels.extend_from_slice(&data[start..start + row_bytes]);
+            }
+        }
+        pixels
+    }
+}
+
+// Bytes per texel a copy of `aspect` writes, depth and stencil are copied separately.
+// None for compressed and unknown formats.
+pub fn aspect_texel_size(format: vk::Format, aspect: vk::ImageAspectFlags) -> Option<u32> {
+    match aspect {
+        vk::ImageAspectFlags::COLOR => texel_size(format),
+        vk::ImageAspectFlags::DEPTH => match format {
+            vk::Format::D16_UNORM | vk::Format::D16_UNORM_S8_UINT => Some(2),
+            vk::Format::D32_SFLOAT
+            | vk::Format::D32_SFLOAT_S8_UINT
+            | vk::Format::X8_D24_UNORM_PACK32
+            | vk::Format::D24_UNORM_S8_UINT => Some(4),
+            _ => None,
+        },
+        vk::ImageAspectFlags::STENCIL => match format {
+            vk::Format::S8_UINT
+            | vk::Format::D16_UNORM_S8_UINT
+            | vk::Format::D24_UNORM_S8_UINT
+            | vk::Format::D32_SFLOAT_S8_UINT => Some(1),
+            _ => None,
+        },
+        _ => None,
+    }
+}
@@ -0,0 +1,499 @@
+use ash::vk;
+use std::collections::HashMap;
+
+use super::pipeline::SPIRV_MAGIC;
+use super::{Device, OwnedDescriptorSetLayout, OwnedPipelineLayout};
+
+// Opcodes and enum values of the SPIR-V spec, only what reflection needs
+const OP_ENTRY_POINT: u32 = 15;
+const OP_TYPE_BOOL: u32 = 20;
+const OP_TYPE_INT: u32 = 21;
+const OP_TYPE_FLOAT: u32 = 22;
+const OP_TYPE_VECTOR: u32 = 23;
+const OP_TYPE_MATRIX: u32 = 24;
+const OP_TYPE_IMAGE: u32 = 25;
+const OP_TYPE_SAMPLER: u32 = 26;
+const OP_TYPE_SAMPLED_IMAGE: u32 = 27;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_RUNTIME_ARRAY: u32 = 29;
+const OP_TYPE_STRUCT: u32 = 30;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_CONSTANT: u32 = 43;
+const OP_SPEC_CONSTANT: u32 = 50;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const OP_MEMBER_DECORATE: u32 = 72;
+const OP_TYPE_ACCELERATION_STRUCTURE: u32 = 5341;
+
+const DECORATION_BUFFER_BLOCK: u32 = 3;
+const DECORATION_ROW_MAJOR: u32 = 4;
+const DECORATION_ARRAY_STRIDE: u32 = 6;
+const DECORATION_MATRIX_STRIDE: u32 = 7;
+const DECORATION_BINDING: u32 = 33;
+const DECORATION_DESCRIPTOR_SET: u32 = 34;
+const DECORATION_OFFSET: u32 = 35;
+
+const STORAGE_CLASS_UNIFORM_CONSTANT: u32 = 0;
+const STORAGE_CLASS_UNIFORM: u32 = 2;
+const STORAGE_CLASS_PUSH_CONSTANT: u32 = 9;
+const STORAGE_CLASS_STORAGE_BUFFER: u32 = 12;
+
+// Types nested deeper in a block are rejected, sizes are computed recursively and a
+// malformed module could otherwise overflow the stack (or refer to itself)
+const MAX_TYPE_DEPTH: u32 = 64;
+
+const DIM_BUFFER: u32 = 5;
+const DIM_SUBPASS_DATA: u32 = 6;
+
+// One descriptor binding a shader declares
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReflectedBinding {
+    pub set: u32,
+    pub binding: u32,
+    // Uniform and storage buffers are never reported as dynamic, the shader can't tell
+    pub descriptor_type: vk::DescriptorType,
+    // Array length, 0 for runtime sized arrays (bindless, see BindlessTextures)
+    pub count: u32,
+    pub stages: vk::ShaderStageFlags,
+}
+
+// Descriptor bindings and push constants of one or more shader stages, see
+// reflect_descriptors. Stages of a pipeline are combined with `merge`.
+#[derive(Clone, Debug, Default)]
+pub struct ReflectedLayout {
+    // Sorted by set, then binding
+    pub bindings: Vec<ReflectedBinding>,
+    // A single range covering every stage's push constant block
+    pub push_constants: Option<vk::PushConstantRange>,
+    // Stages of the module's entry points
+    pub stages: vk::ShaderStageFlags,
+}
+
+impl ReflectedLayout {
+    // Adds the bindings of another stage. The same set/binding must have the same type and
+    // count in both, the stage flags are combined.
+    pub fn merge(&mut self, other: &ReflectedLayout) -> Result<(), Box<dyn std::error::Error>> {
+        for binding in &other.bindings {
+            match self
+                .bindings
+                .iter_mut()
+                .find(|b| b.set == binding.set && b.binding == binding.binding)
+            {
+                Some(existing) => {
+                    if existing.descriptor_type != binding.descriptor_type
+                        || existing.count != binding.count
+                    {
+                        return Err(format!(
+                            "Set {} binding {} is declared as {:?}[{}] and {:?}[{}]",
+                            binding.set,
+                            binding.binding,
+                            existing.descriptor_type,
+                            existing.count,
+                            binding.descriptor_type,
+                            binding.count
+                        )
+                        .into());
+                    }
+                    existing.stages |= binding.stages;
+                }
+                None => self.bindings.push(*binding),
+            }
+        }
+        self.bindings.sort_by_key(|b| (b.set, b.binding));
+
+        self.push_constants = match (self.push_constants, other.push_constants) {
+            (Some(a), Some(b)) => {
+                let offset = a.offset.min(b.offset);
+                let end = (a.offset + a.size).max(b.offset + b.size);
+                Some(
+                    vk::PushConstantRange::default()
+                        .stage_flags(a.stage_flags | b.stage_flags)
+                        .offset(offset)
+                        .size(end - offset),
+                )
+            }
+            (a, b) => a.or(b),
+        };
+        self.stages |= other.stages;
+
+        Ok(())
+    }
+
+    // 0 when no descriptors are used, otherwise the highest set + 1
+    pub fn set_count(&self) -> u32 {
+        self.bindings.last().map_or(0, |b| b.set.saturating_add(1))
+    }
+
+    pub fn push_constant_ranges(&self) -> Vec<vk::PushConstantRange> {
+        self.push_constants.into_iter().collect()
+    }
+
+    // One layout per set up to set_count, sets the shaders skip get an empty layout.
+    // Runtime sized arrays need a count and binding flags of their own, they are rejected.
+    pub fn create_set_layouts(
+        &self,
+        device: &Device,
+    ) -> Result<Vec<OwnedDescriptorSetLayout>, Box<dyn std::error::Error>> {
+        (0..self.set_count())
+            .map(|set| {
+                let bindings = self
+                    .bindings
+                    .iter()
+                    .filter(|b| b.set == set)
+                    .map(|b| {
+                        if b.count == 0 {
+                            return Err(format!(
+                                "Set {set} binding {} is a runtime sized array",
+                                b.binding
+                            ));
+                        }
+                        Ok(vk::DescriptorSetLayoutBinding::default()
+                            .binding(b.binding)
+                            .descriptor_type(b.descriptor_type)
+                            .descriptor_count(b.count)
+                            .stage_flags(b.stages))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                let create_info = vk::DescriptorSetLayoutCreateInfo::default().bindings(&bindings);
+                Ok(OwnedDescriptorSetLayout::descriptor_set_layout(
+                    &device.device,
+                    unsafe {
+                        device
+                            .device
+                            .create_descriptor_set_layout(&create_info, None)?
+                    },
+                ))
+            })
+            .collect()
+    }
+
+    // `set_layouts` as returned by create_set_layouts, plus the push constant range
+    pub fn create_pipeline_layout(
+        &self,
+        device: &Device,
+        set_layouts: &[OwnedDescriptorSetLayout],
+    ) -> Result<OwnedPipelineLayout, Box<dyn std::error::Error>> {
+        let set_layouts: Vec<_> = set_layouts.iter().map(|layout| layout.handle()).collect();
+        let push_constant_ranges = self.push_constant_ranges();
+        let create_info = vk::PipelineLayoutCreateInfo::default()
+            .set_layouts(&set_layouts)
+            .push_constant_ranges(&push_constant_ranges);
+        Ok(OwnedPipelineLayout::pipeline_layout(
+            &device.device,
+            unsafe { device.device.create_pipeline_layout(&create_info, None)? },
+        ))
+    }
+}
+
+enum SpirvType {
+    Scalar { width: u32 },
+    Vector { component: u32, count: u32 },
+    Matrix { column: u32, count: u32 },
+    Image { dim: u32, sampled: u32 },
+    Sampler,
+    SampledImage,
+    Array { element: u32, length: u32 },
+    RuntimeArray { element: u32 },
+    Struct { members: Vec<u32> },
+    Pointer { pointee: u32 },
+    AccelerationStructure,
+}
+
+#[derive(Clone, Copy, Default)]
+struct MemberLayout {
+    offset: Option<u32>,
+    matrix_stride: Option<u32>,
+    row_major: bool,
+}
+
+#[derive(Default)]
+struct Module {
+    types: HashMap<u32, SpirvType>,
+    constants: HashMap<u32, u32>,
+    // (pointer type, variable id, storage class)
+    variables: Vec<(u32, u32, u32)>,
+    sets: HashMap<u32, u32>,
+    bindings: HashMap<u32, u32>,
+    buffer_blocks: Vec<u32>,
+    array_strides: HashMap<u32, u32>,
+    members: HashMap<(u32, u32), MemberLayout>,
+    stages: vk::ShaderStageFlags,
+}
+
+// Descriptor bindings and push constant range of a SPIR-V module, as read by read_spirv.
+// Only decorated variables of the module are looked at, so bindings the optimizer
+// removed aren't reported. The stage flags come from the module's entry points.
+pub fn reflect_descriptors(code: &[u32]) -> Result<ReflectedLayout, Box<dyn std::error::Error>> {
+    if code.len() < 5 || code[0] != SPIRV_MAGIC {
+        return Err("Not a SPIR-V module".into());
+    }
+
+    let mut module = Module::default();
+    let mut words = &code[5..];
+    while let Some(&first) = words.first() {
+        let word_count = (first >> 16) as usize;
+        if word_count == 0 || word_count > words.len() {
+            return Err("Truncated SPIR-V instruction".into());
+        }
+        let (instruction, rest) = words.split_at(word_count);
+        module.parse_instruction(first & 0xffff, &instruction[1..]);
+        words = rest;
+    }
+
+    let mut layout = ReflectedLayout {
+        stages: module.stages,
+        ..Default::default()
+    };
+    for &(pointer_type, id, storage_class) in &module.variables {
+        let Some(&SpirvType::Pointer { pointee }) = module.types.get(&pointer_type) else {
+            continue;
+        };
+
+        if storage_class == STORAGE_CLASS_PUSH_CONSTANT {
+            let (offset, size) = module.struct_extent(pointee, 0)?;
+            layout.push_constants = Some(
+                vk::PushConstantRange::default()
+                    .stage_flags(module.stages)
+                    .offset(offset)
+                    .size(size - offset),
+            );
+            continue;
+        }
+
+        let (Some(&set), Some(&binding)) = (module.sets.get(&id), module.bindings.get(&id)) else {
+            continue;
+        };
+        // Arrays of descriptors, a single level is all Vulkan allows
+        let (element, count) = match module.types.get(&pointee) {
+            Some(&SpirvType::Array { element, length }) => (element, length),
+            Some(&SpirvType::RuntimeArray { element }) => (element, 0),
+            _ => (pointee, 1),
+        };
+        let descriptor_type = module
+            .descriptor_type(element, storage_class)
+            .ok_or_else(|| format!("Set {set} binding {binding} has an unsupported type"))?;
+
+        layout.merge(&ReflectedLayout {
+            bindings: vec![ReflectedBinding {
+                set,
+                binding,
+                descriptor_type,
+                count,
+                stages: module.stages,
+            }],
+            ..Default::default()
+        })?;
+    }
+
+    Ok(layout)
+}
+
+impl Module {
+    fn parse_instruction(&mut self, opcode: u32, operands: &[u32]) {
+        let operand = |i: usize| operands.get(i).copied().unwrap_or(0);
+        match opcode {
+            OP_ENTRY_POINT => self.stages |= execution_model_stage(operand(0)),
+            OP_TYPE_BOOL => {
+                // Booleans in interface blocks are 32 bit
+                self.types
+                    .insert(operand(0), SpirvType::Scalar { width: 32 });
+            }
+            OP_TYPE_INT | OP_TYPE_FLOAT => {
+                let width = operand(1);
+                self.types.insert(operand(0), SpirvType::Scalar { width });
+            }
+            OP_TYPE_VECTOR => {
+                let (component, count) = (operand(1), operand(2));
+                self.types
+                    .insert(operand(0), SpirvType::Vector { component, count });
+            }
+            OP_TYPE_MATRIX => {
+                let (column, count) = (operand(1), operand(2));
+                self.types
+                    .insert(operand(0), SpirvType::Matrix { column, count });
+            }
+            OP_TYPE_IMAGE => {
+                let (dim, sampled) = (operand(2), operand(6));
+                self.types
+                    .insert(operand(0), SpirvType::Image { dim, sampled });
+            }
+            OP_TYPE_SAMPLER => {
+                self.types.insert(operand(0), SpirvType::Sampler);
+            }
+            OP_TYPE_SAMPLED_IMAGE => {
+                self.types.insert(operand(0), SpirvType::SampledImage);
+            }
+            OP_TYPE_ARRAY => {
+                let element = operand(1);
+                let length = self.constants.get(&operand(2)).copied().unwrap_or(1);
+                self.types
+                    .insert(operand(0), SpirvType::Array { element, length });
+            }
+            OP_TYPE_RUNTIME_ARRAY => {
+                let element = operand(1);
+                self.types
+                    .insert(operand(0), SpirvType::RuntimeArray { element });
+            }
+            OP_TYPE_STRUCT => {
+                let members = operands.get(1..).unwrap_or_default().to_vec();
+                self.types.insert(operand(0), SpirvType::Struct { members });
+            }
+            OP_TYPE_POINTER => {
+                let pointee = operand(2);
+                self.types
+                    .insert(operand(0), SpirvType::Pointer { pointee });
+            }
+            OP_TYPE_ACCELERATION_STRUCTURE => {
+                self.types
+                    .insert(operand(0), SpirvType::AccelerationStructure);
+            }
+            // Specialization constants keep their default, 64 bit constants their low word
+            OP_CONSTANT | OP_SPEC_CONSTANT => {
+                self.constants.insert(operand(1), operand(2));
+            }
+            OP_VARIABLE => self.variables.push((operand(0), operand(1), operand(2))),
+            OP_DECORATE => match operand(1) {
+                DECORATION_DESCRIPTOR_SET => {
+                    self.sets.insert(operand(0), operand(2));
+                }
+                DECORATION_BINDING => {
+                    self.bindings.insert(operand(0), operand(2));
+                }
+                DECORATION_BUFFER_BLOCK => self.buffer_blocks.push(operand(0)),
+                DECORATION_ARRAY_STRIDE => {
+                    self.array_strides.insert(operand(0), operand(2));
+                }
+                _ => {}
+            },
+            OP_MEMBER_DECORATE => {
+                let member = self.members.entry((operand(0), operand(1))).or_default();
+                match operand(2) {
+                    DECORATION_OFFSET => member.offset = Some(operand(3)),
+                    DECORATION_MATRIX_STRIDE => member.matrix_stride = Some(operand(3)),
+                    DECORATION_ROW_MAJOR => member.row_major = true,
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn descriptor_type(&self, ty: u32, storage_class: u32) -> Option<vk::DescriptorType> {
+        let ty_info = self.types.get(&ty)?;
+        match storage_class {
+            STORAGE_CLASS_UNIFORM_CONSTANT => match *ty_info {
+                SpirvType::Sampler => Some(vk::DescriptorType::SAMPLER),
+                SpirvType::SampledImage => Some(vk::DescriptorType::COMBINED_IMAGE_SAMPLER),
+                // Sampled 2 means used without a sampler, i.e. storage
+                SpirvType::Image {
+                    dim: DIM_BUFFER,
+                    sampled,
+                } => Some(if sampled == 2 {
+                    vk::DescriptorType::STORAGE_TEXEL_BUFFER
+                } else {
+                    vk::DescriptorType::UNIFORM_TEXEL_BUFFER
+                }),
+                SpirvType::Image {
+                    dim: DIM_SUBPASS_DATA,
+                    ..
+                } => Some(vk::DescriptorType::INPUT_ATTACHMENT),
+                SpirvType::Image { sampled, .. } => Some(if sampled == 2 {
+                    vk::DescriptorType::STORAGE_IMAGE
+                } else {
+                    vk::DescriptorType::SAMPLED_IMAGE
+                }),
+                SpirvType::AccelerationStructure => {
+                    Some(vk::DescriptorType::ACCELERATION_STRUCTURE_KHR)
+                }
+                _ => None,
+            },
+            // GLSL before 1.3 SPIR-V declares buffer blocks as Uniform + BufferBlock
+            STORAGE_CLASS_UNIFORM if self.buffer_blocks.contains(&ty) => {
+                Some(vk::DescriptorType::STORAGE_BUFFER)
+            }
+            STORAGE_CLASS_UNIFORM => Some(vk::DescriptorType::UNIFORM_BUFFER),
+            STORAGE_CLASS_STORAGE_BUFFER => Some(vk::DescriptorType::STORAGE_BUFFER),
+            _ => None,
+        }
+    }
+
+    // Offset of the first member and end of the last one of a block, `depth` counts the
+    // types it is nested in
+    fn struct_extent(&self, ty: u32, depth: u32) -> Result<(u32, u32), Box<dyn std::error::Error>> {
+        let Some(SpirvType::Struct { members }) = self.types.get(&ty) else {
+            return Err("Push constants are not a block".into());
+        };
+        let mut start = u32::MAX;
+        let mut end = 0;
+        for (i, &member) in members.iter().enumerate() {
+            let layout = self
+                .members
+                .get(&(ty, i as u32))
+                .copied()
+                .unwrap_or_default();
+            let offset = layout.offset.ok_or("Block member without an offset")?;
+            start = start.min(offset);
+            let member_end = offset
+                .checked_add(self.size_of(member, layout, depth + 1)?)
+                .ok_or("Block member extends past 4 GiB")?;
+            end = end.max(member_end);
+        }
+        Ok((start.min(end), end))
+    }
+
+    fn size_of(
+        &self,
+        ty: u32,
+        member: MemberLayout,
+        depth: u32,
+    ) -> Result<u32, Box<dyn std::error::Error>> {
+        if depth > MAX_TYPE_DEPTH {
+            return Err(format!("Block types nested deeper than {MAX_TYPE_DEPTH} levels").into());
+        }
+        let size = match self.types.get(&ty) {
+            Some(&SpirvType::Scalar { width }) => Some(width / 8),
+            Some(&SpirvType::Vector { component, count }) => self
+                .size_of(component, MemberLayout::default(), depth + 1)?
+                .checked_mul(count),
+            Some(&SpirvType::Matrix { column, count }) => {
+                let rows = match self.types.get(&column) {
+                    Some(&SpirvType::Vector { count, .. }) => count,
+                    _ => return Err("Matrix column is not a vector".into()),
+                };
+                let stride = member
+                    .matrix_stride
+                    .ok_or("Matrix member without a stride")?;
+                // The stride separates columns, or rows when row major
+                if member.row_major {
+                    stride.checked_mul(rows)
+                } else {
+                    stride.checked_mul(count)
+                }
+            }
+            Some(&SpirvType::Array { element, length }) => {
+                let stride = match self.array_strides.get(&ty) {
+                    Some(&stride) => stride,
+                    None => self.size_of(element, member, depth + 1)?,
+                };
+                stride.checked_mul(length)
+            }
+            // Only allowed as the last member of a storage block, takes no space here
+            Some(SpirvType::RuntimeArray { .. }) => Some(0),
+            Some(SpirvType::Struct { .. }) => Some(self.struct_extent(ty, depth)?.1),
+            _ => return Err("Unsupported type in block".into()),
+        };
+        size.ok_or_else(|| "Block member size exceeds 4 GiB".into())
+    }
+}
+
+fn execution_model_stage(model: u32) -> vk::ShaderStageFlags {
+    match model {
+        0 => vk::ShaderStageFlags::VERTEX,
+        1 => vk::ShaderStageFlags::TESSELLATION_CONTROL,
+        2 => vk::ShaderStageFlags::TESSELLATION_EVALUATION,
+        3 => vk::ShaderStageFlags::GEOMETRY,
+        4 => vk::ShaderStageFlags::FRAGMENT,
+        5 => vk::ShaderStageFlags::COMPUTE,
+        _ => vk::ShaderStageFlags::empty(),
+    }
+}
@@ -0,0 +1,839 @@
+use ash::vk;
+
+use super::depth::has_stencil_component;
+use super::{DepthStencilLayouts, Device, Swapchain};
+
+pub struct RenderPass {
+    pub render_pass: vk::RenderPass,
+    // Framebuffers and swapchains used with the pass must match these. `color_format` is
+    // the first color attachment's, UNDEFINED for depth-only passes.
+    pub color_format: vk::Format,
+    pub color_formats: Vec<vk::Format>,
+    pub depth_format: Option<vk::Format>,
+    // Sample count of the color and depth attachments, TYPE_1 except for `multisampled`
+    pub samples: vk::SampleCountFlags,
+    // Some when multisampled depth is resolved, see `multisampled`
+    pub depth_resolve: Option<vk::ResolveModeFlags>,
+    device: ash::Device,
+}
+
+impl Drop for RenderPass {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_render_pass(self.render_pass, None);
+        }
+    }
+}
+
+impl RenderPass {
+    // Color attachment 0 is the swapchain image, attachment 1 the depth buffer when
+    // `depth_format` is given. Stencil is cleared too when the format has a stencil component.
+    pub fn new(
+        device: &Device,
+        swapchain: &Swapchain,
+        depth_format: Option<vk::Format>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::with_color_format(
+            device,
+            swapchain.format.format,
+            depth_format,
+            vk::AttachmentLoadOp::CLEAR,
+        )
+    }
+
+    // Like `new` for swapchain images of `color_format`, e.g. after the surface format
+    // changed. With `color_load_op` LOAD the image's previous contents are kept, it has to
+    // be in PRESENT_SRC_KHR already (see cmd_initialize_swapchain_image). Compatible with
+    // `new` either way.
+    pub fn with_color_format(
+        device: &Device,
+        color_format: vk::Format,
+        depth_format: Option<vk::Format>,
+        color_load_op: vk::AttachmentLoadOp,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut color_attachment =
+            color_attachment(color_format, vk::ImageLayout::PRESENT_SRC_KHR).load_op(color_load_op);
+        let mut dependency = external_dependency();
+        if color_load_op == vk::AttachmentLoadOp::LOAD {
+            // The last frame's writes to the image have to be visible to the load
+            color_attachment = color_attachment.initial_layout(vk::ImageLayout::PRESENT_SRC_KHR);
+            dependency = dependency
+                .src_access_mask(
+                    dependency.src_access_mask | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                )
+                .dst_access_mask(
+                    dependency.dst_access_mask | vk::AccessFlags::COLOR_ATTACHMENT_READ,
+                );
+        }
+        let color_attachments = [color_attachment];
+        // Depth isn't read after the pass
+        let depth_attachment = depth_format.map(|format| {
+            depth_attachment(
+                format,
+                vk::AttachmentStoreOp::DONT_CARE,
+                vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            )
+        });
+
+        let dependencies = [dependency];
+
+        Self::create(device, &color_attachments, depth_attachment, &dependencies)
+    }
+
+    // Same attachments as `new`, so compatible with its pipelines, but color goes to an
+    // offscreen image that ends in TRANSFER_SRC_OPTIMAL to be blitted to the swapchain
+    // image (see ScaledTarget)
+    pub fn blit_source(
+        device: &Device,
+        color_format: vk::Format,
+        depth_format: Option<vk::Format>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let color_attachments = [color_attachment(
+            color_format,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        )];
+        let depth_attachment = depth_format.map(|format| {
+            depth_attachment(
+                format,
+                vk::AttachmentStoreOp::DONT_CARE,
+                vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            )
+        });
+
+        // The image is shared by all frames in flight, the previous frame's blit has to
+        // finish reading before it is cleared, and this frame's writes have to land before
+        // the blit
+        let dependencies = [
+            external_dependency().src_stage_mask(
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags::TRANSFER,
+            ),
+            vk::SubpassDependency::default()
+                .src_subpass(0)
+                .dst_subpass(vk::SUBPASS_EXTERNAL)
+                .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+                .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                .dst_stage_mask(vk::PipelineStageFlags::TRANSFER)
+                .dst_access_mask(vk::AccessFlags::TRANSFER_READ),
+        ];
+
+        Self::create(device, &color_attachments, depth_attachment, &dependencies)
+    }
+
+    // Offscreen pass for render targets sampled afterwards (G-buffers, shadow maps):
+    // one color attachment per format, none for depth-only passes. Everything is cleared
+    // and stored, colors end in SHADER_READ_ONLY_OPTIMAL and depth in
+    // DEPTH_STENCIL_READ_ONLY_OPTIMAL.
+    pub fn offscreen(
+        device: &Device,
+        color_formats: &[vk::Format],
+        depth_format: Option<vk::Format>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let color_attachments: Vec<_> = color_formats
+            .iter()
+            .map(|&format| color_attachment(format, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL))
+            .collect();
+        let depth_attachment = depth_format.map(|format| {
+            depth_attachment(
+                format,
+                vk::AttachmentStoreOp::STORE,
+                vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+            )
+        });
+
+        // Previous frame's reads have to finish before the attachments are cleared again,
+        // and the writes have to land before later passes sample them
+        let dependencies = [
+            external_dependency().src_stage_mask(
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags::FRAGMENT_SHADER,
+            ),
+            vk::SubpassDependency::default()
+                .src_subpass(0)
+                .dst_subpass(vk::SUBPASS_EXTERNAL)
+                .src_stage_mask(
+                    vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                        | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                )
+                .src_access_mask(
+                    vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                        | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                )
+                .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ),
+        ];
+
+        Self::create(device, &color_attachments, depth_attachment, &dependencies)
+    }
+
+    // Single subpass writing every color attachment in order, then the depth attachment
+    fn create(
+        device: &Device,
+        color_attachments: &[vk::AttachmentDescription],
+        depth_attachment: Option<vk::AttachmentDescription>,
+        dependencies: &[vk::SubpassDependency],
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let max_color_attachments = device.properties.limits.max_color_attachments;
+        if color_attachments.len() as u32 > max_color_attachments {
+            return Err(format!(
+                "{} color attachments requested, the device supports {max_color_attachments}",
+                color_attachments.len()
+            )
+            .into());
+        }
+
+        let attachments: Vec<_> = color_attachments
+            .iter()
+            .copied()
+            .chain(depth_attachment)
+            .collect();
+
+        let color_refs: Vec<_> = (0..color_attachments.len() as u32)
+            .map(|attachment| {
+                vk::AttachmentReference::default()
+                    .attachment(attachment)
+                    .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            })
+            .collect();
+        let depth_ref = vk::AttachmentReference::default()
+            .attachment(color_attachments.len() as u32)
+            .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+
+        let mut subpass = vk::SubpassDescription::default()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(&color_refs);
+        if depth_attachment.is_some() {
+            subpass = subpass.depth_stencil_attachment(&depth_ref);
+        }
+        let subpasses = [subpass];
+
+        let create_info = vk::RenderPassCreateInfo::default()
+            .attachments(&attachments)
+            .subpasses(&subpasses)
+            .dependencies(dependencies);
+
+        let render_pass = unsafe { device.device.create_render_pass(&create_info, None)? };
+
+        let color_formats: Vec<_> = color_attachments
+            .iter()
+            .map(|attachment| attachment.format)
+            .collect();
+
+        Ok(Self {
+            render_pass,
+            color_format: color_formats
+                .first()
+                .copied()
+                .unwrap_or(vk::Format::UNDEFINED),
+            color_formats,
+            depth_format: depth_attachment.map(|attachment| attachment.format),
+            samples: vk::SampleCountFlags::TYPE_1,
+            depth_resolve: None,
+            device: device.device.clone(),
+        })
+    }
+
+    // MSAA pass drawing color and depth with `samples`, color is resolved into a single
+    // sample attachment at the end of the subpass. With `depth_resolve` (pick it with
+    // Device::depth_resolve_mode) depth gets resolved the same way, for later passes that
+    // read depth (e.g. SSAO). None, or a device below Vulkan 1.2, resolves color only.
+    // Framebuffer attachment order: MSAA color, MSAA depth, color resolve, depth resolve
+    // (only with depth resolve). Resolved color ends in `resolve_final_layout`
+    // (SHADER_READ_ONLY_OPTIMAL to sample it, PRESENT_SRC_KHR for swapchain images, see
+    // Framebuffers::multisampled_swapchain), resolved depth in
+    // DEPTH_STENCIL_READ_ONLY_OPTIMAL, the multisampled contents are discarded. Pipelines
+    // used in it need the same rasterization sample count.
+    pub fn multisampled(
+        device: &Device,
+        color_format: vk::Format,
+        depth_format: vk::Format,
+        samples: vk::SampleCountFlags,
+        depth_resolve: Option<vk::ResolveModeFlags>,
+        resolve_final_layout: vk::ImageLayout,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        // vkCreateRenderPass2 and depth/stencil resolve are both core in 1.2
+        if device.api_version < vk::API_VERSION_1_2 {
+            return Err("Multisampled render passes need Vulkan 1.2".into());
+        }
+        let limits = &device.properties.limits;
+        if !(limits.framebuffer_color_sample_counts & limits.framebuffer_depth_sample_counts)
+            .contains(samples)
+        {
+            return Err(format!("{samples:?} isn't supported for color and depth").into());
+        }
+        // Without independentResolveNone stencil would need the same mode, keep it simple
+        if depth_resolve.is_some() && has_stencil_component(depth_format) {
+            return Err(
+                format!("Depth resolve needs a depth-only format, got {depth_format:?}").into(),
+            );
+        }
+
+        let attachment = |format, samples, load_op, store_op, final_layout| {
+            vk::AttachmentDescription2::default()
+                .format(format)
+                .samples(samples)
+                .load_op(load_op)
+                .store_op(store_op)
+                .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+                .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .final_layout(final_layout)
+        };
+        let single = vk::SampleCountFlags::TYPE_1;
+        let mut attachments = vec![
+            attachment(
+                color_format,
+                samples,
+                vk::AttachmentLoadOp::CLEAR,
+                vk::AttachmentStoreOp::DONT_CARE,
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            ),
+            attachment(
+                depth_format,
+                samples,
+                vk::AttachmentLoadOp::CLEAR,
+                vk::AttachmentStoreOp::DONT_CARE,
+                vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            ),
+            attachment(
+                color_format,
+                single,
+                vk::AttachmentLoadOp::DONT_CARE,
+                vk::AttachmentStoreOp::STORE,
+                resolve_final_layout,
+            ),
+        ];
+        if depth_resolve.is_some() {
+            attachments.push(attachment(
+                depth_format,
+                single,
+                vk::AttachmentLoadOp::DONT_CARE,
+                vk::AttachmentStoreOp::STORE,
+                vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+            ));
+        }
+
+        let reference = |attachment, layout| {
+            vk::AttachmentReference2::default()
+                .attachment(attachment)
+                .layout(layout)
+        };
+        let color_refs = [reference(0, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)];
+        let depth_ref = reference(1, vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+        let resolve_refs = [reference(2, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)];
+        let depth_resolve_ref = reference(3, vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+
+        let mut depth_stencil_resolve = vk::SubpassDescriptionDepthStencilResolve::default()
+            .depth_resolve_mode(depth_resolve.unwrap_or(vk::ResolveModeFlags::NONE))
+            .stencil_resolve_mode(vk::ResolveModeFlags::NONE)
+            .depth_stencil_resolve_attachment(&depth_resolve_ref);
+        let mut subpass = vk::SubpassDescription2::default()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(&color_refs)
+            .resolve_attachments(&resolve_refs)
+            .depth_stencil_attachment(&depth_ref);
+        if depth_resolve.is_some() {
+            subpass = subpass.push_next(&mut depth_stencil_resolve);
+        }
+        let subpasses = [subpass];
+
+        // Like `offscreen`: the previous frame's reads of the resolved targets finish before
+        // they are written again, and resolves (COLOR_ATTACHMENT_OUTPUT, depth writes too)
+        // land before later passes sample them. The multisampled attachments are shared by
+        // all frames in flight, so the previous frame's writes to them finish first too.
+        let attachment_stages = vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+            | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+            | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS;
+        let attachment_writes = vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+            | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE;
+        let dependencies = [
+            vk::SubpassDependency2::default()
+                .src_subpass(vk::SUBPASS_EXTERNAL)
+                .dst_subpass(0)
+                .src_stage_mask(attachment_stages | vk::PipelineStageFlags::FRAGMENT_SHADER)
+                .src_access_mask(attachment_writes)
+                .dst_stage_mask(attachment_stages)
+                .dst_access_mask(attachment_writes),
+            vk::SubpassDependency2::default()
+                .src_subpass(0)
+                .dst_subpass(vk::SUBPASS_EXTERNAL)
+                .src_stage_mask(attachment_stages)
+                .src_access_mask(attachment_writes)
+                .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ),
+        ];
+
+        let create_info = vk::RenderPassCreateInfo2::default()
+            .attachments(&attachments)
+            .subpasses(&subpasses)
+            .dependencies(&dependencies);
+        let render_pass = unsafe { device.device.create_render_pass2(&create_info, None)? };
+
+        Ok(Self {
+            render_pass,
+            color_format,
+            color_formats: vec![color_format],
+            depth_format: Some(depth_format),
+            samples,
+            depth_resolve,
+            device: device.device.clone(),
+        })
+    }
+
+    // Two subpass deferred shading that keeps the G-buffer on tile on tiled GPUs (mobile):
+    // subpass 0 writes one color attachment per `input_formats` entry plus depth, subpass 1
+    // reads them as input attachments at the same pixel and writes the output attachment,
+    // which ends in `output_final_layout` (PRESENT_SRC_KHR for swapchain images). The
+    // inputs and depth are cleared and never stored, give them images with
+    // INPUT_ATTACHMENT usage (TRANSIENT_ATTACHMENT and lazily allocated memory where
+    // available). Framebuffer attachment order: output, inputs, depth. Pipelines of
+    // subpass 0 use `.color_attachments(input_formats.len())`, the lighting pipeline
+    // (subpass 1, one color attachment) binds the inputs with
+    // DescriptorSetLayoutBuilder::input_attachment, index = position in `input_formats`:
+    //
+    //     layout(input_attachment_index = 0, set = 0, binding = 0) uniform subpassInput in_albedo;
+    //     ... subpassLoad(in_albedo)
+    pub fn with_input_attachments(
+        device: &Device,
+        output_format: vk::Format,
+        output_final_layout: vk::ImageLayout,
+        input_formats: &[vk::Format],
+        depth_format: Option<vk::Format>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let limits = &device.properties.limits;
+        let input_count = input_formats.len() as u32;
+        if input_count > limits.max_color_attachments {
+            return Err(format!(
+                "{input_count} inputs requested, the device supports {} color attachments",
+                limits.max_color_attachments
+            )
+            .into());
+        }
+        if input_count > limits.max_per_stage_descriptor_input_attachments {
+            return Err(format!(
+                "{input_count} inputs requested, the device supports {} input attachments per stage",
+                limits.max_per_stage_descriptor_input_attachments
+            )
+            .into());
+        }
+
+        let output = color_attachment(output_format, output_final_layout);
+        // Only read within the pass
+        let inputs = input_formats
+            .iter()
+            .map(|&format| vk::AttachmentDescription {
+                store_op: vk::AttachmentStoreOp::DONT_CARE,
+                ..color_attachment(format, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            });
+        let depth = depth_format.map(|format| {
+            depth_attachment(
+                format,
+                vk::AttachmentStoreOp::DONT_CARE,
+                vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            )
+        });
+        let attachments: Vec<_> = std::iter::once(output).chain(inputs).chain(depth).collect();
+
+        let gbuffer_refs: Vec<_> = (1..=input_count)
+            .map(|attachment| {
+                vk::AttachmentReference::default()
+                    .attachment(attachment)
+                    .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            })
+            .collect();
+        let input_refs: Vec<_> = (1..=input_count)
+            .map(|attachment| {
+                vk::AttachmentReference::default()
+                    .attachment(attachment)
+                    .layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            })
+            .collect();
+        let depth_ref = vk::AttachmentReference::default()
+            .attachment(input_count + 1)
+            .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+        let output_refs = [vk::AttachmentReference::default()
+            .attachment(0)
+            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)];
+
+        let mut gbuffer_subpass = vk::SubpassDescription::default()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(&gbuffer_refs);
+        if depth.is_some() {
+            gbuffer_subpass = gbuffer_subpass.depth_stencil_attachment(&depth_ref);
+        }
+        let lighting_subpass = vk::SubpassDescription::default()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .input_attachments(&input_refs)
+            .color_attachments(&output_refs);
+        let subpasses = [gbuffer_subpass, lighting_subpass];
+
+        let dependencies = [
+            external_dependency(),
+            // The output is first used in subpass 1, its transition has to wait for the
+            // swapchain image too
+            vk::SubpassDependency::default()
+                .src_subpass(vk::SUBPASS_EXTERNAL)
+                .dst_subpass(1)
+                .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+                .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+                .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE),
+            // Per pixel only, which is what lets tilers keep the G-buffer on chip
+            vk::SubpassDependency::default()
+                .src_subpass(0)
+                .dst_subpass(1)
+                .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+                .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+                .dst_access_mask(vk::AccessFlags::INPUT_ATTACHMENT_READ)
+                .dependency_flags(vk::DependencyFlags::BY_REGION),
+            // For an output sampled by later passes
+            vk::SubpassDependency::default()
+                .src_subpass(1)
+                .dst_subpass(vk::SUBPASS_EXTERNAL)
+                .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+                .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ),
+        ];
+
+        let create_info = vk::RenderPassCreateInfo::default()
+            .attachments(&attachments)
+            .subpasses(&subpasses)
+            .dependencies(&dependencies);
+        let render_pass = unsafe { device.device.create_render_pass(&create_info, None)? };
+
+        Ok(Self {
+            render_pass,
+            color_format: output_format,
+            color_formats: std::iter::once(output_format)
+                .chain(input_formats.iter().copied())
+                .collect(),
+            depth_format,
+            samples: vk::SampleCountFlags::TYPE_1,
+            depth_resolve: None,
+            device: device.device.clone(),
+        })
+    }
+
+    // Pass drawing into one color attachment (cleared, ends in SHADER_READ_ONLY_OPTIMAL)
+    // on top of depth/stencil contents from an earlier pass, loaded and kept in `layouts`
+    // for the whole pass. A read-only aspect can be sampled by the pass's shaders at the
+    // same time, e.g. stencil while depth is tested and written (see
+    // DepthStencilLayouts::new). Framebuffer attachment order: color, depth.
+    pub fn depth_stencil_input(
+        device: &Device,
+        color_format: vk::Format,
+        depth_format: vk::Format,
+        layouts: DepthStencilLayouts,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        // Separate stencil layouts only exist for vkCreateRenderPass2 (core in 1.2)
+        if device.api_version < vk::API_VERSION_1_2 {
+            return Err("Depth/stencil input passes need Vulkan 1.2".into());
+        }
+
+        let color = vk::AttachmentDescription2::default()
+            .format(color_format)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        let mut stencil_description = vk::AttachmentDescriptionStencilLayout::default()
+            .stencil_initial_layout(layouts.stencil)
+            .stencil_final_layout(layouts.stencil);
+        let mut depth = vk::AttachmentDescription2::default()
+            .format(depth_format)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::LOAD)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(vk::AttachmentLoadOp::LOAD)
+            .stencil_store_op(vk::AttachmentStoreOp::STORE)
+            .initial_layout(layouts.depth)
+            .final_layout(layouts.depth);
+        // Without it the depth layout applies to stencil too
+        if device.separate_depth_stencil_layouts {
+            depth = depth.push_next(&mut stencil_description);
+        }
+        let attachments = [color, depth];
+
+        let color_refs = [vk::AttachmentReference2::default()
+            .attachment(0)
+            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)];
+        let mut stencil_ref =
+            vk::AttachmentReferenceStencilLayout::default().stencil_layout(layouts.stencil);
+        let mut depth_ref = vk::AttachmentReference2::default()
+            .attachment(1)
+            .layout(layouts.depth);
+        if device.separate_depth_stencil_layouts {
+            depth_ref = depth_ref.push_next(&mut stencil_ref);
+        }
+        let subpasses = [vk::SubpassDescription2::default()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(&color_refs)
+            .depth_stencil_attachment(&depth_ref)];
+
+        // The earlier pass's depth/stencil writes land before they are tested or sampled
+        // here, and the color writes before later passes sample them
+        let depth_stages = vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+            | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS;
+        let dependencies = [
+            vk::SubpassDependency2::default()
+                .src_subpass(vk::SUBPASS_EXTERNAL)
+                .dst_subpass(0)
+                .src_stage_mask(
+                    depth_stages
+                        | vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                        | vk::PipelineStageFlags::FRAGMENT_SHADER,
+                )
+                .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+                .dst_stage_mask(
+                    depth_stages
+                        | vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                        | vk::PipelineStageFlags::FRAGMENT_SHADER,
+                )
+                .dst_access_mask(
+                    vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                        | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE
+                        | vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                        | vk::AccessFlags::SHADER_READ,
+                ),
+            vk::SubpassDependency2::default()
+                .src_subpass(0)
+                .dst_subpass(vk::SUBPASS_EXTERNAL)
+                .src_stage_mask(depth_stages | vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+                .src_access_mask(
+                    vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                        | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                )
+                .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ),
+        ];
+
+        let create_info = vk::RenderPassCreateInfo2::default()
+            .attachments(&attachments)
+            .subpasses(&subpasses)
+            .dependencies(&dependencies);
+        let render_pass = unsafe { device.device.create_render_pass2(&create_info, None)? };
+
+        Ok(Self {
+            render_pass,
+            color_format,
+            color_formats: vec![color_format],
+            depth_format: Some(depth_format),
+            samples: vk::SampleCountFlags::TYPE_1,
+            depth_resolve: None,
+            device: device.device.clone(),
+        })
+    }
+}
+
+// Previous contents are cleared anyway, so the initial layout is UNDEFINED
+fn color_attachment(
+    format: vk::Format,
+    final_layout: vk::ImageLayout,
+) -> vk::AttachmentDescription {
+    vk::AttachmentDescription::default()
+        .format(format)
+        .samples(vk::SampleCountFlags::TYPE_1)
+        .load_op(vk::AttachmentLoadOp::CLEAR)
+        .store_op(vk::AttachmentStoreOp::STORE)
+        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .final_layout(final_layout)
+}
+
+fn depth_attachment(
+    format: vk::Format,
+    store_op: vk::AttachmentStoreOp,
+    final_layout: vk::ImageLayout,
+) -> vk::AttachmentDescription {
+    let stencil_load_op = if has_stencil_component(format) {
+        vk::AttachmentLoadOp::CLEAR
+    } else {
+        vk::AttachmentLoadOp::DONT_CARE
+    };
+
+    vk::AttachmentDescription::default()
+        .format(format)
+        .samples(vk::SampleCountFlags::TYPE_1)
+        .load_op(vk::AttachmentLoadOp::CLEAR)
+        .store_op(store_op)
+        .stencil_load_op(stencil_load_op)
+        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .final_layout(final_layout)
+}
+
+// Wait for the image_available semaphore (signaled at COLOR_ATTACHMENT_OUTPUT) before
+// the layout transition writes to the image. A single depth buffer is shared by all frames
+// in flight, so the previous frame's depth writes must finish before this one clears it.
+fn external_dependency() -> vk::SubpassDependency {
+    vk::SubpassDependency::default()
+        .src_subpass(vk::SUBPASS_EXTERNAL)
+        .dst_subpass(0)
+        .src_stage_mask(
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+        )
+        .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+        .dst_stage_mask(
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+        )
+        .dst_access_mask(
+            vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+        )
+}
+
+pub struct Framebuffers {
+    pub framebuffers: Vec<vk::Framebuffer>,
+    device: ash::Device,
+}
+
+impl Drop for Framebuffers {
+    fn drop(&mut self) {
+        unsafe {
+            for &framebuffer in &self.framebuffers {
+                self.device.destroy_framebuffer(framebuffer, None);
+            }
+        }
+    }
+}
+
+impl Framebuffers {
+    // One framebuffer per swapchain image view, all sharing `depth_view` when the render
+    // pass has a depth attachment
+    pub fn new(
+        device: &Device,
+        render_pass: &RenderPass,
+        swapchain: &Swapchain,
+        depth_view: Option<vk::ImageView>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let framebuffers = swapchain
+            .image_views
+            .iter()
+            .map(|&view| {
+                let attachments: Vec<_> = std::iter::once(view).chain(depth_view).collect();
+                let create_info = vk::FramebufferCreateInfo::default()
+                    .render_pass(render_pass.render_pass)
+                    .attachments(&attachments)
+                    .width(swapchain.extent.width)
+                    .height(swapchain.extent.height)
+                    .layers(1);
+
+                unsafe { device.device.create_framebuffer(&create_info, None) }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            framebuffers,
+            device: device.device.clone(),
+        })
+    }
+
+    // Single framebuffer for RenderPass::multisampled, `depth_resolve_view` is required
+    // exactly when the pass resolves depth
+    pub fn multisampled(
+        device: &Device,
+        render_pass: &RenderPass,
+        color_view: vk::ImageView,
+        depth_view: vk::ImageView,
+        resolve_view: vk::ImageView,
+        depth_resolve_view: Option<vk::ImageView>,
+        extent: vk::Extent2D,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        if depth_resolve_view.is_some() != render_pass.depth_resolve.is_some() {
+            return Err("Depth resolve view doesn't match the render pass".into());
+        }
+
+        let attachments: Vec<_> = [color_view, depth_view, resolve_view]
+            .into_iter()
+            .chain(depth_resolve_view)
+            .collect();
+        let create_info = vk::FramebufferCreateInfo::default()
+            .render_pass(render_pass.render_pass)
+            .attachments(&attachments)
+            .width(extent.width)
+            .height(extent.height)
+            .layers(1);
+        let framebuffer = unsafe { device.device.create_framebuffer(&create_info, None)? };
+
+        Ok(Self {
+            framebuffers: vec![framebuffer],
+            device: device.device.clone(),
+        })
+    }
+
+    // One framebuffer per swapchain image for a RenderPass::multisampled that resolves into
+    // the swapchain image, all sharing the multisampled `color_view` and `depth_view`
+    pub fn multisampled_swapchain(
+        device: &Device,
+        render_pass: &RenderPass,
+        swapchain: &Swapchain,
+        color_view: vk::ImageView,
+        depth_view: vk::ImageView,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        if render_pass.depth_resolve.is_some() {
+            return Err("Swapchain framebuffers have no depth resolve attachment".into());
+        }
+
+        let framebuffers = swapchain
+            .image_views
+            .iter()
+            .map(|&view| {
+                let attachments = [color_view, depth_view, view];
+                let create_info = vk::FramebufferCreateInfo::default()
+                    .render_pass(render_pass.render_pass)
+                    .attachments(&attachments)
+                    .width(swapchain.extent.width)
+                    .height(swapchain.extent.height)
+                    .layers(1);
+
+                unsafe { device.device.create_framebuffer(&create_info, None) }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            framebuffers,
+            device: device.device.clone(),
+        })
+    }
+
+    // Single framebuffer for an offscreen pass, `color_views` in attachment order
+    pub fn offscreen(
+        device: &Device,
+        render_pass: &RenderPass,
+        color_views: &[vk::ImageView],
+        depth_view: Option<vk::ImageView>,
+        extent: vk::Extent2D,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        if color_views.len() != render_pass.color_formats.len() {
+            return Err(format!(
+                "Render pass has {} color attachments, got {} views",
+                render_pass.color_formats.len(),
+                color_views.len()
+            )
+            .into());
+        }
+
+        let attachments: Vec<_> = color_views.iter().copied().chain(depth_view).collect();
+        let create_info = vk::FramebufferCreateInfo::default()
+            .render_pass(render_pass.render_pass)
+            .attachments(&attachments)
+            .width(extent.width)
+            .height(extent.height)
+            .layers(1);
+        let framebuffer = unsafe { device.device.create_framebuffer(&create_info, None)? };
+
+        Ok(Self {
+            framebuffers: vec![framebuffer],
+            device: device.device.clone(),
+        })
+    }
+}
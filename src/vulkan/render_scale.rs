@@ -0,0 +1,193 @@
+use ash::vk;
+
+use super::{
+    DepthBuffer, Device, Framebuffers, Image, RenderPass, aspect_flags_for_format,
+    has_stencil_component,
+};
+
+// Accepted by Context::set_render_scale, above 1.0 is supersampling
+pub const MAX_RENDER_SCALE: f32 = 2.0;
+
+// Offscreen color and depth targets the scene renders into at a scaled resolution,
+// blitted (linear filtering) into the swapchain image at the end of the frame. The
+// render pass matches the main pass' formats, so pipelines built for it work here too.
+// Usable only when the swapchain images have TRANSFER_DST usage.
+pub struct ScaledTarget {
+    // Fields are dropped in declaration order, dependents before what they depend on
+    pub framebuffers: Framebuffers,
+    pub color: Image,
+    pub depth: Option<DepthBuffer>,
+    pub render_pass: RenderPass,
+    pub extent: vk::Extent2D,
+}
+
+impl ScaledTarget {
+    pub fn new(
+        device: &Device,
+        main_pass: &RenderPass,
+        extent: vk::Extent2D,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let format = main_pass.color_format;
+        if !supports_blit(device, format) {
+            return Err(format!("{format:?} can't be blitted with linear filtering").into());
+        }
+
+        let render_pass = RenderPass::blit_source(device, format, main_pass.depth_format)?;
+
+        let create_info = vk::ImageCreateInfo::default()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED);
+        let color = Image::new(
+            device,
+            &create_info,
+            vk::ImageViewType::TYPE_2D,
+            aspect_flags_for_format(format),
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+
+        // Same format as the main depth buffer, the render passes have to stay compatible
+        let depth = main_pass
+            .depth_format
+            .map(|depth_format| DepthBuffer::with_format(device, extent, depth_format))
+            .transpose()?;
+
+        let framebuffers = Framebuffers::offscreen(
+            device,
+            &render_pass,
+            &[color.view],
+            depth.as_ref().map(|depth| depth.image.view),
+            extent,
+        )?;
+
+        Ok(Self {
+            framebuffers,
+            color,
+            depth,
+            render_pass,
+            extent,
+        })
+    }
+
+    // Recorded after the render pass ended (the color image is in TRANSFER_SRC_OPTIMAL),
+    // leaves `dst_image` in PRESENT_SRC_KHR. The swapchain image's previous contents are
+    // discarded.
+    pub fn cmd_blit(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        dst_image: vk::Image,
+        dst_extent: vk::Extent2D,
+    ) {
+        let range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        let layers = vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level: 0,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+
+        // The submission waits for image_available at COLOR_ATTACHMENT_OUTPUT, the
+        // transition has to come after that wait
+        let to_transfer = vk::ImageMemoryBarrier::default()
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .src_access_mask(vk::AccessFlags::empty())
+            .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(dst_image)
+            .subresource_range(range);
+        // Ending in COLOR_ATTACHMENT_OUTPUT keeps the ownership release (which waits on
+        // that stage) ordered after the blit
+        let to_present = vk::ImageMemoryBarrier::default()
+            .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .new_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::empty())
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(dst_image)
+            .subresource_range(range);
+
+        let corner = |extent: vk::Extent2D| vk::Offset3D {
+            x: extent.width as i32,
+            y: extent.height as i32,
+            z: 1,
+        };
+        let region = vk::ImageBlit::default()
+            .src_subresource(layers)
+            .src_offsets([vk::Offset3D::default(), corner(self.extent)])
+            .dst_subresource(layers)
+            .dst_offsets([vk::Offset3D::default(), corner(dst_extent)]);
+
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[to_transfer],
+            );
+            device.cmd_blit_image(
+                command_buffer,
+                self.color.image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                dst_image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[region],
+                vk::Filter::LINEAR,
+            );
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[to_present],
+            );
+        }
+    }
+}
+
+// `extent` times `scale`, at least 1x1
+pub fn scaled_extent(extent: vk::Extent2D, scale: f32) -> vk::Extent2D {
+    let scale_axis = |axis: u32| ((axis as f32 * scale).round() as u32).max(1);
+    vk::Extent2D {
+        width: scale_axis(extent.width),
+        height: scale_axis(extent.height),
+    }
+}
+
+// Blits between two optimal tiling images of `format` with linear filtering
+pub fn supports_blit(device: &Device, format: vk::Format) -> bool {
+    device
+        .find_supported_format(
+            &[format],
+            vk::ImageTiling::OPTIMAL,
+            vk::FormatFeatureFlags::BLIT_SRC
+                | vk::FormatFeatureFlags::BLIT_DST
+                | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR,
+        )
+        .is_ok()
+}
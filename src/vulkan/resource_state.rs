@@ -0,0 +1,190 @@
+use ash::vk;
+use std::collections::HashMap;
+
+use super::layout_access_stage;
+
+// What a pass is about to do with an image, each maps to one layout
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageUsage {
+    // Contents don't matter (yet), only valid as the initial state
+    Undefined,
+    TransferSrc,
+    TransferDst,
+    // Sampled in fragment or compute shaders
+    Sampled,
+    // Read and written as a storage image
+    Storage,
+    ColorAttachment,
+    DepthAttachment,
+    // Depth test without writes, or sampled (shadow maps)
+    DepthRead,
+    Present,
+}
+
+impl ImageUsage {
+    pub fn layout(self) -> vk::ImageLayout {
+        match self {
+            Self::Undefined => vk::ImageLayout::UNDEFINED,
+            Self::TransferSrc => vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            Self::TransferDst => vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            Self::Sampled => vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            Self::Storage => vk::ImageLayout::GENERAL,
+            Self::ColorAttachment => vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            Self::DepthAttachment => vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            Self::DepthRead => vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+            Self::Present => vk::ImageLayout::PRESENT_SRC_KHR,
+        }
+    }
+
+    // Read after read needs no barrier, everything else does
+    fn is_read_only(self) -> bool {
+        matches!(
+            self,
+            Self::TransferSrc | Self::Sampled | Self::DepthRead | Self::Present
+        )
+    }
+}
+
+struct TrackedImage {
+    range: vk::ImageSubresourceRange,
+    usage: ImageUsage,
+}
+
+// Tracks the layout of registered images within one command buffer timeline so passes only
+// declare what they need next, `transition` works out the barrier from the last usage.
+// Not a render graph: images are tracked as a whole (every mip and layer share a state)
+// and single queue only. Render passes change layouts on their own through final_layout,
+// tell the tracker with `assume` afterwards.
+pub struct ResourceState {
+    images: HashMap<vk::Image, TrackedImage>,
+    device: ash::Device,
+}
+
+impl ResourceState {
+    pub fn new(device: &ash::Device) -> Self {
+        Self {
+            images: HashMap::new(),
+            device: device.clone(),
+        }
+    }
+
+    // Starts tracking `image`, `usage` is its current state (Undefined for new images)
+    pub fn register(
+        &mut self,
+        image: vk::Image,
+        range: vk::ImageSubresourceRange,
+        usage: ImageUsage,
+    ) {
+        self.images.insert(image, TrackedImage { range, usage });
+    }
+
+    // Stops tracking, call before destroying the image since handles can be reused
+    pub fn forget(&mut self, image: vk::Image) {
+        self.images.remove(&image);
+    }
+
+    pub fn usage(&self, image: vk::Image) -> Option<ImageUsage> {
+        self.images.get(&image).map(|tracked| tracked.usage)
+    }
+
+    // Records a transition done outside the tracker, e.g. a render pass's final_layout
+    pub fn assume(
+        &mut self,
+        image: vk::Image,
+        usage: ImageUsage,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.tracked_mut(image)?.usage = usage;
+        Ok(())
+    }
+
+    pub fn transition(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        image: vk::Image,
+        new_usage: ImageUsage,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.transition_all(command_buffer, &[(image, new_usage)])
+    }
+
+    // Moves every image to its new usage with a single vkCmdPipelineBarrier. Images
+    // already in a read-only usage that stays the same are skipped, same layout writes
+    // still get a barrier for the memory dependency.
+    pub fn transition_all(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        transitions: &[(vk::Image, ImageUsage)],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(&(_, usage)) = transitions
+            .iter()
+            .find(|(_, usage)| *usage == ImageUsage::Undefined)
+        {
+            return Err(format!("Can't transition to {usage:?}").into());
+        }
+        // Checked up front so a failure leaves every tracked state untouched
+        if let Some(&(image, _)) = transitions
+            .iter()
+            .find(|(image, _)| !self.images.contains_key(image))
+        {
+            return Err(format!("Image {image:?} isn't registered").into());
+        }
+
+        let mut src_stage = vk::PipelineStageFlags::empty();
+        let mut dst_stage = vk::PipelineStageFlags::empty();
+        let mut barriers = Vec::with_capacity(transitions.len());
+
+        for &(image, new_usage) in transitions {
+            let tracked = self.tracked_mut(image)?;
+            let old_usage = tracked.usage;
+            tracked.usage = new_usage;
+            if old_usage == new_usage && new_usage.is_read_only() {
+                continue;
+            }
+
+            let old_layout = old_usage.layout();
+            let new_layout = new_usage.layout();
+            let (src_access, old_stage) = layout_access_stage(old_layout);
+            let (dst_access, new_stage) = layout_access_stage(new_layout);
+            src_stage |= old_stage;
+            dst_stage |= new_stage;
+
+            barriers.push(
+                vk::ImageMemoryBarrier::default()
+                    .old_layout(old_layout)
+                    .new_layout(new_layout)
+                    .src_access_mask(src_access)
+                    .dst_access_mask(dst_access)
+                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .image(image)
+                    .subresource_range(tracked.range),
+            );
+        }
+
+        if barriers.is_empty() {
+            return Ok(());
+        }
+
+        unsafe {
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                src_stage,
+                dst_stage,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &barriers,
+            );
+        }
+
+        Ok(())
+    }
+
+    fn tracked_mut(
+        &mut self,
+        image: vk::Image,
+    ) -> Result<&mut TrackedImage, Box<dyn std::error::Error>> {
+        self.images
+            .get_mut(&image)
+            .ok_or_else(|| format!("Image {image:?} isn't registered").into())
+    }
+}
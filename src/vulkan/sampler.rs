@@ -0,0 +1,155 @@
+use ash::vk;
+
+use super::{Device, aspect_flags_for_format};
+
+pub struct Sampler {
+    pub sampler: vk::Sampler,
+    // Some for comparison samplers (`sampler2DShadow` and friends)
+    pub compare_op: Option<vk::CompareOp>,
+    // Anisotropy level actually used, None when disabled
+    pub max_anisotropy: Option<f32>,
+    device: ash::Device,
+}
+
+impl Drop for Sampler {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_sampler(self.sampler, None);
+        }
+    }
+}
+
+impl Sampler {
+    // Linear filtering, REPEAT addressing, no comparison, no anisotropy
+    pub fn builder() -> SamplerBuilder {
+        SamplerBuilder {
+            filter: vk::Filter::LINEAR,
+            mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+            address_mode: vk::SamplerAddressMode::REPEAT,
+            border_color: vk::BorderColor::FLOAT_OPAQUE_BLACK,
+            max_lod: vk::LOD_CLAMP_NONE,
+            compare_op: None,
+            anisotropy: None,
+        }
+    }
+
+    // For a COMBINED_IMAGE_SAMPLER write. Comparison samplers only work on depth formats
+    // in a depth read-only layout, anything else is rejected here instead of sampling
+    // garbage.
+    pub fn image_info(
+        &self,
+        view: vk::ImageView,
+        format: vk::Format,
+        layout: vk::ImageLayout,
+    ) -> Result<vk::DescriptorImageInfo, Box<dyn std::error::Error>> {
+        if self.compare_op.is_some() {
+            if !aspect_flags_for_format(format).contains(vk::ImageAspectFlags::DEPTH) {
+                return Err(
+                    format!("Comparison sampler used with non-depth format {format:?}").into(),
+                );
+            }
+            if !matches!(
+                layout,
+                vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL
+                    | vk::ImageLayout::DEPTH_READ_ONLY_OPTIMAL
+                    | vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+            ) {
+                return Err(
+                    format!("Comparison sampler used with depth image in {layout:?}").into(),
+                );
+            }
+        }
+
+        Ok(vk::DescriptorImageInfo::default()
+            .sampler(self.sampler)
+            .image_view(view)
+            .image_layout(layout))
+    }
+}
+
+pub struct SamplerBuilder {
+    filter: vk::Filter,
+    mipmap_mode: vk::SamplerMipmapMode,
+    address_mode: vk::SamplerAddressMode,
+    border_color: vk::BorderColor,
+    max_lod: f32,
+    compare_op: Option<vk::CompareOp>,
+    anisotropy: Option<f32>,
+}
+
+impl SamplerBuilder {
+    // Used for both magnification and minification
+    pub fn filter(mut self, filter: vk::Filter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn mipmap_mode(mut self, mipmap_mode: vk::SamplerMipmapMode) -> Self {
+        self.mipmap_mode = mipmap_mode;
+        self
+    }
+
+    // Same mode for U, V and W
+    pub fn address_mode(mut self, address_mode: vk::SamplerAddressMode) -> Self {
+        self.address_mode = address_mode;
+        self
+    }
+
+    // Only used with CLAMP_TO_BORDER
+    pub fn border_color(mut self, border_color: vk::BorderColor) -> Self {
+        self.border_color = border_color;
+        self
+    }
+
+    pub fn max_lod(mut self, max_lod: f32) -> Self {
+        self.max_lod = max_lod;
+        self
+    }
+
+    // Turns this into a comparison sampler for `sampler2DShadow`: texture() returns the
+    // result of `reference op stored_depth` instead of the depth. With LINEAR filtering
+    // the results of 2x2 texels are averaged (hardware PCF). The image has to be a depth
+    // format in DEPTH_STENCIL_READ_ONLY_OPTIMAL (or DEPTH_READ_ONLY_OPTIMAL).
+    pub fn compare(mut self, compare_op: vk::CompareOp) -> Self {
+        self.compare_op = Some(compare_op);
+        self
+    }
+
+    // Anisotropic filtering with up to `level` samples, clamped to maxSamplerAnisotropy.
+    // Levels up to 1 and devices without samplerAnisotropy silently leave it off, see
+    // Sampler::max_anisotropy for what was used.
+    pub fn anisotropy(mut self, level: f32) -> Self {
+        self.anisotropy = Some(level);
+        self
+    }
+
+    pub fn build(&self, device: &Device) -> Result<Sampler, Box<dyn std::error::Error>> {
+        let max_anisotropy = self
+            .anisotropy
+            .filter(|&level| device.enabled_features.sampler_anisotropy == vk::TRUE && level > 1.0)
+            .map(|level| level.min(device.properties.limits.max_sampler_anisotropy));
+
+        let create_info = vk::SamplerCreateInfo::default()
+            .mag_filter(self.filter)
+            .min_filter(self.filter)
+            .mipmap_mode(self.mipmap_mode)
+            .address_mode_u(self.address_mode)
+            .address_mode_v(self.address_mode)
+            .address_mode_w(self.address_mode)
+            .border_color(self.border_color)
+            .compare_enable(self.compare_op.is_some())
+            .compare_op(self.compare_op.unwrap_or(vk::CompareOp::ALWAYS))
+            .anisotropy_enable(max_anisotropy.is_some())
+            .max_anisotropy(max_anisotropy.unwrap_or(1.0))
+            .max_lod(self.max_lod);
+
+        let sampler = unsafe { device.device.create_sampler(&create_info, None)? };
+
+        Ok(Sampler {
+            sampler,
+            compare_op: self.compare_op,
+            max_anisotropy,
+            device: device.device.clone(),
+        })
+    }
+}
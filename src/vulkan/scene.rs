@@ -0,0 +1,332 @@
+use ash::vk;
+use std::path::PathBuf;
+
+use super::shadow::{SHADOW_DEPTH_BIAS_CONSTANT, SHADOW_DEPTH_BIAS_SLOPE};
+use super::{
+    DebugLabels, Device, GraphicsPipeline, GraphicsPipelineBuilder, InstanceData, Mesh, RenderPass,
+    ShadowMap, UniformLayout, Vertex, VertexBuffer, VertexInputBuilder, has_stencil_component,
+    look_at, mat4_mul, orthographic, read_spirv, stencil_test_state, stencil_write_state, viewport,
+};
+
+pub const SHADER_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/shaders");
+pub const ASSET_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/assets");
+
+pub(super) const GRID_SIZE: u32 = 8;
+const LABEL_COLOR: [f32; 4] = [0.2, 1.0, 0.4, 1.0];
+// Routes the scene through shaders/passthrough.geom, skipped without geometryShader support
+const PASSTHROUGH_GEOMETRY_SHADER: bool = false;
+
+// Value the stencil mask writes, cleared to 0 every frame
+const STENCIL_MASK_REFERENCE: u32 = 1;
+// Of the mask quad in the middle of the grid, covers the inner 4x4 cells
+const STENCIL_MASK_SCALE: f32 = 0.5;
+
+const SHADOW_MAP_RESOLUTION: u32 = 2048;
+const SHADOW_LABEL_COLOR: [f32; 4] = [0.5, 0.5, 0.5, 1.0];
+// Directional light shining at the origin from LIGHT_POSITION, its orthographic box
+// covers the whole grid
+const LIGHT_POSITION: [f32; 3] = [2.0, 2.0, 3.0];
+const LIGHT_EXTENT: f32 = 1.5;
+
+// Demo content: one quad (loaded from assets/quad.obj) drawn GRID_SIZE x GRID_SIZE times with per-instance transforms
+pub struct Scene {
+    pub pipeline: GraphicsPipeline,
+    pub mesh: Mesh,
+    pub instances: VertexBuffer,
+    // Depth from the light's point of view, None when its shaders aren't compiled. The
+    // shadow pipeline isn't hot reloaded.
+    pub shadow: Option<(ShadowMap, GraphicsPipeline)>,
+    // None without a stencil component in the main pass' depth buffer, see
+    // ContextBuilder::depth_stencil
+    pub stencil_mask: Option<StencilMask>,
+    // Device::flip_viewport_y, the camera's projection is corrected for it
+    pub flip_viewport_y: bool,
+}
+
+// Stencil mask demo: a quad in the middle of the grid writes STENCIL_MASK_REFERENCE, the
+// grid is then drawn with a stencil test that skips every pixel the quad covered. Same
+// shaders as the grid, only the depth/stencil state differs.
+pub struct StencilMask {
+    // Draws the mask quad, stencil_write_state without depth writes, so only the stencil
+    // test keeps the grid out of it
+    pub write: GraphicsPipeline,
+    // Grid pipeline with stencil_test_state NOT_EQUAL
+    pub test: GraphicsPipeline,
+    pub quad: VertexBuffer,
+}
+
+impl Scene {
+    pub fn new(
+        device: &Device,
+        render_pass: &RenderPass,
+        uniform_layout: &UniformLayout,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let pipeline = Self::create_pipeline(device, render_pass, uniform_layout)?;
+        let stencil_mask = Self::create_stencil_mask(device, render_pass, uniform_layout)?;
+
+        let mesh = Mesh::from_obj(device, format!("{ASSET_DIR}/quad.obj"))?;
+
+        // Grid covering -1..1 on X and Y in world space, each quad at 80% of its cell
+        let cell = 2.0 / GRID_SIZE as f32;
+        let instances: Vec<_> = (0..GRID_SIZE * GRID_SIZE)
+            .map(|idx| {
+                let x = -1.0 + cell * ((idx % GRID_SIZE) as f32 + 0.5);
+                let y = -1.0 + cell * ((idx / GRID_SIZE) as f32 + 0.5);
+                InstanceData::from_translation_scale([x, y, 0.0], cell * 0.4)
+            })
+            .collect();
+        let instances = VertexBuffer::new(device, &instances)?;
+
+        let shadow = match Self::create_shadow(device) {
+            Ok(shadow) => Some(shadow),
+            Err(err) => {
+                println!("Warning: Shadow pass disabled ({err})");
+                None
+            }
+        };
+
+        Ok(Self {
+            pipeline,
+            mesh,
+            instances,
+            shadow,
+            stencil_mask,
+            flip_viewport_y: device.flip_viewport_y,
+        })
+    }
+
+    // Vertex and fragment shader files the pipeline is built from
+    pub fn shader_paths() -> [PathBuf; 2] {
+        [
+            PathBuf::from(format!("{SHADER_DIR}/mesh.vert.spv")),
+            PathBuf::from(format!("{SHADER_DIR}/mesh.frag.spv")),
+        ]
+    }
+
+    // Reads the shaders from disk again, used for hot reloading
+    pub fn create_pipeline(
+        device: &Device,
+        render_pass: &RenderPass,
+        uniform_layout: &UniformLayout,
+    ) -> Result<GraphicsPipeline, Box<dyn std::error::Error>> {
+        Self::pipeline_builder(device, render_pass, uniform_layout)?.build(
+            device,
+            render_pass.render_pass,
+            0,
+        )
+    }
+
+    // None unless the pass' depth format has a stencil component
+    pub fn create_stencil_mask(
+        device: &Device,
+        render_pass: &RenderPass,
+        uniform_layout: &UniformLayout,
+    ) -> Result<Option<StencilMask>, Box<dyn std::error::Error>> {
+        if !render_pass.depth_format.is_some_and(has_stencil_component) {
+            return Ok(None);
+        }
+
+        let write_state = stencil_write_state(STENCIL_MASK_REFERENCE);
+        let write = Self::pipeline_builder(device, render_pass, uniform_layout)?
+            .depth_test(false, false, vk::CompareOp::ALWAYS)
+            .stencil_test(write_state, write_state)
+            .build(device, render_pass.render_pass, 0)?;
+        let test_state = stencil_test_state(STENCIL_MASK_REFERENCE, vk::CompareOp::NOT_EQUAL);
+        let test = Self::pipeline_builder(device, render_pass, uniform_layout)?
+            .stencil_test(test_state, test_state)
+            .build(device, render_pass.render_pass, 0)?;
+        let quad = VertexBuffer::new(
+            device,
+            &[InstanceData::from_translation_scale(
+                [0.0, 0.0, 0.0],
+                STENCIL_MASK_SCALE,
+            )],
+        )?;
+
+        Ok(Some(StencilMask { write, test, quad }))
+    }
+
+    // State shared by the grid pipeline and the stencil mask's
+    fn pipeline_builder(
+        device: &Device,
+        render_pass: &RenderPass,
+        uniform_layout: &UniformLayout,
+    ) -> Result<GraphicsPipelineBuilder, Box<dyn std::error::Error>> {
+        let [vertex_path, fragment_path] = Self::shader_paths();
+        let vertex_spv = read_spirv(vertex_path)?;
+        let fragment_spv = read_spirv(fragment_path)?;
+
+        let vertex_input =
+            InstanceData::vertex_input(Vertex::vertex_input(VertexInputBuilder::new()));
+
+        let mut builder = GraphicsPipeline::builder(vertex_spv, fragment_spv);
+        if PASSTHROUGH_GEOMETRY_SHADER {
+            if device.enabled_features.geometry_shader == vk::TRUE {
+                let geometry_spv = read_spirv(format!("{SHADER_DIR}/passthrough.geom.spv"))?;
+                builder = builder.geometry_shader(geometry_spv);
+            } else {
+                println!("Warning: Geometry shaders not supported, drawing without one");
+            }
+        }
+
+        Ok(builder
+            .vertex_input(vertex_input)
+            .descriptor_set_layouts(&[uniform_layout.layout])
+            .cull_mode(vk::CullModeFlags::NONE, vk::FrontFace::COUNTER_CLOCKWISE)
+            .depth_test(true, true, vk::CompareOp::LESS)
+            .samples(render_pass.samples))
+    }
+
+    fn create_shadow(
+        device: &Device,
+    ) -> Result<(ShadowMap, GraphicsPipeline), Box<dyn std::error::Error>> {
+        let shadow_map = ShadowMap::new(device, SHADOW_MAP_RESOLUTION)?;
+
+        let vertex_spv = read_spirv(format!("{SHADER_DIR}/shadow.vert.spv"))?;
+        let fragment_spv = read_spirv(format!("{SHADER_DIR}/shadow.frag.spv"))?;
+        let vertex_input =
+            InstanceData::vertex_input(Vertex::vertex_input(VertexInputBuilder::new()));
+
+        let pipeline = GraphicsPipeline::builder(vertex_spv, fragment_spv)
+            .vertex_input(vertex_input)
+            .cull_mode(vk::CullModeFlags::NONE, vk::FrontFace::COUNTER_CLOCKWISE)
+            .depth_test(true, true, vk::CompareOp::LESS)
+            .depth_bias(SHADOW_DEPTH_BIAS_CONSTANT, SHADOW_DEPTH_BIAS_SLOPE)
+            .color_attachments(0)
+            .push_constant_range(
+                vk::PushConstantRange::default()
+                    .stage_flags(vk::ShaderStageFlags::VERTEX)
+                    .offset(0)
+                    .size(std::mem::size_of::<[[f32; 4]; 4]>() as u32),
+            )
+            .build(device, shadow_map.render_pass.render_pass, 0)?;
+
+        Ok((shadow_map, pipeline))
+    }
+
+    pub fn light_view_projection() -> [[f32; 4]; 4] {
+        let view = look_at(LIGHT_POSITION, [0.0, 0.0, 0.0], [0.0, 1.0, 0.0]);
+        let projection = orthographic(
+            -LIGHT_EXTENT,
+            LIGHT_EXTENT,
+            -LIGHT_EXTENT,
+            LIGHT_EXTENT,
+            0.1,
+            10.0,
+        );
+        mat4_mul(projection, view)
+    }
+
+    // Renders the scene's depth into the shadow map, must be called outside of any render
+    // pass. `instances` replaces the static instance buffer (see InstanceAnimation).
+    pub fn record_shadow(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        instances: Option<&VertexBuffer>,
+        labels: &DebugLabels,
+    ) {
+        let Some((shadow_map, pipeline)) = &self.shadow else {
+            return;
+        };
+
+        let viewport = vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: shadow_map.extent.width as f32,
+            height: shadow_map.extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        };
+        let scissor = vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent: shadow_map.extent,
+        };
+        let light_view_projection = Self::light_view_projection();
+
+        labels.cmd_begin_label(command_buffer, "Shadow Pass", SHADOW_LABEL_COLOR);
+        shadow_map.cmd_begin(command_buffer);
+
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline.pipeline,
+            );
+            device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+            device.cmd_set_scissor(command_buffer, 0, &[scissor]);
+            device.cmd_push_constants(
+                command_buffer,
+                pipeline.layout,
+                vk::ShaderStageFlags::VERTEX,
+                0,
+                super::buffer::as_bytes(std::slice::from_ref(&light_view_projection)),
+            );
+        }
+
+        self.mesh
+            .draw_instanced(device, command_buffer, instances.unwrap_or(&self.instances));
+
+        unsafe { device.cmd_end_render_pass(command_buffer) };
+        labels.cmd_end_label(command_buffer);
+    }
+
+    // Records the draws, must be called inside the render pass the pipeline was built for
+    pub fn record(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        extent: vk::Extent2D,
+        uniform_set: vk::DescriptorSet,
+        instances: Option<&VertexBuffer>,
+        labels: &DebugLabels,
+    ) {
+        let viewport = viewport(extent, self.flip_viewport_y);
+        let scissor = vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent,
+        };
+
+        labels.cmd_begin_label(command_buffer, "Scene: Instanced Grid", LABEL_COLOR);
+
+        // The mask is drawn first, the grid pipeline then tests against it
+        let (first_pipeline, grid_pipeline) = match &self.stencil_mask {
+            Some(mask) => (&mask.write, &mask.test),
+            None => (&self.pipeline, &self.pipeline),
+        };
+
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                first_pipeline.pipeline,
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline.layout,
+                0,
+                &[uniform_set],
+                &[],
+            );
+            device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+            device.cmd_set_scissor(command_buffer, 0, &[scissor]);
+        }
+
+        if let Some(mask) = &self.stencil_mask {
+            self.mesh.draw_instanced(device, command_buffer, &mask.quad);
+            // Compatible pipeline layouts, the descriptor set stays bound
+            unsafe {
+                device.cmd_bind_pipeline(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    grid_pipeline.pipeline,
+                )
+            };
+        }
+        self.mesh
+            .draw_instanced(device, command_buffer, instances.unwrap_or(&self.instances));
+
+        labels.cmd_end_label(command_buffer);
+    }
+}
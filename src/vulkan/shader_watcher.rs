@@ -0,0 +1,41 @@
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+// Polls modification times of SPIR-V files, no file system notifications involved
+pub struct ShaderWatcher {
+    // None when the file doesn't exist (yet)
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+}
+
+impl ShaderWatcher {
+    pub fn new(paths: impl IntoIterator<Item = PathBuf>) -> Self {
+        let files = paths
+            .into_iter()
+            .map(|path| {
+                let modified = modified_time(&path);
+                (path, modified)
+            })
+            .collect();
+
+        Self { files }
+    }
+
+    // True when any file was created, modified or removed since the last call
+    pub fn poll(&mut self) -> bool {
+        let mut changed = false;
+        for (path, last_modified) in &mut self.files {
+            let modified = modified_time(path);
+            if modified != *last_modified {
+                *last_modified = modified;
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
@@ -0,0 +1,85 @@
+use ash::{khr, vk};
+
+use super::{Device, Instance};
+
+// Keeps the pipeline's rate, ignoring the (unused) primitive and attachment rates
+pub const KEEP_PIPELINE_RATE: [vk::FragmentShadingRateCombinerOpKHR; 2] = [
+    vk::FragmentShadingRateCombinerOpKHR::KEEP,
+    vk::FragmentShadingRateCombinerOpKHR::KEEP,
+];
+
+// Per-draw fragment shading rates (VK_KHR_fragment_shading_rate): one fragment shader
+// invocation covers e.g. 2x2 pixels, for draws where the detail doesn't matter (distant
+// or blurred geometry, backgrounds). Only pipelines built with
+// GraphicsPipelineBuilder::fragment_shading_rate accept the command. Without device
+// support every call is a no-op and everything shades at full rate.
+pub struct FragmentShadingRate {
+    loader: Option<khr::fragment_shading_rate::Device>,
+    // Device::fragment_shading_rates
+    rates: Vec<vk::Extent2D>,
+}
+
+impl FragmentShadingRate {
+    pub fn new(instance: &Instance, device: &Device) -> Self {
+        let loader = device
+            .fragment_shading_rate
+            .then(|| khr::fragment_shading_rate::Device::new(&instance.instance, &device.device));
+
+        Self {
+            loader,
+            rates: device.fragment_shading_rates.clone(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.loader.is_some()
+    }
+
+    pub fn supported_size(&self, size: vk::Extent2D) -> vk::Extent2D {
+        supported_fragment_size(&self.rates, size)
+    }
+
+    // Rate for the following draws, recorded inside or outside a render pass. `size` is
+    // rounded down to a supported one, `combiner_ops` merge it with the primitive and
+    // attachment rates (KEEP_PIPELINE_RATE when neither is used).
+    pub fn cmd_set(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        size: vk::Extent2D,
+        combiner_ops: [vk::FragmentShadingRateCombinerOpKHR; 2],
+    ) {
+        let Some(loader) = &self.loader else {
+            return;
+        };
+
+        let size = self.supported_size(size);
+        unsafe {
+            (loader.fp().cmd_set_fragment_shading_rate_khr)(command_buffer, &size, &combiner_ops)
+        };
+    }
+
+    // Back to one invocation per pixel
+    pub fn cmd_reset(&self, command_buffer: vk::CommandBuffer) {
+        self.cmd_set(
+            command_buffer,
+            vk::Extent2D {
+                width: 1,
+                height: 1,
+            },
+            KEEP_PIPELINE_RATE,
+        );
+    }
+}
+
+// Largest of `rates` not above `size` in either dimension, 1x1 is always supported
+pub fn supported_fragment_size(rates: &[vk::Extent2D], size: vk::Extent2D) -> vk::Extent2D {
+    rates
+        .iter()
+        .copied()
+        .filter(|rate| rate.width <= size.width && rate.height <= size.height)
+        .max_by_key(|rate| rate.width * rate.height)
+        .unwrap_or(vk::Extent2D {
+            width: 1,
+            height: 1,
+        })
+}
@@ -0,0 +1,133 @@
+use ash::vk;
+
+use super::{Device, Framebuffers, Image, RenderPass, Sampler, aspect_flags_for_format};
+
+// Preferred first, has to be both a depth attachment and sampleable
+const SHADOW_FORMATS: &[vk::Format] = &[vk::Format::D32_SFLOAT, vk::Format::D16_UNORM];
+
+// Default bias for `GraphicsPipelineBuilder::depth_bias`, tune per scene
+pub const SHADOW_DEPTH_BIAS_CONSTANT: f32 = 1.25;
+pub const SHADOW_DEPTH_BIAS_SLOPE: f32 = 1.75;
+
+// Depth-only offscreen target rendered from the light's point of view. After the pass
+// `depth.view` is in DEPTH_STENCIL_READ_ONLY_OPTIMAL and can be bound together with
+// `sampler` as a `sampler2DShadow` (see `image_info`):
+//
+//     layout(set = 1, binding = 0) uniform sampler2DShadow shadow_map;
+//     ... float lit = texture(shadow_map, vec3(light_uv, light_depth));
+pub struct ShadowMap {
+    // Dropped first, it references the depth view
+    pub framebuffer: Framebuffers,
+    pub depth: Image,
+    pub render_pass: RenderPass,
+    // Compares with LESS_OR_EQUAL, linear filtering gives 2x2 hardware PCF
+    pub sampler: Sampler,
+    pub extent: vk::Extent2D,
+    device: ash::Device,
+}
+
+impl ShadowMap {
+    // Square map of `resolution` texels, independent of the swapchain so it survives resizes
+    pub fn new(device: &Device, resolution: u32) -> Result<Self, Box<dyn std::error::Error>> {
+        let max_dimension = device.properties.limits.max_image_dimension2_d;
+        if resolution == 0 || resolution > max_dimension {
+            return Err(format!(
+                "Shadow map resolution {resolution} outside of supported range 1..={max_dimension}"
+            )
+            .into());
+        }
+
+        let format = device.find_supported_format(
+            SHADOW_FORMATS,
+            vk::ImageTiling::OPTIMAL,
+            vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT
+                | vk::FormatFeatureFlags::SAMPLED_IMAGE,
+        )?;
+        let extent = vk::Extent2D {
+            width: resolution,
+            height: resolution,
+        };
+
+        let create_info = vk::ImageCreateInfo::default()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED);
+        let depth = Image::new(
+            device,
+            &create_info,
+            vk::ImageViewType::TYPE_2D,
+            aspect_flags_for_format(format),
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+
+        let render_pass = RenderPass::offscreen(device, &[], Some(format))?;
+        let framebuffer =
+            Framebuffers::offscreen(device, &render_pass, &[], Some(depth.view), extent)?;
+
+        // Outside of the map counts as lit (depth 1.0 with a white border)
+        let sampler = Sampler::builder()
+            .filter(vk::Filter::LINEAR)
+            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+            .address_mode(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+            .border_color(vk::BorderColor::FLOAT_OPAQUE_WHITE)
+            .max_lod(0.0)
+            .compare(vk::CompareOp::LESS_OR_EQUAL)
+            .build(device)?;
+
+        Ok(Self {
+            framebuffer,
+            depth,
+            render_pass,
+            sampler,
+            extent,
+            device: device.device.clone(),
+        })
+    }
+
+    // COMBINED_IMAGE_SAMPLER descriptor for sampling the map after the pass
+    pub fn image_info(&self) -> Result<vk::DescriptorImageInfo, Box<dyn std::error::Error>> {
+        self.sampler.image_info(
+            self.depth.view,
+            self.depth.format,
+            vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+        )
+    }
+
+    // Begins the shadow pass, record depth-only draws with a pipeline built for
+    // `render_pass` and end it with cmd_end_render_pass
+    pub fn cmd_begin(&self, command_buffer: vk::CommandBuffer) {
+        let clear_values = [vk::ClearValue {
+            depth_stencil: vk::ClearDepthStencilValue {
+                depth: 1.0,
+                stencil: 0,
+            },
+        }];
+        let render_pass_begin = vk::RenderPassBeginInfo::default()
+            .render_pass(self.render_pass.render_pass)
+            .framebuffer(self.framebuffer.framebuffers[0])
+            .render_area(vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: self.extent,
+            })
+            .clear_values(&clear_values);
+
+        unsafe {
+            self.device.cmd_begin_render_pass(
+                command_buffer,
+                &render_pass_begin,
+                vk::SubpassContents::INLINE,
+            );
+        }
+    }
+}
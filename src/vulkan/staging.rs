@@ -0,0 +1,118 @@
+use ash::vk;
+
+use super::{Buffer, Device};
+
+// One persistently mapped host-visible buffer split into a region per frame in flight.
+// Each frame sub-allocates linearly from its own region, and the region is only reused
+// after `reset` is called for that frame, i.e. once its in-flight fence has been waited on,
+// so the CPU never overwrites data the GPU is still copying from.
+pub struct StagingRing {
+    buffer: Buffer,
+    mapped: *mut u8,
+    region_size: vk::DeviceSize,
+    region_count: usize,
+    alignment: vk::DeviceSize,
+
+    frame: usize,
+    offset: vk::DeviceSize,
+}
+
+impl StagingRing {
+    pub fn new(
+        device: &Device,
+        size_per_frame: vk::DeviceSize,
+        frames_in_flight: usize,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        if size_per_frame == 0 || frames_in_flight == 0 {
+            return Err("Staging ring needs a non-zero size and frame count".into());
+        }
+
+        // Buffer-to-image copies need offsets that are a multiple of 4 and of the texel size.
+        // Uncompressed texels are 1-4, 6, 8, 12, 16, 24 or 32 bytes and BCn blocks 8 or 16,
+        // all of which divide 96.
+        let alignment = lcm(
+            device
+                .properties
+                .limits
+                .optimal_buffer_copy_offset_alignment
+                .max(1),
+            96,
+        );
+        let region_size = size_per_frame.next_multiple_of(alignment);
+
+        let buffer = Buffer::new(
+            device,
+            region_size * frames_in_flight as vk::DeviceSize,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+        let mapped = buffer.map()?;
+
+        Ok(Self {
+            buffer,
+            mapped,
+            region_size,
+            region_count: frames_in_flight,
+            alignment,
+
+            frame: 0,
+            offset: 0,
+        })
+    }
+
+    // Copies `data` into the current frame's region and returns the (offset, buffer) to use
+    // as the source of a transfer command recorded for this frame.
+    // Errors when the region is exhausted, use a bigger ring or a dedicated staging Buffer.
+    pub fn stage(
+        &mut self,
+        data: &[u8],
+    ) -> Result<(vk::DeviceSize, vk::Buffer), Box<dyn std::error::Error>> {
+        let size = data.len() as vk::DeviceSize;
+        let offset = self.offset.next_multiple_of(self.alignment);
+
+        if offset + size > self.region_size {
+            return Err(format!(
+                "Staging ring region exhausted: {size} bytes requested, {} of {} bytes free",
+                self.region_size.saturating_sub(offset),
+                self.region_size
+            )
+            .into());
+        }
+
+        let absolute_offset = self.frame as vk::DeviceSize * self.region_size + offset;
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                data.as_ptr(),
+                self.mapped.add(absolute_offset as usize),
+                data.len(),
+            );
+        }
+
+        self.offset = offset + size;
+
+        Ok((absolute_offset, self.buffer.buffer))
+    }
+
+    // Makes `frame_index` the current region, must only be called once the GPU has finished
+    // every transfer recorded the last time that frame was current
+    pub fn reset(&mut self, frame_index: usize) {
+        self.frame = frame_index % self.region_count;
+        self.offset = 0;
+    }
+
+    pub fn used(&self) -> vk::DeviceSize {
+        self.offset
+    }
+
+    pub fn region_size(&self) -> vk::DeviceSize {
+        self.region_size
+    }
+}
+
+fn lcm(a: vk::DeviceSize, b: vk::DeviceSize) -> vk::DeviceSize {
+    let (mut x, mut y) = (a, b);
+    while y != 0 {
+        (x, y) = (y, x % y);
+    }
+    a / x * b
+}
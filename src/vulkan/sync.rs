@@ -0,0 +1,178 @@
+use ash::vk;
+
+use super::Device;
+
+// Outcome of Device::wait_for_fences
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FenceStatus {
+    Signaled,
+    // The fences may still signal later, wait again or give up
+    TimedOut,
+}
+
+// Sync objects of a window. image_available and in_flight exist per frame in flight,
+// render_finished per swapchain image.
+//
+// The semaphore presentation waits on can't be signaled again until the presentation
+// engine consumed it, and nothing reports when that happens: the frame's fence only
+// covers the graphics submission. With one render_finished per frame in flight, frame
+// N + frames_in_flight signals the semaphore frame N's present may still be waiting
+// on (e.g. 2 frames in flight, 3 images, MAILBOX keeping an image queued), which the
+// validation layers report as a semaphore reuse. Acquiring an image index again does
+// guarantee the previous present of that image is done with its wait, so one semaphore
+// per image is safe to reuse. image_available is waited on by a submission, and the
+// frame's fence covers that.
+//
+// Needs a presenting device, so there's no unit test. To check by hand: validation on
+// (InstanceConfig::VALIDATION_ENV), SwapchainConfig with PresentModePreference::LowLatency
+// (MAILBOX), frames_in_flight 2 and desired_image_count Some(3). Indexing render_finished
+// by the frame gets the semaphore reuse reported within a few frames
+// (VUID-vkQueueSubmit-pSignalSemaphores-00067), indexing it by the image doesn't.
+pub struct FrameSync {
+    pub image_available: Vec<vk::Semaphore>,
+    // Indexed by the acquired image index, not the frame
+    pub render_finished: Vec<vk::Semaphore>,
+    pub in_flight: Vec<vk::Fence>,
+    device: ash::Device,
+}
+
+impl Drop for FrameSync {
+    fn drop(&mut self) {
+        unsafe {
+            for &semaphore in self.image_available.iter().chain(&self.render_finished) {
+                self.device.destroy_semaphore(semaphore, None);
+            }
+            for &fence in &self.in_flight {
+                self.device.destroy_fence(fence, None);
+            }
+        }
+    }
+}
+
+impl FrameSync {
+    pub fn new(
+        device: &Device,
+        frames_in_flight: usize,
+        image_count: usize,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        // Dropping on error destroys whatever was already created
+        let mut sync = Self {
+            image_available: Vec::with_capacity(frames_in_flight),
+            render_finished: Vec::with_capacity(image_count),
+            in_flight: Vec::with_capacity(frames_in_flight),
+            device: device.device.clone(),
+        };
+
+        let semaphore_info = vk::SemaphoreCreateInfo::default();
+        // Start signaled so the first wait on each frame doesn't block forever
+        let fence_info = vk::FenceCreateInfo::default().flags(vk::FenceCreateFlags::SIGNALED);
+
+        for _ in 0..frames_in_flight {
+            unsafe {
+                sync.image_available
+                    .push(device.device.create_semaphore(&semaphore_info, None)?);
+                sync.in_flight
+                    .push(device.device.create_fence(&fence_info, None)?);
+            }
+        }
+        resize_semaphores(&sync.device, &mut sync.render_finished, image_count)?;
+
+        Ok(sync)
+    }
+
+    // After swapchain recreation, while the device is idle
+    pub fn set_image_count(
+        &mut self,
+        image_count: usize,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        resize_semaphores(&self.device, &mut self.render_finished, image_count)
+    }
+}
+
+// Grows or shrinks `semaphores` to `count`, existing ones are kept. Semaphores dropped
+// here must not be in use anymore.
+pub(super) fn resize_semaphores(
+    device: &ash::Device,
+    semaphores: &mut Vec<vk::Semaphore>,
+    count: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for semaphore in semaphores.drain(count.min(semaphores.len())..) {
+        unsafe { device.destroy_semaphore(semaphore, None) };
+    }
+
+    let semaphore_info = vk::SemaphoreCreateInfo::default();
+    while semaphores.len() < count {
+        let semaphore = unsafe { device.create_semaphore(&semaphore_info, None)? };
+        semaphores.push(semaphore);
+    }
+
+    Ok(())
+}
+
+// Recycled fences for one-off submissions (see submit_once), so frequent uploads don't
+// create and destroy a fence every time. Device owns one, see Device::fence_pool.
+pub struct FencePool {
+    available: Vec<vk::Fence>,
+    // Acquired and not released yet
+    in_use: usize,
+    device: ash::Device,
+}
+
+impl Drop for FencePool {
+    fn drop(&mut self) {
+        if self.in_use > 0 {
+            println!(
+                "Warning: {} fences still acquired from the fence pool",
+                self.in_use
+            );
+        }
+        unsafe {
+            for &fence in &self.available {
+                self.device.destroy_fence(fence, None);
+            }
+        }
+    }
+}
+
+impl FencePool {
+    pub fn new(device: &ash::Device) -> Self {
+        Self {
+            available: Vec::new(),
+            in_use: 0,
+            device: device.clone(),
+        }
+    }
+
+    // Unsignaled fence, a new one only when none is available
+    pub fn acquire(&mut self) -> Result<vk::Fence, Box<dyn std::error::Error>> {
+        let fence = match self.available.pop() {
+            Some(fence) => fence,
+            None => unsafe {
+                self.device
+                    .create_fence(&vk::FenceCreateInfo::default(), None)?
+            },
+        };
+        self.in_use += 1;
+        Ok(fence)
+    }
+
+    // Once the submission signaling `fence` was waited on (or it was never submitted),
+    // the fence is reset for the next acquire
+    pub fn release(&mut self, fence: vk::Fence) -> Result<(), Box<dyn std::error::Error>> {
+        self.in_use = self.in_use.saturating_sub(1);
+        if let Err(err) = unsafe { self.device.reset_fences(&[fence]) } {
+            unsafe { self.device.destroy_fence(fence, None) };
+            return Err(err.into());
+        }
+        self.available.push(fence);
+        Ok(())
+    }
+
+    pub fn in_use(&self) -> usize {
+        self.in_use
+    }
+
+    pub fn available(&self) -> usize {
+        self.available.len()
+    }
+}
@@ -0,0 +1,598 @@
+use ash::vk;
+
+use super::dds::{self, DdsImage};
+use super::{
+    Buffer, DepthBuffer, Device, Framebuffers, Image, RenderContext, RenderPass, camera,
+    submit_once, texel_size, transition_layout,
+};
+
+// Pixel data is expected as tightly packed RGBA8
+const TEXTURE_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+const BYTES_PER_PIXEL: usize = 4;
+
+// Sampled 2D texture, sampled with `sampler2D`
+pub struct Texture {
+    pub image: Image,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Texture {
+    pub fn from_rgba(
+        device: &Device,
+        pixels: &[u8],
+        width: u32,
+        height: u32,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let max_size = device.properties.limits.max_image_dimension2_d;
+        if width == 0 || height == 0 || width > max_size || height > max_size {
+            return Err(format!(
+                "Texture size {width}x{height} outside of supported range 1..={max_size}"
+            )
+            .into());
+        }
+
+        let expected = width as usize * height as usize * BYTES_PER_PIXEL;
+        if pixels.len() != expected {
+            return Err(format!(
+                "Texture has {} bytes, expected {expected} for {width}x{height}",
+                pixels.len()
+            )
+            .into());
+        }
+
+        let image = Image::new(
+            device,
+            &texture_create_info(TEXTURE_FORMAT, width, height, 1),
+            vk::ImageViewType::TYPE_2D,
+            vk::ImageAspectFlags::COLOR,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+
+        image.upload_layers(device, &[pixels])?;
+
+        Ok(Self {
+            image,
+            width,
+            height,
+        })
+    }
+
+    // Block-compressed (BC1-BC7) texture uploaded as-is, every mip level in the file included
+    pub fn from_dds(device: &Device, bytes: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        let dds = DdsImage::parse(bytes)?;
+        let max_size = device.properties.limits.max_image_dimension2_d;
+        if dds.width > max_size || dds.height > max_size {
+            return Err(format!(
+                "DDS texture size {}x{} outside of supported range 1..={max_size}",
+                dds.width, dds.height
+            )
+            .into());
+        }
+
+        // BCn support is optional (textureCompressionBC), check the exact format
+        if !device.format_supports(
+            dds.format,
+            vk::ImageTiling::OPTIMAL,
+            vk::FormatFeatureFlags::SAMPLED_IMAGE,
+        ) {
+            return Err(format!(
+                "Compressed format {:?} can't be sampled on this device",
+                dds.format
+            )
+            .into());
+        }
+
+        let image = Image::new(
+            device,
+            &texture_create_info(dds.format, dds.width, dds.height, dds.mip_levels),
+            vk::ImageViewType::TYPE_2D,
+            vk::ImageAspectFlags::COLOR,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+
+        let mut offset = 0;
+        let regions: Vec<_> = (0..dds.mip_levels)
+            .map(|level| {
+                let (width, height) = dds::mip_extent(dds.width, dds.height, level);
+                let region = vk::BufferImageCopy::default()
+                    .buffer_offset(offset)
+                    .image_subresource(vk::ImageSubresourceLayers {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        mip_level: level,
+                        base_array_layer: 0,
+                        layer_count: 1,
+                    })
+                    // Texel size of the mip, not rounded up to whole blocks
+                    .image_extent(vk::Extent3D {
+                        width,
+                        height,
+                        depth: 1,
+                    });
+                offset += dds::mip_size(dds.width, dds.height, level, dds.block_bytes);
+                region
+            })
+            .collect();
+
+        image.upload_regions(device, dds.data, &regions)?;
+
+        println!(
+            "Loaded DDS texture: {}x{}, {} mips, {:?}",
+            dds.width, dds.height, dds.mip_levels, dds.format
+        );
+
+        Ok(Self {
+            image,
+            width: dds.width,
+            height: dds.height,
+        })
+    }
+
+    // Renders into a new `format` texture with `record` (see RenderContext) and blocks
+    // until done, e.g. for a UI element or an environment probe. Color is cleared to
+    // transparent black, with `depth_format` a transient depth buffer is cleared to
+    // `clear_depth` and discarded afterwards. Pipelines have to be built for a compatible
+    // pass, RenderPass::offscreen(device, &[format], depth_format). Viewport and scissor
+    // cover the texture, frame_index and image_index are 0. The result is in
+    // SHADER_READ_ONLY_OPTIMAL and can also be read back (TRANSFER_SRC usage).
+    pub fn render(
+        device: &Device,
+        extent: vk::Extent2D,
+        format: vk::Format,
+        depth_format: Option<vk::Format>,
+        clear_depth: f32,
+        record: impl FnOnce(&RenderContext),
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let limits = &device.properties.limits;
+        let (max_width, max_height) = (limits.max_framebuffer_width, limits.max_framebuffer_height);
+        if extent.width == 0
+            || extent.height == 0
+            || extent.width > max_width
+            || extent.height > max_height
+        {
+            return Err(format!(
+                "Render target size {}x{} outside of supported range 1x1..={max_width}x{max_height}",
+                extent.width, extent.height
+            )
+            .into());
+        }
+        if !device.format_supports(
+            format,
+            vk::ImageTiling::OPTIMAL,
+            vk::FormatFeatureFlags::COLOR_ATTACHMENT | vk::FormatFeatureFlags::SAMPLED_IMAGE,
+        ) {
+            return Err(format!("{format:?} can't be used as a sampled color attachment").into());
+        }
+
+        let render_pass = RenderPass::offscreen(device, &[format], depth_format)?;
+        let create_info = vk::ImageCreateInfo::default()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(
+                vk::ImageUsageFlags::COLOR_ATTACHMENT
+                    | vk::ImageUsageFlags::SAMPLED
+                    | vk::ImageUsageFlags::TRANSFER_SRC,
+            )
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED);
+        let image = Image::new(
+            device,
+            &create_info,
+            vk::ImageViewType::TYPE_2D,
+            vk::ImageAspectFlags::COLOR,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+        let depth = depth_format
+            .map(|depth_format| DepthBuffer::with_format(device, extent, depth_format))
+            .transpose()?;
+        let framebuffers = Framebuffers::offscreen(
+            device,
+            &render_pass,
+            &[image.view],
+            depth.as_ref().map(|depth| depth.image.view),
+            extent,
+        )?;
+
+        let clear_values = [
+            vk::ClearValue {
+                color: vk::ClearColorValue {
+                    float32: [0.0, 0.0, 0.0, 0.0],
+                },
+            },
+            vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: clear_depth,
+                    stencil: 0,
+                },
+            },
+        ];
+        let render_area = vk::Rect2D {
+            offset: vk::Offset2D::default(),
+            extent,
+        };
+        let render_pass_begin = vk::RenderPassBeginInfo::default()
+            .render_pass(render_pass.render_pass)
+            .framebuffer(framebuffers.framebuffers[0])
+            .render_area(render_area)
+            .clear_values(&clear_values);
+
+        // The pass ends with the color in SHADER_READ_ONLY_OPTIMAL, no transition needed.
+        // Offscreen passes keep the projection's Y flip, no negative viewport.
+        submit_once(device, |cmd| unsafe {
+            device.device.cmd_begin_render_pass(
+                cmd,
+                &render_pass_begin,
+                vk::SubpassContents::INLINE,
+            );
+            device
+                .device
+                .cmd_set_viewport(cmd, 0, &[camera::viewport(extent, false)]);
+            device.device.cmd_set_scissor(cmd, 0, &[render_area]);
+            record(&RenderContext {
+                device: &device.device,
+                command_buffer: cmd,
+                render_pass: render_pass.render_pass,
+                frame_index: 0,
+                image_index: 0,
+                extent,
+            });
+            device.device.cmd_end_render_pass(cmd);
+        })?;
+
+        Ok(Self {
+            image,
+            width: extent.width,
+            height: extent.height,
+        })
+    }
+
+    pub fn from_dds_file(
+        device: &Device,
+        path: impl AsRef<std::path::Path>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::from_dds(device, &std::fs::read(path)?)
+    }
+
+    // Replaces `extent` texels at `offset` of one mip level, e.g. a tile of a streamed
+    // texture, and blocks until the copy is done. `data` is tightly packed in the texture's
+    // format, for block-compressed textures the region has to be aligned to 4x4 blocks
+    // (or end at the mip's edge). Only that mip goes SHADER_READ_ONLY -> TRANSFER_DST ->
+    // SHADER_READ_ONLY_OPTIMAL, the rest of the texture is untouched. Textures from
+    // Texture::render lack TRANSFER_DST usage and can't be updated.
+    pub fn update_region(
+        &self,
+        device: &Device,
+        mip_level: u32,
+        offset: vk::Offset3D,
+        extent: vk::Extent3D,
+        data: &[u8],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.image.usage.contains(vk::ImageUsageFlags::TRANSFER_DST) {
+            return Err(
+                "Texture was created without TRANSFER_DST usage, it can't be updated".into(),
+            );
+        }
+        if mip_level >= self.image.mip_levels {
+            return Err(format!(
+                "Mip level {mip_level} out of range, texture has {} mips",
+                self.image.mip_levels
+            )
+            .into());
+        }
+
+        let (mip_width, mip_height) = dds::mip_extent(self.width, self.height, mip_level);
+        let fits = |offset: i32, size: u32, max: u32| {
+            offset >= 0 && size > 0 && offset as u64 + size as u64 <= max as u64
+        };
+        if !fits(offset.x, extent.width, mip_width)
+            || !fits(offset.y, extent.height, mip_height)
+            || !fits(offset.z, extent.depth, 1)
+        {
+            return Err(format!(
+                "Region {extent:?} at {offset:?} outside of mip {mip_level} ({mip_width}x{mip_height})"
+            )
+            .into());
+        }
+
+        let (block_size, block_bytes) = block_layout(self.image.format).ok_or_else(|| {
+            format!(
+                "Partial updates of {:?} textures are not supported",
+                self.image.format
+            )
+        })?;
+        let aligned = |offset: i32, size: u32, max: u32| {
+            (offset as u32).is_multiple_of(block_size)
+                && (size.is_multiple_of(block_size) || offset as u32 + size == max)
+        };
+        if !aligned(offset.x, extent.width, mip_width)
+            || !aligned(offset.y, extent.height, mip_height)
+        {
+            return Err(format!(
+                "Region {extent:?} at {offset:?} isn't aligned to the {block_size}x{block_size} blocks of {:?}",
+                self.image.format
+            )
+            .into());
+        }
+
+        let expected = extent.width.div_ceil(block_size) as usize
+            * extent.height.div_ceil(block_size) as usize
+            * block_bytes as usize;
+        if data.len() != expected {
+            return Err(format!(
+                "Region has {} bytes, expected {expected} for {}x{}",
+                data.len(),
+                extent.width,
+                extent.height
+            )
+            .into());
+        }
+
+        let staging = Buffer::new(
+            device,
+            data.len() as vk::DeviceSize,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+        staging.write(data)?;
+
+        let range = vk::ImageSubresourceRange {
+            aspect_mask: self.image.aspect,
+            base_mip_level: mip_level,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        let region = vk::BufferImageCopy::default()
+            .image_subresource(vk::ImageSubresourceLayers {
+                aspect_mask: self.image.aspect,
+                mip_level,
+                base_array_layer: 0,
+                layer_count: 1,
+            })
+            .image_offset(offset)
+            .image_extent(extent);
+
+        // Not from UNDEFINED, the texels around the region have to survive
+        submit_once(device, |cmd| unsafe {
+            transition_layout(
+                &device.device,
+                cmd,
+                self.image.image,
+                range,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            );
+            device.device.cmd_copy_buffer_to_image(
+                cmd,
+                staging.buffer,
+                self.image.image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[region],
+            );
+            transition_layout(
+                &device.device,
+                cmd,
+                self.image.image,
+                range,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            );
+        })
+    }
+}
+
+// (block width/height in texels, bytes per block), 1x1 blocks of one texel for
+// uncompressed formats, 4x4 for BCn
+fn block_layout(format: vk::Format) -> Option<(u32, u32)> {
+    let bc_formats = vk::Format::BC1_RGB_UNORM_BLOCK.as_raw()..=vk::Format::BC7_SRGB_BLOCK.as_raw();
+    if bc_formats.contains(&format.as_raw()) {
+        return Some((4, dds::block_bytes(format)));
+    }
+    texel_size(format).map(|size| (1, size))
+}
+
+fn texture_create_info(
+    format: vk::Format,
+    width: u32,
+    height: u32,
+    mip_levels: u32,
+) -> vk::ImageCreateInfo<'static> {
+    vk::ImageCreateInfo::default()
+        .image_type(vk::ImageType::TYPE_2D)
+        .format(format)
+        .extent(vk::Extent3D {
+            width,
+            height,
+            depth: 1,
+        })
+        .mip_levels(mip_levels)
+        .array_layers(1)
+        .samples(vk::SampleCountFlags::TYPE_1)
+        .tiling(vk::ImageTiling::OPTIMAL)
+        .usage(vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+}
+
+// Six square faces in +X, -X, +Y, -Y, +Z, -Z order, sampled with `samplerCube`
+pub struct Cubemap {
+    pub image: Image,
+    pub size: u32,
+}
+
+impl Cubemap {
+    pub fn from_faces(
+        device: &Device,
+        faces: [&[u8]; 6],
+        size: u32,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let max_size = device.properties.limits.max_image_dimension_cube;
+        if size == 0 || size > max_size {
+            return Err(
+                format!("Cubemap size {size} outside of supported range 1..={max_size}").into(),
+            );
+        }
+
+        // Faces share one width/height, so each must hold exactly size x size pixels
+        let face_bytes = size as usize * size as usize * BYTES_PER_PIXEL;
+        for (idx, face) in faces.iter().enumerate() {
+            if face.len() != face_bytes {
+                return Err(format!(
+                    "Cubemap face {idx} has {} bytes, expected {face_bytes} for a {size}x{size} face",
+                    face.len()
+                )
+                .into());
+            }
+        }
+
+        let create_info = vk::ImageCreateInfo::default()
+            .flags(vk::ImageCreateFlags::CUBE_COMPATIBLE)
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(TEXTURE_FORMAT)
+            .extent(vk::Extent3D {
+                width: size,
+                height: size,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(6)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED);
+
+        let image = Image::new(
+            device,
+            &create_info,
+            vk::ImageViewType::CUBE,
+            vk::ImageAspectFlags::COLOR,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+
+        image.upload_layers(device, &faces)?;
+
+        Ok(Self { image, size })
+    }
+}
+
+// N same-sized layers in one image, sampled with `sampler2DArray` and a layer coordinate:
+//
+//     texture(layers, vec3(uv, float(layer)))
+pub struct TextureArray {
+    pub image: Image,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl TextureArray {
+    pub fn from_layers(
+        device: &Device,
+        layers: &[&[u8]],
+        width: u32,
+        height: u32,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let max_layers = device.properties.limits.max_image_array_layers;
+        if layers.is_empty() || layers.len() > max_layers as usize {
+            return Err(format!(
+                "Texture array layer count {} outside of supported range 1..={max_layers}",
+                layers.len()
+            )
+            .into());
+        }
+
+        let max_size = device.properties.limits.max_image_dimension2_d;
+        if width == 0 || height == 0 || width > max_size || height > max_size {
+            return Err(format!(
+                "Texture array size {width}x{height} outside of supported range 1..={max_size}"
+            )
+            .into());
+        }
+
+        let layer_bytes = width as usize * height as usize * BYTES_PER_PIXEL;
+        for (idx, layer) in layers.iter().enumerate() {
+            if layer.len() != layer_bytes {
+                return Err(format!(
+                    "Texture array layer {idx} has {} bytes, expected {layer_bytes} for {width}x{height}",
+                    layer.len()
+                )
+                .into());
+            }
+        }
+
+        let create_info = vk::ImageCreateInfo::default()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(TEXTURE_FORMAT)
+            .extent(vk::Extent3D {
+                width,
+                height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(layers.len() as u32)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED);
+
+        let image = Image::new(
+            device,
+            &create_info,
+            vk::ImageViewType::TYPE_2D_ARRAY,
+            vk::ImageAspectFlags::COLOR,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+
+        image.upload_layers(device, layers)?;
+
+        Ok(Self {
+            image,
+            width,
+            height,
+        })
+    }
+
+    pub fn layer_count(&self) -> u32 {
+        self.image.array_layers
+    }
+
+    // TYPE_2D view of a single layer, for sampling it as a plain `sampler2D`.
+    // The caller owns the view and destroys it before the array.
+    pub fn create_layer_view(
+        &self,
+        device: &Device,
+        layer: u32,
+    ) -> Result<vk::ImageView, Box<dyn std::error::Error>> {
+        if layer >= self.layer_count() {
+            return Err(format!(
+                "Layer {layer} out of range, texture array has {} layers",
+                self.layer_count()
+            )
+            .into());
+        }
+
+        let create_info = vk::ImageViewCreateInfo::default()
+            .image(self.image.image)
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(self.image.format)
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: layer,
+                layer_count: 1,
+            });
+
+        Ok(unsafe { device.device.create_image_view(&create_info, None)? })
+    }
+}
@@ -0,0 +1,127 @@
+use ash::vk;
+
+use super::{Buffer, DescriptorWriter, Device};
+
+// Set 0 with a single uniform buffer at binding 0, visible to the vertex shader
+pub struct UniformLayout {
+    pub layout: vk::DescriptorSetLayout,
+    device: ash::Device,
+}
+
+impl Drop for UniformLayout {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_descriptor_set_layout(self.layout, None);
+        }
+    }
+}
+
+impl UniformLayout {
+    pub const BINDING: u32 = 0;
+
+    pub fn new(device: &Device) -> Result<Self, Box<dyn std::error::Error>> {
+        let bindings = [vk::DescriptorSetLayoutBinding::default()
+            .binding(Self::BINDING)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::VERTEX)];
+
+        let layout_create_info = vk::DescriptorSetLayoutCreateInfo::default().bindings(&bindings);
+        let layout = unsafe {
+            device
+                .device
+                .create_descriptor_set_layout(&layout_create_info, None)?
+        };
+
+        Ok(Self {
+            layout,
+            device: device.device.clone(),
+        })
+    }
+}
+
+// One host visible uniform buffer and descriptor set per frame in flight, a frame only
+// writes its own buffer after waiting on its fence
+pub struct UniformBuffers {
+    pub buffers: Vec<Buffer>,
+    pub sets: Vec<vk::DescriptorSet>,
+    pub pool: vk::DescriptorPool,
+    device: ash::Device,
+}
+
+impl Drop for UniformBuffers {
+    fn drop(&mut self) {
+        unsafe {
+            // Destroying the pool frees the sets allocated from it
+            self.device.destroy_descriptor_pool(self.pool, None);
+        }
+    }
+}
+
+impl UniformBuffers {
+    pub fn new(
+        device: &Device,
+        layout: &UniformLayout,
+        size: vk::DeviceSize,
+        frames_in_flight: usize,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let buffers = (0..frames_in_flight)
+            .map(|_| {
+                Buffer::new(
+                    device,
+                    size,
+                    vk::BufferUsageFlags::UNIFORM_BUFFER,
+                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let pool_sizes = [vk::DescriptorPoolSize::default()
+            .ty(vk::DescriptorType::UNIFORM_BUFFER)
+            .descriptor_count(frames_in_flight as u32)];
+        let pool_create_info = vk::DescriptorPoolCreateInfo::default()
+            .pool_sizes(&pool_sizes)
+            .max_sets(frames_in_flight as u32);
+        let pool = unsafe {
+            device
+                .device
+                .create_descriptor_pool(&pool_create_info, None)?
+        };
+
+        let set_layouts = vec![layout.layout; frames_in_flight];
+        let allocate_info = vk::DescriptorSetAllocateInfo::default()
+            .descriptor_pool(pool)
+            .set_layouts(&set_layouts);
+        let sets = match unsafe { device.device.allocate_descriptor_sets(&allocate_info) } {
+            Ok(sets) => sets,
+            Err(err) => {
+                unsafe { device.device.destroy_descriptor_pool(pool, None) };
+                return Err(err.into());
+            }
+        };
+
+        // Dropping on error frees the pool
+        let uniforms = Self {
+            buffers,
+            sets,
+            pool,
+            device: device.device.clone(),
+        };
+        for (&set, buffer) in uniforms.sets.iter().zip(&uniforms.buffers) {
+            DescriptorWriter::new(set)
+                .uniform_buffer(UniformLayout::BINDING, buffer, 0, vk::WHOLE_SIZE)
+                .update(device)?;
+        }
+
+        Ok(uniforms)
+    }
+
+    // Only call once the frame's fence has signaled, the GPU may still read the buffer before
+    pub fn update<T: Copy>(
+        &self,
+        frame: usize,
+        data: &T,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.buffers[frame].write(std::slice::from_ref(data))
+    }
+}